@@ -0,0 +1,127 @@
+//! Throughput of the cost kernels, run with `cargo bench`.
+//! On aarch64 this compares the NEON kernels against the portable ones,
+//! elsewhere it shows what the autovectorized portable kernels manage
+
+#[path = "../src/kernels.rs"]
+#[allow(dead_code)]
+mod kernels;
+
+use image::Rgb;
+use rand::random;
+use std::{hint::black_box, time::Instant};
+
+const PIXELS: usize = 1 << 20;
+const ROUNDS: u32 = 200;
+
+/// Runs `f` `ROUNDS` times and prints how many pixels per second it got through
+fn bench(name: &str, mut f: impl FnMut() -> u64) {
+    let start = Instant::now();
+    let mut s = 0;
+    for _ in 0..ROUNDS {
+        s += black_box(f());
+    }
+    let seconds = start.elapsed().as_secs_f64();
+    println!(
+        "{name:<28} {:>10.1} Mpixels/s (checksum {s})",
+        (PIXELS as f64 * ROUNDS as f64) / seconds / 1e6
+    );
+}
+
+fn main() {
+    // the kernels work on one channel plane at a time, so each pixel is 3 values
+    let planes1: [Vec<u8>; 3] = std::array::from_fn(|_| (0..PIXELS).map(|_| random()).collect());
+    let planes2: [Vec<u8>; 3] = std::array::from_fn(|_| (0..PIXELS).map(|_| random()).collect());
+    let color = Rgb([random(), random(), random()]);
+
+    let plane_differences = |f: fn(&[u8], &[u8]) -> u64| {
+        let (planes1, planes2) = (&planes1, &planes2);
+        move || {
+            (0..3)
+                .map(|c| f(black_box(&planes1[c]), black_box(&planes2[c])))
+                .sum()
+        }
+    };
+    bench(
+        "portable pixel differences",
+        plane_differences(kernels::portable::sum_differences),
+    );
+    bench("portable color differences", || {
+        (0..3)
+            .map(|c| kernels::portable::sum_value_differences(black_box(&planes1[c]), color.0[c]))
+            .sum()
+    });
+    #[cfg(target_arch = "aarch64")]
+    {
+        bench(
+            "neon pixel differences",
+            plane_differences(kernels::neon::sum_differences),
+        );
+        bench("neon color differences", || {
+            (0..3)
+                .map(|c| kernels::neon::sum_value_differences(black_box(&planes1[c]), color.0[c]))
+                .sum()
+        });
+    }
+    // overlay at partial opacity, which has the most going on of the blends. Both ways start each
+    // round from a fresh copy of the pixels, so they pay for the same copying
+    let opacity: u8 = 200;
+    let overlay = |a: u32, b: u32| {
+        let doubled = 2 * a;
+        let dark = doubled.min(255) * b / 255;
+        let light = 255 - (255 - (doubled.max(255) - 255)) * (255 - b) / 255;
+        if a < 128 {
+            dark
+        } else {
+            light
+        }
+    };
+    let mut blended: [Vec<u8>; 3] = planes1.clone();
+    bench("portable overlay blending", || {
+        blended.clone_from(&planes1);
+        for (plane, value) in blended.iter_mut().zip(color.0) {
+            kernels::blend_values(black_box(plane), value, opacity, overlay);
+        }
+        blended.iter().map(|plane| plane[0] as u64).sum()
+    });
+    // the way the pixels used to get blended, one at a time, picking the blend for every value
+    let blends: [fn(u32, u32) -> u32; 2] = [|_, b| b, overlay];
+    let which = black_box(1);
+    let mut per_pixel: [Vec<u8>; 3] = planes1.clone();
+    bench("per-pixel overlay blending", || {
+        per_pixel.clone_from(&planes1);
+        let opacity = opacity as u32;
+        for i in 0..PIXELS {
+            for (plane, value) in per_pixel.iter_mut().zip(color.0) {
+                let (a, b) = (plane[i] as u32, value as u32);
+                let painted = blends[which](a, b);
+                plane[i] = ((painted * opacity + a * (255 - opacity) + 127) / 255) as u8;
+            }
+        }
+        per_pixel.iter().map(|plane| plane[0] as u64).sum()
+    });
+    assert_eq!(blended, per_pixel);
+
+    // making sure whichever kernels got picked agree with the portable ones
+    let portable_pixel_differences = (0..3)
+        .map(|c| kernels::portable::sum_differences(&planes1[c], &planes2[c]))
+        .sum::<u64>();
+    let portable_color_differences = (0..3)
+        .map(|c| kernels::portable::sum_value_differences(&planes1[c], color.0[c]))
+        .sum::<u64>();
+    assert_eq!(
+        kernels::sum_plane_differences(&planes1, &planes2),
+        portable_pixel_differences
+    );
+    assert_eq!(
+        kernels::par_sum_plane_differences(&planes1, &planes2),
+        portable_pixel_differences
+    );
+    assert_eq!(
+        kernels::sum_plane_color_differences(&planes1, color),
+        portable_color_differences
+    );
+    assert_eq!(
+        kernels::par_sum_plane_color_differences(&planes1, color),
+        portable_color_differences
+    );
+}
@@ -0,0 +1,551 @@
+//! Everything `anneal` can be told besides the target, gathered up so that new settings don't
+//! change its signature. `AnnealParams::new` starts from the same defaults as the command line,
+//! and every setter checks its value, so whoever builds the settings gets told straight away
+//! when one doesn't make sense
+
+use crate::{
+    budget::BudgetSplit,
+    distributed::Coordinator,
+    edges::EdgeStrokes,
+    fixed_shapes::FixedShapes,
+    flow::FlowStrokes,
+    footprints::Footprints,
+    frames::Frames,
+    geometry::MAX_VERTICES,
+    hints::Hints,
+    journal::{Journal, JournalEntry},
+    live_params::LiveParams,
+    orientation::OrientationMap,
+    superpixels::Superpixels,
+    target::Target,
+    Blend, ShapeKind, SharedCanvas, Symmetry,
+};
+use std::time::Duration;
+
+/// Settings for annealing an image. Start from `AnnealParams::new` and change them with the
+/// setters, which panic on values that don't make sense
+pub struct AnnealParams<'a> {
+    pub alpha: f64,
+    pub shape_kinds: &'a [ShapeKind],
+    pub vertices: usize,
+    pub stroke_width: (f64, f64),
+    pub grid: usize,
+    pub tileable: bool,
+    pub overhang: f64,
+    pub edge_bias: f64,
+    pub adaptive_sizes: bool,
+    pub converged_threshold: Option<f64>,
+    pub symmetry: Option<Symmetry>,
+    pub blend_modes: &'a [Blend],
+    pub opacity: (f64, f64),
+    pub anti_alias: bool,
+    pub gradients: f64,
+    pub color_jitter: bool,
+    pub removal_moves: f64,
+    pub recolor_moves: f64,
+    pub channel_moves: f64,
+    pub sample: Option<u32>,
+    pub multithreading: bool,
+    pub shared_canvas: Option<SharedCanvas>,
+    pub throttle: Option<f64>,
+    pub progressive_output: Option<(&'a str, Duration, Option<&'a [u8]>)>,
+    pub frames: Option<Frames>,
+    pub status_interval: Duration,
+    pub max_iterations: Option<u64>,
+    pub min_iterations_per_second: Option<f64>,
+    pub dhash_distance: Option<u32>,
+    pub journal: Option<(Journal, Vec<JournalEntry>)>,
+    pub coordinator: Option<Coordinator>,
+    pub hints: Option<Hints>,
+    pub orientation: Option<(&'a OrientationMap, f64)>,
+    pub mesh: bool,
+    pub color_depth_schedule: Option<(u8, u8)>,
+    pub polish: bool,
+    pub live_params: Option<LiveParams>,
+    pub superpixels: Option<(&'a Superpixels, f64)>,
+    pub edge_strokes: Option<(&'a EdgeStrokes, f64)>,
+    pub flow_strokes: Option<(&'a FlowStrokes, f64)>,
+    pub fixed_shapes: Option<&'a FixedShapes>,
+    pub color_target: Option<&'a Target>,
+    pub min_improvement: Option<(f64, f64)>,
+    pub budget: Option<BudgetSplit>,
+    pub footprints: Footprints,
+}
+
+impl<'a> AnnealParams<'a> {
+    /// Rectangles on a 1 pixel grid, in plain paint, cooling down by 0.999 every iteration, with
+    /// none of the extras
+    pub fn new() -> AnnealParams<'a> {
+        AnnealParams {
+            alpha: 0.999,
+            shape_kinds: &[ShapeKind::Rectangle],
+            vertices: 5,
+            stroke_width: (2.0, 8.0),
+            grid: 1,
+            tileable: false,
+            overhang: 0.0,
+            edge_bias: 0.0,
+            adaptive_sizes: false,
+            converged_threshold: None,
+            symmetry: None,
+            blend_modes: &[Blend::Normal],
+            opacity: (1.0, 1.0),
+            anti_alias: false,
+            gradients: 0.0,
+            color_jitter: false,
+            removal_moves: 0.0,
+            recolor_moves: 0.0,
+            channel_moves: 0.0,
+            sample: None,
+            multithreading: false,
+            shared_canvas: None,
+            throttle: None,
+            progressive_output: None,
+            frames: None,
+            status_interval: Duration::from_millis(250),
+            max_iterations: None,
+            min_iterations_per_second: None,
+            dhash_distance: None,
+            journal: None,
+            coordinator: None,
+            hints: None,
+            orientation: None,
+            mesh: false,
+            color_depth_schedule: None,
+            polish: false,
+            live_params: None,
+            superpixels: None,
+            edge_strokes: None,
+            flow_strokes: None,
+            fixed_shapes: None,
+            color_target: None,
+            min_improvement: None,
+            budget: None,
+            footprints: Footprints::new(256 << 20, None),
+        }
+    }
+
+    /// How much the temperature gets multiplied by every iteration
+    pub fn alpha(self, alpha: f64) -> Self {
+        if !(0.0 < alpha && alpha < 1.0) {
+            panic!("alpha must be greater than 0 and less than 1");
+        }
+        AnnealParams { alpha, ..self }
+    }
+
+    /// Kinds of shapes that proposals get picked from at random
+    pub fn shape_kinds(self, shape_kinds: &'a [ShapeKind]) -> Self {
+        if shape_kinds.is_empty() {
+            panic!("there has to be at least one kind of shape");
+        }
+        AnnealParams {
+            shape_kinds,
+            ..self
+        }
+    }
+
+    /// Most vertices polygons get proposed with
+    pub fn vertices(self, vertices: usize) -> Self {
+        if !(3..=MAX_VERTICES).contains(&vertices) {
+            panic!("polygons must have at least 3 and at most {MAX_VERTICES} vertices");
+        }
+        AnnealParams { vertices, ..self }
+    }
+
+    /// Thinnest and thickest strokes get proposed, in pixels
+    pub fn stroke_width(self, stroke_width: (f64, f64)) -> Self {
+        let (thinnest, thickest) = stroke_width;
+        if !(1.0 <= thinnest && thinnest <= thickest && thickest.is_finite()) {
+            panic!("stroke widths must be at least 1, with the thinnest first");
+        }
+        AnnealParams {
+            stroke_width,
+            ..self
+        }
+    }
+
+    /// Size of the grid that shapes' corners snap to, in pixels
+    pub fn grid(self, grid: usize) -> Self {
+        if grid == 0 {
+            panic!("grid must be at least 1");
+        }
+        AnnealParams { grid, ..self }
+    }
+
+    /// Whether shapes hanging off one edge wrap around to the other, so the result tiles
+    pub fn tileable(self, tileable: bool) -> Self {
+        AnnealParams { tileable, ..self }
+    }
+
+    /// How far past each edge rectangles can get proposed, as a fraction of the image's size
+    pub fn overhang(self, overhang: f64) -> Self {
+        if !(0.0..=1.0).contains(&overhang) {
+            panic!("overhang must be at least 0 and at most 1");
+        }
+        AnnealParams { overhang, ..self }
+    }
+
+    /// Fraction of proposals that get put near an edge or corner of the image
+    pub fn edge_bias(self, edge_bias: f64) -> Self {
+        if !(0.0..=1.0).contains(&edge_bias) {
+            panic!("edge bias must be at least 0 and at most 1");
+        }
+        AnnealParams { edge_bias, ..self }
+    }
+
+    /// Whether shape sizes follow the size of the details that are still missing
+    pub fn adaptive_sizes(self, adaptive_sizes: bool) -> Self {
+        AnnealParams {
+            adaptive_sizes,
+            ..self
+        }
+    }
+
+    /// How close (in average difference per channel) a tile of the canvas has to get to the original
+    /// image for shapes that only land on done tiles to get thrown away, if they ever do
+    pub fn converged_threshold(self, converged_threshold: Option<f64>) -> Self {
+        if converged_threshold.is_some_and(|threshold| threshold <= 0.0) {
+            panic!("converged threshold must be greater than 0");
+        }
+        AnnealParams {
+            converged_threshold,
+            ..self
+        }
+    }
+
+    /// Symmetry every shape gets mirrored by, if any
+    pub fn symmetry(self, symmetry: Option<Symmetry>) -> Self {
+        AnnealParams { symmetry, ..self }
+    }
+
+    /// Blend modes that shapes get picked from at random
+    pub fn blend_modes(self, blend_modes: &'a [Blend]) -> Self {
+        if blend_modes.is_empty() {
+            panic!("there has to be at least one blend mode");
+        }
+        AnnealParams {
+            blend_modes,
+            ..self
+        }
+    }
+
+    /// Least and most opaque shapes get painted, as fractions
+    pub fn opacity(self, opacity: (f64, f64)) -> Self {
+        let (lowest, highest) = opacity;
+        if !(0.0 < lowest && lowest <= highest && highest <= 1.0) {
+            panic!("opacities must be greater than 0 and at most 1, with the least opaque first");
+        }
+        AnnealParams { opacity, ..self }
+    }
+
+    /// Whether shapes get painted over the pixels they only partly cover by how much they cover
+    pub fn anti_alias(self, anti_alias: bool) -> Self {
+        AnnealParams { anti_alias, ..self }
+    }
+
+    /// Fraction of new shapes that get painted with a linear gradient instead of a single color
+    pub fn gradients(self, gradients: f64) -> Self {
+        if !(0.0..=1.0).contains(&gradients) {
+            panic!("gradients must be at least 0 and at most 1");
+        }
+        AnnealParams { gradients, ..self }
+    }
+
+    /// Whether shape colors get jittered around their best fit, less and less as it cools down
+    pub fn color_jitter(self, color_jitter: bool) -> Self {
+        AnnealParams {
+            color_jitter,
+            ..self
+        }
+    }
+
+    /// Fraction of proposals that try taking an accepted shape back out
+    pub fn removal_moves(self, removal_moves: f64) -> Self {
+        if !(0.0..1.0).contains(&removal_moves)
+            || removal_moves + self.recolor_moves + self.channel_moves >= 1.0
+        {
+            panic!("removal moves must be at least 0 and less than 1");
+        }
+        AnnealParams {
+            removal_moves,
+            ..self
+        }
+    }
+
+    /// Fraction of proposals that try a new color on the oldest shapes, one after another
+    pub fn recolor_moves(self, recolor_moves: f64) -> Self {
+        if recolor_moves < 0.0 || self.removal_moves + recolor_moves + self.channel_moves >= 1.0 {
+            panic!(
+                "recolor moves must be at least 0, and add up to less than 1 with removal moves"
+            );
+        }
+        AnnealParams {
+            recolor_moves,
+            ..self
+        }
+    }
+
+    /// Fraction of proposals that try fitting just one color channel of a random shape better
+    pub fn channel_moves(self, channel_moves: f64) -> Self {
+        if channel_moves < 0.0 || self.removal_moves + self.recolor_moves + channel_moves >= 1.0 {
+            panic!(
+                "channel moves must be at least 0, and add up to less than 1 with removal and recolor moves"
+            );
+        }
+        AnnealParams {
+            channel_moves,
+            ..self
+        }
+    }
+
+    /// How many pixels the cost of a move gets estimated from to begin with, if it isn't worked out
+    /// exactly. Moves too close to call draw more
+    pub fn sample(self, sample: Option<u32>) -> Self {
+        if sample == Some(0) {
+            panic!("sample must be at least 1");
+        }
+        AnnealParams { sample, ..self }
+    }
+
+    /// Whether pixels get painted by several threads at once
+    pub fn multithreading(self, multithreading: bool) -> Self {
+        AnnealParams {
+            multithreading,
+            ..self
+        }
+    }
+
+    /// Shared memory that every committed change gets copied over to
+    pub fn shared_canvas(self, shared_canvas: Option<SharedCanvas>) -> Self {
+        AnnealParams {
+            shared_canvas,
+            ..self
+        }
+    }
+
+    /// Fraction of the time to be busy annealing, pausing the rest of it
+    pub fn throttle(self, throttle: Option<f64>) -> Self {
+        if throttle.is_some_and(|throttle| !(0.0 < throttle && throttle <= 1.0)) {
+            panic!("throttle must be greater than 0 and at most 1");
+        }
+        AnnealParams { throttle, ..self }
+    }
+
+    /// Path the image annealed so far gets saved to every interval, along with the ICC profile to
+    /// embed in it
+    pub fn progressive_output(
+        self,
+        progressive_output: Option<(&'a str, Duration, Option<&'a [u8]>)>,
+    ) -> Self {
+        if progressive_output.is_some_and(|(_, interval, _)| interval.is_zero()) {
+            panic!("progressive interval must be greater than 0");
+        }
+        AnnealParams {
+            progressive_output,
+            ..self
+        }
+    }
+
+    /// How the progressive output's snapshots get numbered, if they get saved as frames of their
+    /// own instead of overwriting the output
+    pub fn frames(self, frames: Option<Frames>) -> Self {
+        if frames.is_some_and(|frames| frames.digits == 0) {
+            panic!("frame digits must be greater than 0");
+        }
+        AnnealParams { frames, ..self }
+    }
+
+    /// How long to wait between updates of the status line
+    pub fn status_interval(self, status_interval: Duration) -> Self {
+        if status_interval.is_zero() {
+            panic!("status interval must be greater than 0");
+        }
+        AnnealParams {
+            status_interval,
+            ..self
+        }
+    }
+
+    /// How many iterations to stop after at most
+    pub fn max_iterations(self, max_iterations: Option<u64>) -> Self {
+        AnnealParams {
+            max_iterations,
+            ..self
+        }
+    }
+
+    /// How few iterations per second (not counting throttling) to give up at
+    pub fn min_iterations_per_second(self, min_iterations_per_second: Option<f64>) -> Self {
+        AnnealParams {
+            min_iterations_per_second,
+            ..self
+        }
+    }
+
+    /// How many bits (out of 64) the canvas's dHash can be off the target's for annealing to stop
+    pub fn dhash_distance(self, dhash_distance: Option<u32>) -> Self {
+        if dhash_distance.is_some_and(|distance| distance > 64) {
+            panic!("dHash distance must be at most 64");
+        }
+        AnnealParams {
+            dhash_distance,
+            ..self
+        }
+    }
+
+    /// Journal that every accepted move gets logged to, after replaying the moves that are
+    /// already in it to pick up where a previous run left off
+    pub fn journal(self, journal: Option<(Journal, Vec<JournalEntry>)>) -> Self {
+        AnnealParams { journal, ..self }
+    }
+
+    /// Coordinator whose workers' shapes get proposed alongside the random ones
+    pub fn coordinator(self, coordinator: Option<Coordinator>) -> Self {
+        AnnealParams {
+            coordinator,
+            ..self
+        }
+    }
+
+    /// Hints about where shapes should mostly get proposed, picking up any changes made to the
+    /// hints file along the way
+    pub fn hints(self, hints: Option<Hints>) -> Self {
+        AnnealParams { hints, ..self }
+    }
+
+    /// Structure of the original image, and how much lower than they do shapes lined up with it
+    /// are treated as lowering the cost by, at most
+    pub fn orientation(self, orientation: Option<(&'a OrientationMap, f64)>) -> Self {
+        AnnealParams {
+            orientation,
+            ..self
+        }
+    }
+
+    /// Whether every triangle after the first shares an edge with one accepted before it
+    pub fn mesh(self, mesh: bool) -> Self {
+        AnnealParams { mesh, ..self }
+    }
+
+    /// Bits per channel that shape colors are limited to at first, and as it finishes cooling down
+    pub fn color_depth_schedule(self, color_depth_schedule: Option<(u8, u8)>) -> Self {
+        if color_depth_schedule
+            .is_some_and(|(start, end)| !(1..=8).contains(&start) || !(1..=8).contains(&end))
+        {
+            panic!("color depths must be between 1 and 8 bits");
+        }
+        AnnealParams {
+            color_depth_schedule,
+            ..self
+        }
+    }
+
+    /// Whether the accepted shapes get nudged around afterwards while that lowers the cost
+    pub fn polish(self, polish: bool) -> Self {
+        AnnealParams { polish, ..self }
+    }
+
+    /// Settings file that can change some of these while annealing
+    pub fn live_params(self, live_params: Option<LiveParams>) -> Self {
+        AnnealParams {
+            live_params,
+            ..self
+        }
+    }
+
+    /// Superpixels of the original image, and the fraction of the random shapes that are their
+    /// shapes instead
+    pub fn superpixels(self, superpixels: Option<(&'a Superpixels, f64)>) -> Self {
+        let params = AnnealParams {
+            superpixels,
+            ..self
+        };
+        params.check_content_fractions();
+        params
+    }
+
+    /// Strokes along the edges of the original image, and the fraction of the random shapes that
+    /// are those strokes instead
+    pub fn edge_strokes(self, edge_strokes: Option<(&'a EdgeStrokes, f64)>) -> Self {
+        let params = AnnealParams {
+            edge_strokes,
+            ..self
+        };
+        params.check_content_fractions();
+        params
+    }
+
+    /// Strokes along the flow field of the original image, and the fraction of the random shapes
+    /// that are those strokes instead
+    pub fn flow_strokes(self, flow_strokes: Option<(&'a FlowStrokes, f64)>) -> Self {
+        let params = AnnealParams {
+            flow_strokes,
+            ..self
+        };
+        params.check_content_fractions();
+        params
+    }
+
+    /// Shapes that get painted on first, and that nothing gets painted over
+    pub fn fixed_shapes(self, fixed_shapes: Option<&'a FixedShapes>) -> Self {
+        AnnealParams {
+            fixed_shapes,
+            ..self
+        }
+    }
+
+    /// Full-color image that the shapes' colors get annealed against afterwards, with the target
+    /// being a grayscale copy of it
+    pub fn color_target(self, color_target: Option<&'a Target>) -> Self {
+        AnnealParams {
+            color_target,
+            ..self
+        }
+    }
+
+    /// How much moves have to lower the cost by to be accepted, and the temperature that starts
+    /// applying below
+    pub fn min_improvement(self, min_improvement: Option<(f64, f64)>) -> Self {
+        if min_improvement.is_some_and(|(epsilon, _)| epsilon < 0.0) {
+            panic!("minimum improvement must be at least 0");
+        }
+        AnnealParams {
+            min_improvement,
+            ..self
+        }
+    }
+
+    /// Split of the shapes between the foreground and background, with new shapes only getting
+    /// proposed in the region that's behind on its share of them
+    pub fn budget(self, budget: Option<BudgetSplit>) -> Self {
+        AnnealParams { budget, ..self }
+    }
+
+    /// Where the pixels covered by each accepted shape get kept
+    pub fn footprints(self, footprints: Footprints) -> Self {
+        AnnealParams { footprints, ..self }
+    }
+
+    /// Superpixels, edge strokes and flow strokes are all taken out of the random shapes, so there
+    /// has to be enough of those to go around
+    fn check_content_fractions(&self) {
+        let superpixel_fraction = self.superpixels.map_or(0.0, |(_, fraction)| fraction);
+        let edge_fraction = self.edge_strokes.map_or(0.0, |(_, fraction)| fraction);
+        let flow_fraction = self.flow_strokes.map_or(0.0, |(_, fraction)| fraction);
+        if superpixel_fraction < 0.0
+            || edge_fraction < 0.0
+            || flow_fraction < 0.0
+            || superpixel_fraction + edge_fraction + flow_fraction > 1.0
+        {
+            panic!(
+                "superpixel, edge and flow proposals must be at least 0, and add up to at most 1"
+            );
+        }
+    }
+}
+
+impl Default for AnnealParams<'_> {
+    fn default() -> Self {
+        AnnealParams::new()
+    }
+}
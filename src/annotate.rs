@@ -0,0 +1,81 @@
+//! Captions for `--annotate`, drawn onto a strip below the saved image so runs with different
+//! settings can be told apart at a glance when they're laid out side by side. There's no font
+//! to load, just a tiny built-in bitmap font with the few characters a caption needs
+
+use image::{Rgb, RgbImage};
+
+const GLYPH_WIDTH: u32 = 3;
+const GLYPH_HEIGHT: u32 = 5;
+
+/// Space around the caption, in font pixels
+const PADDING: u32 = 2;
+
+/// Font pixels are scaled up by one screen pixel for every this many pixels the image is wide
+const PIXELS_PER_SCALE: u32 = 256;
+
+/// The rows of a character, top to bottom, with the leftmost font pixel in the highest bit.
+/// Characters the font doesn't have come out blank
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT as usize] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        _ => [0; GLYPH_HEIGHT as usize],
+    }
+}
+
+/// The caption for a run that ended up with `shapes` shapes at a cost of `cost` after `seconds`
+pub fn caption(shapes: usize, cost: f64, seconds: f64) -> String {
+    format!("{shapes} shapes  cost {cost:.2}  {seconds:.1}s")
+}
+
+/// `image` with a black strip added below it, with `caption` written across it in white. The
+/// font gets bigger for wider images, and captions that don't fit get cut off on the right
+pub fn annotate(image: &RgbImage, caption: &str) -> RgbImage {
+    let (w, h) = image.dimensions();
+    let scale = (w / PIXELS_PER_SCALE).max(1);
+    let strip = (GLYPH_HEIGHT + 2 * PADDING) * scale;
+    let mut annotated = RgbImage::new(w, h + strip);
+    for (x, y, &pixel) in image.enumerate_pixels() {
+        annotated.put_pixel(x, y, pixel);
+    }
+    for (i, c) in caption.chars().enumerate() {
+        // a blank column between characters
+        let left = (PADDING + i as u32 * (GLYPH_WIDTH + 1)) * scale;
+        for (row, bits) in glyph(c).into_iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits >> (GLYPH_WIDTH - 1 - column) & 1 == 0 {
+                    continue;
+                }
+                let top = h + (PADDING + row as u32) * scale;
+                for dx in 0..scale {
+                    for dy in 0..scale {
+                        let x = left + column * scale + dx;
+                        if x < w {
+                            annotated.put_pixel(x, top + dy, Rgb([255, 255, 255]));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    annotated
+}
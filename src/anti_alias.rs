@@ -0,0 +1,82 @@
+//! Anti-aliased rasterization for `--anti-alias`. Instead of a pixel being either in a shape or
+//! not, going by whether its center is, each pixel gets how much of it the shape covers, and
+//! shapes get painted over partly covered pixels only that much. The shapes are rasterized from
+//! the same outlines the vector exports draw, a few scanlines per row of pixels, with exactly how
+//! much of each pixel every scanline crosses worked out along it
+
+use crate::{coord::Coord, vector::polygons, Shape};
+
+/// How many scanlines go through each row of pixels
+pub const SCANLINES: usize = 4;
+
+/// Pixels covered by any of `shapes` in a `w` by `h` image, column-major like the rasterizers, and
+/// how much of each one they cover, from 1 to 255. Where the shapes overlap, their coverage adds up
+pub fn coverage(shapes: &[Shape], w: usize, h: usize) -> (Vec<Coord>, Vec<u8>) {
+    let outlines = shapes
+        .iter()
+        .flat_map(|&shape| polygons(shape, w, h))
+        .collect::<Vec<_>>();
+    let points = || outlines.iter().flatten();
+    let left = points()
+        .fold(f64::INFINITY, |left, p| left.min(p.0))
+        .max(0.0);
+    let top = points().fold(f64::INFINITY, |top, p| top.min(p.1)).max(0.0);
+    let right = points()
+        .fold(0.0, |right: f64, p| right.max(p.0))
+        .min(w as f64);
+    let bottom = points()
+        .fold(0.0, |bottom: f64, p| bottom.max(p.1))
+        .min(h as f64);
+    if left >= right || top >= bottom {
+        return (Vec::new(), Vec::new());
+    }
+    let (x0, y0) = (left as usize, top as usize);
+    let (box_w, box_h) = (right.ceil() as usize - x0, bottom.ceil() as usize - y0);
+    // how much of each pixel in the bounding box is covered, column-major
+    let mut covered = vec![0.0; box_w * box_h];
+    let weight = 1.0 / SCANLINES as f64;
+    let mut crossings = Vec::new();
+    for row in 0..box_h {
+        for scanline in 0..SCANLINES {
+            let y = (y0 + row) as f64 + (scanline as f64 + 0.5) * weight;
+            // each outline gets filled on its own, since mirrored copies can overlap each other
+            for outline in &outlines {
+                crossings.clear();
+                for (i, &(ax, ay)) in outline.iter().enumerate() {
+                    let (bx, by) = outline[(i + 1) % outline.len()];
+                    if (ay <= y) != (by <= y) {
+                        crossings.push(ax + (y - ay) / (by - ay) * (bx - ax));
+                    }
+                }
+                crossings.sort_unstable_by(f64::total_cmp);
+                for span in crossings.chunks_exact(2) {
+                    let (start, end) = (span[0].max(left), span[1].min(right));
+                    if start >= end {
+                        continue;
+                    }
+                    let (first, last) = (start as usize, (end.ceil() as usize).max(1) - 1);
+                    let pixel = |x: usize| (x - x0) * box_h + row;
+                    if first == last {
+                        covered[pixel(first)] += (end - start) * weight;
+                        continue;
+                    }
+                    covered[pixel(first)] += ((first + 1) as f64 - start) * weight;
+                    for x in first + 1..last {
+                        covered[pixel(x)] += weight;
+                    }
+                    covered[pixel(last)] += (end - last as f64) * weight;
+                }
+            }
+        }
+    }
+    let mut coords = Vec::new();
+    let mut coverage = Vec::new();
+    for (i, &amount) in covered.iter().enumerate() {
+        let amount = (amount.min(1.0) * 255.0).round() as u8;
+        if amount > 0 {
+            coords.push(Coord::new(x0 + i / box_h, y0 + i % box_h));
+            coverage.push(amount);
+        }
+    }
+    (coords, coverage)
+}
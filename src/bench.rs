@@ -0,0 +1,145 @@
+//! `--bench corpus`, which anneals every image in a folder with the same settings and a fixed seed,
+//! then reports how each one turned out. Running it with every version of the program (appending
+//! to the same `--run-log`) shows when a change made the results worse or the annealing slower.
+//! Every image gets annealed by running this program again, with the same arguments `--bench` was
+//! given alongside, so it goes through exactly what a normal run would. An image that can't be
+//! annealed (a corrupt file, say) doesn't stop the rest, and just gets listed at the end
+
+use serde_json::Value;
+use std::{
+    env::{args, current_exe, temp_dir},
+    fs::{create_dir_all, read_dir, read_to_string, remove_file},
+    path::Path,
+    process::{exit, Command},
+};
+
+/// Seed every image gets annealed with, unless the arguments give one
+const DEFAULT_SEED: u64 = 0;
+
+/// File extensions of the images that get annealed
+const EXTENSIONS: [&str; 7] = ["png", "jpg", "jpeg", "webp", "bmp", "tif", "tiff"];
+
+/// The arguments the program was started with, minus `--bench` and its folder
+fn passed_on_arguments() -> Vec<String> {
+    let mut arguments = Vec::new();
+    let mut skip_next = false;
+    for argument in args().skip(1) {
+        if skip_next {
+            skip_next = false;
+        } else if argument == "--bench" {
+            skip_next = true;
+        } else if !argument.starts_with("--bench=") {
+            arguments.push(argument);
+        }
+    }
+    arguments
+}
+
+/// What went wrong in a run that failed, going by what it printed. Panics print the message on its
+/// own line between the thread it happened on and a note about backtraces
+fn failure_reason(stderr: &[u8]) -> String {
+    let stderr = String::from_utf8_lossy(stderr);
+    stderr
+        .lines()
+        .map(str::trim)
+        .find(|line| {
+            !line.is_empty() && !line.starts_with("thread '") && !line.starts_with("note:")
+        })
+        .unwrap_or("it exited without saying why")
+        .to_string()
+}
+
+/// How many runs are in the log at `log` so far, with none in a log that isn't there yet
+fn logged_runs(log: &Path) -> usize {
+    read_to_string(log).map_or(0, |runs| runs.lines().count())
+}
+
+/// Anneals every image in the folder at `corpus` and prints the cost and time of each. With
+/// `seeded`, the arguments already have a seed, and otherwise every image gets `DEFAULT_SEED`.
+/// Runs get logged to `run_log` if it's given (the arguments already pass it on), and to a
+/// temporary log otherwise. Images that fail get skipped, and once the rest are done, they get
+/// listed with what went wrong and the program exits with an error
+pub fn bench(corpus: &str, seeded: bool, run_log: Option<&str>) {
+    let mut images = read_dir(corpus)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+        })
+        .collect::<Vec<_>>();
+    images.sort();
+    if images.is_empty() {
+        panic!("there are no images in {corpus}");
+    }
+    let output_dir = temp_dir().join("anneal_image_bench");
+    create_dir_all(&output_dir).unwrap();
+    let temporary_log = output_dir.join("runs.jsonl");
+    let log = run_log.map_or(temporary_log.as_path(), Path::new);
+    println!("{:<32} {:>12} {:>10}", "image", "cost", "seconds");
+    let (mut total_cost, mut total_seconds) = (0.0, 0.0);
+    let mut failures = Vec::new();
+    for image in &images {
+        let name = image.file_name().unwrap().to_string_lossy();
+        let mut command = Command::new(current_exe().unwrap());
+        command
+            .arg("--input")
+            .arg(image)
+            .arg("--output")
+            .arg(output_dir.join(format!("{name}.png")))
+            .args(passed_on_arguments());
+        if !seeded {
+            command.args(["--seed", &DEFAULT_SEED.to_string()]);
+        }
+        if run_log.is_none() {
+            command.arg("--run-log").arg(log);
+        }
+        let runs_before = logged_runs(log);
+        let output = command.output().unwrap();
+        // the run that just finished is the last one in the log, as long as it logged anything
+        let run = if !output.status.success() {
+            Err(failure_reason(&output.stderr))
+        } else if logged_runs(log) == runs_before {
+            Err("it didn't log anything".to_string())
+        } else {
+            read_to_string(log)
+                .unwrap()
+                .lines()
+                .last()
+                .and_then(|line| serde_json::from_str::<Value>(line).ok())
+                .and_then(|run| Some((run["cost"].as_f64()?, run["seconds"].as_f64()?)))
+                .ok_or_else(|| "its logged run couldn't be read".to_string())
+        };
+        let (cost, seconds) = match run {
+            Ok(run) => run,
+            Err(reason) => {
+                println!("{name:<32} {:>12} {:>10}", "failed", "-");
+                failures.push((name.to_string(), reason));
+                continue;
+            }
+        };
+        println!("{name:<32} {cost:>12.5} {seconds:>10.3}");
+        total_cost += cost;
+        total_seconds += seconds;
+    }
+    let annealed = images.len() - failures.len();
+    if annealed > 0 {
+        println!(
+            "{:<32} {:>12.5} {total_seconds:>10.3}",
+            "mean cost, total time",
+            total_cost / annealed as f64
+        );
+    }
+    if run_log.is_none() && temporary_log.exists() {
+        remove_file(temporary_log).unwrap();
+    }
+    if !failures.is_empty() {
+        println!();
+        println!("{} of {} images failed:", failures.len(), images.len());
+        for (name, reason) in failures {
+            println!("{name}: {reason}");
+        }
+        exit(1);
+    }
+}
@@ -0,0 +1,65 @@
+//! Splitting the shapes between the foreground and the background for `--budget-split`. A mask
+//! (or saliency map) says where the foreground is, and proposals only get through if they land in
+//! whichever region is behind on its share of the accepted shapes, so the foreground gets its
+//! share of the detail however much easier the background is to improve
+
+use crate::coord::Coord;
+
+pub struct BudgetSplit {
+    h: usize,
+    /// Whether each pixel is in the foreground, column-major like the images
+    foreground: Vec<bool>,
+    /// Fraction of the accepted shapes that go in the foreground
+    fraction: f64,
+    /// How many of the accepted shapes are in the foreground and the background
+    counts: [usize; 2],
+}
+
+impl BudgetSplit {
+    /// Splits a `h` pixel tall image by `mask` (from 0 to 1, column-major), with the bright half
+    /// as the foreground, so that `fraction` of the shapes land in it
+    pub fn new(mask: &[f64], h: usize, fraction: f64) -> BudgetSplit {
+        let foreground = mask.iter().map(|&value| value >= 0.5).collect::<Vec<_>>();
+        if fraction > 0.0 && !foreground.contains(&true) {
+            panic!("the budget mask doesn't have any foreground for shapes to go in");
+        }
+        if fraction < 1.0 && !foreground.contains(&false) {
+            panic!("the budget mask doesn't have any background for shapes to go in");
+        }
+        BudgetSplit {
+            h,
+            foreground,
+            fraction,
+            counts: [0, 0],
+        }
+    }
+
+    /// Whether a shape covering `coords` is in the foreground, going by where most of it is
+    pub fn in_foreground(&self, coords: &[Coord]) -> bool {
+        let inside = coords
+            .iter()
+            .filter(|coord| self.foreground[coord.index(self.h)])
+            .count();
+        inside * 2 >= coords.len()
+    }
+
+    /// Whether a shape covering `coords` is in the region that's due the next shape
+    pub fn accepts(&self, coords: &[Coord]) -> bool {
+        let [foreground, background] = self.counts;
+        let foreground_due =
+            (foreground as f64) < self.fraction * (foreground + background + 1) as f64;
+        self.in_foreground(coords) == foreground_due
+    }
+
+    /// Counts a shape covering `coords` as accepted
+    pub fn add(&mut self, coords: &[Coord]) {
+        let region = if self.in_foreground(coords) { 0 } else { 1 };
+        self.counts[region] += 1;
+    }
+
+    /// Stops counting a shape covering `coords`, now that it's been taken back out
+    pub fn remove(&mut self, coords: &[Coord]) {
+        let region = if self.in_foreground(coords) { 0 } else { 1 };
+        self.counts[region] = self.counts[region].saturating_sub(1);
+    }
+}
@@ -0,0 +1,371 @@
+//! The image being annealed. Proposals get painted onto it straight away as a transaction, which
+//! keeps the pixels they painted over so that the cost can be worked out from those and the
+//! transaction can be rolled back if the proposal is rejected. That way there's only one place that
+//! paints pixels, and what gets evaluated is always exactly what ends up on the canvas.
+//!
+//! On a single thread, each pixel gets kept right next to the original image's pixel it's compared
+//! with. Painting then works out the errors under the shape in the same pass that reads the old
+//! pixels and writes the new ones, going down one stream of memory instead of gathering the
+//! original image's pixels from their planes afterwards
+
+use crate::{
+    coord::{spans, Coord, Pixels},
+    error_sums::ErrorSums,
+    pixel_difference,
+    target::Target,
+    BlendMode, Fill, Image, SharedCanvas,
+};
+use image::Rgb;
+use std::{
+    iter::zip,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+/// Either single-threaded image or multi-threaded image.
+/// Used so I don't have to write multiple anneal functions
+enum EitherThreadedImage {
+    SingleThreaded(Image),
+    MultiThreaded(Arc<Mutex<Image>>),
+    /// Single-threaded, as pairs of the original image's pixel and the annealed image's pixel
+    Interleaved(Vec<Vec<[Rgb<u8>; 2]>>),
+}
+
+pub struct Canvas {
+    image: EitherThreadedImage,
+    error_sums: Option<ErrorSums>,
+    shared_canvas: Option<SharedCanvas>,
+    available_parallelism: usize,
+    /// Whether each pixel is locked, so that nothing gets painted over it anymore
+    locked: Option<Vec<Vec<bool>>>,
+}
+
+/// Pixels that have been painted onto the canvas but not committed yet
+#[must_use = "transactions have to be committed or rolled back"]
+pub struct Transaction {
+    coords: Vec<Coord>,
+    old_pixels: Vec<Rgb<u8>>,
+    /// Sums of the pixel differences from the original image under `coords` before and after
+    /// painting, if they got worked out while painting
+    errors: Option<(u64, u64)>,
+    /// What each of the painted pixels is now, if the canvas has locked pixels
+    new_pixels: Option<Vec<Rgb<u8>>>,
+}
+
+impl Transaction {
+    /// Coordinates of the painted pixels
+    pub fn coords(&self) -> &[Coord] {
+        &self.coords
+    }
+
+    /// What each of the painted pixels was before
+    pub fn old_pixels(&self) -> &[Rgb<u8>] {
+        &self.old_pixels
+    }
+
+    /// Sums of the pixel differences under the painted pixels before and after painting, if the
+    /// canvas worked them out along the way
+    pub fn errors(&self) -> Option<(u64, u64)> {
+        self.errors
+    }
+
+    /// What each of the painted pixels is now, if the canvas has locked pixels. Painting leaves
+    /// those as they were, so costs have to go by these instead of by what was meant to be painted
+    pub fn new_pixels(&self) -> Option<&[Rgb<u8>]> {
+        self.new_pixels.as_deref()
+    }
+}
+
+impl Canvas {
+    /// Starts off with `image`. With `error_sums`, the errors of rectangles can be looked up
+    /// instead of added up. `shared_canvas` gets every committed change copied over.
+    /// Single-threaded canvases for plain targets that are kept in memory get interleaved with the
+    /// target, and work out the errors of everything they paint, so they don't need the table
+    pub fn new(
+        image: Image,
+        multithreading: bool,
+        target: &Target,
+        error_sums: bool,
+        shared_canvas: Option<SharedCanvas>,
+    ) -> Canvas {
+        let interleaved = !multithreading && target.is_plain() && !target.is_mapped();
+        let error_sums = (error_sums && !interleaved).then(|| ErrorSums::new(target, &image));
+        let image = if multithreading {
+            EitherThreadedImage::MultiThreaded(Arc::new(Mutex::new(image)))
+        } else if interleaved {
+            let pairs = image
+                .iter()
+                .enumerate()
+                .map(|(x, column)| {
+                    column
+                        .iter()
+                        .enumerate()
+                        .map(|(y, &pixel)| [target.pixel(Coord::new(x, y)), pixel])
+                        .collect()
+                })
+                .collect();
+            EitherThreadedImage::Interleaved(pairs)
+        } else {
+            EitherThreadedImage::SingleThreaded(image)
+        };
+        Canvas {
+            image,
+            error_sums,
+            shared_canvas,
+            available_parallelism: usize::from(thread::available_parallelism().unwrap()),
+            locked: None,
+        }
+    }
+
+    /// Locks the pixels at `coords` as they are. Painting leaves locked pixels as they were, so
+    /// they never change the cost. Lookups of rectangle errors don't know about them, so the canvas
+    /// shouldn't have `error_sums` when anything gets locked
+    pub fn lock(&mut self, coords: &[Coord]) {
+        let (w, h) = self.read(|image| (image.len(), image.first().map_or(0, Vec::len)));
+        let locked = self.locked.get_or_insert_with(|| vec![vec![false; h]; w]);
+        for &coord in coords {
+            *locked.at_mut(coord) = true;
+        }
+    }
+
+    /// Runs `f` on the image as it is. Interleaved canvases have to pick their pixels out into an
+    /// image first, so this shouldn't be run all that often
+    pub fn read<R>(&self, f: impl FnOnce(&[Vec<Rgb<u8>>]) -> R) -> R {
+        match self.image {
+            EitherThreadedImage::MultiThreaded(ref guard) => f(&guard.lock().unwrap()),
+            EitherThreadedImage::SingleThreaded(ref raw) => f(raw),
+            EitherThreadedImage::Interleaved(ref pairs) => f(&deinterleave(pairs)),
+        }
+    }
+
+    /// Sum of the pixel differences in a rectangle that doesn't wrap around, if it can be looked up.
+    /// Has to be called before painting over the rectangle
+    pub fn rectangle_error(
+        &mut self,
+        target: &Target,
+        top_left: (usize, usize),
+        bottom_right: (usize, usize),
+    ) -> Option<u64> {
+        let error_sums = self.error_sums.as_mut()?;
+        match self.image {
+            EitherThreadedImage::MultiThreaded(ref guard) => {
+                error_sums.rectangle(target, &guard.lock().unwrap(), top_left, bottom_right)
+            }
+            EitherThreadedImage::SingleThreaded(ref raw) => {
+                error_sums.rectangle(target, raw, top_left, bottom_right)
+            }
+            // interleaved canvases work out errors while painting instead
+            EitherThreadedImage::Interleaved(_) => None,
+        }
+    }
+
+    /// Paints `fill` over the pixels at `coords`, blended according to `blend_mode`
+    pub fn paint(&mut self, coords: Vec<Coord>, fill: Fill, blend_mode: BlendMode) -> Transaction {
+        // most shapes are plain paint in one color, which is worth not going through the blending for
+        match fill {
+            Fill::Solid(color) if blend_mode == BlendMode::NORMAL => {
+                self.apply(coords, Some(color), true, |_, _, _| color)
+            }
+            _ => self.apply(coords, None, true, |_, coord, pixel| {
+                blend_mode.blend(pixel, fill.color_at(coord))
+            }),
+        }
+    }
+
+    /// Like `paint`, but only painting over each of the pixels at `coords` as much as the matching
+    /// `coverage` (out of 255) says the shape covers it
+    pub fn paint_covered(
+        &mut self,
+        coords: Vec<Coord>,
+        coverage: &[u8],
+        fill: Fill,
+        blend_mode: BlendMode,
+    ) -> Transaction {
+        self.apply(coords, None, true, |i, coord, pixel| {
+            blend_mode
+                .covering(coverage[i])
+                .blend(pixel, fill.color_at(coord))
+        })
+    }
+
+    /// Sets each of the pixels at `coords` to the matching pixel in `new_pixels`. Replacements can
+    /// be measured against other targets (like the colors of `--luma-first`), so they don't get
+    /// their errors worked out
+    pub fn replace(&mut self, coords: Vec<Coord>, new_pixels: &[Rgb<u8>]) -> Transaction {
+        self.apply(coords, None, false, |i, _, _| new_pixels[i])
+    }
+
+    /// Paints over the pixels at `coords`, setting the `i`th one to `paint(i, coord, old pixel)` unless
+    /// it's locked. `solid` is the color `paint` always gives, if it doesn't depend on anything.
+    /// With `with_errors`, interleaved canvases work out the errors under the pixels as they go
+    fn apply(
+        &mut self,
+        coords: Vec<Coord>,
+        solid: Option<Rgb<u8>>,
+        with_errors: bool,
+        paint: impl Fn(usize, Coord, Rgb<u8>) -> Rgb<u8> + Sync,
+    ) -> Transaction {
+        let locked = self.locked.as_ref();
+        let solid = solid.filter(|_| locked.is_none());
+        let paint = |i: usize, coord: Coord, old_pixel| match locked {
+            Some(locked) if locked.at(coord) => old_pixel,
+            _ => paint(i, coord, old_pixel),
+        };
+        let mut errors = None;
+        let old_pixels = match self.image {
+            EitherThreadedImage::MultiThreaded(ref guard) => {
+                let chunk_size = (coords.len() / self.available_parallelism).max(1);
+                let paint = &paint;
+                thread::scope(|s| {
+                    coords
+                        .chunks(chunk_size)
+                        .enumerate()
+                        .map(|(chunk_index, chunk)| {
+                            let image = Arc::clone(guard);
+                            s.spawn(move || {
+                                let mut image = image.lock().unwrap();
+                                chunk
+                                    .iter()
+                                    .enumerate()
+                                    .map(|(i, &coord)| {
+                                        let pixel = image.at_mut(coord);
+                                        let old_pixel = *pixel;
+                                        *pixel =
+                                            paint(chunk_index * chunk_size + i, coord, old_pixel);
+                                        old_pixel
+                                    })
+                                    .collect::<Vec<Rgb<u8>>>()
+                            })
+                        })
+                        .collect::<Vec<_>>()
+                        .into_iter()
+                        .flat_map(|handle| handle.join().unwrap())
+                        .collect()
+                })
+            }
+            EitherThreadedImage::SingleThreaded(ref mut raw) => {
+                let mut old_pixels = Vec::with_capacity(coords.len());
+                for (span, indices) in spans(&coords) {
+                    let pixels = raw.span_mut(&span);
+                    old_pixels.extend_from_slice(pixels);
+                    match solid {
+                        Some(color) => pixels.fill(color),
+                        None => {
+                            for ((i, coord), pixel) in zip(indices.zip(span.coords()), pixels) {
+                                *pixel = paint(i, coord, *pixel);
+                            }
+                        }
+                    }
+                }
+                old_pixels
+            }
+            EitherThreadedImage::Interleaved(ref mut pairs) => {
+                let mut old_pixels = Vec::with_capacity(coords.len());
+                let (mut old_error, mut new_error) = (0, 0);
+                for (span, indices) in spans(&coords) {
+                    let pairs = pairs.span_mut(&span);
+                    for ((i, coord), pair) in zip(indices.zip(span.coords()), pairs) {
+                        let [original, ref mut pixel] = *pair;
+                        let old_pixel = *pixel;
+                        *pixel = paint(i, coord, old_pixel);
+                        if with_errors {
+                            old_error += pixel_difference(original, old_pixel);
+                            new_error += pixel_difference(original, *pixel);
+                        }
+                        old_pixels.push(old_pixel);
+                    }
+                }
+                errors = with_errors.then_some((old_error, new_error));
+                old_pixels
+            }
+        };
+        let new_pixels = self.locked.is_some().then(|| self.pixels_at(&coords));
+        Transaction {
+            coords,
+            old_pixels,
+            errors,
+            new_pixels,
+        }
+    }
+
+    /// What the image has at each of `coords`
+    fn pixels_at(&self, coords: &[Coord]) -> Vec<Rgb<u8>> {
+        match self.image {
+            EitherThreadedImage::MultiThreaded(ref guard) => {
+                let image = guard.lock().unwrap();
+                coords.iter().map(|&coord| image.at(coord)).collect()
+            }
+            EitherThreadedImage::SingleThreaded(ref raw) => {
+                coords.iter().map(|&coord| raw.at(coord)).collect()
+            }
+            EitherThreadedImage::Interleaved(ref pairs) => {
+                coords.iter().map(|&coord| pairs.at(coord)[1]).collect()
+            }
+        }
+    }
+
+    /// Keeps the painted pixels, handing back their coordinates
+    pub fn commit(&mut self, transaction: Transaction) -> Vec<Coord> {
+        let coords = transaction.coords;
+        if let Some(ref mut error_sums) = self.error_sums {
+            error_sums.mark_dirty(&coords);
+        }
+        if let Some(ref mut shared_canvas) = self.shared_canvas {
+            match self.image {
+                EitherThreadedImage::MultiThreaded(ref guard) => {
+                    let image = guard.lock().unwrap();
+                    shared_canvas.sync(&coords, |coord| image.at(coord))
+                }
+                EitherThreadedImage::SingleThreaded(ref raw) => {
+                    shared_canvas.sync(&coords, |coord| raw.at(coord))
+                }
+                EitherThreadedImage::Interleaved(ref pairs) => {
+                    shared_canvas.sync(&coords, |coord| pairs.at(coord)[1])
+                }
+            }
+        }
+        coords
+    }
+
+    /// Puts back the pixels that were painted over, handing back their coordinates
+    pub fn rollback(&mut self, transaction: Transaction) -> Vec<Coord> {
+        let Transaction {
+            coords, old_pixels, ..
+        } = transaction;
+        let restore = |image: &mut Image| {
+            for (span, indices) in spans(&coords) {
+                image.span_mut(&span).copy_from_slice(&old_pixels[indices]);
+            }
+        };
+        match self.image {
+            EitherThreadedImage::MultiThreaded(ref guard) => restore(&mut guard.lock().unwrap()),
+            EitherThreadedImage::SingleThreaded(ref mut raw) => restore(raw),
+            EitherThreadedImage::Interleaved(ref mut pairs) => {
+                for (span, indices) in spans(&coords) {
+                    for (pair, &old_pixel) in zip(pairs.span_mut(&span), &old_pixels[indices]) {
+                        pair[1] = old_pixel;
+                    }
+                }
+            }
+        }
+        coords
+    }
+
+    pub fn into_image(self) -> Image {
+        match self.image {
+            EitherThreadedImage::MultiThreaded(guard) => {
+                Arc::try_unwrap(guard).unwrap().into_inner().unwrap()
+            }
+            EitherThreadedImage::SingleThreaded(raw) => raw,
+            EitherThreadedImage::Interleaved(pairs) => deinterleave(&pairs),
+        }
+    }
+}
+
+/// The annealed image's pixels out of an interleaved canvas
+fn deinterleave(pairs: &[Vec<[Rgb<u8>; 2]>]) -> Image {
+    pairs
+        .iter()
+        .map(|column| column.iter().map(|pair| pair[1]).collect())
+        .collect()
+}
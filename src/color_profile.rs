@@ -0,0 +1,218 @@
+//! Just enough color management to deal with wide-gamut images (Display P3 from phones, Adobe RGB
+//! from cameras, and so on). Those come with an ICC profile describing how their colors map onto
+//! real colors, and treating them as sRGB makes everything look washed out. Only matrix/TRC RGB
+//! profiles are understood, which is what basically every one of those images has
+
+use image::{
+    codecs::{jpeg::JpegDecoder, png::PngDecoder, tiff::TiffDecoder, webp::WebPDecoder},
+    ImageDecoder, ImageFormat, Rgb, RgbImage,
+};
+use std::{fs::File, io::BufReader};
+
+/// Linear sRGB from the D50 XYZ that ICC profiles convert to
+const XYZ_D50_TO_SRGB: [[f64; 3]; 3] = [
+    [3.1338561, -1.6168667, -0.4906146],
+    [-0.9787684, 1.9161415, 0.0334540],
+    [0.0719453, -0.2289914, 1.4052427],
+];
+
+/// The sRGB primaries in D50 XYZ, as ICC profiles store them
+const SRGB_PRIMARIES: [[f64; 3]; 3] = [
+    [0.4361, 0.2225, 0.0139],
+    [0.3851, 0.7169, 0.0971],
+    [0.1431, 0.0606, 0.7141],
+];
+
+/// The ICC profile embedded in the image at `path`, for the formats that can have one
+pub fn icc_profile(path: &str) -> Option<Vec<u8>> {
+    let reader = || BufReader::new(File::open(path).unwrap());
+    match ImageFormat::from_path(path).ok()? {
+        ImageFormat::Png => PngDecoder::new(reader()).ok()?.icc_profile(),
+        ImageFormat::Jpeg => JpegDecoder::new(reader()).ok()?.icc_profile(),
+        ImageFormat::Tiff => TiffDecoder::new(reader()).ok()?.icc_profile(),
+        ImageFormat::WebP => WebPDecoder::new(reader()).ok()?.icc_profile(),
+        _ => None,
+    }
+}
+
+/// Tone response curve of one channel, turning encoded values into linear light
+enum Curve {
+    Gamma(f64),
+    Table(Vec<f64>),
+    /// ICC parametric curve: `g, a, b, c, d, e, f`, with unused parameters left at their defaults
+    Parametric([f64; 7]),
+}
+
+impl Curve {
+    fn linearize(&self, x: f64) -> f64 {
+        match *self {
+            Curve::Gamma(gamma) => x.powf(gamma),
+            Curve::Table(ref table) => {
+                let position = x * (table.len() - 1) as f64;
+                let i = (position as usize).min(table.len() - 2);
+                let t = position - i as f64;
+                table[i] * (1.0 - t) + table[i + 1] * t
+            }
+            Curve::Parametric([g, a, b, c, d, e, f]) => {
+                if x >= d {
+                    (a * x + b).max(0.0).powf(g) + e
+                } else {
+                    c * x + f
+                }
+            }
+        }
+    }
+}
+
+/// An RGB matrix/TRC ICC profile
+pub struct RgbProfile {
+    curves: [Curve; 3],
+    /// D50 XYZ of each primary at full intensity
+    primaries: [[f64; 3]; 3],
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn s15_fixed16_at(bytes: &[u8], offset: usize) -> Option<f64> {
+    Some(u32_at(bytes, offset)? as i32 as f64 / 65536.0)
+}
+
+fn parse_curve(tag: &[u8]) -> Option<Curve> {
+    match tag.get(..4)? {
+        b"curv" => {
+            let count = u32_at(tag, 8)? as usize;
+            let entries = (0..count)
+                .map(|i| {
+                    let bytes = tag.get(12 + 2 * i..14 + 2 * i)?;
+                    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+                })
+                .collect::<Option<Vec<u16>>>()?;
+            Some(match count {
+                0 => Curve::Gamma(1.0),
+                1 => Curve::Gamma(entries[0] as f64 / 256.0),
+                _ => Curve::Table(entries.iter().map(|&v| v as f64 / 65535.0).collect()),
+            })
+        }
+        b"para" => {
+            let function = u16::from_be_bytes([*tag.get(8)?, *tag.get(9)?]);
+            let count = [1, 3, 4, 5, 7].get(function as usize)?;
+            let mut p = [0.0; 7];
+            for (i, value) in p.iter_mut().enumerate().take(*count) {
+                *value = s15_fixed16_at(tag, 12 + 4 * i)?;
+            }
+            let [g, a, b, c, d, e, f] = p;
+            // filling in each function type as the general `d`-split form
+            Some(Curve::Parametric(match function {
+                0 => [g, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+                1 => [g, a, b, 0.0, -b / a, 0.0, 0.0],
+                2 => [g, a, b, 0.0, -b / a, c, c],
+                3 => [g, a, b, c, d, 0.0, 0.0],
+                _ => [g, a, b, c, d, e, f],
+            }))
+        }
+        _ => None,
+    }
+}
+
+impl RgbProfile {
+    /// Reads an ICC profile, or `None` if it isn't an RGB matrix/TRC profile
+    pub fn parse(icc: &[u8]) -> Option<RgbProfile> {
+        if icc.get(16..20)? != b"RGB " {
+            return None;
+        }
+        let tag = |signature: &[u8; 4]| {
+            let count = u32_at(icc, 128)? as usize;
+            (0..count).find_map(|i| {
+                let entry = 132 + 12 * i;
+                if icc.get(entry..entry + 4)? != signature {
+                    return None;
+                }
+                let offset = u32_at(icc, entry + 4)? as usize;
+                let size = u32_at(icc, entry + 8)? as usize;
+                icc.get(offset..offset + size)
+            })
+        };
+        let primary = |signature| {
+            let tag = tag(signature)?;
+            if tag.get(..4)? != b"XYZ " {
+                return None;
+            }
+            Some([
+                s15_fixed16_at(tag, 8)?,
+                s15_fixed16_at(tag, 12)?,
+                s15_fixed16_at(tag, 16)?,
+            ])
+        };
+        Some(RgbProfile {
+            curves: [
+                parse_curve(tag(b"rTRC")?)?,
+                parse_curve(tag(b"gTRC")?)?,
+                parse_curve(tag(b"bTRC")?)?,
+            ],
+            primaries: [primary(b"rXYZ")?, primary(b"gXYZ")?, primary(b"bXYZ")?],
+        })
+    }
+
+    /// Whether the profile has the sRGB primaries, so there's nothing to convert
+    pub fn is_srgb(&self) -> bool {
+        self.primaries
+            .iter()
+            .flatten()
+            .zip(SRGB_PRIMARIES.iter().flatten())
+            .all(|(a, b)| (a - b).abs() < 0.002)
+    }
+
+    /// Converts an image in this profile to sRGB, clipping colors sRGB can't show
+    pub fn to_srgb(&self, image: &mut RgbImage) {
+        let tables = self.curves.each_ref().map(|curve| {
+            (0..256)
+                .map(|v| curve.linearize(v as f64 / 255.0))
+                .collect::<Vec<f64>>()
+        });
+        // profile RGB -> D50 XYZ -> linear sRGB, all in one matrix
+        let mut matrix = [[0.0; 3]; 3];
+        for (row, srgb_row) in matrix.iter_mut().zip(XYZ_D50_TO_SRGB) {
+            for (channel, value) in row.iter_mut().enumerate() {
+                *value = (0..3)
+                    .map(|i| srgb_row[i] * self.primaries[channel][i])
+                    .sum();
+            }
+        }
+        for pixel in image.pixels_mut() {
+            let linear = [0, 1, 2].map(|channel| tables[channel][pixel[channel] as usize]);
+            *pixel = Rgb(matrix.map(|row| {
+                let value = (0..3)
+                    .map(|i| row[i] * linear[i])
+                    .sum::<f64>()
+                    .clamp(0.0, 1.0);
+                let encoded = if value <= 0.0031308 {
+                    12.92 * value
+                } else {
+                    1.055 * value.powf(1.0 / 2.4) - 0.055
+                };
+                (encoded * 255.0).round() as u8
+            }));
+        }
+    }
+}
+
+/// Adds an ICC profile to an encoded PNG, right after its header where the profile has to go
+pub fn embed_icc_profile(png: &[u8], icc: &[u8]) -> Vec<u8> {
+    let mut data = b"ICC profile\0\0".to_vec();
+    let mut encoder = flate2::write::ZlibEncoder::new(&mut data, flate2::Compression::best());
+    std::io::Write::write_all(&mut encoder, icc).unwrap();
+    encoder.finish().unwrap();
+    let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+    chunk.extend(b"iCCP");
+    chunk.extend(&data);
+    chunk.extend(crc32fast::hash(&chunk[4..]).to_be_bytes());
+    // the signature is 8 bytes and the header chunk another 25
+    let mut output = png[..33].to_vec();
+    output.extend(chunk);
+    output.extend(&png[33..]);
+    output
+}
@@ -0,0 +1,83 @@
+//! Other ways of splitting colors into channels than red, green, and blue, for seeing which part of
+//! a color the annealing keeps getting wrong. The cost itself only ever works in RGB
+
+use clap::ValueEnum;
+use image::Rgb;
+
+/// Colorspace the channels of the error map are split by
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Colorspace {
+    /// Red, green, and blue, the way the cost sees them
+    Rgb,
+    /// CIE lightness, green to red, and blue to yellow
+    Lab,
+    /// Hue, saturation, and value
+    Hsv,
+}
+
+/// Linear light of an sRGB channel
+fn linearize(value: u8) -> f64 {
+    let value = value as f64 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+impl Colorspace {
+    /// Channels of `pixel` in this colorspace, each scaled to cover about 0 to 255
+    pub fn channels(self, pixel: Rgb<u8>) -> [f64; 3] {
+        let [r, g, b] = pixel.0;
+        match self {
+            Colorspace::Rgb => [r as f64, g as f64, b as f64],
+            Colorspace::Lab => {
+                let [r, g, b] = [r, g, b].map(linearize);
+                // D65 XYZ, relative to the white point
+                let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+                let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+                let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+                let f = |t: f64| {
+                    if t > 216.0 / 24389.0 {
+                        t.cbrt()
+                    } else {
+                        (24389.0 / 27.0 * t + 16.0) / 116.0
+                    }
+                };
+                let (fx, fy, fz) = (f(x), f(y), f(z));
+                [
+                    (116.0 * fy - 16.0) * 2.55,
+                    500.0 * (fx - fy) + 128.0,
+                    200.0 * (fy - fz) + 128.0,
+                ]
+            }
+            Colorspace::Hsv => {
+                let (r, g, b) = (r as f64, g as f64, b as f64);
+                let max = r.max(g).max(b);
+                let range = max - r.min(g).min(b);
+                let hue = if range == 0.0 {
+                    0.0
+                } else if max == r {
+                    ((g - b) / range).rem_euclid(6.0)
+                } else if max == g {
+                    (b - r) / range + 2.0
+                } else {
+                    (r - g) / range + 4.0
+                };
+                let saturation = if max == 0.0 { 0.0 } else { range / max };
+                [hue / 6.0 * 255.0, saturation * 255.0, max]
+            }
+        }
+    }
+
+    /// Difference between two colors in each of this colorspace's channels
+    pub fn differences(self, pixel1: Rgb<u8>, pixel2: Rgb<u8>) -> [f64; 3] {
+        let (channels1, channels2) = (self.channels(pixel1), self.channels(pixel2));
+        let mut differences = [0, 1, 2].map(|i| (channels1[i] - channels2[i]).abs());
+        if let Colorspace::Hsv = self {
+            // hue goes around in a circle, so opposite hues are as different as it gets
+            differences[0] = differences[0].min(255.0 - differences[0]) * 2.0;
+        }
+        differences
+    }
+}
@@ -0,0 +1,71 @@
+//! Coarse tiles of the canvas that are already close enough to the original image, for
+//! `--converged-threshold`. Late on, most of the canvas is done and most random shapes land
+//! somewhere they can only make worse, so shapes that would fit entirely inside tiles that are done
+//! get thrown away before they're rasterized, and new ones get picked instead. The tiles only get
+//! checked every so often, since working out their errors means going over the whole canvas
+
+use crate::{
+    coord::{Coord, Pixels},
+    target::Target,
+};
+use image::Rgb;
+
+/// How many pixels across each tile is
+const TILE_SIZE: usize = 16;
+
+pub struct ConvergedMask {
+    w: usize,
+    h: usize,
+    tiles_h: usize,
+    /// Average difference per channel a tile has to be under to count as done
+    threshold: f64,
+    /// Whether each tile is done, column-major like the images
+    converged: Vec<bool>,
+}
+
+impl ConvergedMask {
+    /// A mask over a `w` by `h` image with none of the tiles done yet, for tiles that are done once
+    /// their pixels are `threshold` off the original image on average in each channel
+    pub fn new(w: usize, h: usize, threshold: f64) -> ConvergedMask {
+        let tiles_h = h.div_ceil(TILE_SIZE);
+        ConvergedMask {
+            w,
+            h,
+            tiles_h,
+            threshold,
+            converged: vec![false; w.div_ceil(TILE_SIZE) * tiles_h],
+        }
+    }
+
+    /// Works out again which tiles of `image` are done
+    pub fn update(&mut self, target: &Target, image: &[Vec<Rgb<u8>>]) {
+        let mut errors = vec![(0.0, 0); self.converged.len()];
+        for x in 0..self.w {
+            for y in 0..self.h {
+                let coord = Coord::new(x, y);
+                let tile = &mut errors[x / TILE_SIZE * self.tiles_h + y / TILE_SIZE];
+                tile.0 += target.difference(coord, image.at(coord));
+                tile.1 += 3;
+            }
+        }
+        for (converged, (error, channels)) in self.converged.iter_mut().zip(errors) {
+            *converged = error / (channels as f64) < self.threshold;
+        }
+        // with every tile done there'd be nowhere left for shapes to go, so none of them count
+        if self.converged.iter().all(|&converged| converged) {
+            self.converged.fill(false);
+        }
+    }
+
+    /// Whether every tile under the box from `top_left` up to (but not including) `bottom_right` is
+    /// done. Boxes reaching past the edges wrap around onto other tiles, so they never are
+    pub fn covers(&self, top_left: (usize, usize), bottom_right: (usize, usize)) -> bool {
+        if bottom_right.0 > self.w || bottom_right.1 > self.h {
+            return false;
+        }
+        (top_left.0 / TILE_SIZE..bottom_right.0.div_ceil(TILE_SIZE)).all(|tx| {
+            (top_left.1 / TILE_SIZE..bottom_right.1.div_ceil(TILE_SIZE))
+                .all(|ty| self.converged[tx * self.tiles_h + ty])
+        })
+    }
+}
@@ -0,0 +1,87 @@
+//! Pixel coordinates, runs of pixels down a column, and the accessors every read and write of an
+//! image's pixels goes through. The accessors check that coordinates are inside the image, and
+//! panic on any that aren't, in release builds as well as debug ones. Skipping the check in release
+//! builds would only be sound if every coordinate came from something that keeps it inside the
+//! image, but shapes also come from journals and other machines, and not all of those get checked
+//! against the image on the way in (journal replays don't), so a bad file would read and write
+//! past the end of the image instead of stopping the run
+
+use std::ops::Range;
+
+/// A pixel's position, `x` pixels from the left and `y` from the top. Coordinates sort column by
+/// column, the same order the images are laid out in
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Coord {
+    pub x: usize,
+    pub y: usize,
+}
+
+impl Coord {
+    pub const fn new(x: usize, y: usize) -> Coord {
+        Coord { x, y }
+    }
+
+    /// Index of the pixel in a column-major buffer of an image `h` pixels tall
+    pub fn index(self, h: usize) -> usize {
+        self.x * h + self.y
+    }
+}
+
+/// A run of pixels straight down column `x`. They sit next to each other in the image, so they
+/// can be read and painted all at once
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub x: usize,
+    pub ys: Range<usize>,
+}
+
+impl Span {
+    /// Coordinates of the pixels in the span, from the top down
+    pub fn coords(&self) -> impl Iterator<Item = Coord> + '_ {
+        self.ys.clone().map(|y| Coord::new(self.x, y))
+    }
+}
+
+/// The spans that `coords` go straight down a column in, along with which of `coords` each one
+/// covers
+pub fn spans(coords: &[Coord]) -> Vec<(Span, Range<usize>)> {
+    let mut spans = Vec::new();
+    let mut start = 0;
+    while start < coords.len() {
+        let Coord { x, y } = coords[start];
+        let mut end = start + 1;
+        while end < coords.len() && coords[end] == Coord::new(x, y + end - start) {
+            end += 1;
+        }
+        spans.push((
+            Span {
+                x,
+                ys: y..y + end - start,
+            },
+            start..end,
+        ));
+        start = end;
+    }
+    spans
+}
+
+/// Access to the pixels of a column-major image, or anything else kept per pixel like one
+pub trait Pixels<T> {
+    fn at(&self, coord: Coord) -> T;
+    fn at_mut(&mut self, coord: Coord) -> &mut T;
+    fn span_mut(&mut self, span: &Span) -> &mut [T];
+}
+
+impl<T: Copy> Pixels<T> for [Vec<T>] {
+    fn at(&self, coord: Coord) -> T {
+        self[coord.x][coord.y]
+    }
+
+    fn at_mut(&mut self, coord: Coord) -> &mut T {
+        &mut self[coord.x][coord.y]
+    }
+
+    fn span_mut(&mut self, span: &Span) -> &mut [T] {
+        &mut self[span.x][span.ys.clone()]
+    }
+}
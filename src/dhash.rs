@@ -0,0 +1,39 @@
+//! Difference hashes (dHash) of images, for `--dhash-distance`. The image gets shrunk down to 9 by
+//! 8 cells of brightness, and each of the 64 bits says whether a cell is brighter than the one to
+//! its right. Images that look alike at a glance get hashes that differ in only a few bits, however
+//! far apart their pixels are, which is a better sign of a draft being recognizable than the cost
+
+use crate::coord::Coord;
+use image::Rgb;
+
+const CELLS_W: usize = 9;
+const CELLS_H: usize = 8;
+
+/// dHash of a `w` by `h` image with the given pixels
+pub fn dhash(w: usize, h: usize, pixel: impl Fn(Coord) -> Rgb<u8>) -> u64 {
+    let mut cells = [[0.0; CELLS_H]; CELLS_W];
+    let mut counts = [[0u32; CELLS_H]; CELLS_W];
+    for x in 0..w {
+        let cell_x = x * CELLS_W / w;
+        for y in 0..h {
+            let cell_y = y * CELLS_H / h;
+            let Rgb([r, g, b]) = pixel(Coord::new(x, y));
+            cells[cell_x][cell_y] += 0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64;
+            counts[cell_x][cell_y] += 1;
+        }
+    }
+    // images smaller than the cells leave some of them empty, which count as black
+    let brightness = |x: usize, y: usize| cells[x][y] / counts[x][y].max(1) as f64;
+    let mut hash = 0;
+    for y in 0..CELLS_H {
+        for x in 0..CELLS_W - 1 {
+            hash = hash << 1 | (brightness(x, y) > brightness(x + 1, y)) as u64;
+        }
+    }
+    hash
+}
+
+/// How many bits two hashes differ in
+pub fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
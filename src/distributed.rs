@@ -0,0 +1,380 @@
+//! Spreading the search for good shapes over several machines. The coordinator runs the annealing
+//! as usual, but also hands the original image and a regularly refreshed snapshot of the canvas to
+//! any workers that connect to it. Workers try lots of shapes against their snapshot and send back
+//! the ones that would have improved it, which the coordinator proposes in place of its own random
+//! ones (checking them against the real canvas first, since the snapshot might be out of date).
+//!
+//! After connecting, the coordinator sends a line with the settings the shapes have to follow
+//!
+//! ```text
+//! anneal_image worker <width> <height> <shape kinds> <vertices> <stroke width> <grid> <tileable>
+//! <overhang> <edge bias> <blend modes> <opacity>
+//! ```
+//!
+//! followed by the original image and then every snapshot of the canvas, all as column-major
+//! packed RGB bytes. Workers send their shapes back as journal `add` lines, one shape per line
+
+use crate::rng::random;
+use crate::{
+    coord::{Coord, Pixels},
+    geometry::{self, MAX_VERTICES},
+    get_cost, get_neighbor,
+    journal::{add_line, parse_entry, JournalEntry},
+    parse_opacity, parse_stroke_width, random_opacity,
+    raster_cache::RasterCache,
+    target::Target,
+    update_cost, Blend, BlendMode, Fill, Image, Shape, ShapeKind,
+};
+use clap::ValueEnum;
+use image::Rgb;
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    process::exit,
+    sync::{
+        mpsc::{channel, Receiver, Sender, TryRecvError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How often the coordinator sends workers a fresh snapshot of the canvas
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many shapes a worker tries before sending back the best of them
+const WORKER_BATCH: usize = 64;
+
+/// What the shapes proposed by workers have to follow
+#[derive(Clone)]
+pub struct WorkerSettings {
+    pub shape_kinds: Vec<ShapeKind>,
+    pub vertices: usize,
+    pub stroke_width: (f64, f64),
+    pub grid: usize,
+    pub tileable: bool,
+    pub overhang: f64,
+    pub edge_bias: f64,
+    pub blend_modes: Vec<Blend>,
+    pub opacity: (f64, f64),
+}
+
+/// Column-major packed RGB bytes of an image
+fn to_bytes(pixels: impl Iterator<Item = Rgb<u8>>) -> Vec<u8> {
+    pixels.flat_map(|pixel| pixel.0).collect()
+}
+
+/// Inverse of `to_bytes` for a `w` by `h` image
+fn from_bytes(bytes: &[u8], w: usize, h: usize) -> Image {
+    (0..w)
+        .map(|x| {
+            (0..h)
+                .map(|y| {
+                    let i = (x * h + y) * 3;
+                    Rgb([bytes[i], bytes[i + 1], bytes[i + 2]])
+                })
+                .collect()
+        })
+        .collect()
+}
+
+pub struct Coordinator {
+    proposals: Receiver<(Shape, Fill, BlendMode)>,
+    /// The latest snapshot of the canvas, numbered so workers can tell when there's a new one.
+    /// Number 0 means there hasn't been one yet
+    snapshot: Arc<Mutex<(u64, Arc<Vec<u8>>)>>,
+    last_snapshot: Option<Instant>,
+    w: usize,
+    h: usize,
+    tileable: bool,
+}
+
+impl Coordinator {
+    /// Starts listening for workers on `address` in the background
+    pub fn listen(address: &str, target: &Target, settings: WorkerSettings) -> Coordinator {
+        let listener = TcpListener::bind(address).unwrap();
+        let (w, h) = (target.width(), target.height());
+        let header = format!(
+            "anneal_image worker {w} {h} {} {} {}-{} {} {} {} {} {} {}-{}\n",
+            settings
+                .shape_kinds
+                .iter()
+                .map(|kind| kind.to_possible_value().unwrap().get_name().to_string())
+                .collect::<Vec<String>>()
+                .join(","),
+            settings.vertices,
+            settings.stroke_width.0,
+            settings.stroke_width.1,
+            settings.grid,
+            settings.tileable as u8,
+            settings.overhang,
+            settings.edge_bias,
+            settings
+                .blend_modes
+                .iter()
+                .map(|mode| mode.to_possible_value().unwrap().get_name().to_string())
+                .collect::<Vec<String>>()
+                .join(","),
+            settings.opacity.0,
+            settings.opacity.1
+        );
+        let target_bytes =
+            to_bytes((0..w).flat_map(|x| (0..h).map(move |y| target.pixel(Coord::new(x, y)))));
+        let snapshot = Arc::new(Mutex::new((0, Arc::new(Vec::new()))));
+        let (sender, proposals) = channel();
+        let shared_snapshot = Arc::clone(&snapshot);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let mut handshake = header.clone().into_bytes();
+                handshake.extend(&target_bytes);
+                serve_worker(
+                    stream,
+                    handshake,
+                    Arc::clone(&shared_snapshot),
+                    sender.clone(),
+                );
+            }
+        });
+        Coordinator {
+            proposals,
+            snapshot,
+            last_snapshot: None,
+            w,
+            h,
+            tileable: settings.tileable,
+        }
+    }
+
+    /// A shape some worker found, if any are waiting
+    pub fn proposal(&self) -> Option<(Shape, Fill, BlendMode)> {
+        // workers can send anything, so shapes that aren't fit to be rasterized get skipped
+        loop {
+            let (shape, fill, blend_mode) = self.proposals.try_recv().ok()?;
+            if let Some(shape) = geometry::validate(shape, self.w, self.h, self.tileable) {
+                return Some((shape, fill, blend_mode));
+            }
+        }
+    }
+
+    /// Hands the workers a fresh snapshot of the canvas, if it's been long enough since the last one
+    pub fn share(&mut self, image: &[Vec<Rgb<u8>>]) {
+        if self
+            .last_snapshot
+            .is_none_or(|last| last.elapsed() >= SNAPSHOT_INTERVAL)
+        {
+            let bytes = Arc::new(to_bytes(image.iter().flatten().copied()));
+            let mut snapshot = self.snapshot.lock().unwrap();
+            *snapshot = (snapshot.0 + 1, bytes);
+            self.last_snapshot = Some(Instant::now());
+        }
+    }
+}
+
+/// Sends a newly connected worker the original image and snapshots of the canvas,
+/// and passes on the shapes it sends back, until it disconnects
+fn serve_worker(
+    stream: TcpStream,
+    handshake: Vec<u8>,
+    snapshot: Arc<Mutex<(u64, Arc<Vec<u8>>)>>,
+    proposals: Sender<(Shape, Fill, BlendMode)>,
+) {
+    let mut writer = stream.try_clone().unwrap();
+    thread::spawn(move || {
+        if writer.write_all(&handshake).is_err() {
+            return;
+        }
+        let mut sent = 0;
+        loop {
+            let (version, bytes) = snapshot.lock().unwrap().clone();
+            if version != sent {
+                if writer.write_all(&bytes).is_err() {
+                    return;
+                }
+                sent = version;
+            }
+            thread::sleep(SNAPSHOT_INTERVAL / 10);
+        }
+    });
+    thread::spawn(move || {
+        for line in BufReader::new(stream).lines() {
+            let Ok(line) = line else { return };
+            if let Some(JournalEntry::Add {
+                shapes,
+                fill,
+                blend_mode,
+                ..
+            }) = parse_entry(&line)
+            {
+                for shape in shapes {
+                    if proposals.send((shape, fill, blend_mode)).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// The image's width and height and the settings shapes have to follow, from the coordinator's
+/// handshake line, or `None` if it isn't one
+fn parse_handshake(header: &str) -> Option<(usize, usize, WorkerSettings)> {
+    let mut words = header.split_whitespace();
+    let mut word = || words.next();
+    if (word()?, word()?) != ("anneal_image", "worker") {
+        return None;
+    }
+    let (w, h) = (
+        word()?.parse::<usize>().ok()?,
+        word()?.parse::<usize>().ok()?,
+    );
+    // the image has to be there, and not too big to even hold
+    w.checked_mul(h)?
+        .checked_mul(3)
+        .filter(|&bytes| bytes > 0)?;
+    let fraction = |value: &str| {
+        value
+            .parse()
+            .ok()
+            .filter(|value| (0.0..=1.0).contains(value))
+    };
+    let settings = WorkerSettings {
+        shape_kinds: word()?
+            .split(',')
+            .map(|kind| ShapeKind::from_str(kind, false).ok())
+            .collect::<Option<Vec<_>>>()
+            // stamps only ever come from files on the coordinator's machine
+            .filter(|kinds| !kinds.contains(&ShapeKind::Stamp))?,
+        vertices: word()?
+            .parse()
+            .ok()
+            .filter(|vertices| (3..=MAX_VERTICES).contains(vertices))?,
+        stroke_width: parse_stroke_width(word()?)
+            .ok()
+            .filter(|&(thinnest, thickest)| {
+                1.0 <= thinnest && thinnest <= thickest && thickest.is_finite()
+            })?,
+        grid: word()?.parse().ok().filter(|&grid| grid > 0)?,
+        tileable: word()? == "1",
+        overhang: fraction(word()?)?,
+        edge_bias: fraction(word()?)?,
+        blend_modes: word()?
+            .split(',')
+            .map(|mode| Blend::from_str(mode, false).ok())
+            .collect::<Option<_>>()?,
+        opacity: parse_opacity(word()?)
+            .ok()
+            .filter(|&(lowest, highest)| 0.0 < lowest && lowest <= highest && highest <= 1.0)?,
+    };
+    // and nothing after the settings
+    word().is_none().then_some((w, h, settings))
+}
+
+/// Connects to the coordinator at `address` and looks for shapes that improve its canvas,
+/// until the coordinator hangs up
+pub fn work(address: &str) {
+    let stream = TcpStream::connect(address).unwrap_or_else(|error| {
+        eprintln!("couldn't connect to the coordinator at {address}: {error}");
+        exit(1);
+    });
+    let mut writer = stream.try_clone().unwrap();
+    let mut reader = BufReader::new(stream);
+    let mut header = String::new();
+    let handshake = reader
+        .read_line(&mut header)
+        .ok()
+        .and_then(|_| parse_handshake(&header));
+    let Some((w, h, settings)) = handshake else {
+        eprintln!("didn't get a handshake from the coordinator at {address}");
+        exit(1);
+    };
+    let mut bytes = vec![0u8; w * h * 3];
+    if reader.read_exact(&mut bytes).is_err() {
+        eprintln!("the coordinator hung up before sending the image");
+        exit(1);
+    }
+    let target = Target::new(&from_bytes(&bytes, w, h));
+    println!("connected to {address}, annealing a {w}x{h} image");
+
+    // snapshots come in on their own thread so the search never has to wait for them
+    let (sender, snapshots) = channel();
+    thread::spawn(move || loop {
+        let mut bytes = vec![0u8; w * h * 3];
+        if reader.read_exact(&mut bytes).is_err() || sender.send(bytes).is_err() {
+            return;
+        }
+    });
+    let Ok(bytes) = snapshots.recv() else {
+        println!("coordinator hung up");
+        return;
+    };
+    let mut image = from_bytes(&bytes, w, h);
+    let mut cost = get_cost(&target, &image);
+    let mut cache = RasterCache::new(w, h, settings.grid);
+    loop {
+        let mut latest = None;
+        loop {
+            match snapshots.try_recv() {
+                Ok(bytes) => latest = Some(bytes),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    println!("coordinator hung up");
+                    return;
+                }
+            }
+        }
+        if let Some(bytes) = latest {
+            image = from_bytes(&bytes, w, h);
+            cost = get_cost(&target, &image);
+        }
+        let mut candidates = (0..WORKER_BATCH)
+            .map(|_| {
+                let (shape, coords, color) = get_neighbor(
+                    &target,
+                    settings.shape_kinds[random::<usize>() % settings.shape_kinds.len()],
+                    settings.vertices,
+                    settings.stroke_width,
+                    settings.grid,
+                    settings.tileable,
+                    settings.overhang,
+                    settings.edge_bias,
+                    None,
+                    None,
+                    None,
+                    &mut cache,
+                );
+                let blend_mode = BlendMode {
+                    blend: settings.blend_modes[random::<usize>() % settings.blend_modes.len()],
+                    opacity: random_opacity(settings.opacity),
+                };
+                let old_pixels = coords
+                    .iter()
+                    .map(|&coord| image.at(coord))
+                    .collect::<Vec<_>>();
+                let neighbor_cost =
+                    update_cost(cost, &target, &coords, &old_pixels, color, blend_mode, None);
+                (neighbor_cost, shape, coords, color, blend_mode)
+            })
+            .collect::<Vec<_>>();
+        let best = (0..candidates.len())
+            .min_by(|&a, &b| candidates[a].0.total_cmp(&candidates[b].0))
+            .unwrap();
+        let (neighbor_cost, shape, coords, color, blend_mode) = candidates.swap_remove(best);
+        // the rest might come up again
+        for (_, shape, coords, _, _) in candidates {
+            cache.insert(shape, coords);
+        }
+        if neighbor_cost < cost {
+            let line = add_line(0, &[shape], Fill::Solid(color), blend_mode);
+            if writeln!(writer, "{line}").is_err() {
+                println!("coordinator hung up");
+                return;
+            }
+            // keeping our own snapshot up to date until the next one arrives
+            for coord in coords {
+                let pixel = image.at_mut(coord);
+                *pixel = blend_mode.blend(*pixel, color);
+            }
+            cost = neighbor_cost;
+        }
+    }
+}
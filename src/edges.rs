@@ -0,0 +1,229 @@
+//! Strokes along the strong edges of the original image, for proposing alongside the random shapes.
+//! Edges get found from the image's gradients, thinned down to single pixels, and traced into
+//! chains, which get cut into short straight strokes. Without them, outlines are usually the last
+//! thing to show up, since random shapes hardly ever happen to line up with one
+
+use crate::rng::random;
+use crate::{
+    best_color,
+    geometry::{self, Polygon},
+    orientation::gradients,
+    target::Target,
+    Shape, ShapeKind,
+};
+use image::Rgb;
+
+/// Fraction of the pixels with the strongest gradients that count as edges
+const EDGE_FRACTION: f64 = 0.05;
+
+/// Weakest gradient that counts as an edge however few edges there are, so flat images don't get
+/// strokes along their noise
+const MIN_MAGNITUDE: f64 = 64.0;
+
+/// Shortest chain of edge pixels worth turning into strokes
+const MIN_CHAIN: usize = 10;
+
+/// How many pixels of a chain each stroke covers
+const STROKE_LENGTH: usize = 16;
+
+/// How thick strokes are, in pixels
+const STROKE_WIDTH: usize = 3;
+
+pub struct EdgeStrokes {
+    shapes: Vec<(Shape, Rgb<u8>)>,
+}
+
+/// Whether each pixel (column-major) is on a strong edge, thinned down to the pixels that are
+/// stronger than both of their neighbors across the edge
+fn edge_pixels(target: &Target) -> Vec<bool> {
+    let (w, h) = (target.width(), target.height());
+    let gradients = gradients(target);
+    let magnitudes = gradients
+        .iter()
+        .map(|(gx, gy)| gx.hypot(*gy))
+        .collect::<Vec<_>>();
+    let mut sorted = magnitudes.clone();
+    sorted.sort_unstable_by(f64::total_cmp);
+    let cutoff = ((sorted.len() as f64 * (1.0 - EDGE_FRACTION)) as usize).min(sorted.len() - 1);
+    let threshold = sorted[cutoff].max(MIN_MAGNITUDE);
+    let magnitude = |x: isize, y: isize| {
+        if x < 0 || y < 0 || x >= w as isize || y >= h as isize {
+            0.0
+        } else {
+            magnitudes[x as usize * h + y as usize]
+        }
+    };
+    (0..w)
+        .flat_map(|x| (0..h).map(move |y| (x, y)))
+        .map(|(x, y)| {
+            let i = x * h + y;
+            if magnitudes[i] < threshold {
+                return false;
+            }
+            // the neighbors across the edge are along the gradient, rounded to one of 4 directions
+            let (gx, gy) = gradients[i];
+            let angle = gy.atan2(gx).to_degrees().rem_euclid(180.0);
+            let (dx, dy) = match angle {
+                a if !(22.5..157.5).contains(&a) => (1, 0),
+                a if a < 67.5 => (1, 1),
+                a if a < 112.5 => (0, 1),
+                _ => (-1, 1),
+            };
+            let (x, y) = (x as isize, y as isize);
+            magnitudes[i] >= magnitude(x + dx, y + dy) && magnitudes[i] >= magnitude(x - dx, y - dy)
+        })
+        .collect()
+}
+
+/// Chains of neighboring edge pixels, each in order from one end to the other
+fn trace(edges: &[bool], w: usize, h: usize) -> Vec<Vec<(usize, usize)>> {
+    let mut visited = vec![false; w * h];
+    let mut chains = Vec::new();
+    for start in (0..w * h).filter(|&i| edges[i]) {
+        if visited[start] {
+            continue;
+        }
+        visited[start] = true;
+        let mut chain = vec![(start / h, start % h)];
+        // following unvisited edge pixels for as long as there are any next to the end
+        loop {
+            let (x, y) = *chain.last().unwrap();
+            let Some(next) = (x.saturating_sub(1)..=(x + 1).min(w - 1))
+                .flat_map(|nx| (y.saturating_sub(1)..=(y + 1).min(h - 1)).map(move |ny| (nx, ny)))
+                .find(|&(nx, ny)| edges[nx * h + ny] && !visited[nx * h + ny])
+            else {
+                break;
+            };
+            visited[next.0 * h + next.1] = true;
+            chain.push(next);
+        }
+        if chain.len() >= MIN_CHAIN {
+            chains.push(chain);
+        }
+    }
+    chains
+}
+
+/// A stroke from `start` to `end`, as a thin shape of the given kind, with its corners snapped to
+/// `grid`
+fn stroke(
+    start: (usize, usize),
+    end: (usize, usize),
+    kind: ShapeKind,
+    grid: usize,
+    w: usize,
+    h: usize,
+) -> Option<Shape> {
+    let snap = |value: f64, limit: usize| {
+        ((value.max(0.0) / grid as f64).round() as usize * grid).min(limit)
+    };
+    let shape = match kind {
+        ShapeKind::Triangle | ShapeKind::Polygon => {
+            // the third vertex sticks out sideways from the end, giving the stroke its width
+            let (dx, dy) = (end.0 as f64 - start.0 as f64, end.1 as f64 - start.1 as f64);
+            let length = dx.hypot(dy).max(1.0);
+            let width = STROKE_WIDTH as f64;
+            let side = (
+                end.0 as f64 - dy / length * width,
+                end.1 as f64 + dx / length * width,
+            );
+            let vertex = |(x, y): (f64, f64)| (snap(x, w - 1), snap(y, h - 1));
+            let vertices = [
+                vertex((start.0 as f64, start.1 as f64)),
+                vertex((end.0 as f64, end.1 as f64)),
+                vertex(side),
+            ];
+            match kind {
+                ShapeKind::Polygon => Shape::Polygon(Polygon::new(&vertices)),
+                _ => Shape::Triangle(vertices),
+            }
+        }
+        // blobs go around the same thin quadrilateral, which rounds its ends off
+        ShapeKind::RotatedRectangle | ShapeKind::Blob => {
+            // turned to run from one end to the other, half its width out to either side
+            let (dx, dy) = (end.0 as f64 - start.0 as f64, end.1 as f64 - start.1 as f64);
+            let length = dx.hypot(dy).max(1.0);
+            let (nx, ny) = (
+                -dy / length * STROKE_WIDTH as f64 / 2.0,
+                dx / length * STROKE_WIDTH as f64 / 2.0,
+            );
+            let vertex = |(x, y): (usize, usize), side: f64| {
+                (
+                    snap(x as f64 + nx * side, w - 1),
+                    snap(y as f64 + ny * side, h - 1),
+                )
+            };
+            let corners = Polygon::new(&[
+                vertex(start, 1.0),
+                vertex(end, 1.0),
+                vertex(end, -1.0),
+                vertex(start, -1.0),
+            ]);
+            match kind {
+                ShapeKind::Blob => Shape::Blob(corners),
+                _ => Shape::Polygon(corners),
+            }
+        }
+        ShapeKind::Stroke => {
+            let point = |(x, y): (usize, usize)| (x as f64, y as f64);
+            let outline = geometry::capsule(point(start), point(end), STROKE_WIDTH as f64);
+            let vertices = geometry::clip_polygon(&outline, (w - 1) as f64, (h - 1) as f64)
+                .into_iter()
+                .map(|(x, y)| (snap(x, w - 1), snap(y, h - 1)))
+                .collect::<Vec<_>>();
+            Shape::Polygon(Polygon::new(&vertices))
+        }
+        _ => {
+            // widening whichever way the stroke is thin
+            let widen = |a: usize, b: usize| {
+                let (low, high) = (a.min(b), a.max(b) + 1);
+                let missing = STROKE_WIDTH.saturating_sub(high - low);
+                (
+                    low.saturating_sub(missing / 2),
+                    high + missing - missing / 2,
+                )
+            };
+            let (x1, x2) = widen(start.0, end.0);
+            let (y1, y2) = widen(start.1, end.1);
+            geometry::boxed(
+                kind,
+                (snap(x1 as f64, w), snap(y1 as f64, h)),
+                (snap(x2 as f64, w), snap(y2 as f64, h)),
+            )
+        }
+    };
+    geometry::validate(shape, w, h, false)
+}
+
+impl EdgeStrokes {
+    /// Traces the edges of the original image into strokes (shapes of the given kind) with their
+    /// corners snapped to `grid`, each in the color that best fits the original image under it
+    pub fn new(target: &Target, kind: ShapeKind, grid: usize) -> EdgeStrokes {
+        let (w, h) = (target.width(), target.height());
+        let chains = trace(&edge_pixels(target), w, h);
+        let shapes = chains
+            .iter()
+            .flat_map(|chain| {
+                // cutting the chain every `STROKE_LENGTH` pixels, and once more at its end
+                let mut ends = chain
+                    .iter()
+                    .step_by(STROKE_LENGTH)
+                    .copied()
+                    .collect::<Vec<_>>();
+                if (chain.len() - 1) % STROKE_LENGTH != 0 {
+                    ends.push(*chain.last().unwrap());
+                }
+                ends.windows(2)
+                    .filter_map(|pair| stroke(pair[0], pair[1], kind, grid, w, h))
+                    .collect::<Vec<_>>()
+            })
+            .map(|shape| (shape, best_color(target, &shape.coords(w, h))))
+            .collect();
+        EdgeStrokes { shapes }
+    }
+
+    /// One of the strokes and its color, at random
+    pub fn proposal(&self) -> Option<(Shape, Rgb<u8>)> {
+        (!self.shapes.is_empty()).then(|| self.shapes[random::<usize>() % self.shapes.len()])
+    }
+}
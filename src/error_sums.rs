@@ -0,0 +1,92 @@
+//! Summed-area table of the pixel differences between the original image and the annealed image,
+//! so the error under a rectangle can be looked up without going over its pixels.
+//! Accepted shapes only dirty the columns they touch (the images are column-major, so columns
+//! are what gets summed first), and those are only recomputed the next time the table is needed
+
+use crate::{coord::Coord, pixel_difference, target::Target};
+use image::Rgb;
+
+pub struct ErrorSums {
+    w: usize,
+    h: usize,
+    /// Running sums of the pixel differences down each column, `h + 1` long
+    columns: Vec<Vec<u64>>,
+    /// `table[x][y]` is the sum of the pixel differences of every pixel left of `x` and above `y`
+    table: Vec<Vec<u64>>,
+    dirty: Vec<bool>,
+    /// Every column of `table` right of this one is stale
+    dirty_from: usize,
+    /// Pixels summed directly since the last refresh, instead of bringing the table up to date
+    skipped: usize,
+}
+
+impl ErrorSums {
+    /// Table for the pixel differences between `target` and `annealed_image`
+    pub fn new(target: &Target, annealed_image: &[Vec<Rgb<u8>>]) -> ErrorSums {
+        let (w, h) = (target.width(), target.height());
+        let mut error_sums = ErrorSums {
+            w,
+            h,
+            columns: vec![vec![0; h + 1]; w],
+            table: vec![vec![0; h + 1]; w + 1],
+            dirty: vec![true; w],
+            dirty_from: 0,
+            skipped: 0,
+        };
+        error_sums.refresh(target, annealed_image);
+        error_sums
+    }
+
+    /// Marks the columns of the given coordinates as changed in the annealed image
+    pub fn mark_dirty(&mut self, coords: &[Coord]) {
+        for &Coord { x, .. } in coords {
+            self.dirty[x] = true;
+            self.dirty_from = self.dirty_from.min(x);
+        }
+    }
+
+    /// Sum of the pixel differences under the rectangle from `top_left` up to (but not including)
+    /// `bottom_right`, which has to be within the image.
+    /// Returns `None` if the table is out of date and bringing it up to date would (so far) have
+    /// taken longer than summing the pixels of this and the other skipped rectangles directly
+    pub fn rectangle(
+        &mut self,
+        target: &Target,
+        annealed_image: &[Vec<Rgb<u8>>],
+        top_left: (usize, usize),
+        bottom_right: (usize, usize),
+    ) -> Option<u64> {
+        let (x1, y1) = top_left;
+        let (x2, y2) = bottom_right;
+        if self.dirty_from < x2 {
+            let area = (x2 - x1) * (y2 - y1);
+            let dirty_columns = self.dirty[self.dirty_from..].iter().filter(|&&d| d).count();
+            if (dirty_columns + self.w - self.dirty_from) * self.h > self.skipped + area {
+                self.skipped += area;
+                return None;
+            }
+            self.refresh(target, annealed_image);
+        }
+        let table = &self.table;
+        Some(table[x2][y2] + table[x1][y1] - table[x1][y2] - table[x2][y1])
+    }
+
+    /// Recomputes the dirty columns and everything in `table` right of them
+    fn refresh(&mut self, target: &Target, annealed_image: &[Vec<Rgb<u8>>]) {
+        for (x, annealed_column) in annealed_image.iter().enumerate().skip(self.dirty_from) {
+            if self.dirty[x] {
+                let column = &mut self.columns[x];
+                for (y, &pixel) in annealed_column.iter().enumerate() {
+                    column[y + 1] =
+                        column[y] + pixel_difference(target.pixel(Coord::new(x, y)), pixel);
+                }
+                self.dirty[x] = false;
+            }
+            for y in 0..=self.h {
+                self.table[x + 1][y] = self.table[x][y] + self.columns[x][y];
+            }
+        }
+        self.dirty_from = self.w;
+        self.skipped = 0;
+    }
+}
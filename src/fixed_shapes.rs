@@ -0,0 +1,325 @@
+//! Hand-drawn shapes from the paths of an SVG file, for `--fixed-shapes`. They get painted onto the
+//! canvas before annealing and are locked in place, so the annealing only fills in around them.
+//!
+//! Only what hand-drawn outlines need is supported: each `<path>`'s `d` outline made of move, line,
+//! horizontal, vertical, cubic and quadratic curve, and close commands (absolute or relative), and
+//! its fill as a `fill` attribute or in its `style`, in `#rgb`, `#rrggbb`, or a basic color name
+//! (black by default, like in SVG). Paths get filled with the nonzero rule, and the `viewBox` (or
+//! the `width` and `height`) gets stretched over the whole image
+
+use crate::coord::Coord;
+use image::Rgb;
+use std::fs::read_to_string;
+
+/// How many straight lines each curve gets flattened into
+const CURVE_SEGMENTS: usize = 16;
+
+pub struct FixedShapes {
+    /// The fixed pixels and their colors, with later paths painted over earlier ones
+    pixels: Vec<(Coord, Rgb<u8>)>,
+}
+
+/// Value of the attribute `name` in the tag `tag`
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = tag;
+    while let Some(start) = rest.find(name) {
+        let before = rest[..start].chars().last();
+        let after = rest[start + name.len()..].trim_start();
+        rest = &rest[start + name.len()..];
+        if before.is_some_and(|c| !c.is_whitespace()) {
+            continue;
+        }
+        let Some(after) = after.strip_prefix('=') else {
+            continue;
+        };
+        let after = after.trim_start();
+        let quote = after.chars().next()?;
+        let value = &after[1..];
+        return value.find(quote).map(|end| &value[..end]);
+    }
+    None
+}
+
+/// Every tag named `name` in the document
+fn tags<'a>(document: &'a str, name: &str) -> Vec<&'a str> {
+    let opening = format!("<{name}");
+    let mut tags = Vec::new();
+    let mut rest = document;
+    while let Some(start) = rest.find(&opening) {
+        rest = &rest[start + opening.len()..];
+        if rest.starts_with(|c: char| c.is_whitespace() || c == '/' || c == '>') {
+            let end = rest.find('>').unwrap_or(rest.len());
+            tags.push(&rest[..end]);
+        }
+    }
+    tags
+}
+
+/// A color like `#f80`, `#ff8800`, or `red`, or `None` for `none`
+fn parse_color(text: &str) -> Result<Option<Rgb<u8>>, String> {
+    let text = text.trim();
+    let hex =
+        |digits: &str| u8::from_str_radix(digits, 16).map_err(|_| format!("bad color {text}"));
+    match text {
+        "none" | "transparent" => Ok(None),
+        "black" => Ok(Some(Rgb([0, 0, 0]))),
+        "white" => Ok(Some(Rgb([255, 255, 255]))),
+        "red" => Ok(Some(Rgb([255, 0, 0]))),
+        "green" => Ok(Some(Rgb([0, 128, 0]))),
+        "blue" => Ok(Some(Rgb([0, 0, 255]))),
+        _ if text.len() == 4 && text.starts_with('#') => {
+            let digits = text[1..]
+                .chars()
+                .map(|c| hex(&c.to_string().repeat(2)))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Some(Rgb([digits[0], digits[1], digits[2]])))
+        }
+        _ if text.len() == 7 && text.starts_with('#') => Ok(Some(Rgb([
+            hex(&text[1..3])?,
+            hex(&text[3..5])?,
+            hex(&text[5..7])?,
+        ]))),
+        _ => Err(format!("can't make sense of the color {text}")),
+    }
+}
+
+/// Splits path data into command letters and numbers
+fn path_tokens(data: &str) -> Result<Vec<PathToken>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = data.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_ascii_alphabetic() && c != 'e' && c != 'E' {
+            tokens.push(PathToken::Command(c));
+            chars.next();
+        } else if c.is_ascii_digit() || c == '-' || c == '+' || c == '.' {
+            // a number goes on until something that can't be part of it, and a second `.` or a
+            // sign that isn't right after an exponent starts the next one
+            let mut end = start + c.len_utf8();
+            let mut seen_dot = c == '.';
+            let mut previous = c;
+            chars.next();
+            while let Some(&(i, c)) = chars.peek() {
+                let continues = c.is_ascii_digit()
+                    || c == '.' && !seen_dot
+                    || (c == 'e' || c == 'E')
+                    || (c == '-' || c == '+') && (previous == 'e' || previous == 'E');
+                if !continues {
+                    break;
+                }
+                seen_dot |= c == '.';
+                previous = c;
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            let number = data[start..end]
+                .parse()
+                .map_err(|_| format!("bad number {}", &data[start..end]))?;
+            tokens.push(PathToken::Number(number));
+        } else {
+            chars.next();
+        }
+    }
+    Ok(tokens)
+}
+
+enum PathToken {
+    Command(char),
+    Number(f64),
+}
+
+/// The closed outlines of the path data, as lists of points
+fn outlines(data: &str) -> Result<Vec<Vec<(f64, f64)>>, String> {
+    let tokens = path_tokens(data)?;
+    let mut outlines: Vec<Vec<(f64, f64)>> = Vec::new();
+    let mut current = (0.0, 0.0);
+    let mut start = (0.0, 0.0);
+    let mut i = 0;
+    let mut command = None;
+    let number = |i: &mut usize| match tokens.get(*i) {
+        Some(&PathToken::Number(value)) => {
+            *i += 1;
+            Ok(value)
+        }
+        _ => Err("path data ends in the middle of a command".to_string()),
+    };
+    while i < tokens.len() {
+        if let PathToken::Command(c) = tokens[i] {
+            command = Some(c);
+            i += 1;
+            if c == 'Z' || c == 'z' {
+                current = start;
+                command = None;
+                continue;
+            }
+        }
+        let Some(c) = command else {
+            return Err("path data has to start with a command".to_string());
+        };
+        let relative = c.is_ascii_lowercase();
+        let offset = |(x, y): (f64, f64)| {
+            if relative {
+                (current.0 + x, current.1 + y)
+            } else {
+                (x, y)
+            }
+        };
+        match c.to_ascii_uppercase() {
+            'M' => {
+                current = offset((number(&mut i)?, number(&mut i)?));
+                start = current;
+                outlines.push(vec![current]);
+                // more coordinates after a move are lines
+                command = Some(if relative { 'l' } else { 'L' });
+                continue;
+            }
+            'L' => current = offset((number(&mut i)?, number(&mut i)?)),
+            'H' => {
+                let x = number(&mut i)?;
+                current.0 = if relative { current.0 + x } else { x };
+            }
+            'V' => {
+                let y = number(&mut i)?;
+                current.1 = if relative { current.1 + y } else { y };
+            }
+            'C' | 'Q' => {
+                let cubic = c.eq_ignore_ascii_case(&'C');
+                let control1 = offset((number(&mut i)?, number(&mut i)?));
+                let control2 = if cubic {
+                    offset((number(&mut i)?, number(&mut i)?))
+                } else {
+                    control1
+                };
+                let end = offset((number(&mut i)?, number(&mut i)?));
+                let outline = outlines
+                    .last_mut()
+                    .ok_or("path data has to start with a move")?;
+                for step in 1..CURVE_SEGMENTS {
+                    let t = step as f64 / CURVE_SEGMENTS as f64;
+                    let u = 1.0 - t;
+                    let point = |a: f64, b: f64, c: f64, d: f64| {
+                        if cubic {
+                            u * u * u * a
+                                + 3.0 * u * u * t * b
+                                + 3.0 * u * t * t * c
+                                + t * t * t * d
+                        } else {
+                            u * u * a + 2.0 * u * t * b + t * t * d
+                        }
+                    };
+                    outline.push((
+                        point(current.0, control1.0, control2.0, end.0),
+                        point(current.1, control1.1, control2.1, end.1),
+                    ));
+                }
+                current = end;
+            }
+            _ => return Err(format!("unsupported path command {c}")),
+        }
+        outlines
+            .last_mut()
+            .ok_or("path data has to start with a move")?
+            .push(current);
+    }
+    Ok(outlines)
+}
+
+/// Pixels (whose centers are) inside the outlines by the nonzero rule, within a `w` by `h` image
+fn fill(outlines: &[Vec<(f64, f64)>], w: usize, h: usize) -> Vec<Coord> {
+    let mut coords = Vec::new();
+    for y in 0..h {
+        let scan_y = y as f64 + 0.5;
+        // where each edge crosses the scanline, and which way it's going
+        let mut crossings = Vec::new();
+        for outline in outlines {
+            for (i, &(x1, y1)) in outline.iter().enumerate() {
+                let (x2, y2) = outline[(i + 1) % outline.len()];
+                if (y1 <= scan_y) != (y2 <= scan_y) {
+                    let x = x1 + (scan_y - y1) / (y2 - y1) * (x2 - x1);
+                    crossings.push((x, if y2 > y1 { 1 } else { -1 }));
+                }
+            }
+        }
+        crossings.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+        let mut winding = 0;
+        for pair in crossings.windows(2) {
+            winding += pair[0].1;
+            if winding != 0 {
+                let from = (pair[0].0 - 0.5).ceil().max(0.0) as usize;
+                let to = ((pair[1].0 - 0.5).ceil().max(0.0) as usize).min(w);
+                coords.extend((from..to).map(|x| Coord::new(x, y)));
+            }
+        }
+    }
+    coords
+}
+
+impl FixedShapes {
+    /// Loads the paths in the SVG file at `path`, stretched over a `w` by `h` image
+    pub fn load(path: &str, w: usize, h: usize) -> FixedShapes {
+        let document = read_to_string(path).unwrap();
+        let svg = tags(&document, "svg")
+            .into_iter()
+            .next()
+            .unwrap_or_else(|| panic!("{path} isn't an SVG file"));
+        let number = |text: &str| text.trim().trim_end_matches("px").parse::<f64>().ok();
+        let (min_x, min_y, view_w, view_h) = match attribute(svg, "viewBox") {
+            Some(view_box) => {
+                let numbers = view_box
+                    .split(|c: char| c.is_whitespace() || c == ',')
+                    .filter_map(number)
+                    .collect::<Vec<_>>();
+                match numbers[..] {
+                    [min_x, min_y, view_w, view_h] => (min_x, min_y, view_w, view_h),
+                    _ => panic!("can't make sense of the viewBox {view_box}"),
+                }
+            }
+            None => (
+                0.0,
+                0.0,
+                attribute(svg, "width").and_then(number).unwrap_or(w as f64),
+                attribute(svg, "height")
+                    .and_then(number)
+                    .unwrap_or(h as f64),
+            ),
+        };
+        let (scale_x, scale_y) = (w as f64 / view_w, h as f64 / view_h);
+        let mut pixels = Vec::new();
+        for tag in tags(&document, "path") {
+            let fill_color = attribute(tag, "style")
+                .and_then(|style| {
+                    style
+                        .split(';')
+                        .filter_map(|declaration| declaration.split_once(':'))
+                        .find(|(property, _)| property.trim() == "fill")
+                        .map(|(_, value)| value)
+                })
+                .or_else(|| attribute(tag, "fill"))
+                .map_or(Ok(Some(Rgb([0, 0, 0]))), parse_color)
+                .unwrap_or_else(|error| panic!("{path}: {error}"));
+            let (Some(color), Some(data)) = (fill_color, attribute(tag, "d")) else {
+                continue;
+            };
+            let outlines = outlines(data)
+                .unwrap_or_else(|error| panic!("{path}: {error}"))
+                .into_iter()
+                .map(|outline| {
+                    outline
+                        .into_iter()
+                        .map(|(x, y)| ((x - min_x) * scale_x, (y - min_y) * scale_y))
+                        .collect()
+                })
+                .collect::<Vec<_>>();
+            pixels.extend(
+                fill(&outlines, w, h)
+                    .into_iter()
+                    .map(|coord| (coord, color)),
+            );
+        }
+        FixedShapes { pixels }
+    }
+
+    /// The fixed pixels and their colors, with later paths painted over earlier ones
+    pub fn pixels(&self) -> &[(Coord, Rgb<u8>)] {
+        &self.pixels
+    }
+}
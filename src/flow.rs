@@ -0,0 +1,78 @@
+//! Strokes that follow a flow field of the original image, for proposing alongside the random
+//! shapes. The field is the image's orientation from its structure tensor, smoothed well past what
+//! `--orientation-weight` goes by, so that it swirls around the image instead of jittering pixel
+//! to pixel. Strokes land anywhere (where they end up gets annealed like any other shape), but they
+//! always run whichever way the field does under their middle, which paints the image in
+//! Van Gogh-like swirls
+
+use crate::rng::random;
+use crate::{
+    best_color,
+    coord::Coord,
+    geometry::{self, Polygon},
+    orientation::OrientationMap,
+    target::Target,
+    Shape,
+};
+use image::Rgb;
+use std::f64::consts::PI;
+
+const MIN_LENGTH: f64 = 6.0;
+
+/// Longest strokes, as a fraction of the image's shorter side
+const MAX_LENGTH: f64 = 0.12;
+
+/// How wide strokes are, as a fraction of how long they are
+const WIDTH: f64 = 0.25;
+
+pub struct FlowStrokes {
+    w: usize,
+    h: usize,
+    grid: usize,
+    field: OrientationMap,
+    max_length: f64,
+}
+
+impl FlowStrokes {
+    /// The flow field of the original image, with its structure tensor averaged over `smoothness`
+    /// pixels every way, for strokes with their corners snapped to `grid`
+    pub fn new(target: &Target, smoothness: usize, grid: usize) -> FlowStrokes {
+        let (w, h) = (target.width(), target.height());
+        FlowStrokes {
+            w,
+            h,
+            grid,
+            field: OrientationMap::smoothed(target, smoothness),
+            max_length: (MAX_LENGTH * w.min(h) as f64).max(MIN_LENGTH),
+        }
+    }
+
+    /// A stroke somewhere random, along the field, in the color that best fits the original image
+    /// under it. Strokes are thin quadrilaterals (as polygons), since rectangles and ellipses can't
+    /// turn to follow the field
+    pub fn proposal(&self, target: &Target) -> Option<(Shape, Rgb<u8>)> {
+        let (cx, cy) = (random::<usize>() % self.w, random::<usize>() % self.h);
+        // flat patches don't run any way, so strokes there go any way at all
+        let (dx, dy) = self.field.direction(Coord::new(cx, cy)).unwrap_or_else(|| {
+            let angle = random::<f64>() * PI;
+            (angle.cos(), angle.sin())
+        });
+        let length = MIN_LENGTH + random::<f64>() * (self.max_length - MIN_LENGTH);
+        let (along, across) = (length / 2.0, (length * WIDTH / 2.0).max(1.0));
+        let snap = |value: f64, limit: usize| {
+            ((value.max(0.0) / self.grid as f64).round() as usize * self.grid).min(limit)
+        };
+        let vertices = [(1.0, 1.0), (1.0, -1.0), (-1.0, -1.0), (-1.0, 1.0)].map(|(a, b)| {
+            let x = cx as f64 + a * along * dx - b * across * dy;
+            let y = cy as f64 + a * along * dy + b * across * dx;
+            (snap(x, self.w - 1), snap(y, self.h - 1))
+        });
+        let shape = geometry::validate(
+            Shape::Polygon(Polygon::new(&vertices)),
+            self.w,
+            self.h,
+            false,
+        )?;
+        Some((shape, best_color(target, &shape.coords(self.w, self.h))))
+    }
+}
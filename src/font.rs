@@ -0,0 +1,470 @@
+//! Glyphs from a TrueType font used as stamps, for `--font`, which makes typographic mosaics out of
+//! letters scattered around at every size and angle. Each glyph gets rasterized once up front into
+//! an alpha mask the size of the ink in it, and from then on it's a stamp like any other. There's
+//! no font library, just enough of TrueType to find glyphs by character in `cmap` and read their
+//! outlines out of `glyf`, so fonts with PostScript outlines (which most `.otf` files have) can't
+//! be used
+
+use crate::stamps::{self, Stamp};
+use std::{collections::HashSet, fs::read};
+
+/// How many pixels across a glyph's em square gets rasterized at, which is as big as a stamp can be
+/// painted without its edges getting soft from being scaled up
+const EM_PIXELS: f64 = 256.0;
+
+/// How many scanlines go through each row of a glyph's pixels
+const SCANLINES: usize = 8;
+
+/// How many straight lines each curve in an outline gets flattened into
+const CURVE_SEGMENTS: usize = 8;
+
+/// Composite glyphs nested any deeper than this are taken to be broken
+const MAX_NESTING: usize = 8;
+
+/// Glyphs are taken to be broken if they'd be rasterized any more pixels across than this, which is
+/// far more than any em square's worth of ink could take up
+const MAX_PIXELS: f64 = 16.0 * EM_PIXELS;
+
+type Point = (f64, f64);
+
+/// The tables of a TrueType font that it takes to look up glyphs and read their outlines, as
+/// offsets into the file
+struct Font<'a> {
+    data: &'a [u8],
+    cmap: usize,
+    loca: usize,
+    glyf: usize,
+    /// Whether `loca` has 32-bit offsets instead of halved 16-bit ones
+    long_offsets: bool,
+    glyphs: usize,
+    units_per_em: f64,
+}
+
+fn u8_at(data: &[u8], offset: usize) -> Option<u8> {
+    data.get(offset).copied()
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn i16_at(data: &[u8], offset: usize) -> Option<i16> {
+    Some(u16_at(data, offset)? as i16)
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+impl<'a> Font<'a> {
+    /// Finds the tables in `data`, or says what's wrong with it as something that goes after the
+    /// font's path
+    fn parse(data: &'a [u8]) -> Result<Font<'a>, &'static str> {
+        match data.get(..4) {
+            Some([0, 1, 0, 0] | b"true") => {}
+            Some(b"OTTO") => {
+                return Err("has PostScript outlines, and only TrueType ones can be read")
+            }
+            _ => return Err("isn't a TrueType font"),
+        }
+        let table = |tag: &[u8; 4]| {
+            (0..u16_at(data, 4)? as usize)
+                .map(|i| 12 + 16 * i)
+                .find(|&record| data.get(record..record + 4) == Some(tag))
+                .and_then(|record| Some(u32_at(data, record + 8)? as usize))
+        };
+        let missing =
+            "is missing one of the head, cmap, loca, glyf and maxp tables, or is cut short";
+        let (Some(head), Some(cmap), Some(loca), Some(glyf), Some(maxp)) = (
+            table(b"head"),
+            table(b"cmap"),
+            table(b"loca"),
+            table(b"glyf"),
+            table(b"maxp"),
+        ) else {
+            return Err(missing);
+        };
+        let broken = "is cut short or broken";
+        // the Unicode subtable, preferring the one that reaches past the Basic Multilingual Plane
+        let cmap = (0..u16_at(data, cmap + 2).ok_or(broken)? as usize)
+            .map(|i| cmap + 4 + 8 * i)
+            .filter_map(|record| {
+                let (platform, encoding) = (u16_at(data, record)?, u16_at(data, record + 2)?);
+                let subtable = cmap + u32_at(data, record + 4)? as usize;
+                let format = u16_at(data, subtable)?;
+                let unicode = platform == 0 || (platform == 3 && matches!(encoding, 1 | 10));
+                (unicode && matches!(format, 4 | 12)).then_some((format, subtable))
+            })
+            .max()
+            .ok_or("doesn't map Unicode characters to its glyphs")?
+            .1;
+        let units_per_em = u16_at(data, head + 18).ok_or(broken)?;
+        // the smallest em square TrueType allows, which keeps glyphs from getting scaled up to
+        // however many pixels
+        if units_per_em < 16 {
+            return Err("has an em square too small to scale glyphs from");
+        }
+        Ok(Font {
+            data,
+            cmap,
+            loca,
+            glyf,
+            long_offsets: i16_at(data, head + 50).ok_or(broken)? != 0,
+            glyphs: u16_at(data, maxp + 4).ok_or(broken)? as usize,
+            units_per_em: units_per_em as f64,
+        })
+    }
+
+    /// The glyph drawn for `c`, if the font has one for it (and the part of `cmap` saying which one
+    /// it is can be read)
+    fn glyph(&self, c: char) -> Option<usize> {
+        let (data, c) = (self.data, c as u32);
+        let glyph = if u16_at(data, self.cmap)? == 12 {
+            let mut glyph = 0;
+            for i in 0..u32_at(data, self.cmap + 12)? as usize {
+                let group = self.cmap + 16 + 12 * i;
+                let start = u32_at(data, group)?;
+                if start <= c && c <= u32_at(data, group + 4)? {
+                    glyph = u32_at(data, group + 8)?.checked_add(c - start)?;
+                    break;
+                }
+            }
+            glyph
+        } else {
+            let segments = u16_at(data, self.cmap + 6)? as usize;
+            let ends = self.cmap + 14;
+            let starts = ends + segments + 2;
+            let (deltas, range_offsets) = (starts + segments, starts + 2 * segments);
+            let mut glyph = 0;
+            for i in 0..segments / 2 {
+                if c > u16_at(data, ends + 2 * i)? as u32 {
+                    continue;
+                }
+                let start = u16_at(data, starts + 2 * i)? as u32;
+                if c < start {
+                    break;
+                }
+                let delta = u16_at(data, deltas + 2 * i)? as u32;
+                let range_offset = u16_at(data, range_offsets + 2 * i)? as usize;
+                glyph = if range_offset == 0 {
+                    (c + delta) & 0xFFFF
+                } else {
+                    // the offset counts from where it's stored, into the glyph index array after it
+                    let at = range_offsets + 2 * i + range_offset + 2 * (c - start) as usize;
+                    match u16_at(data, at)? as u32 {
+                        0 => 0,
+                        glyph => (glyph + delta) & 0xFFFF,
+                    }
+                };
+                break;
+            }
+            glyph
+        };
+        let glyph = glyph as usize;
+        (glyph != 0 && glyph < self.glyphs).then_some(glyph)
+    }
+
+    /// The bytes of the glyph's outline in `glyf`, which are empty if it doesn't have one, or
+    /// `None` if they can't be read
+    fn location(&self, glyph: usize) -> Option<&'a [u8]> {
+        if glyph >= self.glyphs {
+            return None;
+        }
+        let (start, end) = if self.long_offsets {
+            let at = self.loca + 4 * glyph;
+            (u32_at(self.data, at)?, u32_at(self.data, at + 4)?)
+        } else {
+            let at = self.loca + 2 * glyph;
+            (
+                u16_at(self.data, at)? as u32 * 2,
+                u16_at(self.data, at + 2)? as u32 * 2,
+            )
+        };
+        if start >= end {
+            return Some(&[]);
+        }
+        self.data
+            .get(self.glyf + start as usize..self.glyf + end as usize)
+    }
+
+    /// The contours of the glyph in font units, with y going up, and their curves flattened, or
+    /// `None` if the glyph is broken
+    fn outline(&self, glyph: usize, nesting: usize) -> Option<Vec<Vec<Point>>> {
+        let data = self.location(glyph)?;
+        if data.is_empty() || nesting > MAX_NESTING {
+            return Some(Vec::new());
+        }
+        let contours = i16_at(data, 0)?;
+        if contours < 0 {
+            return self.composite(data, nesting);
+        }
+        let ends = (0..contours as usize)
+            .map(|i| Some(u16_at(data, 10 + 2 * i)? as usize))
+            .collect::<Option<Vec<_>>>()?;
+        // each contour has to end after the one before it, or their points would get mixed up
+        if ends.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return None;
+        }
+        let points = ends.last().map_or(0, |&last| last + 1);
+        let instructions = 10 + 2 * ends.len();
+        let mut at = instructions + 2 + u16_at(data, instructions)? as usize;
+        let mut flags = Vec::with_capacity(points);
+        while flags.len() < points {
+            let flag = u8_at(data, at)?;
+            at += 1;
+            // flags that repeat are followed by how many more times they do
+            let repeats = if flag & 8 != 0 {
+                at += 1;
+                u8_at(data, at - 1)? as usize
+            } else {
+                0
+            };
+            flags.extend(std::iter::repeat_n(flag, repeats + 1));
+        }
+        flags.truncate(points);
+        // x and y are each stored as how far they move from the point before, as a byte and a sign
+        // bit, or the same as before, or two bytes
+        let mut coordinates = |short: u8, same_or_positive: u8| {
+            let mut value = 0;
+            flags
+                .iter()
+                .map(|&flag| {
+                    if flag & short != 0 {
+                        let step = u8_at(data, at)? as i32;
+                        at += 1;
+                        value += if flag & same_or_positive != 0 {
+                            step
+                        } else {
+                            -step
+                        };
+                    } else if flag & same_or_positive == 0 {
+                        value += i16_at(data, at)? as i32;
+                        at += 2;
+                    }
+                    Some(value as f64)
+                })
+                .collect::<Option<Vec<_>>>()
+        };
+        let xs = coordinates(2, 16)?;
+        let ys = coordinates(4, 32)?;
+        let mut start = 0;
+        let contours = ends
+            .iter()
+            .map(|&end| {
+                let contour = (start..=end)
+                    .map(|i| ((xs[i], ys[i]), flags[i] & 1 != 0))
+                    .collect::<Vec<_>>();
+                start = end + 1;
+                flattened(&contour)
+            })
+            .filter(|contour| contour.len() > 2)
+            .collect();
+        Some(contours)
+    }
+
+    /// The contours of the glyphs a composite glyph (whose outline is `data`) is put together from,
+    /// each moved (and maybe scaled or turned) into place, or `None` if any of them are broken
+    fn composite(&self, data: &[u8], nesting: usize) -> Option<Vec<Vec<Point>>> {
+        let mut contours = Vec::new();
+        let mut at = 10;
+        loop {
+            let (flags, glyph) = (u16_at(data, at)?, u16_at(data, at + 2)? as usize);
+            at += 4;
+            let (dx, dy) = if flags & 1 != 0 {
+                at += 4;
+                (i16_at(data, at - 4)? as f64, i16_at(data, at - 2)? as f64)
+            } else {
+                at += 2;
+                (
+                    u8_at(data, at - 2)? as i8 as f64,
+                    u8_at(data, at - 1)? as i8 as f64,
+                )
+            };
+            // components put in place by matching up points are just left where they are
+            let (dx, dy) = if flags & 2 != 0 { (dx, dy) } else { (0.0, 0.0) };
+            let scale = |at: usize| Some(i16_at(data, at)? as f64 / 16384.0);
+            let [a, b, c, d] = if flags & 8 != 0 {
+                at += 2;
+                [scale(at - 2)?, 0.0, 0.0, scale(at - 2)?]
+            } else if flags & 0x40 != 0 {
+                at += 4;
+                [scale(at - 4)?, 0.0, 0.0, scale(at - 2)?]
+            } else if flags & 0x80 != 0 {
+                at += 8;
+                [
+                    scale(at - 8)?,
+                    scale(at - 6)?,
+                    scale(at - 4)?,
+                    scale(at - 2)?,
+                ]
+            } else {
+                [1.0, 0.0, 0.0, 1.0]
+            };
+            contours.extend(
+                self.outline(glyph, nesting + 1)?
+                    .into_iter()
+                    .map(|contour| {
+                        contour
+                            .into_iter()
+                            .map(|(x, y)| (a * x + c * y + dx, b * x + d * y + dy))
+                            .collect()
+                    }),
+            );
+            if flags & 0x20 == 0 {
+                return Some(contours);
+            }
+        }
+    }
+}
+
+/// A contour of on and off-curve points flattened into a polygon. Every off-curve point is the
+/// control point of a quadratic curve, and two off-curve points in a row have an on-curve point
+/// halfway between them
+fn flattened(contour: &[(Point, bool)]) -> Vec<Point> {
+    let n = contour.len();
+    let midpoint = |a: Point, b: Point| ((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0);
+    // starting from an on-curve point, or between the first two if there aren't any
+    let (first, start) = match contour.iter().position(|&(_, on)| on) {
+        Some(i) => (i, contour[i].0),
+        None => (0, midpoint(contour[0].0, contour[1 % n].0)),
+    };
+    let mut polygon = vec![start];
+    let mut control = None;
+    for i in 1..=n {
+        let (point, on) = contour[(first + i) % n];
+        match (on, control) {
+            (true, None) => polygon.push(point),
+            (true, Some(control)) => curve(&mut polygon, control, point),
+            (false, None) => control = Some(point),
+            (false, Some(previous)) => {
+                curve(&mut polygon, previous, midpoint(previous, point));
+                control = Some(point);
+            }
+        }
+        if on {
+            control = None;
+        }
+    }
+    if let Some(control) = control {
+        curve(&mut polygon, control, start);
+    }
+    // the contour closes itself, so the start doesn't need repeating
+    polygon.pop();
+    polygon
+}
+
+/// Adds a quadratic curve from the last point of `polygon` to `end` to it, as straight lines
+fn curve(polygon: &mut Vec<Point>, control: Point, end: Point) {
+    let start = *polygon.last().unwrap();
+    for step in 1..=CURVE_SEGMENTS {
+        let t = step as f64 / CURVE_SEGMENTS as f64;
+        let (a, b, c) = ((1.0 - t) * (1.0 - t), 2.0 * t * (1.0 - t), t * t);
+        polygon.push((
+            a * start.0 + b * control.0 + c * end.0,
+            a * start.1 + b * control.1 + c * end.1,
+        ));
+    }
+}
+
+/// Width, height, and column-major alpha of `contours` (going up like font units) rasterized at
+/// `scale` pixels per unit, cropped to the ink with a pixel of room around it. Wherever the
+/// contours wind around a pixel is covered, so holes wound the other way stay empty but contours
+/// that overlap don't cancel each other out. `None` if there aren't any contours, or the ink would
+/// be more than `MAX_PIXELS` across
+pub fn rasterize(contours: &[Vec<Point>], scale: f64) -> Option<(usize, usize, Vec<u8>)> {
+    let points = || contours.iter().flatten();
+    let left = points().fold(f64::INFINITY, |left, p| left.min(p.0));
+    let right = points().fold(f64::NEG_INFINITY, |right, p| right.max(p.0));
+    let bottom = points().fold(f64::INFINITY, |bottom, p| bottom.min(p.1));
+    let top = points().fold(f64::NEG_INFINITY, |top, p| top.max(p.1));
+    let (width, height) = ((right - left) * scale, (top - bottom) * scale);
+    // written so that NaNs (from there being no points) don't make it through either
+    if !(width <= MAX_PIXELS && height <= MAX_PIXELS) {
+        return None;
+    }
+    let w = width.ceil() as usize + 2;
+    let h = height.ceil() as usize + 2;
+    // flipped over to go down like the image, with the room around it
+    let pixels = contours
+        .iter()
+        .map(|contour| {
+            contour
+                .iter()
+                .map(|&(x, y)| ((x - left) * scale + 1.0, (top - y) * scale + 1.0))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let mut covered = vec![0.0; w * h];
+    let weight = 1.0 / SCANLINES as f64;
+    let mut crossings = Vec::new();
+    for row in 0..h {
+        for scanline in 0..SCANLINES {
+            let y = row as f64 + (scanline as f64 + 0.5) * weight;
+            crossings.clear();
+            for contour in &pixels {
+                for (i, &(ax, ay)) in contour.iter().enumerate() {
+                    let (bx, by) = contour[(i + 1) % contour.len()];
+                    if (ay <= y) != (by <= y) {
+                        let winding = if ay < by { 1 } else { -1 };
+                        crossings.push((ax + (y - ay) / (by - ay) * (bx - ax), winding));
+                    }
+                }
+            }
+            crossings.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+            let mut winding = 0;
+            for (i, &(start, turn)) in crossings.iter().enumerate() {
+                winding += turn;
+                let Some(&(end, _)) = crossings.get(i + 1).filter(|_| winding != 0) else {
+                    continue;
+                };
+                // how much of each pixel the span crosses
+                let (first, last) = (start as usize, (end.ceil() as usize).max(1) - 1);
+                for x in first..=last.min(w - 1) {
+                    let overlap = end.min((x + 1) as f64) - start.max(x as f64);
+                    covered[x * h + row] += overlap.max(0.0) * weight;
+                }
+            }
+        }
+    }
+    let alpha = covered
+        .into_iter()
+        .map(|amount: f64| (amount.min(1.0) * 255.0).round() as u8)
+        .collect();
+    Some((w, h, alpha))
+}
+
+/// Loads the glyphs for every character in `characters` from the TrueType font at `path` as
+/// stamps, in the order they're listed. Characters the font doesn't have (or that don't have any
+/// ink, like spaces) get left out, but there has to be at least one left
+pub fn load(path: &str, characters: &str) {
+    let data = read(path).unwrap_or_else(|error| panic!("{path}: {error}"));
+    let font = Font::parse(&data).unwrap_or_else(|problem| panic!("{path} {problem}"));
+    let scale = EM_PIXELS / font.units_per_em;
+    let mut seen = HashSet::new();
+    let glyphs = characters
+        .chars()
+        .filter(|&c| seen.insert(c))
+        .filter_map(|c| Some((c, font.glyph(c)?)))
+        .map(|(c, glyph)| {
+            let contours = font
+                .outline(glyph, 0)
+                .unwrap_or_else(|| panic!("{path} has a broken glyph for {c:?}"));
+            (c, contours)
+        })
+        .filter(|(_, contours)| !contours.is_empty())
+        .map(|(c, contours)| {
+            let (w, h, alpha) = rasterize(&contours, scale)
+                .unwrap_or_else(|| panic!("{path} has a glyph for {c:?} too big to be right"));
+            Stamp::new(w, h, alpha)
+        })
+        .collect::<Vec<_>>();
+    if glyphs.is_empty() {
+        panic!("{path} doesn't have any of the glyphs to use as stamps");
+    }
+    // mirror writing doesn't read as letters anymore
+    stamps::install(glyphs, false);
+}
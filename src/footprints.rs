@@ -0,0 +1,153 @@
+//! The pixels covered by each accepted shape, kept along with it so that repainting around a
+//! shape (to take it out, or give it a new color) doesn't mean rasterizing every shape near it all
+//! over again. A footprint is stored as its runs of pixels down each column, with every number
+//! taking as few bytes as it needs, which is a lot smaller than the coordinates themselves
+//!
+//! Footprints are kept in memory up to a budget, and after that get appended to a spill file if
+//! there is one. Without one, the footprints that don't fit don't get kept at all, and their
+//! shapes get rasterized again whenever they're needed, like before there were footprints
+
+use crate::coord::Coord;
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+};
+
+/// Where a shape's footprint ended up
+pub enum Footprint {
+    Resident(Vec<u8>),
+    Spilled { offset: u64, len: usize },
+    Missing,
+}
+
+pub struct Footprints {
+    budget: usize,
+    resident: usize,
+    /// The spill file and how much has been written to it. Space taken up by footprints that are
+    /// gone isn't reused, since shapes rarely get taken out compared to how many get added
+    spill: Option<(File, u64)>,
+}
+
+fn write_number(bytes: &mut Vec<u8>, mut number: usize) {
+    while number >= 0x80 {
+        bytes.push(number as u8 | 0x80);
+        number >>= 7;
+    }
+    bytes.push(number as u8);
+}
+
+fn read_number(bytes: &mut impl Iterator<Item = u8>) -> Option<usize> {
+    let mut number = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes.next()?;
+        number |= ((byte & 0x7f) as usize) << shift;
+        if byte < 0x80 {
+            return Some(number);
+        }
+        shift += 7;
+    }
+}
+
+/// The coordinates as runs down each column, each one the distance from the previous run's column,
+/// the row it starts at, and how long it is
+fn encode(coords: &[Coord]) -> Vec<u8> {
+    let mut coords = coords.to_vec();
+    coords.sort_unstable();
+    coords.dedup();
+    let mut bytes = Vec::new();
+    let mut last_x = 0;
+    let mut i = 0;
+    while i < coords.len() {
+        let Coord { x, y } = coords[i];
+        let mut len = 1;
+        while coords.get(i + len) == Some(&Coord::new(x, y + len)) {
+            len += 1;
+        }
+        write_number(&mut bytes, x - last_x);
+        write_number(&mut bytes, y);
+        write_number(&mut bytes, len);
+        last_x = x;
+        i += len;
+    }
+    bytes
+}
+
+/// The coordinates that got encoded, in column-major order
+fn decode(bytes: &[u8]) -> Vec<Coord> {
+    let mut bytes = bytes.iter().copied();
+    let mut coords = Vec::new();
+    let mut x = 0;
+    while let Some(dx) = read_number(&mut bytes) {
+        x += dx;
+        let y = read_number(&mut bytes).unwrap();
+        let len = read_number(&mut bytes).unwrap();
+        coords.extend((y..y + len).map(|y| Coord::new(x, y)));
+    }
+    coords
+}
+
+impl Footprints {
+    /// Keeps up to `budget` bytes of footprints in memory, and the rest in a spill file created at
+    /// `spill` (if given), which gets overwritten if it's already there
+    pub fn new(budget: usize, spill: Option<&str>) -> Footprints {
+        Footprints {
+            budget,
+            resident: 0,
+            spill: spill.map(|path| {
+                (
+                    File::options()
+                        .read(true)
+                        .write(true)
+                        .create(true)
+                        .truncate(true)
+                        .open(path)
+                        .unwrap(),
+                    0,
+                )
+            }),
+        }
+    }
+
+    /// Footprint of a shape covering the given pixels
+    pub fn store(&mut self, coords: &[Coord]) -> Footprint {
+        let bytes = encode(coords);
+        if self.resident + bytes.len() <= self.budget {
+            self.resident += bytes.len();
+            return Footprint::Resident(bytes);
+        }
+        let Some((ref mut file, ref mut end)) = self.spill else {
+            return Footprint::Missing;
+        };
+        file.seek(SeekFrom::Start(*end)).unwrap();
+        file.write_all(&bytes).unwrap();
+        let offset = *end;
+        *end += bytes.len() as u64;
+        Footprint::Spilled {
+            offset,
+            len: bytes.len(),
+        }
+    }
+
+    /// Pixels covered by the footprint's shape, or `None` if the footprint wasn't kept
+    pub fn load(&self, footprint: &Footprint) -> Option<Vec<Coord>> {
+        match *footprint {
+            Footprint::Resident(ref bytes) => Some(decode(bytes)),
+            Footprint::Spilled { offset, len } => {
+                let mut file = &self.spill.as_ref().unwrap().0;
+                let mut bytes = vec![0; len];
+                file.seek(SeekFrom::Start(offset)).unwrap();
+                file.read_exact(&mut bytes).unwrap();
+                Some(decode(&bytes))
+            }
+            Footprint::Missing => None,
+        }
+    }
+
+    /// Lets go of a footprint whose shape is gone, making room in memory for another one
+    pub fn free(&mut self, footprint: Footprint) {
+        if let Footprint::Resident(bytes) = footprint {
+            self.resident -= bytes.len();
+        }
+    }
+}
@@ -0,0 +1,40 @@
+//! Numbered frames for `--frame-digits`, so the snapshots `--progressive-output` takes of the image
+//! annealed so far pile up into an image sequence instead of overwriting each other. Frames get
+//! numbered one after another without any gaps, which is what ffmpeg and video editors expect of
+//! a sequence, wherever the numbering starts from
+
+use std::path::Path;
+
+/// How snapshots get turned into numbered frames
+#[derive(Clone, Copy)]
+pub struct Frames {
+    /// The number the first frame gets
+    pub start: u64,
+    /// How many digits frame numbers get padded out to with zeros
+    pub digits: usize,
+    /// How many snapshots get left out after each one that's saved as a frame
+    pub skip: u64,
+}
+
+impl Frames {
+    /// Where the `snapshot`th snapshot (counting from 0) gets saved, next to `output` with the frame
+    /// number after its name, or `None` if it's one of the ones left out
+    pub fn path(&self, output: &str, snapshot: u64) -> Option<String> {
+        if !snapshot.is_multiple_of(self.skip + 1) {
+            return None;
+        }
+        let number = self.start + snapshot / (self.skip + 1);
+        let output = Path::new(output);
+        let stem = output.file_stem().unwrap().to_string_lossy();
+        // the output's format goes by its extension, so every frame keeps it
+        let name = match output.extension() {
+            Some(extension) => format!(
+                "{stem}_{number:0digits$}.{}",
+                extension.to_string_lossy(),
+                digits = self.digits
+            ),
+            None => format!("{stem}_{number:0digits$}", digits = self.digits),
+        };
+        Some(output.with_file_name(name).to_string_lossy().into_owned())
+    }
+}
@@ -0,0 +1,333 @@
+//! The checks every shape goes through before it gets rasterized, wherever it came from, so the
+//! rasterizers only ever see shapes that cover some area and fit in the image. Triangles also get
+//! put into one canonical vertex order, so the same triangle always looks the same (to the raster
+//! cache, the journal, and anything else comparing shapes), and so do polygons, which also get
+//! turned into their convex hull
+//!
+//! Ellipses are kept as the box they're inscribed in, which gets checked the same as a rectangle.
+//! Blobs are kept as their control points, which the curve never leaves the hull of, so they only
+//! need checking themselves. Stamps get clipped to the image, so only their middle has to be in it
+
+use crate::{
+    stamps::{self, Placement},
+    Shape, ShapeKind,
+};
+
+/// How many points go around each rounded end of a stroke
+const CAP_POINTS: usize = 4;
+
+/// Most vertices a polygon can have. Polygons keep them in an array this big, so that shapes can
+/// be copied around like the fixed-size things they otherwise are
+pub const MAX_VERTICES: usize = 12;
+
+/// A polygon's vertices, only the first `len` of which are used (the rest are left at 0 so equal
+/// polygons compare and hash the same)
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Polygon {
+    vertices: [(usize, usize); MAX_VERTICES],
+    len: usize,
+}
+
+impl Polygon {
+    pub fn new(vertices: &[(usize, usize)]) -> Polygon {
+        if vertices.len() > MAX_VERTICES {
+            panic!("polygons can have at most {MAX_VERTICES} vertices");
+        }
+        let mut polygon = Polygon {
+            vertices: [(0, 0); MAX_VERTICES],
+            len: vertices.len(),
+        };
+        polygon.vertices[..vertices.len()].copy_from_slice(vertices);
+        polygon
+    }
+
+    pub fn vertices(&self) -> &[(usize, usize)] {
+        &self.vertices[..self.len]
+    }
+}
+
+/// How many control points blobs get proposed with, at the least and the most
+pub const MIN_BLOB_POINTS: usize = 3;
+pub const MAX_BLOB_POINTS: usize = 5;
+
+/// Twice the signed area of a triangle, positive when its vertices go clockwise on screen
+fn doubled_area([a, b, c]: [(usize, usize); 3]) -> i128 {
+    let [a, b, c] = [a, b, c].map(|(x, y)| (x as i128, y as i128));
+    (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)
+}
+
+/// The same triangle with its vertices going clockwise on screen, starting from the top one
+/// (the leftmost of those, if there's a tie)
+pub fn canonical_triangle(vertices: [(usize, usize); 3]) -> [(usize, usize); 3] {
+    let [a, b, c] = vertices;
+    let clockwise = if doubled_area(vertices) < 0 {
+        [a, c, b]
+    } else {
+        vertices
+    };
+    let first = (0..3)
+        .min_by_key(|&i| (clockwise[i].1, clockwise[i].0))
+        .unwrap();
+    [0, 1, 2].map(|i| clockwise[(first + i) % 3])
+}
+
+/// Convex hull of the points, going clockwise on screen around them and leaving out any points
+/// that lie on its edges
+pub fn convex_hull(mut points: Vec<(i64, i64)>) -> Vec<(i64, i64)> {
+    points.sort_unstable();
+    points.dedup();
+    if points.len() < 3 {
+        return points;
+    }
+    // widened so that points far apart can't overflow it
+    let cross = |o: (i64, i64), a: (i64, i64), b: (i64, i64)| {
+        let [o, a, b] = [o, a, b].map(|(x, y)| (x as i128, y as i128));
+        (a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0)
+    };
+    // Andrew's monotone chain, the lower half and then the upper half
+    let reversed = points.iter().rev().copied().collect::<Vec<_>>();
+    let mut hull: Vec<(i64, i64)> = Vec::new();
+    for ordered in [&points, &reversed] {
+        let start = hull.len();
+        for &point in ordered {
+            while hull.len() >= start + 2
+                && cross(hull[hull.len() - 2], hull[hull.len() - 1], point) <= 0
+            {
+                hull.pop();
+            }
+            hull.push(point);
+        }
+        hull.pop();
+    }
+    hull
+}
+
+/// The convex hull of the polygon, going clockwise on screen from its top vertex (the leftmost of
+/// those, if there's a tie) like a canonical triangle
+pub fn canonical_polygon(polygon: Polygon) -> Polygon {
+    let points = polygon
+        .vertices()
+        .iter()
+        .map(|&(x, y)| (x as i64, y as i64))
+        .collect();
+    let hull = convex_hull(points);
+    let first = (0..hull.len())
+        .min_by_key(|&i| (hull[i].1, hull[i].0))
+        .unwrap_or(0);
+    let vertices = (0..hull.len())
+        .map(|i| hull[(first + i) % hull.len()])
+        .map(|(x, y)| (x as usize, y as usize))
+        .collect::<Vec<_>>();
+    Polygon::new(&vertices)
+}
+
+/// The same blob starting from its top control point (the leftmost of those, if there's a tie),
+/// going clockwise on screen. Neither changes the curve, since it's the same all the way around
+/// either way
+pub fn canonical_blob(points: Polygon) -> Polygon {
+    let mut points = points.vertices().to_vec();
+    let n = points.len();
+    let doubled_area = (0..n)
+        .map(|i| {
+            let ((x1, y1), (x2, y2)) = (points[i], points[(i + 1) % n]);
+            x1 as i128 * y2 as i128 - x2 as i128 * y1 as i128
+        })
+        .sum::<i128>();
+    if doubled_area < 0 {
+        points.reverse();
+    }
+    let first = (0..n)
+        .min_by_key(|&i| (points[i].1, points[i].0))
+        .unwrap_or(0);
+    points.rotate_left(first);
+    Polygon::new(&points)
+}
+
+/// Points along a blob's curve, for filling it (or drawing it) as a polygon. Each segment is a
+/// quadratic Bézier curve from the middle of one side of the control polygon to the middle of the
+/// next, pulled towards the control point between them, so the curve is smooth all the way around
+pub fn blob_curve(points: &[(usize, usize)]) -> Vec<(f64, f64)> {
+    let n = points.len();
+    let point = |i: usize| (points[i % n].0 as f64, points[i % n].1 as f64);
+    let middle = |a: (f64, f64), b: (f64, f64)| ((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0);
+    (0..n)
+        .flat_map(|i| {
+            let (start, control, end) = (
+                middle(point(i), point(i + 1)),
+                point(i + 1),
+                middle(point(i + 1), point(i + 2)),
+            );
+            // longer segments get more points so the curve stays smooth
+            let length = (control.0 - start.0).hypot(control.1 - start.1)
+                + (end.0 - control.0).hypot(end.1 - control.1);
+            let steps = (length / 4.0).ceil().clamp(2.0, 16.0) as usize;
+            (0..steps).map(move |step| {
+                let t = step as f64 / steps as f64;
+                let (a, b, c) = ((1.0 - t) * (1.0 - t), 2.0 * (1.0 - t) * t, t * t);
+                (
+                    a * start.0 + b * control.0 + c * end.0,
+                    a * start.1 + b * control.1 + c * end.1,
+                )
+            })
+        })
+        .collect()
+}
+
+/// The shape in its canonical form if it covers some area and lies within a `w` by `h` image,
+/// or `None` if it's not fit to be rasterized. With `tileable`, shapes can hang off the right and
+/// bottom edges since they wrap around, but no further than another image's width or height
+pub fn validate(shape: Shape, w: usize, h: usize, tileable: bool) -> Option<Shape> {
+    // bounds get checked before anything else, so nothing works out areas from coordinates that
+    // could be anything
+    let (w, h) = if tileable { (2 * w, 2 * h) } else { (w, h) };
+    let inside = |vertices: &[(usize, usize)]| vertices.iter().all(|&(x, y)| x < w && y < h);
+    match shape {
+        Shape::Rectangle {
+            top_left,
+            bottom_right,
+        }
+        | Shape::Ellipse {
+            top_left,
+            bottom_right,
+        } => {
+            let has_area = top_left.0 < bottom_right.0 && top_left.1 < bottom_right.1;
+            let in_bounds = bottom_right.0 <= w && bottom_right.1 <= h;
+            (has_area && in_bounds).then_some(shape)
+        }
+        Shape::Triangle(vertices) => {
+            let has_area = || doubled_area(vertices) != 0;
+            (inside(&vertices) && has_area()).then(|| Shape::Triangle(canonical_triangle(vertices)))
+        }
+        Shape::Polygon(polygon) => {
+            if !inside(polygon.vertices()) {
+                return None;
+            }
+            // anything straight or all in a line loses vertices to the hull
+            let polygon = canonical_polygon(polygon);
+            (polygon.vertices().len() >= 3).then_some(Shape::Polygon(polygon))
+        }
+        Shape::Blob(points) => {
+            if !inside(points.vertices()) {
+                return None;
+            }
+            let points = canonical_blob(points);
+            (canonical_polygon(points).vertices().len() >= 3).then_some(Shape::Blob(points))
+        }
+        Shape::Stamp(placement) => {
+            let has_area = placement.size > 0 && placement.index < stamps::count();
+            let in_bounds = placement.center.0 < w && placement.center.1 < h;
+            (has_area && in_bounds).then_some(shape)
+        }
+    }
+}
+
+/// Points around the ellipse inscribed in the box from `top_left` up to (but not including)
+/// `bottom_right`, going clockwise on screen, for drawing it as a polygon. Bigger ellipses get more
+/// points so their outlines stay smooth
+pub fn ellipse_polygon(top_left: (usize, usize), bottom_right: (usize, usize)) -> Vec<(f64, f64)> {
+    let (cx, cy) = (
+        (top_left.0 + bottom_right.0) as f64 / 2.0,
+        (top_left.1 + bottom_right.1) as f64 / 2.0,
+    );
+    let (rx, ry) = (cx - top_left.0 as f64, cy - top_left.1 as f64);
+    let points = (4.0 * (rx + ry)).ceil().clamp(8.0, 64.0) as usize;
+    (0..points)
+        .map(|i| {
+            let angle = i as f64 / points as f64 * std::f64::consts::TAU;
+            (cx + rx * angle.cos(), cy + ry * angle.sin())
+        })
+        .collect()
+}
+
+/// The part of a convex polygon between 0 and `right` across and between 0 and `bottom` down,
+/// clipping off one side of the box at a time. Whatever's left is still convex, with up to 4 more
+/// vertices than it started with
+pub fn clip_polygon(points: &[(f64, f64)], right: f64, bottom: f64) -> Vec<(f64, f64)> {
+    // each side as how far inside of it a point is
+    let sides: [&dyn Fn((f64, f64)) -> f64; 4] =
+        [&|(x, _)| x, &|(x, _)| right - x, &|(_, y)| y, &|(_, y)| {
+            bottom - y
+        }];
+    let mut points = points.to_vec();
+    for inside in sides {
+        let mut clipped = Vec::new();
+        for (i, &start) in points.iter().enumerate() {
+            let end = points[(i + 1) % points.len()];
+            let (a, b) = (inside(start), inside(end));
+            if a >= 0.0 {
+                clipped.push(start);
+            }
+            if (a >= 0.0) != (b >= 0.0) {
+                let t = a / (a - b);
+                clipped.push((
+                    start.0 + (end.0 - start.0) * t,
+                    start.1 + (end.1 - start.1) * t,
+                ));
+            }
+        }
+        points = clipped;
+    }
+    points
+}
+
+/// A line from `start` to `end`, `width` across, with its ends rounded off, as a convex polygon
+/// going around it. Each end is a half circle of `CAP_POINTS` points, which leaves room for clipping
+/// to add 4 more without going over `MAX_VERTICES`
+pub fn capsule(start: (f64, f64), end: (f64, f64), width: f64) -> Vec<(f64, f64)> {
+    let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+    let length = dx.hypot(dy);
+    // a line with no length is just a round dot, so any way along it will do
+    let (dx, dy) = if length > 0.0 {
+        (dx / length, dy / length)
+    } else {
+        (1.0, 0.0)
+    };
+    let radius = width / 2.0;
+    let cap = |(x, y): (f64, f64), forward: f64| {
+        (0..CAP_POINTS).map(move |i| {
+            // from one side of the line around the end to the other side
+            let angle = std::f64::consts::PI * i as f64 / (CAP_POINTS - 1) as f64;
+            let (along, across) = (angle.sin() * forward, angle.cos() * forward);
+            (
+                x + radius * (along * dx - across * dy),
+                y + radius * (along * dy + across * dx),
+            )
+        })
+    };
+    cap(end, 1.0).chain(cap(start, -1.0)).collect()
+}
+
+/// A shape of a kind that fills a box (a rectangle, or the ellipse inscribed in it) from `top_left`
+/// up to (but not including) `bottom_right`. Circles get the box squared off by its shorter side,
+/// keeping its top left corner, rotated rectangles are just the box, not turned at all, and stamps
+/// are a random one of them as big as the box, the right way up
+pub fn boxed(kind: ShapeKind, top_left: (usize, usize), bottom_right: (usize, usize)) -> Shape {
+    match kind {
+        ShapeKind::Rectangle | ShapeKind::RotatedRectangle => Shape::Rectangle {
+            top_left,
+            bottom_right,
+        },
+        ShapeKind::Triangle | ShapeKind::Polygon | ShapeKind::Blob | ShapeKind::Stroke => {
+            panic!("only rectangles and ellipses fill a box")
+        }
+        ShapeKind::Ellipse => Shape::Ellipse {
+            top_left,
+            bottom_right,
+        },
+        ShapeKind::Circle => {
+            let side = bottom_right
+                .0
+                .saturating_sub(top_left.0)
+                .min(bottom_right.1.saturating_sub(top_left.1));
+            Shape::Ellipse {
+                top_left,
+                bottom_right: (top_left.0 + side, top_left.1 + side),
+            }
+        }
+        ShapeKind::Stamp => Shape::Stamp(Placement {
+            angle: 0,
+            flipped: false,
+            ..Placement::random(top_left, bottom_right)
+        }),
+    }
+}
@@ -0,0 +1,267 @@
+//! Hatching for `--hatch`, which draws the image as engraving-style strokes on white paper instead
+//! of annealing shapes. The strokes only go at a few angles, spread evenly from 45 degrees, and
+//! taper to a point like an engraver's cuts. Where they go (and so how closely they're spaced), how
+//! long they are, and how dark they are all get annealed until the blurred ink matches the image.
+//! Every stroke darkens what's under it, so the dark parts end up cross-hatched
+
+use crate::rng::{acceptance_random, random};
+use crate::{
+    coord::Pixels,
+    geometry,
+    ink::{self, Ink},
+    target::Target,
+    Blend, BlendMode, Fill, Image, Shape,
+};
+use image::Rgb;
+use std::f64::consts::PI;
+
+/// How far the ink gets blurred every way, in pixels. It has to take in a few strokes at once to
+/// tell how densely they're packed
+const BLUR_REACH: usize = 4;
+
+/// How wide strokes are at their blunt end, in pixels
+const STROKE_WIDTH: f64 = 3.0;
+
+const MIN_LENGTH: f64 = 4.0;
+
+/// Longest strokes, as a fraction of the image's shorter side
+const MAX_LENGTH: f64 = 0.15;
+
+/// Lightest and darkest strokes, as the fraction of the light under them they take away
+const MIN_DARKNESS: f64 = 0.2;
+const MAX_DARKNESS: f64 = 0.9;
+
+/// Temperature the strokes start annealing at, in pixels' worth of ink
+const INITIAL_TEMP: f64 = 4.0;
+
+const FINAL_TEMP: f64 = 0.004;
+
+/// How many moves get tried at every temperature, one for every this many pixels of the image
+const PIXELS_PER_MOVE: usize = 1024;
+
+/// Fractions of the moves that add a stroke and that remove one. The rest change one a bit
+const ADD_MOVES: f64 = 0.2;
+const REMOVE_MOVES: f64 = 0.1;
+
+#[derive(Clone, Copy)]
+struct Stroke {
+    center: (f64, f64),
+    /// Which of the angles it goes at
+    angle: usize,
+    length: f64,
+    /// The gray it multiplies what's under it by
+    gray: u8,
+}
+
+struct Hatching {
+    w: usize,
+    h: usize,
+    /// Which way each angle goes, as a unit vector
+    directions: Vec<(f64, f64)>,
+    max_length: f64,
+    ink: Ink,
+    strokes: Vec<(Stroke, Shape)>,
+    /// Log of the fraction of the light that gets through the strokes at each pixel (column-major)
+    light: Vec<f64>,
+}
+
+impl Hatching {
+    /// The triangle the stroke gets drawn as, if it fits in the image
+    fn shape(&self, stroke: Stroke) -> Option<Shape> {
+        let (dx, dy) = self.directions[stroke.angle];
+        let (cx, cy) = stroke.center;
+        let half = stroke.length / 2.0;
+        let (nx, ny) = (-dy * STROKE_WIDTH / 2.0, dx * STROKE_WIDTH / 2.0);
+        let vertex = |x: f64, y: f64| {
+            let (x, y) = (x.round(), y.round());
+            (x >= 0.0 && y >= 0.0).then_some((x as usize, y as usize))
+        };
+        let (bx, by) = (cx - dx * half, cy - dy * half);
+        let vertices = [
+            vertex(bx + nx, by + ny)?,
+            vertex(bx - nx, by - ny)?,
+            vertex(cx + dx * half, cy + dy * half)?,
+        ];
+        geometry::validate(Shape::Triangle(vertices), self.w, self.h, false)
+    }
+
+    /// A stroke anywhere in the image, at any of the angles
+    fn random_stroke(&self) -> Stroke {
+        let darkness = MIN_DARKNESS + random::<f64>() * (MAX_DARKNESS - MIN_DARKNESS);
+        Stroke {
+            center: (
+                random::<f64>() * self.w as f64,
+                random::<f64>() * self.h as f64,
+            ),
+            angle: random::<usize>() % self.directions.len(),
+            length: MIN_LENGTH + random::<f64>() * (self.max_length - MIN_LENGTH),
+            gray: gray(darkness),
+        }
+    }
+
+    /// The stroke with one thing about it changed, by less and less as `progress` goes from 0 to 1
+    fn tweaked(&self, stroke: Stroke, progress: f64) -> Stroke {
+        // it never gets all the way down to no change at all, or the last moves would be wasted
+        let nudge = |reach: f64, least: f64| {
+            (random::<f64>() * 2.0 - 1.0) * (reach * (1.0 - progress)).max(least)
+        };
+        let mut stroke = stroke;
+        match random::<usize>() % 4 {
+            0 => {
+                stroke.center.0 += nudge(BLUR_REACH as f64, 1.0);
+                stroke.center.1 += nudge(BLUR_REACH as f64, 1.0);
+            }
+            1 => {
+                stroke.length =
+                    (stroke.length * (1.0 + nudge(0.5, 0.05))).clamp(MIN_LENGTH, self.max_length)
+            }
+            2 => {
+                let darkness = 1.0 - stroke.gray as f64 / 255.0;
+                let darkness = (darkness + nudge(0.3, 0.02)).clamp(MIN_DARKNESS, MAX_DARKNESS);
+                stroke.gray = gray(darkness);
+            }
+            _ => stroke.angle = random::<usize>() % self.directions.len(),
+        }
+        stroke
+    }
+
+    /// Tries taking away the stroke at `removed` and putting in `added` (along with its shape),
+    /// keeping the change if `accept` says so about the change in cost
+    fn try_change(
+        &mut self,
+        removed: Option<usize>,
+        added: Option<(Stroke, Shape)>,
+        accept: impl FnOnce(f64) -> bool,
+    ) {
+        let light = |(stroke, shape): &(Stroke, Shape), sign: f64| {
+            let through = (stroke.gray as f64 / 255.0).ln() * sign;
+            shape
+                .coords(self.w, self.h)
+                .into_iter()
+                .map(move |coord| (coord, through))
+        };
+        let mut changes = removed
+            .map(|index| light(&self.strokes[index], -1.0))
+            .into_iter()
+            .flatten()
+            .chain(added.iter().flat_map(|added| light(added, 1.0)))
+            .collect::<Vec<_>>();
+        changes.sort_unstable_by_key(|&(coord, _)| coord);
+        let changes = changes
+            .chunk_by(|a, b| a.0 == b.0)
+            .map(|chunk| {
+                (
+                    chunk[0].0,
+                    chunk.iter().map(|&(_, change)| change).sum::<f64>(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let ink_changes = changes
+            .iter()
+            .map(|&(coord, change)| {
+                let light = self.light[coord.index(self.h)];
+                (coord, light.exp() - (light + change).exp())
+            })
+            .collect::<Vec<_>>();
+        if !self.ink.try_change(&ink_changes, accept) {
+            return;
+        }
+        for &(coord, change) in &changes {
+            self.light[coord.index(self.h)] += change;
+        }
+        match (removed, added) {
+            (Some(index), Some(added)) => self.strokes[index] = added,
+            (Some(index), None) => {
+                self.strokes.swap_remove(index);
+            }
+            (None, Some(added)) => self.strokes.push(added),
+            (None, None) => (),
+        }
+    }
+}
+
+/// The gray that takes away `darkness` of the light under it
+fn gray(darkness: f64) -> u8 {
+    (255.0 * (1.0 - darkness)).round() as u8
+}
+
+/// Hatches `target` with strokes at `angles` different angles, cooling down by `alpha` after every
+/// round of moves. Returns the hatched image along with its shapes: the paper, then every stroke
+pub fn hatch(target: &Target, angles: usize, alpha: f64) -> (Image, Vec<(Shape, Fill, BlendMode)>) {
+    let (w, h) = (target.width(), target.height());
+    if angles == 0 {
+        panic!("strokes need at least 1 angle to go at");
+    }
+    let max_length = (MAX_LENGTH * w.min(h) as f64).max(MIN_LENGTH);
+    let mut hatching = Hatching {
+        w,
+        h,
+        directions: (0..angles)
+            .map(|i| PI / 4.0 + PI * i as f64 / angles as f64)
+            .map(|angle| (angle.cos(), angle.sin()))
+            .collect(),
+        max_length,
+        ink: Ink::new(target, BLUR_REACH),
+        strokes: Vec::new(),
+        light: vec![0.0; w * h],
+    };
+    let moves = (w * h).div_ceil(PIXELS_PER_MOVE);
+    let steps = (FINAL_TEMP / INITIAL_TEMP).ln() / alpha.ln();
+    let mut temp = INITIAL_TEMP;
+    let mut step = 0;
+    while temp >= FINAL_TEMP {
+        let progress = step as f64 / steps;
+        for _ in 0..moves {
+            let accept =
+                |diff: f64| diff < 0.0 || acceptance_random::<f64>() < (-diff / temp).exp();
+            let kind = random::<f64>();
+            if kind < ADD_MOVES || hatching.strokes.is_empty() {
+                let stroke = hatching.random_stroke();
+                if let Some(shape) = hatching.shape(stroke) {
+                    hatching.try_change(None, Some((stroke, shape)), accept);
+                }
+            } else if kind < ADD_MOVES + REMOVE_MOVES {
+                let index = random::<usize>() % hatching.strokes.len();
+                hatching.try_change(Some(index), None, accept);
+            } else {
+                let index = random::<usize>() % hatching.strokes.len();
+                let stroke = hatching.tweaked(hatching.strokes[index].0, progress);
+                if let Some(shape) = hatching.shape(stroke) {
+                    hatching.try_change(Some(index), Some((stroke, shape)), accept);
+                }
+            }
+        }
+        temp *= alpha;
+        step += 1;
+        if step % (steps as usize / 10).max(1) == 0 {
+            println!(
+                "hatching {:.0}% | strokes: {} | cost: {:.5}",
+                (progress * 100.0).min(100.0),
+                hatching.strokes.len(),
+                hatching.ink.mean_cost()
+            );
+        }
+    }
+    println!(
+        "hatched with {} strokes | cost: {:.5}",
+        hatching.strokes.len(),
+        hatching.ink.mean_cost()
+    );
+    let shapes = std::iter::once(ink::paper(w, h))
+        .chain(hatching.strokes.iter().map(|&(stroke, shape)| {
+            (
+                shape,
+                Fill::Solid(Rgb([stroke.gray; 3])),
+                BlendMode::opaque(Blend::Multiply),
+            )
+        }))
+        .collect::<Vec<_>>();
+    // painted the same way as the shapes would be, so the image matches its shape list exactly
+    let mut image = vec![vec![ink::PAPER; h]; w];
+    for &(shape, fill, blend_mode) in &shapes[1..] {
+        for coord in shape.coords(w, h) {
+            *image.at_mut(coord) = blend_mode.blend(image.at(coord), fill.color_at(coord));
+        }
+    }
+    (image, shapes)
+}
@@ -0,0 +1,148 @@
+//! Hand-drawn regions that steer where shapes get proposed, loaded from JSON like
+//!
+//! ```json
+//! {"regions": [
+//!   {"polygon": [[10, 10], [60, 10], [35, 50]], "priority": 4},
+//!   {"polygon": [[0, 80], [133, 80], [133, 100], [0, 100]], "priority": 0}
+//! ]}
+//! ```
+//!
+//! Polygon points are in pixels from the top left corner. Everywhere outside the regions has a
+//! priority of 1, and where regions overlap the one listed last wins. Shapes get proposed with a
+//! chance proportional to the average priority under them, so a priority of 0 excludes a region.
+//! The file is watched while annealing, so regions can be redrawn mid-run
+
+use crate::{coord::Coord, rng::random};
+use serde_json::Value;
+use std::{
+    fs::{metadata, read_to_string},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// How often the hints file gets checked for changes
+const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct Hints {
+    path: String,
+    w: usize,
+    h: usize,
+    /// Priority of each pixel, column-major like the images
+    priorities: Vec<f64>,
+    max_priority: f64,
+    modified: Option<SystemTime>,
+    last_check: Instant,
+}
+
+/// Whether `point` is inside `polygon`, going by the even-odd rule
+fn contains(polygon: &[(f64, f64)], (x, y): (f64, f64)) -> bool {
+    let mut inside = false;
+    for (i, &(x1, y1)) in polygon.iter().enumerate() {
+        let (x2, y2) = polygon[(i + 1) % polygon.len()];
+        if (y1 > y) != (y2 > y) && x < x1 + (y - y1) * (x2 - x1) / (y2 - y1) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+/// Priority of each pixel of a `w` by `h` image (column-major) going by the regions in `json`
+fn priorities(json: &str, w: usize, h: usize) -> Result<Vec<f64>, String> {
+    let json: Value = serde_json::from_str(json).map_err(|error| error.to_string())?;
+    let mut priorities = vec![1.0; w * h];
+    let regions = json["regions"]
+        .as_array()
+        .ok_or("hints need a list of regions")?;
+    for region in regions {
+        let polygon = region["polygon"]
+            .as_array()
+            .ok_or("every region needs a polygon")?
+            .iter()
+            .map(|point| match (point[0].as_f64(), point[1].as_f64()) {
+                (Some(x), Some(y)) => Ok((x, y)),
+                _ => Err("points are [x, y]"),
+            })
+            .collect::<Result<Vec<(f64, f64)>, _>>()?;
+        let priority = region["priority"]
+            .as_f64()
+            .ok_or("every region needs a priority")?;
+        if polygon.len() < 3 {
+            return Err("region polygons need at least 3 points".to_string());
+        }
+        if priority < 0.0 {
+            return Err("region priorities can't be negative".to_string());
+        }
+        for x in 0..w {
+            for y in 0..h {
+                // pixels count as inside if their centers are
+                if contains(&polygon, (x as f64 + 0.5, y as f64 + 0.5)) {
+                    priorities[x * h + y] = priority;
+                }
+            }
+        }
+    }
+    if priorities.iter().all(|&priority| priority == 0.0) {
+        return Err("hints exclude the whole image".to_string());
+    }
+    Ok(priorities)
+}
+
+/// When the file was last changed, if that can be told
+fn modified(path: &str) -> Option<SystemTime> {
+    metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+impl Hints {
+    /// Loads the regions in the JSON file at `path` over a `w` by `h` image
+    pub fn load(path: &str, w: usize, h: usize) -> Hints {
+        let priorities = priorities(&read_to_string(path).unwrap(), w, h)
+            .unwrap_or_else(|error| panic!("{error}"));
+        Hints {
+            path: path.to_string(),
+            w,
+            h,
+            max_priority: priorities.iter().copied().fold(0.0, f64::max),
+            priorities,
+            modified: modified(path),
+            last_check: Instant::now(),
+        }
+    }
+
+    /// Loads the regions again if the file has changed since they were last loaded,
+    /// so they can be edited while annealing. Edits that don't make sense are ignored
+    pub fn reload_if_changed(&mut self) {
+        if self.last_check.elapsed() < RELOAD_INTERVAL {
+            return;
+        }
+        self.last_check = Instant::now();
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return;
+        }
+        self.modified = modified;
+        let reloaded = read_to_string(&self.path)
+            .map_err(|error| error.to_string())
+            .and_then(|json| priorities(&json, self.w, self.h));
+        match reloaded {
+            Ok(priorities) => {
+                self.max_priority = priorities.iter().copied().fold(0.0, f64::max);
+                self.priorities = priorities;
+                println!("\nreloaded hints from {}", self.path);
+            }
+            Err(error) => println!("\nkeeping the old hints, couldn't reload them: {error}"),
+        }
+    }
+
+    /// Randomly decides whether to propose a shape covering the given coordinates,
+    /// more likely the higher the average priority under it
+    pub fn accepts(&self, coords: &[Coord]) -> bool {
+        if coords.is_empty() {
+            return true;
+        }
+        let priority = coords
+            .iter()
+            .map(|coord| self.priorities[coord.index(self.h)])
+            .sum::<f64>()
+            / coords.len() as f64;
+        random::<f64>() * self.max_priority < priority
+    }
+}
@@ -0,0 +1,167 @@
+//! Ink on white paper, compared with the darkness of the original image with both of them blurred,
+//! for stippling and hatching. Neither can match the image pixel for pixel, since every pixel is
+//! either inked or not (or as dark as a stroke), and they only look right from far enough away that
+//! the ink blurs together. So the cost is how far the blurred ink is off the blurred darkness, which
+//! only goes down when the ink gets about as dense as the image is dark all over
+
+use crate::{coord::Coord, target::Target, BlendMode, Fill, Shape};
+use image::Rgb;
+use std::ops::Range;
+
+pub const PAPER: Rgb<u8> = Rgb([255, 255, 255]);
+
+/// A box blur over a column-major field, which only averages what's inside the image near the
+/// edges
+struct Blur {
+    w: usize,
+    h: usize,
+    reach: usize,
+}
+
+impl Blur {
+    /// Columns and rows that get averaged into the blurred value at `coord`. The other way around,
+    /// they're also the pixels that `coord` gets averaged into
+    fn window(&self, coord: Coord) -> (Range<usize>, Range<usize>) {
+        (
+            coord.x.saturating_sub(self.reach)..(coord.x + self.reach + 1).min(self.w),
+            coord.y.saturating_sub(self.reach)..(coord.y + self.reach + 1).min(self.h),
+        )
+    }
+
+    fn area(&self, coord: Coord) -> f64 {
+        let (xs, ys) = self.window(coord);
+        (xs.len() * ys.len()) as f64
+    }
+
+    /// A field that only covers columns `xs` and rows `ys` of the image (column-major, and nothing
+    /// everywhere else) blurred over the same part of the image, going by a summed-area table
+    fn apply(&self, field: &[f64], xs: Range<usize>, ys: Range<usize>) -> Vec<f64> {
+        let (w, h) = (xs.len(), ys.len());
+        let mut sums = vec![0.0; (w + 1) * (h + 1)];
+        for x in 0..w {
+            for y in 0..h {
+                sums[(x + 1) * (h + 1) + y + 1] =
+                    field[x * h + y] + sums[x * (h + 1) + y + 1] + sums[(x + 1) * (h + 1) + y]
+                        - sums[x * (h + 1) + y];
+            }
+        }
+        let clip = |range: Range<usize>, within: &Range<usize>| {
+            range.start.max(within.start) - within.start..range.end.min(within.end) - within.start
+        };
+        xs.clone()
+            .flat_map(|x| ys.clone().map(move |y| Coord::new(x, y)))
+            .map(|coord| {
+                let (window_xs, window_ys) = self.window(coord);
+                let (window_xs, window_ys) = (clip(window_xs, &xs), clip(window_ys, &ys));
+                let sums = |x: usize, y: usize| sums[x * (h + 1) + y];
+                let sum = sums(window_xs.end, window_ys.end)
+                    - sums(window_xs.start, window_ys.end)
+                    - sums(window_xs.end, window_ys.start)
+                    + sums(window_xs.start, window_ys.start);
+                sum / self.area(coord)
+            })
+            .collect()
+    }
+}
+
+pub struct Ink {
+    h: usize,
+    blur: Blur,
+    /// How dark the ink makes each pixel (from 0 for paper to 1 for solid ink), blurred
+    ink: Vec<f64>,
+    /// The image's darkness, blurred
+    tone: Vec<f64>,
+    /// Sum of how far the ink is off the tone at every pixel
+    cost: f64,
+}
+
+impl Ink {
+    /// Blank paper for inking `target` on, blurred `reach` pixels every way when it gets compared
+    pub fn new(target: &Target, reach: usize) -> Ink {
+        let (w, h) = (target.width(), target.height());
+        let blur = Blur { w, h, reach };
+        let tone = blur.apply(&darkness(target), 0..w, 0..h);
+        Ink {
+            h,
+            blur,
+            ink: vec![0.0; w * h],
+            cost: tone.iter().sum(),
+            tone,
+        }
+    }
+
+    /// Tries changing how dark the given pixels are inked by the given amounts (every pixel listed
+    /// once), keeping the change if `accept` says so about the change in cost. Returns whether it's
+    /// kept
+    pub fn try_change(
+        &mut self,
+        changes: &[(Coord, f64)],
+        accept: impl FnOnce(f64) -> bool,
+    ) -> bool {
+        // the blurred ink only changes within the blur's reach of the changed pixels
+        let windows = changes
+            .iter()
+            .map(|&(coord, _)| self.blur.window(coord))
+            .collect::<Vec<_>>();
+        let bounds = |ranges: Vec<&Range<usize>>| {
+            let start = ranges.iter().map(|range| range.start).min();
+            let end = ranges.iter().map(|range| range.end).max();
+            start.unwrap_or(0)..end.unwrap_or(0)
+        };
+        let xs = bounds(windows.iter().map(|(xs, _)| xs).collect());
+        let ys = bounds(windows.iter().map(|(_, ys)| ys).collect());
+        let local = |coord: Coord| (coord.x - xs.start) * ys.len() + coord.y - ys.start;
+        let mut raw_changes = vec![0.0; xs.len() * ys.len()];
+        for &(coord, change) in changes {
+            raw_changes[local(coord)] += change;
+        }
+        let ink_changes = self.blur.apply(&raw_changes, xs.clone(), ys.clone());
+        let mut cost_diff = 0.0;
+        for x in xs.clone() {
+            for y in ys.clone() {
+                let coord = Coord::new(x, y);
+                let (i, change) = (coord.index(self.h), ink_changes[local(coord)]);
+                cost_diff += (self.ink[i] + change - self.tone[i]).abs()
+                    - (self.ink[i] - self.tone[i]).abs();
+            }
+        }
+        if !accept(cost_diff) {
+            return false;
+        }
+        for x in xs.clone() {
+            for y in ys.clone() {
+                let coord = Coord::new(x, y);
+                self.ink[coord.index(self.h)] += ink_changes[local(coord)];
+            }
+        }
+        self.cost += cost_diff;
+        true
+    }
+
+    /// How far the ink is off the tone on average, in levels of gray
+    pub fn mean_cost(&self) -> f64 {
+        self.cost / self.ink.len() as f64 * 255.0
+    }
+}
+
+/// How dark every pixel of `target` is (column-major), from 0 for white to 1 for black, going by
+/// its luma
+pub fn darkness(target: &Target) -> Vec<f64> {
+    (0..target.width())
+        .flat_map(|x| (0..target.height()).map(move |y| Coord::new(x, y)))
+        .map(|coord| {
+            let Rgb([r, g, b]) = target.pixel(coord);
+            1.0 - (0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64) / 255.0
+        })
+        .collect()
+}
+
+/// The paper as a white rectangle as big as a `w` by `h` image, for putting the ink on top of in
+/// shape lists and vector output
+pub fn paper(w: usize, h: usize) -> (Shape, Fill, BlendMode) {
+    let paper = Shape::Rectangle {
+        top_left: (0, 0),
+        bottom_right: (w, h),
+    };
+    (paper, Fill::Solid(PAPER), BlendMode::NORMAL)
+}
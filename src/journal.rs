@@ -0,0 +1,394 @@
+//! An append-only log of the accepted moves, so a killed run can be picked back up by replaying
+//! them. It's plain text, one line per move after a header with the image size:
+//!
+//! ```text
+//! anneal_image journal <width> <height>
+//! add <iteration> <blend mode> <r> <g> <b> rectangle <x1> <y1> <x2> <y2> [triangle ...]
+//! remove <iteration> <index of the removed add, counting from 0>
+//! recolor <iteration> <index of the recolored add> <r> <g> <b>
+//! replace <iteration> <index of the replaced add> <r> <g> <b> rectangle <x1> <y1> <x2> <y2> [...]
+//! ```
+//!
+//! Translucent adds write their opacity (out of 255) after the blend mode, like `multiply:128`.
+//! Shapes painted with a gradient have `gradient <r> <g> <b> <x1> <y1> <x2> <y2>` after their
+//! color, for the color at the other end and the pixels the gradient goes from and to.
+//! Stamps are written as `stamp <index> <x> <y> <size> <angle> <flipped>`, for the stamp's place in
+//! the order of the stamps' file names, the pixel in its middle, how many pixels its longer side is,
+//! how many degrees it's turned clockwise, and 1 if it's flipped over (0 if not).
+//! An add lists every mirrored copy of the shape one after the other. Writes are buffered and
+//! synced to disk every so often, so a crash loses at most the last few moves
+
+use crate::{
+    geometry::{Polygon, MAX_VERTICES},
+    stamps::Placement,
+    Blend, BlendMode, Fill, Shape,
+};
+use clap::ValueEnum;
+use image::Rgb;
+use std::{
+    fs::{read_to_string, File, OpenOptions},
+    io::{BufWriter, Seek, SeekFrom, Write},
+    time::{Duration, Instant},
+};
+
+/// How often the journal gets synced to disk
+const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A single accepted move
+pub enum JournalEntry {
+    Add {
+        iteration: u64,
+        shapes: Vec<Shape>,
+        fill: Fill,
+        blend_mode: BlendMode,
+    },
+    Remove {
+        iteration: u64,
+        index: usize,
+    },
+    Recolor {
+        iteration: u64,
+        index: usize,
+        fill: Fill,
+    },
+    Replace {
+        iteration: u64,
+        index: usize,
+        shapes: Vec<Shape>,
+        fill: Fill,
+    },
+}
+
+impl JournalEntry {
+    pub fn iteration(&self) -> u64 {
+        match *self {
+            JournalEntry::Add { iteration, .. }
+            | JournalEntry::Remove { iteration, .. }
+            | JournalEntry::Recolor { iteration, .. }
+            | JournalEntry::Replace { iteration, .. } => iteration,
+        }
+    }
+}
+
+pub struct Journal {
+    writer: BufWriter<File>,
+    last_sync: Instant,
+}
+
+/// Parses the shapes at the end of an `add` or `replace` line
+fn parse_shapes(words: &[&str]) -> Option<Vec<Shape>> {
+    let mut shapes = Vec::new();
+    let mut rest = words;
+    while let Some((&shape_kind, points)) = rest.split_first() {
+        let (count, points) = match shape_kind {
+            "rectangle" | "ellipse" => (4, points),
+            "triangle" | "stamp" => (6, points),
+            // polygons say how many vertices they have first, and blobs how many control points
+            "polygon" | "blob" => {
+                let (count, points) = points.split_first()?;
+                (
+                    count
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|&count| count <= MAX_VERTICES)?
+                        * 2,
+                    points,
+                )
+            }
+            _ => return None,
+        };
+        let numbers = points
+            .get(..count)?
+            .iter()
+            .map(|word| word.parse().ok())
+            .collect::<Option<Vec<usize>>>()?;
+        shapes.push(match shape_kind {
+            "rectangle" => Shape::Rectangle {
+                top_left: (numbers[0], numbers[1]),
+                bottom_right: (numbers[2], numbers[3]),
+            },
+            "ellipse" => Shape::Ellipse {
+                top_left: (numbers[0], numbers[1]),
+                bottom_right: (numbers[2], numbers[3]),
+            },
+            "triangle" => Shape::Triangle([
+                (numbers[0], numbers[1]),
+                (numbers[2], numbers[3]),
+                (numbers[4], numbers[5]),
+            ]),
+            "stamp" if numbers[4] < 360 && numbers[5] <= 1 => Shape::Stamp(Placement {
+                index: numbers[0],
+                center: (numbers[1], numbers[2]),
+                size: numbers[3],
+                angle: numbers[4] as u16,
+                flipped: numbers[5] == 1,
+            }),
+            "stamp" => return None,
+            _ => {
+                let points = Polygon::new(
+                    &numbers
+                        .chunks(2)
+                        .map(|pair| (pair[0], pair[1]))
+                        .collect::<Vec<_>>(),
+                );
+                match shape_kind {
+                    "blob" => Shape::Blob(points),
+                    _ => Shape::Polygon(points),
+                }
+            }
+        });
+        rest = &points[count..];
+    }
+    (!shapes.is_empty()).then_some(shapes)
+}
+
+/// The shapes at the end of an `add` or `replace` line
+fn shapes_text(shapes: &[Shape]) -> String {
+    let mut text = String::new();
+    for shape in shapes {
+        text += &match *shape {
+            Shape::Rectangle {
+                top_left,
+                bottom_right,
+            } => format!(
+                " rectangle {} {} {} {}",
+                top_left.0, top_left.1, bottom_right.0, bottom_right.1
+            ),
+            Shape::Triangle([v1, v2, v3]) => format!(
+                " triangle {} {} {} {} {} {}",
+                v1.0, v1.1, v2.0, v2.1, v3.0, v3.1
+            ),
+            Shape::Ellipse {
+                top_left,
+                bottom_right,
+            } => format!(
+                " ellipse {} {} {} {}",
+                top_left.0, top_left.1, bottom_right.0, bottom_right.1
+            ),
+            Shape::Polygon(polygon) => {
+                let vertices = polygon.vertices();
+                let mut text = format!(" polygon {}", vertices.len());
+                for (x, y) in vertices {
+                    text += &format!(" {x} {y}");
+                }
+                text
+            }
+            Shape::Blob(points) => {
+                let points = points.vertices();
+                let mut text = format!(" blob {}", points.len());
+                for (x, y) in points {
+                    text += &format!(" {x} {y}");
+                }
+                text
+            }
+            Shape::Stamp(placement) => format!(
+                " stamp {} {} {} {} {} {}",
+                placement.index,
+                placement.center.0,
+                placement.center.1,
+                placement.size,
+                placement.angle,
+                placement.flipped as u8
+            ),
+        };
+    }
+    text
+}
+
+/// Parses the fill at the start of `words`, along with how many words it took up
+fn parse_fill(words: &[&str]) -> Option<(Fill, usize)> {
+    let number = |i: usize| words.get(i)?.parse::<usize>().ok();
+    let color = |i: usize| {
+        let channel = |i: usize| words.get(i)?.parse::<u8>().ok();
+        Some(Rgb([channel(i)?, channel(i + 1)?, channel(i + 2)?]))
+    };
+    let first = color(0)?;
+    if words.get(3) != Some(&"gradient") {
+        return Some((Fill::Solid(first), 3));
+    }
+    let fill = Fill::Linear {
+        start: (number(7)?, number(8)?),
+        end: (number(9)?, number(10)?),
+        colors: [first, color(4)?],
+    };
+    Some((fill, 11))
+}
+
+/// The fill of an `add`, `recolor`, or `replace` line
+fn fill_text(fill: Fill) -> String {
+    match fill {
+        Fill::Solid(Rgb([r, g, b])) => format!("{r} {g} {b}"),
+        Fill::Linear { start, end, colors } => {
+            let [Rgb([r1, g1, b1]), Rgb([r2, g2, b2])] = colors;
+            format!(
+                "{r1} {g1} {b1} gradient {r2} {g2} {b2} {} {} {} {}",
+                start.0, start.1, end.0, end.1
+            )
+        }
+    }
+}
+
+/// Parses a move from a journal line, or `None` if the line isn't a complete move
+pub fn parse_entry(line: &str) -> Option<JournalEntry> {
+    let mut words = line.split_whitespace();
+    let kind = words.next()?;
+    let iteration = words.next()?.parse().ok()?;
+    let entry = match kind {
+        "add" => {
+            let blend_mode = parse_blend_mode(words.next()?)?;
+            let words = words.collect::<Vec<&str>>();
+            let (fill, used) = parse_fill(&words)?;
+            JournalEntry::Add {
+                iteration,
+                shapes: parse_shapes(&words[used..])?,
+                fill,
+                blend_mode,
+            }
+        }
+        "remove" => JournalEntry::Remove {
+            iteration,
+            index: words.next()?.parse().ok()?,
+        },
+        "recolor" => {
+            let index = words.next()?.parse().ok()?;
+            let words = words.collect::<Vec<&str>>();
+            let (fill, _) = parse_fill(&words)?;
+            JournalEntry::Recolor {
+                iteration,
+                index,
+                fill,
+            }
+        }
+        "replace" => {
+            let index = words.next()?.parse().ok()?;
+            let words = words.collect::<Vec<&str>>();
+            let (fill, used) = parse_fill(&words)?;
+            JournalEntry::Replace {
+                iteration,
+                index,
+                shapes: parse_shapes(&words[used..])?,
+                fill,
+            }
+        }
+        _ => return None,
+    };
+    Some(entry)
+}
+
+/// Parses a blend mode written like `multiply` or `multiply:128`
+fn parse_blend_mode(text: &str) -> Option<BlendMode> {
+    let (blend, opacity) = match text.split_once(':') {
+        Some((blend, opacity)) => (blend, opacity.parse().ok()?),
+        None => (text, 255),
+    };
+    Some(BlendMode {
+        blend: Blend::from_str(blend, false).ok()?,
+        opacity,
+    })
+}
+
+/// Journal line for a newly painted layer of shapes
+pub fn add_line(iteration: u64, shapes: &[Shape], fill: Fill, blend_mode: BlendMode) -> String {
+    let opacity = match blend_mode.opacity {
+        255 => String::new(),
+        opacity => format!(":{opacity}"),
+    };
+    format!(
+        "add {iteration} {}{opacity} {}{}",
+        blend_mode.blend.to_possible_value().unwrap().get_name(),
+        fill_text(fill),
+        shapes_text(shapes)
+    )
+}
+
+impl Journal {
+    /// Starts a new journal at `path` for a `w` by `h` image, replacing whatever was there
+    pub fn create(path: &str, w: usize, h: usize) -> Journal {
+        let mut journal = Journal {
+            writer: BufWriter::new(File::create(path).unwrap()),
+            last_sync: Instant::now(),
+        };
+        writeln!(journal.writer, "anneal_image journal {w} {h}").unwrap();
+        journal
+    }
+
+    /// Reopens the journal at `path` to keep adding to it, along with the moves already in it.
+    /// A half-written move at the end (from the program getting killed mid-write) is dropped, and
+    /// so are the last `rewind` moves before it, for undoing moves that ruined the image
+    pub fn resume(path: &str, w: usize, h: usize, rewind: usize) -> (Journal, Vec<JournalEntry>) {
+        let contents = read_to_string(path).unwrap();
+        let mut lines = contents.split_inclusive('\n');
+        let header = lines.next().unwrap_or_default();
+        if header != format!("anneal_image journal {w} {h}\n") {
+            panic!("journal is for a different image size (or isn't a journal at all)");
+        }
+        let mut entries = Vec::new();
+        // how long the file is up to the end of each move, starting from just the header
+        let mut valid_lens = vec![header.len()];
+        for line in lines {
+            match parse_entry(line).filter(|_| line.ends_with('\n')) {
+                Some(entry) => entries.push(entry),
+                None => break,
+            }
+            valid_lens.push(valid_lens.last().unwrap() + line.len());
+        }
+        if rewind > entries.len() {
+            panic!(
+                "can't rewind {rewind} moves, the journal only has {}",
+                entries.len()
+            );
+        }
+        entries.truncate(entries.len() - rewind);
+        if rewind > 0 {
+            println!("rewound the last {rewind} moves in the journal");
+        }
+        let valid_len = valid_lens[entries.len()];
+        let mut file = OpenOptions::new().write(true).open(path).unwrap();
+        file.set_len(valid_len as u64).unwrap();
+        file.seek(SeekFrom::End(0)).unwrap();
+        let writer = BufWriter::new(file);
+        let journal = Journal {
+            writer,
+            last_sync: Instant::now(),
+        };
+        (journal, entries)
+    }
+
+    /// Logs a newly painted layer of shapes
+    pub fn add(&mut self, iteration: u64, shapes: &[Shape], fill: Fill, blend_mode: BlendMode) {
+        self.write(&add_line(iteration, shapes, fill, blend_mode));
+    }
+
+    /// Logs taking the `index`th layer (counting every add, including removed ones) back out
+    pub fn remove(&mut self, iteration: u64, index: usize) {
+        self.write(&format!("remove {iteration} {index}"));
+    }
+
+    /// Logs painting the `index`th layer with a different fill
+    pub fn recolor(&mut self, iteration: u64, index: usize, fill: Fill) {
+        self.write(&format!("recolor {iteration} {index} {}", fill_text(fill)));
+    }
+
+    /// Logs making the `index`th layer into different shapes painted with `fill`
+    pub fn replace(&mut self, iteration: u64, index: usize, shapes: &[Shape], fill: Fill) {
+        self.write(&format!(
+            "replace {iteration} {index} {}{}",
+            fill_text(fill),
+            shapes_text(shapes)
+        ));
+    }
+
+    fn write(&mut self, line: &str) {
+        writeln!(self.writer, "{line}").unwrap();
+        if self.last_sync.elapsed() >= SYNC_INTERVAL {
+            self.sync();
+        }
+    }
+
+    /// Flushes the journal and waits for it to hit the disk
+    pub fn sync(&mut self) {
+        self.writer.flush().unwrap();
+        self.writer.get_ref().sync_data().unwrap();
+        self.last_sync = Instant::now();
+    }
+}
@@ -0,0 +1,152 @@
+//! Hot loops of the cost functions, with NEON versions for aarch64 (Apple Silicon and friends).
+//! Everywhere else the portable versions are used, which LLVM autovectorizes on its own.
+//! Blending only has the portable version, which is left to LLVM on every target.
+//! They all work on channel planes, so the vectors never have to deal with interleaved pixels
+
+use image::Rgb;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+use std::iter::zip;
+
+/// Number of values each rayon task hands to the kernels at a time
+pub const CHUNK_SIZE: usize = 4096;
+
+/// Sum of the absolute differences between each pair of values in `values1` and `values2`
+pub fn sum_differences(values1: &[u8], values2: &[u8]) -> u64 {
+    #[cfg(target_arch = "aarch64")]
+    {
+        neon::sum_differences(values1, values2)
+    }
+    #[cfg(not(target_arch = "aarch64"))]
+    {
+        portable::sum_differences(values1, values2)
+    }
+}
+
+/// Sum of the absolute differences between every value in `values` and `value`
+pub fn sum_value_differences(values: &[u8], value: u8) -> u64 {
+    #[cfg(target_arch = "aarch64")]
+    {
+        neon::sum_value_differences(values, value)
+    }
+    #[cfg(not(target_arch = "aarch64"))]
+    {
+        portable::sum_value_differences(values, value)
+    }
+}
+
+/// Each of `values` with `value` blended over it by `paint`, and then mixed back in with it by
+/// `opacity` (out of 255). `paint` has to be one blend rather than a match on which one, or the
+/// match ends up inside the loop and it doesn't get vectorized
+pub fn blend_values(values: &mut [u8], value: u8, opacity: u8, paint: impl Fn(u32, u32) -> u32) {
+    let (b, opacity) = (value as u32, opacity as u32);
+    for value in values {
+        let a = *value as u32;
+        *value = ((paint(a, b) * opacity + a * (255 - opacity) + 127) / 255) as u8;
+    }
+}
+
+/// Sum of the pixel differences between the pixels split into `planes1` and `planes2`
+pub fn sum_plane_differences(
+    planes1: &[impl AsRef<[u8]>; 3],
+    planes2: &[impl AsRef<[u8]>; 3],
+) -> u64 {
+    zip(planes1, planes2)
+        .map(|(plane1, plane2)| sum_differences(plane1.as_ref(), plane2.as_ref()))
+        .sum()
+}
+
+/// Sum of the pixel differences between the pixels split into `planes` and `color`
+pub fn sum_plane_color_differences(planes: &[Vec<u8>; 3], color: Rgb<u8>) -> u64 {
+    zip(planes, color.0)
+        .map(|(plane, value)| sum_value_differences(plane, value))
+        .sum()
+}
+
+/// Multithreaded version of `sum_plane_differences`, for big shapes.
+/// Builds without rayon just do it on the one thread
+pub fn par_sum_plane_differences(planes1: &[Vec<u8>; 3], planes2: &[Vec<u8>; 3]) -> u64 {
+    #[cfg(feature = "rayon")]
+    {
+        zip(planes1, planes2)
+            .map(|(plane1, plane2)| {
+                plane1
+                    .par_chunks(CHUNK_SIZE)
+                    .zip(plane2.par_chunks(CHUNK_SIZE))
+                    .map(|(values1, values2)| sum_differences(values1, values2))
+                    .sum::<u64>()
+            })
+            .sum()
+    }
+    #[cfg(not(feature = "rayon"))]
+    {
+        sum_plane_differences(planes1, planes2)
+    }
+}
+
+/// Multithreaded version of `sum_plane_color_differences`, for big shapes.
+/// Builds without rayon just do it on the one thread
+pub fn par_sum_plane_color_differences(planes: &[Vec<u8>; 3], color: Rgb<u8>) -> u64 {
+    #[cfg(feature = "rayon")]
+    {
+        zip(planes, color.0)
+            .map(|(plane, value)| {
+                plane
+                    .par_chunks(CHUNK_SIZE)
+                    .map(|values| sum_value_differences(values, value))
+                    .sum::<u64>()
+            })
+            .sum()
+    }
+    #[cfg(not(feature = "rayon"))]
+    {
+        sum_plane_color_differences(planes, color)
+    }
+}
+
+pub mod portable {
+    pub fn sum_differences(values1: &[u8], values2: &[u8]) -> u64 {
+        std::iter::zip(values1, values2)
+            .map(|(a, b)| a.abs_diff(*b) as u64)
+            .sum()
+    }
+
+    pub fn sum_value_differences(values: &[u8], value: u8) -> u64 {
+        values.iter().map(|a| a.abs_diff(value) as u64).sum()
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+pub mod neon {
+    use std::arch::aarch64::*;
+
+    pub fn sum_differences(values1: &[u8], values2: &[u8]) -> u64 {
+        let len = values1.len().min(values2.len());
+        let vectorized = len - len % 16;
+        // NEON is always available on aarch64
+        let s = unsafe {
+            let mut acc = vdupq_n_u64(0);
+            for i in (0..vectorized).step_by(16) {
+                let a = vld1q_u8(values1.as_ptr().add(i));
+                let b = vld1q_u8(values2.as_ptr().add(i));
+                acc = vpadalq_u32(acc, vpaddlq_u16(vpaddlq_u8(vabdq_u8(a, b))));
+            }
+            vaddvq_u64(acc)
+        };
+        s + super::portable::sum_differences(&values1[vectorized..len], &values2[vectorized..len])
+    }
+
+    pub fn sum_value_differences(values: &[u8], value: u8) -> u64 {
+        let vectorized = values.len() - values.len() % 16;
+        let s = unsafe {
+            let b = vdupq_n_u8(value);
+            let mut acc = vdupq_n_u64(0);
+            for i in (0..vectorized).step_by(16) {
+                let a = vld1q_u8(values.as_ptr().add(i));
+                acc = vpadalq_u32(acc, vpaddlq_u16(vpaddlq_u8(vabdq_u8(a, b))));
+            }
+            vaddvq_u64(acc)
+        };
+        s + super::portable::sum_value_differences(&values[vectorized..], value)
+    }
+}
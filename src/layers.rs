@@ -0,0 +1,429 @@
+//! The accepted shapes in the order they were painted, tracked by which tiles of the image they
+//! touch. Taking a shape back out (or giving it a new color) only needs the shapes touching the same tiles to be repainted,
+//! rather than every shape over the whole image. The pixels each shape covers are kept as its
+//! footprint, so the shapes getting repainted don't have to be rasterized again. Anti-aliased
+//! shapes and stamps also need how much of each pixel they cover, which footprints don't keep, so
+//! they always get rasterized again
+
+use crate::coord::Coord;
+use crate::footprints::{Footprint, Footprints};
+use crate::rng::random;
+use crate::{anti_alias, stamps, BlendMode, Fill, Image, Shape};
+use image::Rgb;
+
+/// Width and height in pixels of the tiles shapes are tracked by
+const TILE_SIZE: usize = 32;
+
+/// A single accepted proposal: a shape and its mirrored copies (if any), painted the same way
+struct Layer {
+    shapes: Vec<Shape>,
+    fill: Fill,
+    blend_mode: BlendMode,
+    footprint: Footprint,
+}
+
+pub struct Layers {
+    w: usize,
+    h: usize,
+    tiles_w: usize,
+    /// `None` for layers that have been removed, so that indices stay valid
+    layers: Vec<Option<Layer>>,
+    /// Indices of the layers touching each tile, in the order they were painted
+    tiles: Vec<Vec<usize>>,
+    live: usize,
+    /// Index of the next layer to be revisited by `oldest_unrevisited`
+    revisit: usize,
+    footprints: Footprints,
+    /// Whether shapes get painted over the pixels they only partly cover
+    anti_aliased: bool,
+    /// The image before any layers get painted over it, which repaints start from
+    initial: Image,
+}
+
+impl Layers {
+    /// No layers at all over `initial`, with their footprints going into `footprints`, for shapes
+    /// that are `anti_aliased` or not
+    pub fn new(initial: Image, footprints: Footprints, anti_aliased: bool) -> Layers {
+        let w = initial.len();
+        let h = initial.first().map_or(0, Vec::len);
+        let tiles_w = w.div_ceil(TILE_SIZE);
+        let tiles_h = h.div_ceil(TILE_SIZE);
+        Layers {
+            w,
+            h,
+            tiles_w,
+            layers: Vec::new(),
+            tiles: vec![Vec::new(); tiles_w * tiles_h],
+            live: 0,
+            revisit: 0,
+            footprints,
+            anti_aliased,
+            initial,
+        }
+    }
+
+    /// The image before any layers get painted over it
+    pub fn initial(&self) -> &Image {
+        &self.initial
+    }
+
+    /// Whether every layer has been removed (or there never were any)
+    pub fn is_empty(&self) -> bool {
+        self.live == 0
+    }
+
+    /// Index of the tile the pixel is in
+    fn tile_of(&self, Coord { x, y }: Coord) -> usize {
+        y / TILE_SIZE * self.tiles_w + x / TILE_SIZE
+    }
+
+    /// Indices of every tile touched by the given coordinates, without repeats
+    fn tiles_of(&self, coords: &[Coord]) -> Vec<usize> {
+        let mut tiles = coords
+            .iter()
+            .map(|&coord| self.tile_of(coord))
+            .collect::<Vec<usize>>();
+        tiles.sort_unstable();
+        tiles.dedup();
+        tiles
+    }
+
+    /// Coordinates of every pixel covered by `shapes`, rasterized from scratch, along with how much
+    /// of each pixel they cover (out of 255, which is all of it unless they're anti-aliased or
+    /// stamps)
+    pub fn covered(&self, shapes: &[Shape]) -> (Vec<Coord>, Vec<u8>) {
+        if let [Shape::Stamp(_), ..] = shapes {
+            return stamps::coverage(shapes, self.w, self.h);
+        }
+        if self.anti_aliased {
+            return anti_alias::coverage(shapes, self.w, self.h);
+        }
+        let mut coords = shapes
+            .iter()
+            .flat_map(|shape| shape.coords(self.w, self.h))
+            .collect::<Vec<_>>();
+        if shapes.len() > 1 {
+            coords.sort_unstable();
+            coords.dedup();
+        }
+        let coverage = vec![255; coords.len()];
+        (coords, coverage)
+    }
+
+    /// Whether the layer only partly covers some of its pixels, so it needs its coverage worked out
+    /// along with them
+    fn partly_covers(&self, layer: &Layer) -> bool {
+        self.anti_aliased || matches!(layer.shapes[..], [Shape::Stamp(_), ..])
+    }
+
+    /// Coordinates of every pixel covered by the layer's shapes, rasterized from scratch
+    fn rasterized(&self, layer: &Layer) -> Vec<Coord> {
+        self.covered(&layer.shapes).0
+    }
+
+    /// Coordinates of every pixel covered by the layer's shapes, from its footprint if it was kept
+    fn coords(&self, layer: &Layer) -> Vec<Coord> {
+        self.footprints
+            .load(&layer.footprint)
+            .unwrap_or_else(|| self.rasterized(layer))
+    }
+
+    /// Adds a layer on top of all the others. `coords` are the pixels covered by `shapes`
+    pub fn push(
+        &mut self,
+        shapes: Vec<Shape>,
+        fill: Fill,
+        blend_mode: BlendMode,
+        coords: &[Coord],
+    ) {
+        let index = self.layers.len();
+        for tile in self.tiles_of(coords) {
+            self.tiles[tile].push(index);
+        }
+        let footprint = self.footprints.store(coords);
+        self.layers.push(Some(Layer {
+            shapes,
+            fill,
+            blend_mode,
+            footprint,
+        }));
+        self.live += 1;
+    }
+
+    /// Index of a random layer that hasn't been removed. There has to be at least one
+    pub fn random_index(&self) -> usize {
+        loop {
+            let index = random::<usize>() % self.layers.len();
+            if self.layers[index].is_some() {
+                return index;
+            }
+        }
+    }
+
+    /// Index of the oldest layer that hasn't been revisited yet, starting over from the oldest
+    /// once every layer has been. There has to be at least one layer that hasn't been removed
+    pub fn oldest_unrevisited(&mut self) -> usize {
+        loop {
+            if self.revisit >= self.layers.len() {
+                self.revisit = 0;
+            }
+            let index = self.revisit;
+            self.revisit += 1;
+            if self.layers[index].is_some() {
+                return index;
+            }
+        }
+    }
+
+    /// Coordinates of the pixels covered by the layer at `index`
+    pub fn layer_coords(&self, index: usize) -> Vec<Coord> {
+        self.coords(self.layers[index].as_ref().unwrap())
+    }
+
+    /// Number of layers that have ever been added, including removed ones
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    /// Shapes and fill of the layer at `index`, or `None` if it's been removed
+    pub fn get(&self, index: usize) -> Option<(&[Shape], Fill)> {
+        let layer = self.layers[index].as_ref()?;
+        Some((&layer.shapes, layer.fill))
+    }
+
+    /// Coordinates of the pixels covered by the layer at `index`,
+    /// and what each of those pixels would be if that layer were removed
+    pub fn without(&self, index: usize) -> (Vec<Coord>, Vec<Rgb<u8>>) {
+        self.repainted(index, None)
+    }
+
+    /// Coordinates of the pixels covered by the layer at `index`,
+    /// and what each of those pixels would be if that layer were painted with `fill` instead
+    pub fn recolored(&self, index: usize, fill: Fill) -> (Vec<Coord>, Vec<Rgb<u8>>) {
+        let shapes = self.layers[index].as_ref().unwrap().shapes.clone();
+        self.repainted(index, Some((shapes, fill)))
+    }
+
+    /// Coordinates of the pixels of the layer at `index` that no layer painted after it covers,
+    /// which are the only ones a new fill changes, or `None` if its fill shows through more than
+    /// that because it (or a layer over it) is blended in or only partly covers some of its pixels
+    pub fn showing(&self, index: usize) -> Option<Vec<Coord>> {
+        let plain =
+            |layer: &Layer| layer.blend_mode == BlendMode::NORMAL && !self.partly_covers(layer);
+        let layer = self.layers[index].as_ref().unwrap();
+        if !plain(layer) {
+            return None;
+        }
+        let coords = self.coords(layer);
+        if coords.is_empty() {
+            return Some(coords);
+        }
+        let min_x = coords.iter().map(|c| c.x).min().unwrap();
+        let min_y = coords.iter().map(|c| c.y).min().unwrap();
+        let max_x = coords.iter().map(|c| c.x).max().unwrap();
+        let max_y = coords.iter().map(|c| c.y).max().unwrap();
+        let box_h = max_y - min_y + 1;
+        let mut covered = vec![false; (max_x - min_x + 1) * box_h];
+        let mut above = self
+            .tiles_of(&coords)
+            .into_iter()
+            .flat_map(|tile| self.tiles[tile].iter().copied())
+            .filter(|&other| other > index)
+            .collect::<Vec<usize>>();
+        above.sort_unstable();
+        above.dedup();
+        for other in above {
+            let other = self.layers[other].as_ref().unwrap();
+            if !plain(other) {
+                return None;
+            }
+            for Coord { x, y } in self.coords(other) {
+                if (min_x..=max_x).contains(&x) && (min_y..=max_y).contains(&y) {
+                    covered[(x - min_x) * box_h + (y - min_y)] = true;
+                }
+            }
+        }
+        Some(
+            coords
+                .into_iter()
+                .filter(|&Coord { x, y }| !covered[(x - min_x) * box_h + (y - min_y)])
+                .collect(),
+        )
+    }
+
+    /// Coordinates of the pixels covered by the layer at `index` either as it is or as `shapes`,
+    /// and what each of those pixels would be if that layer were `shapes` painted with `fill` instead
+    pub fn reshaped(
+        &self,
+        index: usize,
+        shapes: Vec<Shape>,
+        fill: Fill,
+    ) -> (Vec<Coord>, Vec<Rgb<u8>>) {
+        self.repainted(index, Some((shapes, fill)))
+    }
+
+    /// Like `without` if `replacement` is `None`, and like `reshaped` otherwise
+    fn repainted(
+        &self,
+        index: usize,
+        replacement: Option<(Vec<Shape>, Fill)>,
+    ) -> (Vec<Coord>, Vec<Rgb<u8>>) {
+        let old = self.layers[index].as_ref().unwrap();
+        let replacement = replacement.map(|(shapes, fill)| Layer {
+            shapes,
+            fill,
+            blend_mode: old.blend_mode,
+            footprint: Footprint::Missing,
+        });
+        let mut coords = self.coords(old);
+        let reshaped = replacement
+            .as_ref()
+            .is_some_and(|replacement| replacement.shapes != old.shapes);
+        if let Some(ref replacement) = replacement {
+            if reshaped {
+                coords.extend(self.rasterized(replacement));
+                coords.sort_unstable();
+                coords.dedup();
+            }
+        }
+        // repainting just the layer's bounding box, starting from the initial image
+        let min_x = coords.iter().map(|c| c.x).min().unwrap_or(0);
+        let min_y = coords.iter().map(|c| c.y).min().unwrap_or(0);
+        let max_x = coords.iter().map(|c| c.x).max().unwrap_or(0);
+        let max_y = coords.iter().map(|c| c.y).max().unwrap_or(0);
+        let box_h = max_y - min_y + 1;
+        let mut pixels = self.initial[min_x..=max_x]
+            .iter()
+            .flat_map(|column| column[min_y..=max_y].iter().copied())
+            .collect::<Vec<Rgb<u8>>>();
+        let mut others = self
+            .tiles_of(&coords)
+            .into_iter()
+            .flat_map(|tile| self.tiles[tile].iter().copied())
+            .collect::<Vec<usize>>();
+        others.push(index);
+        others.sort_unstable();
+        others.dedup();
+        // a plainly painted layer that keeps its shapes hides everything under it (unless it only
+        // partly covers some of it), so only the layers from it on have to be repainted
+        let covering = replacement.is_some()
+            && !reshaped
+            && old.blend_mode == BlendMode::NORMAL
+            && !self.partly_covers(old);
+        if covering {
+            others.retain(|&other| other >= index);
+        }
+        for other in others {
+            let layer = if other == index {
+                match replacement {
+                    Some(ref replacement) => replacement,
+                    None => continue,
+                }
+            } else {
+                self.layers[other].as_ref().unwrap()
+            };
+            // a new color on the same shapes still covers the old footprint
+            let (layer_coords, coverage) = if self.partly_covers(layer) {
+                self.covered(&layer.shapes)
+            } else if other == index && reshaped {
+                (self.rasterized(layer), Vec::new())
+            } else if other == index {
+                (self.coords(old), Vec::new())
+            } else {
+                (self.coords(layer), Vec::new())
+            };
+            for (i, coord) in layer_coords.into_iter().enumerate() {
+                let Coord { x, y } = coord;
+                if (min_x..=max_x).contains(&x) && (min_y..=max_y).contains(&y) {
+                    let pixel = &mut pixels[(x - min_x) * box_h + (y - min_y)];
+                    let blend_mode = match coverage.get(i) {
+                        Some(&coverage) => layer.blend_mode.covering(coverage),
+                        None => layer.blend_mode,
+                    };
+                    *pixel = blend_mode.blend(*pixel, layer.fill.color_at(coord));
+                }
+            }
+        }
+        let new_pixels = coords
+            .iter()
+            .map(|&Coord { x, y }| pixels[(x - min_x) * box_h + (y - min_y)])
+            .collect();
+        (coords, new_pixels)
+    }
+
+    /// Takes the layer at `index` out. The image has to be updated separately, using `without`
+    pub fn remove(&mut self, index: usize) {
+        let layer = self.layers[index].take().unwrap();
+        for tile in self.tiles_of(&self.coords(&layer)) {
+            self.tiles[tile].retain(|&other| other != index);
+        }
+        self.footprints.free(layer.footprint);
+        self.live -= 1;
+    }
+
+    /// Paints the layer at `index` with `fill` from now on. The image has to be updated separately,
+    /// using `recolored`
+    pub fn recolor(&mut self, index: usize, fill: Fill) {
+        self.layers[index].as_mut().unwrap().fill = fill;
+    }
+
+    /// Makes the layer at `index` into `shapes` painted with `fill` from now on, keeping its place
+    /// in the painting order. The image has to be updated separately, using `reshaped`
+    pub fn reshape(&mut self, index: usize, shapes: Vec<Shape>, fill: Fill) {
+        let old_coords = self.layer_coords(index);
+        for tile in self.tiles_of(&old_coords) {
+            self.tiles[tile].retain(|&other| other != index);
+        }
+        let layer = self.layers[index].take().unwrap();
+        self.footprints.free(layer.footprint);
+        let mut layer = Layer {
+            shapes,
+            fill,
+            footprint: Footprint::Missing,
+            ..layer
+        };
+        let new_coords = self.rasterized(&layer);
+        layer.footprint = self.footprints.store(&new_coords);
+        self.layers[index] = Some(layer);
+        for tile in self.tiles_of(&new_coords) {
+            let position = self.tiles[tile].partition_point(|&other| other < index);
+            self.tiles[tile].insert(position, index);
+        }
+    }
+
+    /// Index of the layer on top of every pixel (column-major, `None` where there isn't one), or
+    /// `None` if any layer is blended in rather than painted plainly (or only partly covers some of
+    /// its pixels), since then more than one layer goes into a pixel
+    pub fn top_layers(&self) -> Option<Vec<Option<usize>>> {
+        if self.anti_aliased {
+            return None;
+        }
+        let mut top = vec![None; self.w * self.h];
+        for (index, layer) in self.layers.iter().enumerate() {
+            let Some(layer) = layer else {
+                continue;
+            };
+            if layer.blend_mode != BlendMode::NORMAL || self.partly_covers(layer) {
+                return None;
+            }
+            for coord in self.coords(layer) {
+                top[coord.index(self.h)] = Some(index);
+            }
+        }
+        Some(top)
+    }
+
+    /// The remaining shapes in the order they were painted
+    pub fn shapes(&self) -> Vec<(Shape, Fill, BlendMode)> {
+        self.layers
+            .iter()
+            .flatten()
+            .flat_map(|layer| {
+                layer
+                    .shapes
+                    .iter()
+                    .map(|&shape| (shape, layer.fill, layer.blend_mode))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,218 @@
+//! Settings that can be changed while annealing, by editing a file of `setting value` lines like
+//!
+//! ```text
+//! alpha 0.99999
+//! sample 5000
+//! removal-moves 0.1
+//! recolor-moves 0.05
+//! channel-moves 0.2
+//! shapes triangle,rectangle
+//! ```
+//!
+//! `sample none` goes back to exact costs, and `shapes` takes the same names as `--shape`.
+//! Settings that are left out stay the way they are. The file is watched like the hints file is,
+//! and changes apply from the next iteration on. Meshes can't have removals turned on, any more
+//! than they can from the command line, and shapes the run wasn't set up for (anything but
+//! triangles for a mesh, say) can't be switched to
+
+use clap::ValueEnum;
+use std::{
+    fs::{metadata, read_to_string},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::ShapeKind;
+
+/// How often the settings file gets checked for changes
+const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The settings that can be changed mid-run
+#[derive(Clone)]
+pub struct Params {
+    pub alpha: f64,
+    pub sample: Option<u32>,
+    pub removal_moves: f64,
+    pub recolor_moves: f64,
+    pub channel_moves: f64,
+    /// The kinds of shapes that random ones get picked from, repeats and all
+    pub shape_kinds: Vec<ShapeKind>,
+}
+
+pub struct LiveParams {
+    path: String,
+    mesh: bool,
+    kinds: Vec<ShapeKind>,
+    modified: Option<SystemTime>,
+    last_check: Instant,
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+fn name(kind: ShapeKind) -> String {
+    kind.to_possible_value().unwrap().get_name().to_string()
+}
+
+fn names(kinds: &[ShapeKind]) -> String {
+    kinds
+        .iter()
+        .map(|&kind| name(kind))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// The settings after the changes in `text`, or what's wrong with them. Removals aren't allowed
+/// for a `mesh`, since taking triangles out of it would leave new ones growing off edges that
+/// aren't painted anymore, and only the shapes in `kinds` are
+fn parse(
+    text: &str,
+    mut params: Params,
+    mesh: bool,
+    kinds: &[ShapeKind],
+) -> Result<Params, String> {
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        let (setting, value) = line
+            .split_once(char::is_whitespace)
+            .map(|(setting, value)| (setting, value.trim()))
+            .ok_or_else(|| format!("no value for `{}`", line.trim()))?;
+        let number = || {
+            value
+                .parse::<f64>()
+                .map_err(|_| format!("`{value}` isn't a number"))
+        };
+        match setting {
+            "alpha" => params.alpha = number()?,
+            "sample" if value == "none" => params.sample = None,
+            "sample" => match value.parse() {
+                Ok(sample) if sample >= 1 => params.sample = Some(sample),
+                _ => {
+                    return Err(format!(
+                        "sample must be `none` or at least 1, not `{value}`"
+                    ))
+                }
+            },
+            "removal-moves" => params.removal_moves = number()?,
+            "recolor-moves" => params.recolor_moves = number()?,
+            "channel-moves" => params.channel_moves = number()?,
+            "shapes" => {
+                params.shape_kinds = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .map(|name| {
+                        ShapeKind::from_str(name, false)
+                            .map_err(|_| format!("there's no `{name}` shape"))
+                    })
+                    .collect::<Result<_, _>>()?;
+                if params.shape_kinds.is_empty() {
+                    return Err("shapes needs at least one kind of shape".to_string());
+                }
+                if let Some(&kind) = params.shape_kinds.iter().find(|kind| !kinds.contains(kind)) {
+                    return Err(format!("this run can't use {} shapes", name(kind)));
+                }
+            }
+            _ => return Err(format!("there's no `{setting}` setting")),
+        }
+    }
+    // the same rules as on the command line, written so that NaNs don't make it through
+    if !(0.0 < params.alpha && params.alpha < 1.0) {
+        return Err("alpha must be greater than 0 and less than 1".to_string());
+    }
+    let moves = params.removal_moves + params.recolor_moves + params.channel_moves;
+    if !(params.removal_moves >= 0.0
+        && params.recolor_moves >= 0.0
+        && params.channel_moves >= 0.0
+        && moves < 1.0)
+    {
+        return Err(
+            "removal, recolor and channel moves must be at least 0, and add up to less than 1"
+                .to_string(),
+        );
+    }
+    if mesh && params.removal_moves != 0.0 {
+        return Err("removal moves have to stay 0 for a mesh".to_string());
+    }
+    Ok(params)
+}
+
+impl LiveParams {
+    /// Watches the file at `path`, which doesn't have to exist yet. If it does, its settings get
+    /// picked up on the first check. `mesh` is whether the triangles are being grown into one, and
+    /// `kinds` are the kinds of shapes the run is set up to use
+    pub fn watch(path: &str, mesh: bool, kinds: Vec<ShapeKind>) -> LiveParams {
+        LiveParams {
+            path: path.to_string(),
+            mesh,
+            kinds,
+            modified: None,
+            last_check: Instant::now() - RELOAD_INTERVAL,
+        }
+    }
+
+    /// Applies the settings in the file to `params` if it has changed since it was last read,
+    /// printing what changed. Returns whether anything did. Edits that don't make sense are ignored
+    pub fn update(&mut self, params: &mut Params) -> bool {
+        if self.last_check.elapsed() < RELOAD_INTERVAL {
+            return false;
+        }
+        self.last_check = Instant::now();
+        let modified = modified(&self.path);
+        if modified.is_none() || modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+        let updated = read_to_string(&self.path)
+            .map_err(|error| error.to_string())
+            .and_then(|text| parse(&text, params.clone(), self.mesh, &self.kinds));
+        let updated = match updated {
+            Ok(updated) => updated,
+            Err(error) => {
+                println!("\nkeeping the old settings, couldn't read the new ones: {error}");
+                return false;
+            }
+        };
+        let mut changes = Vec::new();
+        if updated.alpha != params.alpha {
+            changes.push(format!("alpha {} -> {}", params.alpha, updated.alpha));
+        }
+        if updated.sample != params.sample {
+            let name = |sample: Option<u32>| sample.map_or("none".to_string(), |n| n.to_string());
+            changes.push(format!(
+                "sample {} -> {}",
+                name(params.sample),
+                name(updated.sample)
+            ));
+        }
+        if updated.removal_moves != params.removal_moves {
+            changes.push(format!(
+                "removal moves {} -> {}",
+                params.removal_moves, updated.removal_moves
+            ));
+        }
+        if updated.recolor_moves != params.recolor_moves {
+            changes.push(format!(
+                "recolor moves {} -> {}",
+                params.recolor_moves, updated.recolor_moves
+            ));
+        }
+        if updated.channel_moves != params.channel_moves {
+            changes.push(format!(
+                "channel moves {} -> {}",
+                params.channel_moves, updated.channel_moves
+            ));
+        }
+        if updated.shape_kinds != params.shape_kinds {
+            changes.push(format!(
+                "shapes {} -> {}",
+                names(&params.shape_kinds),
+                names(&updated.shape_kinds)
+            ));
+        }
+        *params = updated;
+        if !changes.is_empty() {
+            println!("\nchanged settings: {}", changes.join(", "));
+        }
+        !changes.is_empty()
+    }
+}
@@ -0,0 +1,154 @@
+//! Staged annealing for `--luma-first`. The shapes get annealed against a grayscale copy of the
+//! original image first, so every one of them goes towards its structure and none get spent on
+//! color noise. Then their geometry stays put and only their colors get annealed against the
+//! full-color image
+
+use crate::rng::{acceptance_random, random};
+use crate::{
+    best_color, canvas::Canvas, coord::Coord, journal::Journal, layers::Layers, target::Target,
+    update_cost_to_pixels, Fill, Image,
+};
+use image::Rgb;
+
+/// Temperature the colors start annealing at. By then every shape starts out in the color that
+/// best fits what's visible of it (which can't be beaten without blending or weights), so it
+/// starts out a lot cooler than the shapes did
+const INITIAL_TEMP: f64 = 0.01;
+
+const FINAL_TEMP: f64 = 0.0001;
+
+/// Furthest (per channel) a color gets nudged at the start
+const MAX_JITTER: f64 = 16.0;
+
+/// Grayscale copy of the image, with every channel set to the pixel's luma (Rec. 601)
+pub fn luma(pixels: &[Vec<Rgb<u8>>]) -> Image {
+    pixels
+        .iter()
+        .map(|column| {
+            column
+                .iter()
+                .map(|&Rgb([r, g, b])| {
+                    let luma = 0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64;
+                    Rgb([luma.round() as u8; 3])
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Tries `color` on the layer at `index`, keeping it if `accept` says so about the change in
+/// cost. `visible` are the pixels where each layer is on top, if that's all a layer's color goes
+/// into. Returns the new cost if it's kept
+#[allow(clippy::too_many_arguments)]
+fn try_color(
+    target: &Target,
+    canvas: &mut Canvas,
+    layers: &mut Layers,
+    visible: Option<&[Vec<Coord>]>,
+    cost: f64,
+    index: usize,
+    color: Rgb<u8>,
+    accept: impl FnOnce(f64) -> bool,
+) -> Option<f64> {
+    let (coords, new_pixels) = match visible {
+        Some(visible) => (visible[index].clone(), vec![color; visible[index].len()]),
+        None => layers.recolored(index, Fill::Solid(color)),
+    };
+    let transaction = canvas.replace(coords, &new_pixels);
+    let new_cost = update_cost_to_pixels(
+        cost,
+        target,
+        transaction.coords(),
+        transaction.old_pixels(),
+        transaction.new_pixels().unwrap_or(&new_pixels),
+    );
+    if accept(new_cost - cost) {
+        canvas.commit(transaction);
+        layers.recolor(index, Fill::Solid(color));
+        Some(new_cost)
+    } else {
+        canvas.rollback(transaction);
+        None
+    }
+}
+
+/// Anneals the colors of the layers against `target` (the full-color image) without touching
+/// their shapes, cooling down by `alpha` every iteration, and returns the new cost. Every layer
+/// first gets the color that best fits the image under it, if that's better than its gray.
+/// Every kept color gets logged to the journal (if there is one) as of `iteration`
+pub fn anneal_colors(
+    target: &Target,
+    canvas: &mut Canvas,
+    layers: &mut Layers,
+    mut cost: f64,
+    alpha: f64,
+    mut journal: Option<&mut Journal>,
+    iteration: u64,
+) -> f64 {
+    // with the shapes staying put, which layer ends up on top of each pixel doesn't change, so
+    // (unless there's blending) a new color only has to be painted where its layer is on top,
+    // instead of repainting everything around it
+    let h = target.height();
+    let visible = layers.top_layers().map(|top| {
+        let mut visible = vec![Vec::new(); layers.len()];
+        for (i, top) in top.into_iter().enumerate() {
+            if let Some(index) = top {
+                visible[index].push(Coord::new(i / h, i % h));
+            }
+        }
+        visible
+    });
+    let visible = visible.as_deref();
+    // layers that are completely hidden can look like anything
+    let candidates = (0..layers.len())
+        .filter(|&index| layers.get(index).is_some())
+        .filter(|&index| visible.is_none_or(|visible| !visible[index].is_empty()))
+        .collect::<Vec<_>>();
+    if candidates.is_empty() {
+        return cost;
+    }
+    let coords = |layers: &Layers, index: usize| match visible {
+        Some(visible) => visible[index].clone(),
+        None => layers.layer_coords(index),
+    };
+    for &index in &candidates {
+        let color = best_color(target, &coords(layers, index));
+        let improves = |diff: f64| diff < 0.0;
+        if let Some(new_cost) = try_color(
+            target, canvas, layers, visible, cost, index, color, improves,
+        ) {
+            cost = new_cost;
+            if let Some(ref mut journal) = journal {
+                journal.recolor(iteration, index, Fill::Solid(color));
+            }
+        }
+    }
+    println!("colored in the shapes | cost: {cost:.5}");
+    let mut temp = INITIAL_TEMP;
+    while temp >= FINAL_TEMP {
+        let progress = (INITIAL_TEMP / temp).ln() / (INITIAL_TEMP / FINAL_TEMP).ln();
+        // the best fits are close already, so the colors only get nudged away from them, less
+        // and less as it cools down
+        let jitter = (MAX_JITTER * (1.0 - progress)).max(1.0);
+        let index = candidates[random::<usize>() % candidates.len()];
+        // there are no gradients to keep with `--luma-first`, so every layer has just the one color
+        let Rgb(color) = layers.get(index).unwrap().1.colors()[0];
+        let color = Rgb(color.map(|value| {
+            (value as f64 + (random::<f64>() * 2.0 - 1.0) * jitter)
+                .round()
+                .clamp(0.0, 255.0) as u8
+        }));
+        let accept = |diff: f64| diff < 0.0 || acceptance_random::<f64>() < (-diff / temp).exp();
+        if let Some(new_cost) =
+            try_color(target, canvas, layers, visible, cost, index, color, accept)
+        {
+            cost = new_cost;
+            if let Some(ref mut journal) = journal {
+                journal.recolor(iteration, index, Fill::Solid(color));
+            }
+        }
+        temp *= alpha;
+    }
+    println!("annealed the colors | cost: {cost:.5}");
+    cost
+}
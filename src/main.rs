@@ -1,138 +1,1264 @@
-use clap::Parser;
-use image::{open, Rgb};
-use rand::random;
-use rayon::prelude::*;
+mod anneal_params;
+mod annotate;
+mod anti_alias;
+mod bench;
+mod budget;
+mod canvas;
+mod color_profile;
+mod colorspace;
+mod converged;
+mod coord;
+mod dhash;
+mod distributed;
+mod edges;
+mod error_sums;
+mod fixed_shapes;
+mod flow;
+mod font;
+mod footprints;
+mod frames;
+mod geometry;
+mod hatching;
+mod hints;
+mod ink;
+mod journal;
+mod kernels;
+mod layers;
+mod live_params;
+mod luma_first;
+mod mem_stats;
+mod metadata;
+mod normalize;
+mod orientation;
+mod polish;
+mod raster_cache;
+mod residual_scale;
+mod rng;
+mod run_log;
+mod sampling;
+mod self_test;
+mod shape_list;
+mod sketch;
+mod stamps;
+mod stipple;
+mod superpixels;
+mod target;
+mod vector;
+
+use anneal_params::AnnealParams;
+use budget::BudgetSplit;
+use canvas::Canvas;
+use clap::{Parser, ValueEnum};
+use color_profile::{embed_icc_profile, icc_profile, RgbProfile};
+use color_quant::NeuQuant;
+use colorspace::Colorspace;
+use converged::ConvergedMask;
+use coord::{Coord, Pixels};
+use distributed::{Coordinator, WorkerSettings};
+use edges::EdgeStrokes;
+use fixed_shapes::FixedShapes;
+use flow::FlowStrokes;
+use footprints::Footprints;
+use frames::Frames;
+use geometry::{Polygon, MAX_BLOB_POINTS, MIN_BLOB_POINTS};
+use hints::Hints;
+use image::{
+    imageops::{dither, resize, ColorMap, FilterType},
+    open, GrayImage, ImageFormat, ImageOutputFormat, Luma, Rgb, RgbImage,
+};
+use journal::{Journal, JournalEntry};
+use kernels::{
+    blend_values, par_sum_plane_color_differences, par_sum_plane_differences,
+    sum_plane_color_differences, sum_plane_differences, CHUNK_SIZE,
+};
+use layers::Layers;
+use live_params::{LiveParams, Params};
+use mem_stats::Subsystem;
+use memmap2::MmapMut;
+use metadata::Metadata;
+use normalize::Normalization;
+use orientation::OrientationMap;
+use png::{BitDepth, ColorType, Encoder};
+use raster_cache::RasterCache;
+use rng::{acceptance_random, random};
+use run_log::Run;
+use sampling::CostEstimate;
+use shape_list::{prune_hidden, save_shape_list};
+use sketch::{save_sketch, save_viewer, SketchKind};
+use stamps::Placement;
 use std::{
+    fs::{rename, write, OpenOptions},
+    io::{stdout, Cursor, Write},
     iter::zip,
-    mem::swap,
-    sync::{Arc, Mutex},
+    path::Path,
     thread,
-    time::Instant,
+    time::{Duration, Instant},
 };
+use superpixels::Superpixels;
+use target::{to_planes, Loss, Target};
+use vector::{save_eps, save_pdf, save_svg};
+
+/// How long the annealing loop runs for between pauses when throttled
+const THROTTLE_BURST: Duration = Duration::from_millis(100);
+
+/// How far in from an edge of the image (as a fraction of its size) `edge_bias` puts shapes
+const EDGE_BAND: f64 = 0.1;
+
+/// How many iterations go by between working out how big shapes should be for `adaptive_sizes`
+const SIZE_INTERVAL: u64 = 1000;
+
+/// How many iterations go by between working out which tiles are done for `converged_threshold`
+const CONVERGED_INTERVAL: u64 = 1000;
+
+/// How many iterations go by between comparing the canvas's dHash with the target's
+const DHASH_INTERVAL: u64 = 1000;
+
+/// How much time spent annealing the watchdog averages the iteration throughput over
+const WATCHDOG_WINDOW: Duration = Duration::from_secs(10);
+
+/// Column-major grid of pixels, read and painted by `Coord` through `Pixels`
+type Image = Vec<Vec<Rgb<u8>>>;
+
+/// Memory-mapped copy of the annealed image that other processes can map to watch the
+/// annealing happen in real time. Pixels are stored row-major as packed RGB bytes
+struct SharedCanvas {
+    mmap: MmapMut,
+    width: usize,
+}
+
+impl SharedCanvas {
+    /// Maps the file at `path`, creating it or resizing it to fit a `w` by `h` image,
+    /// and clears it to black
+    fn new(path: &str, w: usize, h: usize) -> SharedCanvas {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .unwrap();
+        file.set_len((w * h * 3) as u64).unwrap();
+        // other processes mapping the file is the whole point, so this is as safe as it gets
+        let mut mmap = unsafe { MmapMut::map_mut(&file).unwrap() };
+        mmap.fill(0);
+        SharedCanvas { mmap, width: w }
+    }
+
+    fn set(&mut self, x: usize, y: usize, color: Rgb<u8>) {
+        let i = (y * self.width + x) * 3;
+        self.mmap[i..i + 3].copy_from_slice(&color.0);
+    }
+
+    /// Copies the pixels at the given coordinates over from the annealed image, which has `pixel`
+    /// at each of them
+    fn sync(&mut self, coords: &[Coord], pixel: impl Fn(Coord) -> Rgb<u8>) {
+        for &coord in coords {
+            self.set(coord.x, coord.y, pixel(coord));
+        }
+    }
+}
+
+/// A limited palette of colors picked to fit an image
+struct Palette(NeuQuant);
+
+impl Palette {
+    /// Picks a palette of `colors` colors that best fits the image
+    fn new(image: &RgbImage, colors: usize) -> Palette {
+        let pixels = image
+            .pixels()
+            .flat_map(|&Rgb([r, g, b])| [r, g, b, u8::MAX])
+            .collect::<Vec<u8>>();
+        Palette(NeuQuant::new(10, colors, &pixels))
+    }
+}
+
+impl ColorMap for Palette {
+    type Color = Rgb<u8>;
+
+    fn index_of(&self, &Rgb([r, g, b]): &Rgb<u8>) -> usize {
+        self.0.index_of(&[r, g, b, u8::MAX])
+    }
+
+    fn lookup(&self, index: usize) -> Option<Rgb<u8>> {
+        self.0.lookup(index).map(|[r, g, b, _]| Rgb([r, g, b]))
+    }
+
+    fn has_lookup(&self) -> bool {
+        true
+    }
+
+    fn map_color(&self, color: &mut Rgb<u8>) {
+        *color = self.lookup(self.index_of(color)).unwrap();
+    }
+}
+
+/// The kinds of shapes that can get proposed, by the names `--shape` takes. Every kind listed
+/// here shows up in the help and can be picked, and the proposal code has to know how to make it
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ShapeKind {
+    /// Axis-aligned rectangles
+    Rectangle,
+    /// Rectangles turned to any angle
+    RotatedRectangle,
+    /// Triangles with their vertices anywhere
+    Triangle,
+    /// Axis-aligned ellipses
+    Ellipse,
+    /// Circles
+    Circle,
+    /// Convex polygons with up to `--vertices` vertices
+    Polygon,
+    /// Smooth closed curves around a few control points
+    Blob,
+    /// Straight lines with rounded ends, `--stroke-width` pixels thick
+    Stroke,
+    /// The images from `--stamps`, scaled, turned, and flipped over, or the glyphs from `--font`
+    Stamp,
+}
+
+/// A shape that gets drawn onto the annealed image
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Shape {
+    Rectangle {
+        top_left: (usize, usize),
+        bottom_right: (usize, usize),
+    },
+    Triangle([(usize, usize); 3]),
+    /// The ellipse inscribed in a box, going from `top_left` up to (but not including)
+    /// `bottom_right` like a rectangle. Circles are ellipses in a square box
+    Ellipse {
+        top_left: (usize, usize),
+        bottom_right: (usize, usize),
+    },
+    Polygon(Polygon),
+    /// A closed curve of quadratic Bézier segments around its control points
+    Blob(Polygon),
+    /// One of the `--stamps`, covering pixels as much as its alpha says
+    Stamp(Placement),
+}
+
+impl Shape {
+    /// Coordinates of every pixel covered by the shape in a `w` by `h` image.
+    /// Parts of the shape hanging off the right or bottom edge wrap around to the other side
+    fn coords(&self, w: usize, h: usize) -> Vec<Coord> {
+        let coords = match *self {
+            Shape::Rectangle {
+                top_left,
+                bottom_right,
+            } => get_rectangle(top_left, bottom_right),
+            Shape::Triangle(vertices) => get_triangle(&vertices),
+            Shape::Ellipse {
+                top_left,
+                bottom_right,
+            } => get_ellipse(top_left, bottom_right),
+            Shape::Polygon(polygon) => get_polygon(polygon.vertices()),
+            Shape::Blob(points) => get_blob(points.vertices()),
+            // stamps get clipped to the image rather than wrapping around
+            Shape::Stamp(placement) => return placement.coverage(w, h).0,
+        };
+        if coords.iter().any(|coord| coord.x >= w || coord.y >= h) {
+            // wrapping can land several pixels on the same spot, which must only be counted once
+            let mut coords = coords
+                .into_iter()
+                .map(|coord| Coord::new(coord.x % w, coord.y % h))
+                .collect::<Vec<_>>();
+            coords.sort_unstable();
+            coords.dedup();
+            coords
+        } else {
+            coords
+        }
+    }
+
+    /// The box the shape fits in, from its top left corner up to (but not including) its bottom
+    /// right one, before any wrapping around the edges
+    fn bounds(&self) -> ((usize, usize), (usize, usize)) {
+        let points = match *self {
+            Shape::Rectangle {
+                top_left,
+                bottom_right,
+            }
+            | Shape::Ellipse {
+                top_left,
+                bottom_right,
+            } => return (top_left, bottom_right),
+            Shape::Triangle(vertices) => vertices.to_vec(),
+            // blobs never leave the hull of their control points
+            Shape::Polygon(points) | Shape::Blob(points) => points.vertices().to_vec(),
+            Shape::Stamp(placement) => {
+                let corners = placement.corners();
+                let xs = || corners.iter().map(|p| p.0.max(0.0));
+                let ys = || corners.iter().map(|p| p.1.max(0.0));
+                return (
+                    (
+                        xs().fold(f64::INFINITY, f64::min) as usize,
+                        ys().fold(f64::INFINITY, f64::min) as usize,
+                    ),
+                    (
+                        xs().fold(0.0, f64::max).ceil() as usize,
+                        ys().fold(0.0, f64::max).ceil() as usize,
+                    ),
+                );
+            }
+        };
+        let (xs, ys) = (points.iter().map(|p| p.0), points.iter().map(|p| p.1));
+        (
+            (xs.clone().min().unwrap(), ys.clone().min().unwrap()),
+            (xs.max().unwrap() + 1, ys.max().unwrap() + 1),
+        )
+    }
+
+    /// The shape flipped left to right and/or top to bottom within a `w` by `h` image
+    fn mirrored(&self, w: usize, h: usize, flip_x: bool, flip_y: bool) -> Shape {
+        let flip = |(x, y): (usize, usize)| {
+            (
+                if flip_x { w - 1 - x } else { x },
+                if flip_y { h - 1 - y } else { y },
+            )
+        };
+        // the bottom right corner is exclusive, so it's flipped as the last pixel inside
+        let flip_box = |top_left, (x2, y2): (usize, usize)| {
+            let (x1, y1) = flip(top_left);
+            let (x2, y2) = flip((x2 - 1, y2 - 1));
+            ((x1.min(x2), y1.min(y2)), (x1.max(x2) + 1, y1.max(y2) + 1))
+        };
+        match *self {
+            Shape::Rectangle {
+                top_left,
+                bottom_right,
+            } => {
+                let (top_left, bottom_right) = flip_box(top_left, bottom_right);
+                Shape::Rectangle {
+                    top_left,
+                    bottom_right,
+                }
+            }
+            // flipping a triangle turns its vertices the other way around
+            Shape::Triangle(vertices) => {
+                Shape::Triangle(geometry::canonical_triangle(vertices.map(flip)))
+            }
+            Shape::Ellipse {
+                top_left,
+                bottom_right,
+            } => {
+                let (top_left, bottom_right) = flip_box(top_left, bottom_right);
+                Shape::Ellipse {
+                    top_left,
+                    bottom_right,
+                }
+            }
+            Shape::Polygon(polygon) => {
+                let flipped = polygon.vertices().iter().map(|&vertex| flip(vertex));
+                let flipped = Polygon::new(&flipped.collect::<Vec<_>>());
+                Shape::Polygon(geometry::canonical_polygon(flipped))
+            }
+            Shape::Blob(points) => {
+                let flipped = points.vertices().iter().map(|&point| flip(point));
+                let flipped = Polygon::new(&flipped.collect::<Vec<_>>());
+                Shape::Blob(geometry::canonical_blob(flipped))
+            }
+            Shape::Stamp(placement) => Shape::Stamp(placement.mirrored(w, h, flip_x, flip_y)),
+        }
+    }
+
+    /// Coordinates of the pixels making up the outline of the shape in a `w` by `h` image,
+    /// wrapping around the edges the same way as `coords`
+    fn outline(&self, w: usize, h: usize) -> Vec<Coord> {
+        let corners = match *self {
+            Shape::Rectangle {
+                top_left,
+                bottom_right,
+            } => vec![
+                top_left,
+                (bottom_right.0 - 1, top_left.1),
+                (bottom_right.0 - 1, bottom_right.1 - 1),
+                (top_left.0, bottom_right.1 - 1),
+            ],
+            Shape::Triangle(vertices) => vertices.to_vec(),
+            Shape::Ellipse {
+                top_left,
+                bottom_right,
+            } => geometry::ellipse_polygon(top_left, bottom_right)
+                .into_iter()
+                .map(|(x, y)| {
+                    (
+                        (x as usize).min(bottom_right.0 - 1),
+                        (y as usize).min(bottom_right.1 - 1),
+                    )
+                })
+                .collect(),
+            Shape::Polygon(polygon) => polygon.vertices().to_vec(),
+            Shape::Blob(points) => geometry::blob_curve(points.vertices())
+                .into_iter()
+                .map(|(x, y)| (x.round() as usize, y.round() as usize))
+                .collect(),
+            // stamps are outlined by their box, clipped to the image like they are
+            Shape::Stamp(placement) => placement
+                .corners()
+                .into_iter()
+                .map(|(x, y)| {
+                    (
+                        (x.max(0.0) as usize).min(w - 1),
+                        (y.max(0.0) as usize).min(h - 1),
+                    )
+                })
+                .collect(),
+        };
+        (0..corners.len())
+            .flat_map(|i| get_line(corners[i], corners[(i + 1) % corners.len()]))
+            .map(|coord| Coord::new(coord.x % w, coord.y % h))
+            .collect()
+    }
+}
 
-/// Either single-threaded image or multi-threaded image.
-/// Used so I don't have to write multiple anneal functions
-enum EitherThreadedImage {
-    SingleThreaded(Vec<Vec<Rgb<u8>>>),
-    MultiThreaded(Arc<Mutex<Vec<Vec<Rgb<u8>>>>>),
+/// How a shape's color gets combined with the pixels underneath it
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Blend {
+    /// The shape's color replaces what's underneath
+    Normal,
+    /// Darkens what's underneath by the shape's color
+    Multiply,
+    /// Lightens what's underneath by the shape's color
+    Screen,
+    /// Multiplies dark pixels underneath and screens light ones
+    Overlay,
 }
 
-/// Gets the coordinates of a random single-colored triangle with the given vertices.
-/// Returns said coordinates and the random color that it should be filled with
-/// Algorithm stolen from http://www.sunshine2k.de/coding/java/TriangleRasterization/TriangleRasterization.html
-fn get_triangle(vertices: &mut [(usize, usize); 3]) -> (Vec<(usize, usize)>, Rgb<u8>) {
-    fn sort_vertices([v1, v2, v3]: &mut [(i64, i64); 3]) {
-        if v1.1 > v2.1 || v1.1 == v2.1 && v1.0 > v2.0 {
-            swap(v1, v2);
+impl Blend {
+    /// A channel of the pixel underneath, `a`, with the same channel of the shape's color, `b`,
+    /// blended over it
+    fn paint(self, a: u32, b: u32) -> u32 {
+        let multiply = |a: u32, b: u32| a * b / 255;
+        let screen = |a: u32, b: u32| 255 - (255 - a) * (255 - b) / 255;
+        match self {
+            Blend::Normal => b,
+            Blend::Multiply => multiply(a, b),
+            Blend::Screen => screen(a, b),
+            Blend::Overlay => {
+                // both halves get worked out, with `a` clamped into each one's range, so the
+                // kernels can pick between them without branching
+                let doubled = 2 * a;
+                let dark = multiply(doubled.min(255), b);
+                let light = screen(doubled.max(255) - 255, b);
+                if a < 128 {
+                    dark
+                } else {
+                    light
+                }
+            }
+        }
+    }
+}
+
+/// How a shape gets painted over the pixels underneath it: blended with them, and then mixed back
+/// in with them by how opaque it is
+#[derive(Clone, Copy, PartialEq)]
+struct BlendMode {
+    blend: Blend,
+    /// How much of the blended color shows, from 0 for none of it to 255 for all of it
+    opacity: u8,
+}
+
+impl BlendMode {
+    /// Plain opaque paint, which is the only kind that hides what's underneath
+    const NORMAL: BlendMode = BlendMode::opaque(Blend::Normal);
+
+    const fn opaque(blend: Blend) -> BlendMode {
+        BlendMode {
+            blend,
+            opacity: 255,
+        }
+    }
+
+    /// The same blend mode for a pixel the shape only covers `coverage` (out of 255) of
+    fn covering(self, coverage: u8) -> BlendMode {
+        BlendMode {
+            opacity: ((self.opacity as u32 * coverage as u32 + 127) / 255) as u8,
+            ..self
         }
-        if v2.1 > v3.1 || v2.1 == v3.1 && v2.0 > v3.0 {
-            swap(v2, v3);
+    }
+
+    /// The pixel `below` after a shape of the given color is painted over it
+    fn blend(self, below: Rgb<u8>, color: Rgb<u8>) -> Rgb<u8> {
+        let opacity = self.opacity as u32;
+        let mut blended = below;
+        for (channel, &c) in zip(blended.0.iter_mut(), color.0.iter()) {
+            let (a, b) = (*channel as u32, c as u32);
+            let painted = self.blend.paint(a, b);
+            *channel = ((painted * opacity + a * (255 - opacity) + 127) / 255) as u8;
         }
-        if v1.1 > v2.1 || v1.1 == v2.1 && v1.0 > v2.0 {
-            swap(v1, v2);
+        blended
+    }
+
+    /// `blend` for a whole channel plane of pixels underneath at once, with the same channel of
+    /// the shape's color
+    fn blend_plane(self, plane: &mut [u8], value: u8) {
+        // each blend gets a kernel of its own, so none of them has to pick one per value
+        let opacity = self.opacity;
+        match self.blend {
+            Blend::Normal => blend_values(plane, value, opacity, |a, b| Blend::Normal.paint(a, b)),
+            Blend::Multiply => {
+                blend_values(plane, value, opacity, |a, b| Blend::Multiply.paint(a, b))
+            }
+            Blend::Screen => blend_values(plane, value, opacity, |a, b| Blend::Screen.paint(a, b)),
+            Blend::Overlay => {
+                blend_values(plane, value, opacity, |a, b| Blend::Overlay.paint(a, b))
+            }
         }
     }
+}
+
+/// What a shape gets painted with
+#[derive(Clone, Copy, PartialEq)]
+enum Fill {
+    /// The same color all over
+    Solid(Rgb<u8>),
+    /// Going from the first color at the middle of the `start` pixel to the second at the middle of
+    /// the `end` pixel, and staying whichever color is nearer past either end
+    Linear {
+        start: (usize, usize),
+        end: (usize, usize),
+        colors: [Rgb<u8>; 2],
+    },
+}
 
-    fn flat_bottom_triangle([v1, v2, v3]: &[(i64, i64); 3]) -> Vec<(usize, usize)> {
-        let invslope1 = (v2.0 - v1.0) as f64 / (v2.1 - v1.1) as f64;
-        let invslope2 = (v3.0 - v1.0) as f64 / (v3.1 - v1.1) as f64;
-        let mut curx1 = v1.0 as f64;
-        let mut curx2 = v1.0 as f64;
-        let mut coords = Vec::new();
-        for y in v1.1..=v2.1 {
-            coords.extend((curx1 as usize..=curx2 as usize).map(|x| (x, y as usize)));
-            curx1 += invslope1;
-            curx2 += invslope2;
+impl Fill {
+    /// The color the fill paints the pixel at `coord`
+    fn color_at(self, coord: Coord) -> Rgb<u8> {
+        match self {
+            Fill::Solid(color) => color,
+            Fill::Linear { start, end, colors } => {
+                let t = gradient_position(start, end, coord);
+                let mut color = colors[0];
+                for (channel, &to) in zip(color.0.iter_mut(), colors[1].0.iter()) {
+                    *channel = (*channel as f64 + (to as f64 - *channel as f64) * t).round() as u8;
+                }
+                color
+            }
         }
+    }
 
-        coords
+    /// The colors the fill is made of, one for solid fills and both ends' for gradients
+    fn colors(&self) -> &[Rgb<u8>] {
+        match self {
+            Fill::Solid(color) => std::slice::from_ref(color),
+            Fill::Linear { colors, .. } => colors,
+        }
     }
 
-    fn flat_top_triangle([v1, v2, v3]: &[(i64, i64); 3]) -> Vec<(usize, usize)> {
-        let invslope1 = (v3.0 - v1.0) as f64 / (v3.1 - v1.1) as f64;
-        let invslope2 = (v3.0 - v2.0) as f64 / (v3.1 - v2.1) as f64;
-        let mut curx1 = v3.0 as f64;
-        let mut curx2 = v3.0 as f64;
-        let mut coords = Vec::new();
-        for y in (v1.1 + 1..=v3.1).rev() {
-            coords.extend((curx1 as usize..=curx2 as usize).map(|x| (x, y as usize)));
-            curx1 -= invslope1;
-            curx2 -= invslope2;
+    /// The same fill with `f` applied to each of its colors
+    fn map_colors(self, mut f: impl FnMut(usize, Rgb<u8>) -> Rgb<u8>) -> Fill {
+        match self {
+            Fill::Solid(color) => Fill::Solid(f(0, color)),
+            Fill::Linear { start, end, colors } => Fill::Linear {
+                start,
+                end,
+                colors: [f(0, colors[0]), f(1, colors[1])],
+            },
         }
+    }
+}
 
-        coords
+/// How far along the gradient from the middle of the `start` pixel to the middle of the `end` pixel
+/// the middle of the pixel at `coord` is, from 0 at the start to 1 at the end
+fn gradient_position(start: (usize, usize), end: (usize, usize), coord: Coord) -> f64 {
+    let (dx, dy) = (end.0 as f64 - start.0 as f64, end.1 as f64 - start.1 as f64);
+    let (px, py) = (
+        coord.x as f64 - start.0 as f64,
+        coord.y as f64 - start.1 as f64,
+    );
+    let length_squared = dx * dx + dy * dy;
+    // a gradient that starts and ends in the same place is just its first color
+    if length_squared == 0.0 {
+        return 0.0;
     }
+    ((px * dx + py * dy) / length_squared).clamp(0.0, 1.0)
+}
+
+/// Symmetry that every shape gets mirrored with
+#[derive(Clone, Copy, ValueEnum)]
+enum Symmetry {
+    /// Mirrored left to right
+    Horizontal,
+    /// Mirrored top to bottom
+    Vertical,
+    /// Mirrored left to right, top to bottom, and both
+    Radial,
+}
 
-    let mut vertices = vertices.map(|(x, y)| (x as i64, y as i64));
-    sort_vertices(&mut vertices);
-    let [vt1, vt2, vt3] = vertices;
-    let color: Rgb<u8> = Rgb([random(), random(), random()]);
+impl Symmetry {
+    /// The shape along with all of its mirrored copies within a `w` by `h` image
+    fn mirror(self, shape: Shape, w: usize, h: usize) -> Vec<Shape> {
+        match self {
+            Symmetry::Horizontal => vec![shape, shape.mirrored(w, h, true, false)],
+            Symmetry::Vertical => vec![shape, shape.mirrored(w, h, false, true)],
+            Symmetry::Radial => vec![
+                shape,
+                shape.mirrored(w, h, true, false),
+                shape.mirrored(w, h, false, true),
+                shape.mirrored(w, h, true, true),
+            ],
+        }
+    }
+}
 
-    if vt2.1 == vt3.1 {
-        (flat_bottom_triangle(&[vt1, vt2, vt3]), color)
-    } else if vt1.1 == vt2.1 {
-        (flat_top_triangle(&[vt1, vt2, vt3]), color)
+/// An opacity anywhere between the two in `range` (as fractions), from 0 to 255. A range that's
+/// just one opacity doesn't take a random number
+fn random_opacity((lowest, highest): (f64, f64)) -> u8 {
+    let opacity = if lowest == highest {
+        lowest
     } else {
-        // splitting triangle into top half and bottom half
-        let mut coords = Vec::new();
-        let x4 = (vt1.0 as f64
-            + ((vt2.1 - vt1.1) as f64 / (vt3.1 - vt1.1) as f64) * (vt3.0 - vt1.0) as f64)
-            as i64;
-        let vt4 = (x4, vt2.1);
-        let mut flat_bottom = [vt1, vt2, vt4];
-        sort_vertices(&mut flat_bottom);
-        let mut flat_top = [vt2, vt4, vt3];
-        sort_vertices(&mut flat_top);
-        coords.extend(flat_bottom_triangle(&flat_bottom));
-        coords.extend(flat_top_triangle(&flat_top));
-        (coords, color)
-    }
-}
-
-/// Gets the coordinates of a random single-colored rectangle with the given vertices.
-fn get_rectangle(
-    top_left: (usize, usize),
-    bottom_right: (usize, usize),
-) -> (Vec<(usize, usize)>, Rgb<u8>) {
-    let color = Rgb([random(), random(), random()]);
+        lowest + random::<f64>() * (highest - lowest)
+    };
+    (opacity * 255.0).round() as u8
+}
+
+/// A random color to fill a shape with
+fn random_color() -> Rgb<u8> {
+    Rgb([random(), random(), random()])
+}
+
+/// Rounds every channel of the color to one of the `bits`-bit levels, spread evenly from 0 to 255
+fn quantize_color(color: Rgb<u8>, bits: u8) -> Rgb<u8> {
+    let levels = (1u32 << bits) - 1;
+    Rgb(color
+        .0
+        .map(|value| ((value as u32 * levels + 127) / 255 * 255 / levels) as u8))
+}
+
+/// Gets the coordinates of a line between two points using Bresenham's line algorithm
+fn get_line(start: (usize, usize), end: (usize, usize)) -> Vec<Coord> {
+    let (mut x, mut y) = (start.0 as i64, start.1 as i64);
+    let (x1, y1) = (end.0 as i64, end.1 as i64);
+    let dx = (x1 - x).abs();
+    let dy = -(y1 - y).abs();
+    let sx = if x < x1 { 1 } else { -1 };
+    let sy = if y < y1 { 1 } else { -1 };
+    let mut err = dx + dy;
+    let mut coords = Vec::new();
+    loop {
+        coords.push(Coord::new(x as usize, y as usize));
+        if x == x1 && y == y1 {
+            break;
+        }
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+    }
+    coords
+}
+
+/// Gets the coordinates of a triangle with the given vertices, column by column. Triangles are
+/// convex, so they get filled in the same way as polygons are
+fn get_triangle(vertices: &[(usize, usize); 3]) -> Vec<Coord> {
+    get_polygon(vertices)
+}
+
+/// Gets the coordinates of a convex polygon with the given vertices, column by column. The edges
+/// get sorted by where they start, and each column gets filled in between where it crosses the
+/// edges that span it
+fn get_polygon(vertices: &[(usize, usize)]) -> Vec<Coord> {
+    let mut edges = (0..vertices.len())
+        .map(|i| {
+            let (a, b) = (vertices[i], vertices[(i + 1) % vertices.len()]);
+            if a.0 <= b.0 {
+                (a, b)
+            } else {
+                (b, a)
+            }
+        })
+        .collect::<Vec<_>>();
+    edges.sort_unstable_by_key(|&(start, _)| start.0);
+    let (first, last) = (
+        edges[0].0 .0,
+        vertices.iter().map(|&(x, _)| x).max().unwrap(),
+    );
+    let mut spanning = Vec::new();
+    let mut next = 0;
+    let mut coords = Vec::new();
+    for x in first..=last {
+        while next < edges.len() && edges[next].0 .0 <= x {
+            spanning.push(edges[next]);
+            next += 1;
+        }
+        spanning.retain(|&(_, end)| end.0 >= x);
+        let (mut top, mut bottom) = (f64::INFINITY, f64::NEG_INFINITY);
+        for &(start, end) in &spanning {
+            let (y1, y2) = if start.0 == end.0 {
+                (start.1 as f64, end.1 as f64)
+            } else {
+                let y = start.1 as f64
+                    + (x - start.0) as f64 * (end.1 as f64 - start.1 as f64)
+                        / (end.0 - start.0) as f64;
+                (y, y)
+            };
+            top = top.min(y1.min(y2));
+            bottom = bottom.max(y1.max(y2));
+        }
+        // a little slack, so crossings landing right on a pixel don't miss it by a rounding error
+        let (top, bottom) = ((top - 1e-9).ceil(), (bottom + 1e-9).floor());
+        if top <= bottom {
+            coords.extend((top as usize..=bottom as usize).map(|y| Coord::new(x, y)));
+        }
+    }
+    coords
+}
+
+/// Gets the coordinates of a blob with the given control points, column by column. Blobs can be
+/// concave, so the curve (as a polygon) can cross a column any even number of times, and the column
+/// gets filled in between every other pair of crossings
+fn get_blob(points: &[(usize, usize)]) -> Vec<Coord> {
+    let curve = geometry::blob_curve(points);
+    let first = points.iter().map(|&(x, _)| x).min().unwrap();
+    let last = points.iter().map(|&(x, _)| x).max().unwrap();
+    let mut coords = Vec::new();
+    let mut crossings = Vec::new();
+    for x in first..=last {
+        let column = x as f64;
+        crossings.clear();
+        for i in 0..curve.len() {
+            let (a, b) = (curve[i], curve[(i + 1) % curve.len()]);
+            // each edge only counts at one of its ends, so columns through a point of the curve
+            // don't cross it twice
+            if (a.0 <= column) != (b.0 <= column) {
+                crossings.push(a.1 + (column - a.0) * (b.1 - a.1) / (b.0 - a.0));
+            }
+        }
+        crossings.sort_unstable_by(f64::total_cmp);
+        // the curve can touch itself (or come within a rounding error), so each run starts below
+        // wherever the one before it ended
+        let mut free = 0;
+        for pair in crossings.chunks_exact(2) {
+            let top = ((pair[0] - 1e-9).ceil().max(0.0) as usize).max(free);
+            let bottom = (pair[1] + 1e-9).floor();
+            if bottom >= 0.0 && top <= bottom as usize {
+                coords.extend((top..=bottom as usize).map(|y| Coord::new(x, y)));
+                free = bottom as usize + 1;
+            }
+        }
+    }
+    coords
+}
+
+/// Gets the coordinates of the ellipse inscribed in the box from `top_left` up to (but not
+/// including) `bottom_right`: every pixel whose center is inside it
+fn get_ellipse(top_left: (usize, usize), bottom_right: (usize, usize)) -> Vec<Coord> {
+    let (cx, cy) = (
+        (top_left.0 + bottom_right.0) as f64 / 2.0,
+        (top_left.1 + bottom_right.1) as f64 / 2.0,
+    );
+    let (rx, ry) = (cx - top_left.0 as f64, cy - top_left.1 as f64);
     let mut coords = Vec::new();
+    // column by column, like rectangles
+    for x in top_left.0..bottom_right.0 {
+        let dx = (x as f64 + 0.5 - cx) / rx;
+        let half = ry * (1.0 - dx * dx).max(0.0).sqrt();
+        let y1 = ((cy - half - 0.5).ceil().max(0.0) as usize).max(top_left.1);
+        let y2 = ((cy + half - 0.5).floor() as usize + 1).min(bottom_right.1);
+        coords.extend((y1..y2).map(|y| Coord::new(x, y)));
+    }
+    coords
+}
+
+/// Gets the coordinates of a rectangle with the given vertices.
+fn get_rectangle(top_left: (usize, usize), bottom_right: (usize, usize)) -> Vec<Coord> {
+    let (w, h) = (bottom_right.0 - top_left.0, bottom_right.1 - top_left.1);
+    let mut coords = Vec::with_capacity(w * h);
+    // column by column, so that painting goes down spans of pixels next to each other
     for x in top_left.0..bottom_right.0 {
-        for y in top_left.1..bottom_right.1 {
-            coords.push((x, y));
+        coords.extend((top_left.1..bottom_right.1).map(|y| Coord::new(x, y)));
+    }
+    coords
+}
+
+/// Color that best fits the original image under the given coordinates.
+/// Since the cost is a sum of absolute differences, that's the median of each channel
+fn best_color(target: &Target, coords: &[Coord]) -> Rgb<u8> {
+    let mut histograms = [[0usize; 256]; 3];
+    for &coord in coords {
+        for (histogram, value) in zip(histograms.iter_mut(), target.pixel(coord).0) {
+            histogram[value as usize] += 1;
         }
     }
-    (coords, color)
+    Rgb(histograms.map(|histogram| {
+        let mut seen = 0;
+        for (value, count) in histogram.iter().enumerate() {
+            seen += count;
+            if 2 * seen >= coords.len() {
+                return value as u8;
+            }
+        }
+        u8::MAX
+    }))
 }
 
-/// Gets the coordinates and the color for the updated image
-fn get_neighbor(image: &Vec<Vec<Rgb<u8>>>, triangle: bool) -> (Vec<(usize, usize)>, Rgb<u8>) {
-    let w = image.len();
-    let h = image[0].len();
-    if !triangle {
-        let bottom_right = (1 + random::<usize>() % w, 1 + random::<usize>() % h);
-        let top_left = (
-            random::<usize>() % bottom_right.0,
-            random::<usize>() % bottom_right.1,
+/// A random color at most `jitter` away (per channel) from the color that best fits the
+/// original image under the given coordinates
+fn jittered_color(target: &Target, coords: &[Coord], jitter: f64) -> Rgb<u8> {
+    let Rgb(best) = best_color(target, coords);
+    Rgb(best.map(|value| {
+        (value as f64 + (random::<f64>() * 2.0 - 1.0) * jitter)
+            .round()
+            .clamp(0.0, 255.0) as u8
+    }))
+}
+
+/// Gets the shape, its coordinates, and the color for the updated image, as described in
+/// `random_shape`. Shapes that would only land on tiles that `converged` says are done get thrown
+/// away before they're rasterized
+#[allow(clippy::too_many_arguments)]
+fn get_neighbor(
+    target: &Target,
+    kind: ShapeKind,
+    vertices: usize,
+    stroke_width: (f64, f64),
+    grid: usize,
+    tileable: bool,
+    overhang: f64,
+    edge_bias: f64,
+    max_size: Option<usize>,
+    color_jitter: Option<f64>,
+    converged: Option<&ConvergedMask>,
+    cache: &mut RasterCache,
+) -> (Shape, Vec<Coord>, Rgb<u8>) {
+    let (w, h) = (target.width(), target.height());
+    let shape = loop {
+        let shape = random_shape(
+            w,
+            h,
+            kind,
+            vertices,
+            stroke_width,
+            grid,
+            tileable,
+            overhang,
+            edge_bias,
+            max_size,
         );
-        get_rectangle(top_left, bottom_right)
+        let (top_left, bottom_right) = shape.bounds();
+        if converged.is_none_or(|converged| !converged.covers(top_left, bottom_right)) {
+            break shape;
+        }
+    };
+    let coords = cache.coords(shape);
+    let color = shape_color(target, &coords, color_jitter);
+    (shape, coords, color)
+}
+
+/// A random shape of the given kind that fits in a `w` by `h` image.
+/// The shape's vertices are snapped to a grid with cells of `grid` pixels.
+/// If `tileable` is set, the shape can wrap around the edges of the image.
+/// Rectangles can hang up to `overhang` of the image's size off each edge, getting clipped to it.
+/// `edge_bias` of the shapes get one of their sides (or vertices) put near an edge or corner.
+/// If `max_size` is given, shapes are at most about that many pixels across.
+/// Polygons get up to `vertices` vertices, and strokes are anywhere between the two `stroke_width`s
+/// thick
+#[allow(clippy::too_many_arguments)]
+fn random_shape(
+    w: usize,
+    h: usize,
+    kind: ShapeKind,
+    vertices: usize,
+    stroke_width: (f64, f64),
+    grid: usize,
+    tileable: bool,
+    overhang: f64,
+    edge_bias: f64,
+    max_size: Option<usize>,
+) -> Shape {
+    // vertices only land on multiples of `grid`, so we pick grid cells instead of pixels
+    let cells_w = w.div_ceil(grid);
+    let cells_h = h.div_ceil(grid);
+    // in tileable mode shapes get shifted anywhere in the image and wrap around the edges
+    let (anchor_x, anchor_y) = if tileable {
+        (random::<usize>() % cells_w, random::<usize>() % cells_h)
     } else {
-        let v1 = (random::<usize>() % w, random::<usize>() % h);
-        let v2 = (random::<usize>() % w, random::<usize>() % h);
-        let v3 = (random::<usize>() % w, random::<usize>() % h);
-        // ensuring we have a valid triangle
-        if v1 == v2
-            || v2 == v3
-            || v1 == v3
-            || v1.0 == v2.0 && v2.0 == v3.0
-            || v1.1 == v2.1 && v2.1 == v3.1
-        {
-            get_neighbor(image, triangle)
+        (0, 0)
+    };
+    let max_cells = max_size.map(|size| (size / grid).max(1));
+    // shapes hardly ever reach the edges, let alone the corners, without being pulled up against
+    // them. Each axis is either left alone or pulled to its start (`false`) or end (`true`)
+    let edge =
+        (edge_bias > 0.0 && random::<f64>() < edge_bias).then(|| match random::<usize>() % 3 {
+            0 => (Some(random::<bool>()), None),
+            1 => (None, Some(random::<bool>())),
+            _ => (Some(random::<bool>()), Some(random::<bool>())),
+        });
+    // a cell near the start or end of `0..=last`
+    let near_edge = |last: usize, end: bool| {
+        let offset = random::<usize>() % ((last as f64 * EDGE_BAND).ceil() as usize).max(1);
+        if end {
+            last - offset
         } else {
-            get_triangle(&mut [v1, v2, v3])
+            offset
+        }
+    };
+    let shape = match kind {
+        // ellipses, circles, polygons, blobs, rotated rectangles, strokes, and stamps get a box
+        // picked like a rectangle, to be inscribed in (or turned around the middle of, or drawn
+        // across)
+        ShapeKind::Rectangle
+        | ShapeKind::RotatedRectangle
+        | ShapeKind::Ellipse
+        | ShapeKind::Circle
+        | ShapeKind::Polygon
+        | ShapeKind::Blob
+        | ShapeKind::Stroke
+        | ShapeKind::Stamp => {
+            // corners that can only land inside the image rarely make it all the way out to its
+            // edges, so they get picked from further out, and whatever hangs off gets clipped
+            let margin_w = (cells_w as f64 * overhang).ceil() as usize;
+            let margin_h = (cells_h as f64 * overhang).ceil() as usize;
+            let bottom_right = (
+                1 + random::<usize>() % (cells_w + 2 * margin_w),
+                1 + random::<usize>() % (cells_h + 2 * margin_h),
+            );
+            let top_left = match max_cells {
+                Some(max) => (
+                    bottom_right.0 - 1 - random::<usize>() % bottom_right.0.min(max),
+                    bottom_right.1 - 1 - random::<usize>() % bottom_right.1.min(max),
+                ),
+                None => (
+                    random::<usize>() % bottom_right.0,
+                    random::<usize>() % bottom_right.1,
+                ),
+            };
+            let clip = |(x, y): (usize, usize)| {
+                (
+                    x.saturating_sub(margin_w).min(cells_w),
+                    y.saturating_sub(margin_h).min(cells_h),
+                )
+            };
+            let (mut top_left, mut bottom_right) = (clip(top_left), clip(bottom_right));
+            if let Some((edge_x, edge_y)) = edge {
+                match edge_x {
+                    Some(false) => top_left.0 = near_edge(cells_w, false),
+                    Some(true) => bottom_right.0 = near_edge(cells_w, true),
+                    None => {}
+                }
+                match edge_y {
+                    Some(false) => top_left.1 = near_edge(cells_h, false),
+                    Some(true) => bottom_right.1 = near_edge(cells_h, true),
+                    None => {}
+                }
+            }
+            // wrapping shapes can reach as far as another image past the edges
+            let (max_x, max_y) = if tileable { (2 * w, 2 * h) } else { (w, h) };
+            let top_left = (
+                (top_left.0 + anchor_x) * grid,
+                (top_left.1 + anchor_y) * grid,
+            );
+            let bottom_right = (
+                ((bottom_right.0 + anchor_x) * grid).min(max_x),
+                ((bottom_right.1 + anchor_y) * grid).min(max_y),
+            );
+            // a side pulled to an edge (or cut off at the image) can end up past the opposite one,
+            // and nothing is drawn in a box turned inside out, so another one gets picked
+            if top_left.0 > bottom_right.0 || top_left.1 > bottom_right.1 {
+                return random_shape(
+                    w,
+                    h,
+                    kind,
+                    vertices,
+                    stroke_width,
+                    grid,
+                    tileable,
+                    overhang,
+                    edge_bias,
+                    max_size,
+                );
+            }
+            match kind {
+                ShapeKind::Rectangle => Shape::Rectangle {
+                    top_left,
+                    bottom_right,
+                },
+                ShapeKind::Circle => {
+                    // squared off by its shorter side around the middle of the box, or against
+                    // whichever of its sides got pulled to an edge
+                    let side = bottom_right
+                        .0
+                        .saturating_sub(top_left.0)
+                        .min(bottom_right.1.saturating_sub(top_left.1));
+                    let (edge_x, edge_y) = edge.unwrap_or((None, None));
+                    let square = |start: usize, end: usize, edge: Option<bool>| {
+                        let extra = end.saturating_sub(start + side);
+                        let start = match edge {
+                            Some(false) => start,
+                            Some(true) => start + extra,
+                            None => start + extra / 2 / grid * grid,
+                        };
+                        (start, start + side)
+                    };
+                    let (x1, x2) = square(top_left.0, bottom_right.0, edge_x);
+                    let (y1, y2) = square(top_left.1, bottom_right.1, edge_y);
+                    Shape::Ellipse {
+                        top_left: (x1, y1),
+                        bottom_right: (x2, y2),
+                    }
+                }
+                ShapeKind::Polygon | ShapeKind::Blob => {
+                    // vertices at random angles around the ellipse inscribed in the box always
+                    // make a convex polygon. They can go on the bottom and right sides of the box
+                    // too, as long as that's still in the image. Blobs' control points go
+                    // anywhere from halfway out to the ellipse, which makes them lumpier
+                    let last = |cells: usize, size: usize| {
+                        if tileable {
+                            2 * size - 1
+                        } else {
+                            (cells - 1) * grid
+                        }
+                    };
+                    let right = bottom_right.0.min(last(cells_w, w));
+                    let bottom = bottom_right.1.min(last(cells_h, h));
+                    let (cx, cy) = (
+                        (top_left.0 + right) as f64 / 2.0,
+                        (top_left.1 + bottom) as f64 / 2.0,
+                    );
+                    let (rx, ry) = (cx - top_left.0 as f64, cy - top_left.1 as f64);
+                    let count = match kind {
+                        ShapeKind::Blob => {
+                            let choices = MAX_BLOB_POINTS - MIN_BLOB_POINTS + 1;
+                            MIN_BLOB_POINTS + random::<usize>() % choices
+                        }
+                        _ => vertices,
+                    };
+                    let mut angles = (0..count)
+                        .map(|_| random::<f64>() * std::f64::consts::TAU)
+                        .collect::<Vec<_>>();
+                    angles.sort_unstable_by(f64::total_cmp);
+                    let snap = |value: f64, low: usize, high: usize| {
+                        ((value / grid as f64).round() as usize * grid).clamp(low, high.max(low))
+                    };
+                    let vertices = angles
+                        .into_iter()
+                        .map(|angle| {
+                            let reach = match kind {
+                                ShapeKind::Blob => 0.5 + random::<f64>() / 2.0,
+                                _ => 1.0,
+                            };
+                            (
+                                snap(cx + reach * rx * angle.cos(), top_left.0, right),
+                                snap(cy + reach * ry * angle.sin(), top_left.1, bottom),
+                            )
+                        })
+                        .collect::<Vec<_>>();
+                    match kind {
+                        ShapeKind::Blob => Shape::Blob(Polygon::new(&vertices)),
+                        _ => Shape::Polygon(Polygon::new(&vertices)),
+                    }
+                }
+                ShapeKind::RotatedRectangle => {
+                    // the box turned around its middle by a random angle, with whatever ends up
+                    // off the image clipped off, as a polygon of up to 8 vertices
+                    let (cx, cy) = (
+                        (top_left.0 + bottom_right.0) as f64 / 2.0,
+                        (top_left.1 + bottom_right.1) as f64 / 2.0,
+                    );
+                    let (rx, ry) = (cx - top_left.0 as f64, cy - top_left.1 as f64);
+                    let angle = random::<f64>() * std::f64::consts::PI;
+                    let (sin, cos) = angle.sin_cos();
+                    let corners = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)].map(
+                        |(a, b): (f64, f64)| {
+                            (
+                                cx + a * rx * cos - b * ry * sin,
+                                cy + a * rx * sin + b * ry * cos,
+                            )
+                        },
+                    );
+                    // wrapping shapes only have to stay off the start of the image
+                    let last = |cells: usize| {
+                        if tileable {
+                            f64::INFINITY
+                        } else {
+                            ((cells - 1) * grid) as f64
+                        }
+                    };
+                    let vertices = geometry::clip_polygon(&corners, last(cells_w), last(cells_h))
+                        .into_iter()
+                        .map(|(x, y)| {
+                            let snap = |value: f64| (value / grid as f64).round() as usize * grid;
+                            (snap(x), snap(y))
+                        })
+                        .collect::<Vec<_>>();
+                    Shape::Polygon(Polygon::new(&vertices))
+                }
+                ShapeKind::Stroke => {
+                    // from one corner of the box to the opposite one, either way across, rounded
+                    // off at both ends and clipped to the image as a polygon of up to 12 vertices
+                    let (x1, x2) = (top_left.0 as f64, bottom_right.0 as f64);
+                    let (y1, y2) = if random::<bool>() {
+                        (top_left.1 as f64, bottom_right.1 as f64)
+                    } else {
+                        (bottom_right.1 as f64, top_left.1 as f64)
+                    };
+                    let (thinnest, thickest) = stroke_width;
+                    let width = thinnest + random::<f64>() * (thickest - thinnest);
+                    let outline = geometry::capsule((x1, y1), (x2, y2), width);
+                    let last = |cells: usize| {
+                        if tileable {
+                            f64::INFINITY
+                        } else {
+                            ((cells - 1) * grid) as f64
+                        }
+                    };
+                    let vertices = geometry::clip_polygon(&outline, last(cells_w), last(cells_h))
+                        .into_iter()
+                        .map(|(x, y)| {
+                            let snap = |value: f64| (value / grid as f64).round() as usize * grid;
+                            (snap(x), snap(y))
+                        })
+                        .collect::<Vec<_>>();
+                    Shape::Polygon(Polygon::new(&vertices))
+                }
+                ShapeKind::Stamp => Shape::Stamp(Placement::random(top_left, bottom_right)),
+                _ => Shape::Ellipse {
+                    top_left,
+                    bottom_right,
+                },
+            }
+        }
+        ShapeKind::Triangle => {
+            let random_cell = || {
+                (
+                    anchor_x + random::<usize>() % cells_w,
+                    anchor_y + random::<usize>() % cells_h,
+                )
+            };
+            // with a size limit, the other two vertices go within it of the first one
+            let first = random_cell();
+            let nearby_cell = || match max_cells {
+                Some(max) => {
+                    let reach = max;
+                    let near = |start: usize, anchor: usize, cells: usize| {
+                        (start + random::<usize>() % (2 * reach + 1))
+                            .saturating_sub(reach)
+                            .clamp(anchor, anchor + cells - 1)
+                    };
+                    (
+                        near(first.0, anchor_x, cells_w),
+                        near(first.1, anchor_y, cells_h),
+                    )
+                }
+                None => random_cell(),
+            };
+            let mut vertices =
+                [first, nearby_cell(), nearby_cell()].map(|(x, y)| (x * grid, y * grid));
+            if let Some((edge_x, edge_y)) = edge {
+                let vertex = &mut vertices[random::<usize>() % 3];
+                if let Some(end) = edge_x {
+                    vertex.0 = near_edge(cells_w - 1, end) * grid;
+                }
+                if let Some(end) = edge_y {
+                    vertex.1 = near_edge(cells_h - 1, end) * grid;
+                }
+            }
+            Shape::Triangle(vertices)
         }
+    };
+    geometry::validate(shape, w, h, tileable).unwrap_or_else(|| {
+        random_shape(
+            w,
+            h,
+            kind,
+            vertices,
+            stroke_width,
+            grid,
+            tileable,
+            overhang,
+            edge_bias,
+            max_size,
+        )
+    })
+}
+
+/// Color for a shape covering the given coordinates. If `color_jitter` is given, it's a jitter of
+/// at most that much around the color that best fits the original image instead of being completely
+/// random
+fn shape_color(target: &Target, coords: &[Coord], color_jitter: Option<f64>) -> Rgb<u8> {
+    match color_jitter {
+        Some(jitter) if !coords.is_empty() => jittered_color(target, coords, jitter),
+        _ => random_color(),
+    }
+}
+
+/// A linear gradient for a shape covering the given coordinates, from one of them to another picked
+/// at random. Shapes that are only one pixel get a single color instead
+fn shape_gradient(target: &Target, coords: &[Coord], color_jitter: Option<f64>) -> Fill {
+    if coords.len() < 2 {
+        return Fill::Solid(shape_color(target, coords, color_jitter));
+    }
+    let i = random::<usize>() % coords.len();
+    let j = (i + 1 + random::<usize>() % (coords.len() - 1)) % coords.len();
+    let (start, end) = ((coords[i].x, coords[i].y), (coords[j].x, coords[j].y));
+    Fill::Linear {
+        start,
+        end,
+        colors: gradient_colors(target, coords, start, end, color_jitter),
     }
 }
 
+/// Colors for the ends of a linear gradient from `start` to `end` over the given coordinates, each
+/// picked like `shape_color` from the half of them nearer to its end
+fn gradient_colors(
+    target: &Target,
+    coords: &[Coord],
+    start: (usize, usize),
+    end: (usize, usize),
+    color_jitter: Option<f64>,
+) -> [Rgb<u8>; 2] {
+    let (near_start, near_end): (Vec<Coord>, Vec<Coord>) = coords
+        .iter()
+        .partition(|&&coord| gradient_position(start, end, coord) < 0.5);
+    [
+        shape_color(target, &near_start, color_jitter),
+        shape_color(target, &near_end, color_jitter),
+    ]
+}
+
+/// The same kind of fill as `fill` (a gradient keeps its ends where they are) for a shape covering
+/// the given coordinates, with its colors picked again
+fn refit_fill(fill: Fill, target: &Target, coords: &[Coord], color_jitter: Option<f64>) -> Fill {
+    match fill {
+        Fill::Solid(_) => Fill::Solid(shape_color(target, coords, color_jitter)),
+        Fill::Linear { start, end, .. } => Fill::Linear {
+            start,
+            end,
+            colors: gradient_colors(target, coords, start, end, color_jitter),
+        },
+    }
+}
+
+/// Like `get_neighbor`, but for a triangle built on `edge` with a random third vertex
+fn get_mesh_neighbor(
+    target: &Target,
+    grid: usize,
+    edge: [(usize, usize); 2],
+    color_jitter: Option<f64>,
+    cache: &mut RasterCache,
+) -> (Shape, Vec<Coord>, Rgb<u8>) {
+    let (w, h) = (target.width(), target.height());
+    let [v1, v2] = edge;
+    let shape = loop {
+        let v3 = (
+            random::<usize>() % w.div_ceil(grid) * grid,
+            random::<usize>() % h.div_ceil(grid) * grid,
+        );
+        if let Some(shape) = geometry::validate(Shape::Triangle([v1, v2, v3]), w, h, false) {
+            break shape;
+        }
+    };
+    let coords = cache.coords(shape);
+    let color = shape_color(target, &coords, color_jitter);
+    (shape, coords, color)
+}
+
 /// Difference between two pixels as a single value
 fn pixel_difference(pixel1: Rgb<u8>, pixel2: Rgb<u8>) -> u64 {
     let [r1, g1, b1] = pixel1.0;
@@ -142,261 +1268,1724 @@ fn pixel_difference(pixel1: Rgb<u8>, pixel2: Rgb<u8>) -> u64 {
 }
 
 /// RMSE difference between the original image and the generated image
-fn get_cost(original_image: &Vec<Vec<Rgb<u8>>>, generated_image: &Vec<Vec<Rgb<u8>>>) -> f64 {
-    let w = original_image.len();
-    let h = original_image[0].len();
-    let mut s = 0;
-    for x in 0..w {
-        for y in 0..h {
-            let pixel1 = original_image[x][y];
-            let pixel2 = generated_image[x][y];
-            s += pixel_difference(pixel1, pixel2);
-        }
-    }
+fn get_cost(target: &Target, generated_image: &[Vec<Rgb<u8>>]) -> f64 {
+    let w = target.width();
+    let h = target.height();
+    let s = if !target.is_plain() {
+        // the kernels can't weight their sums (or apply losses), so those costs add up pixel by
+        // pixel
+        (0..w)
+            .flat_map(|x| (0..h).map(move |y| Coord::new(x, y)))
+            .map(|coord| target.difference(coord, generated_image.at(coord)))
+            .sum::<f64>()
+    } else {
+        let generated_planes = to_planes(generated_image.iter().flatten().copied());
+        sum_plane_differences(&target.planes(), &generated_planes) as f64
+    };
 
-    let dist = ((s as f64 * s as f64) / ((w * h * 3) as f64)).sqrt();
-    dist
+    ((s * s) / ((w * h * 3) as f64)).sqrt()
 }
 
 /// A less expensive version of `get_cost`.
 /// Takes a previous `get_cost` result, resets it to the sum of pixel differences,
-/// subtracts the pixel differences between the original image and the generated image for a
-/// given area, adds back in the pixel differences between the original image and the new color
-/// and then calculates the new distance result
+/// subtracts the pixel differences between the original image and the old pixels of the generated
+/// image for a given area, adds back in the pixel differences between the original image and the
+/// new color (blended with the old pixels according to `blend_mode`)
+/// and then calculates the new distance result.
+/// `old_pixels` are what the generated image had at each of `coords` before the new color.
+/// `old_error` is the sum of the pixel differences under `coords`, if it's already known
+#[allow(clippy::too_many_arguments)]
 fn update_cost(
     previous_cost: f64,
-    original_image: &Vec<Vec<Rgb<u8>>>,
-    annealed_image: &Vec<Vec<Rgb<u8>>>,
-    coords: &Vec<(usize, usize)>,
+    target: &Target,
+    coords: &[Coord],
+    old_pixels: &[Rgb<u8>],
     new_color: Rgb<u8>,
-    sample: Option<u32>,
+    blend_mode: BlendMode,
+    old_error: Option<u64>,
 ) -> f64 {
     // if there is nothing to update, we just return the previous cost
-    if coords.len() == 0 {
+    if coords.is_empty() {
         return previous_cost;
     }
-    let w = original_image.len();
-    let h = original_image[0].len();
+    let w = target.width();
+    let h = target.height();
     // restoring the sum from `get_cost`
     let mut s = (previous_cost * previous_cost * (w * h * 3) as f64).sqrt();
-    match sample {
-        None => {
-            // storing `original_image`'s pixels so we don't have to fetch them again
-            // because apparently `get_pixel` is an expensive operation??
-            let original_pixels = coords
-                .par_iter()
-                .map(|(x, y)| original_image[*x as usize][*y as usize])
-                .collect::<Vec<Rgb<u8>>>();
-            let annealed_pixels = coords
-                .par_iter()
-                .map(|(x, y)| annealed_image[*x as usize][*y as usize])
-                .collect::<Vec<Rgb<u8>>>();
-            // subtracting off the relevant pixels from the first generated image.
-            s -= (0..original_pixels.len())
-                .into_par_iter()
-                .map(|i| pixel_difference(original_pixels[i], annealed_pixels[i]) as f64)
-                .sum::<f64>();
-            // adding in the relevant pixels from the second generated image
-            s += original_pixels
-                .par_iter()
-                .map(|pixel| pixel_difference(*pixel, new_color) as f64)
-                .sum::<f64>();
-        }
-        Some(n) => {
-            // getting a linspace of indices to sample from
-            let dx = (coords.len() - 1) as f64 / (n - 1) as f64;
-            let sample_indices = (0..n)
-                .map(|i| (i as f64 * dx) as usize)
-                .collect::<Vec<usize>>();
-            // sampling the original image
-            let original_pixels_sample = if (n as usize) < coords.len() {
-                sample_indices
-                    .iter()
-                    .map(|&i| {
-                        let (x, y) = coords[i];
-                        original_image[x as usize][y as usize]
-                    })
-                    .collect::<Vec<Rgb<u8>>>()
-            } else {
-                coords
-                    .iter()
-                    .map(|(x, y)| original_image[*x as usize][*y as usize])
-                    .collect::<Vec<Rgb<u8>>>()
-            };
-            // sampling the old pixels
-            let annealed_sample = if (n as usize) < coords.len() {
-                sample_indices
-                    .iter()
-                    .map(|&i| {
-                        let (x, y) = coords[i];
-                        annealed_image[x as usize][y as usize]
-                    })
-                    .collect::<Vec<Rgb<u8>>>()
-            } else {
-                coords
-                    .iter()
-                    .map(|(x, y)| annealed_image[*x as usize][*y as usize])
-                    .collect::<Vec<Rgb<u8>>>()
-            };
-            // subtracting off the pixel differences between the original image and the old pixels
-            s -= zip(original_pixels_sample.iter(), annealed_sample)
-                .map(|(pixel1, pixel2)| pixel_difference(*pixel1, pixel2) as f64)
-                .sum::<f64>();
-            // adding back in the pixel differences between the original image and the new color
-            s += original_pixels_sample
-                .iter()
-                .map(|&pixel| pixel_difference(pixel, new_color) as f64)
-                .sum::<f64>();
+    if !target.is_plain() {
+        for (&coord, &pixel) in zip(coords, old_pixels) {
+            s -= target.difference(coord, pixel);
+            s += target.difference(coord, blend_mode.blend(pixel, new_color));
         }
+        return ((s * s) / ((w * h * 3) as f64)).sqrt();
     }
+    // splitting the pixels into channel planes up front, so the kernels can run over each
+    // channel without having to pick it out of the pixels
+    let original_planes = target.gather(coords);
+    // shapes that fit in one chunk don't get split across threads anyway, so they skip the pool
+    let small = coords.len() <= CHUNK_SIZE;
+    let sum_differences = |planes1: &[Vec<u8>; 3], planes2: &[Vec<u8>; 3]| {
+        if small {
+            sum_plane_differences(planes1, planes2)
+        } else {
+            par_sum_plane_differences(planes1, planes2)
+        }
+    };
+    let sum_color_differences = |planes: &[Vec<u8>; 3], color: Rgb<u8>| {
+        if small {
+            sum_plane_color_differences(planes, color)
+        } else {
+            par_sum_plane_color_differences(planes, color)
+        }
+    };
+    // subtracting off the pixel differences between the original image and the old pixels
+    s -= old_error.unwrap_or_else(|| {
+        let old_planes = to_planes(old_pixels.iter().copied());
+        sum_differences(&original_planes, &old_planes)
+    }) as f64;
+    // adding back in the pixel differences between the original image and the new color
+    s += if blend_mode == BlendMode::NORMAL {
+        sum_color_differences(&original_planes, new_color) as f64
+    } else {
+        let mut blended_planes = to_planes(old_pixels.iter().copied());
+        for (plane, value) in zip(&mut blended_planes, new_color.0) {
+            blend_mode.blend_plane(plane, value);
+        }
+        sum_differences(&original_planes, &blended_planes) as f64
+    };
     // recalculating the distance
-    let dist = ((s as f64 * s as f64) / ((w * h * 3) as f64)).sqrt();
-    dist
+    ((s * s) / ((w * h * 3) as f64)).sqrt()
 }
 
-/// Approximate an inputted image using a simulated annealing algorithm
-fn anneal(
-    original_image: &Vec<Vec<Rgb<u8>>>,
-    alpha: f64,
-    triangle: bool,
-    sample: Option<u32>,
-    multithreading: bool,
-) -> Vec<Vec<Rgb<u8>>> {
-    let initial_temp = 1e3;
+/// Like `update_cost`, but for when the sums of the pixel differences under `coords` before and
+/// after painting them are already known, as `errors`
+fn update_cost_from_errors(
+    previous_cost: f64,
+    target: &Target,
+    coords: &[Coord],
+    (old_error, new_error): (u64, u64),
+) -> f64 {
+    if coords.is_empty() {
+        return previous_cost;
+    }
+    let pixels = (target.width() * target.height() * 3) as f64;
+    let mut s = (previous_cost * previous_cost * pixels).sqrt();
+    s -= old_error as f64;
+    s += new_error as f64;
+    ((s * s) / pixels).sqrt()
+}
+
+/// Like `update_cost`, but for when every pixel in `coords` changes to its own new color,
+/// given by the matching pixel in `new_pixels`
+fn update_cost_to_pixels(
+    previous_cost: f64,
+    target: &Target,
+    coords: &[Coord],
+    old_pixels: &[Rgb<u8>],
+    new_pixels: &[Rgb<u8>],
+) -> f64 {
+    let w = target.width();
+    let h = target.height();
+    // restoring the sum from `get_cost`
+    let mut s = (previous_cost * previous_cost * (w * h * 3) as f64).sqrt();
+    for ((&coord, &old_pixel), &new_pixel) in zip(zip(coords, old_pixels), new_pixels) {
+        s -= target.difference(coord, old_pixel);
+        s += target.difference(coord, new_pixel);
+    }
+    ((s * s) / ((w * h * 3) as f64)).sqrt()
+}
+
+/// Approximate an inputted image using a simulated annealing algorithm, going by `params`.
+/// Returns the annealed image and the accepted shapes in the order they were accepted
+fn anneal(target: &Target, params: AnnealParams) -> (Image, Vec<(Shape, Fill, BlendMode)>) {
+    let AnnealParams {
+        alpha,
+        shape_kinds,
+        vertices,
+        stroke_width,
+        grid,
+        tileable,
+        overhang,
+        edge_bias,
+        adaptive_sizes,
+        converged_threshold,
+        symmetry,
+        blend_modes,
+        opacity,
+        anti_alias,
+        gradients,
+        color_jitter,
+        removal_moves,
+        recolor_moves,
+        channel_moves,
+        sample,
+        multithreading,
+        shared_canvas,
+        throttle,
+        progressive_output,
+        frames,
+        status_interval,
+        max_iterations,
+        min_iterations_per_second,
+        dhash_distance,
+        journal,
+        mut coordinator,
+        mut hints,
+        orientation,
+        mesh,
+        color_depth_schedule,
+        polish,
+        mut live_params,
+        superpixels,
+        edge_strokes,
+        flow_strokes,
+        fixed_shapes,
+        color_target,
+        min_improvement,
+        mut budget,
+        footprints,
+    } = params;
+    let initial_temp: f64 = 1e3;
     let final_temp = 0.001;
-    let available_parallelism = usize::from(thread::available_parallelism().unwrap());
     let mut current_temp = initial_temp;
-    let total_loops = -(1e6f64).log(alpha);
+    let mut params = Params {
+        alpha,
+        sample,
+        removal_moves,
+        recolor_moves,
+        channel_moves,
+        shape_kinds: shape_kinds.to_vec(),
+    };
+    let mut total_loops = max_iterations
+        .map_or(f64::INFINITY, |n| n as f64)
+        .min(-(1e6f64).log(alpha));
     let total_time_start = Instant::now();
-    let mut image = {
-        let raw = vec![vec![Rgb([0u8, 0u8, 0u8]); original_image[0].len()]; original_image.len()];
-        if multithreading {
-            EitherThreadedImage::MultiThreaded(Arc::new(Mutex::new(raw)))
-        } else {
-            EitherThreadedImage::SingleThreaded(raw)
+    let mut raw = vec![vec![Rgb([0u8, 0u8, 0u8]); target.height()]; target.width()];
+    // what's left showing wherever a layer gets taken back out
+    let mut initial = raw.clone();
+    for &(coord, color) in fixed_shapes.map_or(&[][..], FixedShapes::pixels) {
+        *initial.at_mut(coord) = color;
+    }
+    let mut layers = Layers::new(initial, footprints, anti_alias);
+    let mut num_loops = 0.0;
+    let mut journal = journal.map(|(journal, entries)| {
+        if let Some(last) = entries.last() {
+            num_loops = last.iteration() as f64 + 1.0;
+            current_temp = initial_temp * alpha.powf(num_loops);
         }
-    };
-    let mut cost = match image {
-        EitherThreadedImage::MultiThreaded(ref guard) => {
-            get_cost(&original_image, &guard.lock().unwrap())
+        replay(&entries, &mut raw, &mut layers);
+        journal
+    });
+    // replayed shapes count towards their region's share
+    if let Some(ref mut budget) = budget {
+        for index in (0..layers.len()).filter(|&index| layers.get(index).is_some()) {
+            budget.add(&layers.layer_coords(index));
         }
-        EitherThreadedImage::SingleThreaded(ref raw) => get_cost(&original_image, &raw),
-    };
+    }
+    let resumed_loops = num_loops;
+    // fixed shapes go on top of whatever got replayed, since nothing could have painted over them
+    for &(coord, color) in fixed_shapes.map_or(&[][..], FixedShapes::pixels) {
+        *raw.at_mut(coord) = color;
+    }
+    let mut cache = RasterCache::new(target.width(), target.height(), grid);
+    // edges of every accepted triangle, for growing the mesh from
+    let mut mesh_edges = mesh.then(|| {
+        layers
+            .shapes()
+            .into_iter()
+            .filter_map(|(shape, _, _)| match shape {
+                Shape::Triangle(vertices) => Some(triangle_edges(vertices)),
+                Shape::Rectangle { .. }
+                | Shape::Ellipse { .. }
+                | Shape::Polygon(_)
+                | Shape::Blob(_)
+                | Shape::Stamp(_) => None,
+            })
+            .flatten()
+            .collect::<Vec<_>>()
+    });
+    // sampled costs are estimates anyway, so only exact costs get the summed-area table
+    // (and only plain ones, since it doesn't know about weights or losses, nor about fixed shapes)
+    let error_sums = shape_kinds.contains(&ShapeKind::Rectangle)
+        && sample.is_none()
+        && target.is_plain()
+        && fixed_shapes.is_none();
+    let mut canvas = Canvas::new(raw, multithreading, target, error_sums, shared_canvas);
+    if let Some(fixed_shapes) = fixed_shapes {
+        let coords = fixed_shapes
+            .pixels()
+            .iter()
+            .map(|&(coord, _)| coord)
+            .collect::<Vec<_>>();
+        canvas.lock(&coords);
+    }
+    let mut cost = canvas.read(|image| get_cost(target, image));
 
     let mut time_elapsed = total_time_start.elapsed();
-    let mut num_loops = 0.0;
-    while current_temp >= final_temp {
+    let mut busy_time = Duration::ZERO;
+    let mut last_save = Instant::now();
+    let mut snapshots = 0;
+    let mut last_status = Instant::now();
+    let mut watchdog_time = Duration::ZERO;
+    let mut watchdog_loops = 0.0;
+    let mut max_size = None;
+    let mut converged = converged_threshold
+        .map(|threshold| ConvergedMask::new(target.width(), target.height(), threshold));
+    let target_dhash = dhash_distance
+        .map(|_| dhash::dhash(target.width(), target.height(), |coord| target.pixel(coord)));
+    // how many random shapes of each kind got proposed, and how many of those got accepted, kept
+    // for every kind since the live settings can change which ones get proposed
+    let mut kind_stats = vec![(0u64, 0u64); ShapeKind::value_variants().len()];
+    while current_temp >= final_temp && num_loops < total_loops {
         let loop_start = Instant::now();
-        let (coords, new_color) = match image {
-            EitherThreadedImage::MultiThreaded(ref guard) => {
-                get_neighbor(&guard.lock().unwrap(), triangle)
+        if adaptive_sizes && (num_loops as u64).is_multiple_of(SIZE_INTERVAL) {
+            max_size = Some(canvas.read(|image| {
+                residual_scale::max_shape_size(target.width(), target.height(), |coord| {
+                    let (original, painted) = (target.pixel(coord), image.at(coord));
+                    [0, 1, 2].map(|c| original[c] as f64 - painted[c] as f64)
+                })
+            }));
+        }
+        if let Some(ref mut converged) = converged {
+            if (num_loops as u64).is_multiple_of(CONVERGED_INTERVAL) {
+                canvas.read(|image| converged.update(target, image));
+            }
+        }
+        let progress = (initial_temp / current_temp).ln() / (initial_temp / final_temp).ln();
+        // the jitter starts out covering every color and shrinks as the temperature drops
+        let jitter = color_jitter.then(|| (255.0 * (1.0 - progress)).max(1.0));
+        let color_depth = color_depth_schedule.map(|(start, end)| {
+            (start as f64 + (end as f64 - start as f64) * progress.clamp(0.0, 1.0)).round() as u8
+        });
+        let quantize = |color| color_depth.map_or(color, |bits| quantize_color(color, bits));
+        // once it's cold, barely-better moves are mostly noise (especially when sampling), and
+        // accepting them just churns the canvas
+        let min_improvement = min_improvement
+            .filter(|&(_, below)| current_temp < below)
+            .map_or(0.0, |(epsilon, _)| epsilon);
+        let accept = |cost_diff: f64| {
+            if min_improvement > 0.0 {
+                cost_diff <= -min_improvement
+            } else {
+                cost_diff < 0.0 || acceptance_random::<f64>() < (-cost_diff / current_temp).exp()
             }
-            EitherThreadedImage::SingleThreaded(ref raw) => get_neighbor(&raw, triangle),
         };
-        let neighbor_cost = match image {
-            EitherThreadedImage::MultiThreaded(ref guard) => update_cost(
-                cost,
-                original_image,
-                &guard.lock().unwrap(),
-                &coords,
-                new_color,
-                sample,
-            ),
-            EitherThreadedImage::SingleThreaded(ref raw) => {
-                update_cost(cost, original_image, &raw, &coords, new_color, sample)
+        // the same decision as `accept`, turned around into the most a move can raise the cost by
+        // and still get accepted, for when the cost is only known roughly
+        let threshold = || {
+            if min_improvement > 0.0 {
+                -min_improvement
+            } else {
+                -current_temp * acceptance_random::<f64>().ln()
             }
         };
-        let cost_diff = neighbor_cost - cost;
-        if cost_diff < 0.0 || random::<f64>() < (-cost_diff / current_temp).exp() {
-            cost = neighbor_cost;
-            // changing colors on the image to match the neighboring image
-            match image {
-                EitherThreadedImage::MultiThreaded(ref guard) => {
-                    let coord_chunks = coords.chunks((coords.len() / available_parallelism).max(1));
-                    thread::scope(|s| {
-                        for chunk in coord_chunks {
-                            let image = Arc::clone(&guard);
-                            s.spawn(move || {
-                                let mut image = image.lock().unwrap();
-                                for (x, y) in chunk {
-                                    image[*x][*y] = new_color;
-                                }
-                            });
+        // proposing to take one of the accepted shapes back out, or to give one of the oldest a new
+        // color, instead of adding a new one. The oldest shapes were accepted while the temperature
+        // was still high, so they're the most likely to be bad. Channel moves only fit one of a
+        // random shape's channels to the best color, which changes it less than a whole new color
+        // would, so they still get accepted late on when colors only need touching up
+        let move_roll = random::<f64>();
+        let revision = if layers.is_empty() {
+            None
+        } else if move_roll < params.removal_moves {
+            Some((layers.random_index(), None))
+        } else if move_roll < params.removal_moves + params.recolor_moves {
+            let index = layers.oldest_unrevisited();
+            let fill = layers.get(index).unwrap().1;
+            let fill = refit_fill(fill, target, &layers.layer_coords(index), jitter);
+            Some((index, Some(fill.map_colors(|_, color| quantize(color)))))
+        } else if move_roll < params.removal_moves + params.recolor_moves + params.channel_moves {
+            let index = layers.random_index();
+            let fill = layers.get(index).unwrap().1;
+            let best = refit_fill(fill, target, &layers.layer_coords(index), jitter);
+            let channel = random::<usize>() % 3;
+            let fill = fill.map_colors(|i, mut color| {
+                color[channel] = quantize(best.colors()[i])[channel];
+                color
+            });
+            Some((index, Some(fill)))
+        } else {
+            None
+        };
+        if let Some((index, new_fill)) = revision {
+            let (coords, new_pixels) = match new_fill {
+                Some(fill) => layers.recolored(index, fill),
+                None => layers.without(index),
+            };
+            let transaction = canvas.replace(coords, &new_pixels);
+            let neighbor_cost = update_cost_to_pixels(
+                cost,
+                target,
+                transaction.coords(),
+                transaction.old_pixels(),
+                transaction.new_pixels().unwrap_or(&new_pixels),
+            );
+            if accept(neighbor_cost - cost) {
+                cost = neighbor_cost;
+                match new_fill {
+                    Some(fill) => {
+                        if let Some(ref mut journal) = journal {
+                            journal.recolor(num_loops as u64, index, fill);
                         }
-                    });
-                }
-                EitherThreadedImage::SingleThreaded(ref mut raw) => {
-                    for (x, y) in coords.iter() {
-                        raw[*x][*y] = new_color;
+                        layers.recolor(index, fill);
+                    }
+                    None => {
+                        if let Some(ref mut journal) = journal {
+                            journal.remove(num_loops as u64, index);
+                        }
+                        if let Some(ref mut budget) = budget {
+                            budget.remove(&layers.layer_coords(index));
+                        }
+                        layers.remove(index);
                     }
                 }
-            };
+                canvas.commit(transaction);
+            } else {
+                canvas.rollback(transaction);
+            }
+        } else {
+            // workers' shapes get proposed whenever there are some, and random ones otherwise.
+            // Either way, shapes are kept to where the hints want them, to the region that's due one,
+            // and on the mesh if there is one
+            let (shape, coords, new_fill, blend_mode, kind) = loop {
+                let proposal = match coordinator.as_ref().and_then(Coordinator::proposal) {
+                    Some((shape, fill, blend_mode)) => {
+                        (shape, cache.coords(shape), fill, blend_mode, None)
+                    }
+                    None => {
+                        // shapes taken from the original image stand in for some of the random ones
+                        let content_roll = random::<f64>();
+                        let superpixel_fraction = superpixels.map_or(0.0, |(_, fraction)| fraction);
+                        let edge_fraction = edge_strokes.map_or(0.0, |(_, fraction)| fraction);
+                        let flow_fraction = flow_strokes.map_or(0.0, |(_, fraction)| fraction);
+                        let content_shape = if content_roll < superpixel_fraction {
+                            superpixels.and_then(|(superpixels, _)| superpixels.proposal())
+                        } else if content_roll < superpixel_fraction + edge_fraction {
+                            edge_strokes.and_then(|(edge_strokes, _)| edge_strokes.proposal())
+                        } else if content_roll < superpixel_fraction + edge_fraction + flow_fraction
+                        {
+                            flow_strokes.and_then(|(flow_strokes, _)| flow_strokes.proposal(target))
+                        } else {
+                            None
+                        };
+                        let kind = params.shape_kinds[random::<usize>() % params.shape_kinds.len()];
+                        let (shape, coords, color, kind) = match (content_shape, &mesh_edges) {
+                            (Some((shape, color)), _) => (shape, cache.coords(shape), color, None),
+                            // growing the mesh out from a random edge of it
+                            (None, Some(edges)) if !edges.is_empty() => {
+                                let (shape, coords, color) = get_mesh_neighbor(
+                                    target,
+                                    grid,
+                                    edges[random::<usize>() % edges.len()],
+                                    jitter,
+                                    &mut cache,
+                                );
+                                (shape, coords, color, Some(kind))
+                            }
+                            _ => {
+                                let (shape, coords, color) = get_neighbor(
+                                    target,
+                                    kind,
+                                    vertices,
+                                    stroke_width,
+                                    grid,
+                                    tileable,
+                                    overhang,
+                                    edge_bias,
+                                    max_size,
+                                    jitter,
+                                    converged.as_ref(),
+                                    &mut cache,
+                                );
+                                (shape, coords, color, Some(kind))
+                            }
+                        };
+                        let blend = blend_modes[random::<usize>() % blend_modes.len()];
+                        let blend_mode = BlendMode {
+                            blend,
+                            opacity: random_opacity(opacity),
+                        };
+                        // some of the shapes get a gradient instead of the color they were given
+                        let fill = if gradients > 0.0 && random::<f64>() < gradients {
+                            shape_gradient(target, &coords, jitter)
+                        } else {
+                            Fill::Solid(color)
+                        };
+                        let fill = fill.map_colors(|_, color| quantize(color));
+                        (shape, coords, fill, blend_mode, kind)
+                    }
+                };
+                // workers don't know about the mesh, so their shapes have to be checked against it
+                let on_mesh = match (&mesh_edges, proposal.0) {
+                    (Some(edges), Shape::Triangle(vertices)) if !edges.is_empty() => {
+                        triangle_edges(vertices)
+                            .iter()
+                            .any(|edge| edges.contains(edge))
+                    }
+                    _ => true,
+                };
+                if on_mesh
+                    && hints
+                        .as_ref()
+                        .is_none_or(|hints| hints.accepts(&proposal.1))
+                    && budget
+                        .as_ref()
+                        .is_none_or(|budget| budget.accepts(&proposal.1))
+                {
+                    break proposal;
+                }
+                cache.insert(proposal.0, proposal.1);
+            };
+            if let Some(kind) = kind {
+                kind_stats[kind as usize].0 += 1;
+            }
+            // shapes running along the image's structure get a head start, like brush strokes
+            let alignment_bonus = orientation.map_or(0.0, |(orientation_map, weight)| {
+                weight * orientation_map.alignment(&coords)
+            });
+            // the mirrored copies are all treated as one big shape
+            let (shapes, coords) = match symmetry {
+                Some(symmetry) => {
+                    let (w, h) = (target.width(), target.height());
+                    let shapes = symmetry.mirror(shape, w, h);
+                    let mut coords = shapes
+                        .iter()
+                        .flat_map(|shape| shape.coords(w, h))
+                        .collect::<Vec<_>>();
+                    coords.sort_unstable();
+                    coords.dedup();
+                    (shapes, coords)
+                }
+                None => (vec![shape], coords),
+            };
+            // anti-aliased shapes reach over the edges of what they got rasterized as, into pixels
+            // they only partly cover, and stamps only cover pixels as much as their alpha says
+            let (coords, coverage) = if anti_alias || matches!(shape, Shape::Stamp(_)) {
+                let (coords, coverage) = layers.covered(&shapes);
+                (coords, Some(coverage))
+            } else {
+                (coords, None)
+            };
+            // a single rectangle that doesn't wrap around can have its old error looked up
+            let old_error = match shapes[..] {
+                [Shape::Rectangle {
+                    top_left,
+                    bottom_right,
+                }] if bottom_right.0 <= target.width()
+                    && bottom_right.1 <= target.height()
+                    // the looked up error is exact, which doesn't mix with sampling turned on mid-run
+                    && params.sample.is_none() =>
+                {
+                    canvas.rectangle_error(target, top_left, bottom_right)
+                }
+                _ => None,
+            };
+            let transaction = match coverage {
+                Some(ref coverage) => canvas.paint_covered(coords, coverage, new_fill, blend_mode),
+                None => canvas.paint(coords, new_fill, blend_mode),
+            };
+            // sampled costs only get estimated as closely as it takes to tell whether to accept
+            // the move, in the sums of differences that costs are scaled down from. Estimates
+            // only know about shapes painted all over, so anti-aliased ones get added up
+            let (accepted, neighbor_cost) = match params.sample {
+                Some(samples) if coverage.is_none() => {
+                    let scale = ((target.width() * target.height() * 3) as f64).sqrt();
+                    let coords = transaction.coords();
+                    let old_pixels = transaction.old_pixels();
+                    let estimate = match transaction.new_pixels() {
+                        Some(new_pixels) => {
+                            CostEstimate::to_pixels(target, coords, old_pixels, new_pixels.to_vec())
+                        }
+                        None => CostEstimate::new(target, coords, old_pixels, new_fill, blend_mode),
+                    };
+                    let (accepted, change) =
+                        estimate.decide((threshold() + alignment_bonus) * scale, samples as usize);
+                    (accepted, cost + change / scale)
+                }
+                _ => {
+                    // interleaved canvases already added up the errors while painting, and shapes
+                    // painted in a single color all over have quicker ways of adding them up. Where
+                    // pixels are locked, only what actually got painted counts
+                    let neighbor_cost = match (
+                        transaction.errors(),
+                        transaction.new_pixels(),
+                        &coverage,
+                        new_fill,
+                    ) {
+                        (Some(errors), _, _, _) => {
+                            update_cost_from_errors(cost, target, transaction.coords(), errors)
+                        }
+                        (None, Some(new_pixels), _, _) => update_cost_to_pixels(
+                            cost,
+                            target,
+                            transaction.coords(),
+                            transaction.old_pixels(),
+                            new_pixels,
+                        ),
+                        (None, None, None, Fill::Solid(color)) => update_cost(
+                            cost,
+                            target,
+                            transaction.coords(),
+                            transaction.old_pixels(),
+                            color,
+                            blend_mode,
+                            old_error,
+                        ),
+                        (None, None, _, _) => {
+                            let new_pixels = zip(transaction.coords(), transaction.old_pixels())
+                                .enumerate()
+                                .map(|(i, (&coord, &pixel))| {
+                                    let blend_mode = match coverage {
+                                        Some(ref coverage) => blend_mode.covering(coverage[i]),
+                                        None => blend_mode,
+                                    };
+                                    blend_mode.blend(pixel, new_fill.color_at(coord))
+                                })
+                                .collect::<Vec<_>>();
+                            update_cost_to_pixels(
+                                cost,
+                                target,
+                                transaction.coords(),
+                                transaction.old_pixels(),
+                                &new_pixels,
+                            )
+                        }
+                    };
+                    (
+                        accept(neighbor_cost - cost - alignment_bonus),
+                        neighbor_cost,
+                    )
+                }
+            };
+            if accepted {
+                cost = neighbor_cost;
+                if let Some(kind) = kind {
+                    kind_stats[kind as usize].1 += 1;
+                }
+                if let Some(ref mut journal) = journal {
+                    journal.add(num_loops as u64, &shapes, new_fill, blend_mode);
+                }
+                if let Some(ref mut edges) = mesh_edges {
+                    for shape in &shapes {
+                        if let Shape::Triangle(vertices) = *shape {
+                            edges.extend(triangle_edges(vertices));
+                        }
+                    }
+                }
+                let coords = canvas.commit(transaction);
+                if let Some(ref mut budget) = budget {
+                    budget.add(&coords);
+                }
+                layers.push(shapes, new_fill, blend_mode, &coords);
+            } else {
+                let coords = canvas.rollback(transaction);
+                // without mirrored copies or anti-aliasing, `coords` are still just the shape's own
+                if symmetry.is_none() && !anti_alias {
+                    cache.insert(shape, coords);
+                }
+            }
         }
-        current_temp *= alpha;
+        current_temp *= params.alpha;
         num_loops += 1.0;
         let loop_end = loop_start.elapsed();
         time_elapsed += loop_end;
-        print!(
-            concat!(
-                "temperature: {:.5}",
-                " | seconds elapsed: {:.5}",
-                " | seconds this loop: {:.5}",
-                " | estimated seconds remaining: {:.5}           \r"
-            ),
-            current_temp,
-            time_elapsed.as_secs_f64(),
-            loop_end.as_secs_f64(),
-            (total_loops - num_loops) * time_elapsed.as_secs_f64() / num_loops
-        );
+        // giving up if the iterations have slowed to a crawl, rather than running forever
+        if let Some(min_iterations_per_second) = min_iterations_per_second {
+            watchdog_time += loop_end;
+            watchdog_loops += 1.0;
+            if watchdog_time >= WATCHDOG_WINDOW {
+                let iterations_per_second = watchdog_loops / watchdog_time.as_secs_f64();
+                if iterations_per_second < min_iterations_per_second {
+                    println!(
+                        "\nonly {iterations_per_second:.5} iterations per second, stopping early"
+                    );
+                    break;
+                }
+                watchdog_time = Duration::ZERO;
+                watchdog_loops = 0.0;
+            }
+        }
+        // stopping once the canvas looks like the target at a glance, for quick drafts
+        if let (Some(max_distance), Some(target_dhash)) = (dhash_distance, target_dhash) {
+            if (num_loops as u64).is_multiple_of(DHASH_INTERVAL) {
+                let distance = canvas.read(|image| {
+                    let canvas_dhash =
+                        dhash::dhash(image.len(), target.height(), |coord| image.at(coord));
+                    dhash::distance(canvas_dhash, target_dhash)
+                });
+                if distance <= max_distance {
+                    println!("\ndHash only {distance} bits off the target's, stopping early");
+                    break;
+                }
+            }
+        }
+        // pausing after every burst so we're only busy for `throttle` of the time
+        if let Some(throttle) = throttle {
+            busy_time += loop_end;
+            if busy_time >= THROTTLE_BURST {
+                let pause = busy_time.mul_f64((1.0 - throttle) / throttle);
+                thread::sleep(pause);
+                time_elapsed += pause;
+                busy_time = Duration::ZERO;
+            }
+        }
+        if let Some(ref mut hints) = hints {
+            hints.reload_if_changed();
+        }
+        if let Some(ref mut live_params) = live_params {
+            if live_params.update(&mut params) {
+                // a new alpha changes how many iterations it takes to cool down the rest of the way
+                total_loops = max_iterations
+                    .map_or(f64::INFINITY, |n| n as f64)
+                    .min(num_loops + (final_temp / current_temp).ln() / params.alpha.ln());
+            }
+        }
+        if let Some(ref mut coordinator) = coordinator {
+            canvas.read(|image| coordinator.share(image));
+        }
+        if let Some((path, interval, icc)) = progressive_output {
+            if last_save.elapsed() >= interval {
+                // numbered frames pile up next to the output instead of overwriting it
+                let path = match frames {
+                    Some(frames) => frames.path(path, snapshots),
+                    None => Some(path.to_string()),
+                };
+                if let Some(path) = path {
+                    canvas.read(|image| save_atomically(&to_image(image), &path, icc, None));
+                }
+                snapshots += 1;
+                last_save = Instant::now();
+            }
+        }
+        // printing every iteration slows down quick iterations, and floods logs
+        if last_status.elapsed() >= status_interval {
+            print!(
+                concat!(
+                    "temperature: {:.5}",
+                    " | seconds elapsed: {:.5}",
+                    " | seconds this loop: {:.5}",
+                    " | estimated seconds remaining: {:.5}           \r"
+                ),
+                current_temp,
+                time_elapsed.as_secs_f64(),
+                loop_end.as_secs_f64(),
+                (total_loops - num_loops) * time_elapsed.as_secs_f64()
+                    / (num_loops - resumed_loops)
+            );
+            // the line doesn't end, so it wouldn't show up until a lot more got printed otherwise
+            stdout().flush().unwrap();
+            last_status = Instant::now();
+        }
     }
 
     println!(
         "\ntotal time elapsed: {} seconds",
         time_elapsed.as_secs_f64()
     );
+    let proposed_kinds = zip(ShapeKind::value_variants(), kind_stats)
+        .filter(|(_, (proposed, _))| *proposed > 0)
+        .collect::<Vec<_>>();
+    if proposed_kinds.len() > 1 {
+        for (kind, (proposed, accepted)) in proposed_kinds {
+            println!(
+                "{}: {proposed} proposed, {accepted} accepted ({:.2}%)",
+                kind.to_possible_value().unwrap().get_name(),
+                accepted as f64 / proposed.max(1) as f64 * 100.0
+            );
+        }
+    }
+
+    let target = match color_target {
+        Some(color_target) => {
+            cost = canvas.read(|image| get_cost(color_target, image));
+            cost = luma_first::anneal_colors(
+                color_target,
+                &mut canvas,
+                &mut layers,
+                cost,
+                params.alpha,
+                journal.as_mut(),
+                num_loops as u64,
+            );
+            color_target
+        }
+        None => target,
+    };
+    if polish {
+        polish::polish(
+            target,
+            &mut canvas,
+            &mut layers,
+            cost,
+            grid,
+            symmetry,
+            journal.as_mut(),
+            num_loops as u64,
+        );
+    }
+    if let Some(ref mut journal) = journal {
+        journal.sync();
+    }
+
+    (canvas.into_image(), layers.shapes())
+}
+
+/// Edges of a triangle, each with its ends in order so the same edge always looks the same
+fn triangle_edges([v1, v2, v3]: [(usize, usize); 3]) -> [[(usize, usize); 2]; 3] {
+    [[v1, v2], [v2, v3], [v3, v1]].map(|[a, b]| [a.min(b), a.max(b)])
+}
+
+/// Paints the moves logged in a journal onto `image`, keeping track of them in `layers`
+fn replay(entries: &[JournalEntry], image: &mut Image, layers: &mut Layers) {
+    for entry in entries {
+        match entry {
+            JournalEntry::Add {
+                shapes,
+                fill,
+                blend_mode,
+                ..
+            } => {
+                let (coords, coverage) = layers.covered(shapes);
+                for (&coord, &coverage) in zip(&coords, &coverage) {
+                    let pixel = image.at_mut(coord);
+                    *pixel = blend_mode
+                        .covering(coverage)
+                        .blend(*pixel, fill.color_at(coord));
+                }
+                layers.push(shapes.clone(), *fill, *blend_mode, &coords);
+            }
+            JournalEntry::Remove { index, .. } => {
+                let (coords, new_pixels) = layers.without(*index);
+                for (&coord, &pixel) in zip(&coords, &new_pixels) {
+                    *image.at_mut(coord) = pixel;
+                }
+                layers.remove(*index);
+            }
+            JournalEntry::Recolor { index, fill, .. } => {
+                let (coords, new_pixels) = layers.recolored(*index, *fill);
+                for (&coord, &pixel) in zip(&coords, &new_pixels) {
+                    *image.at_mut(coord) = pixel;
+                }
+                layers.recolor(*index, *fill);
+            }
+            JournalEntry::Replace {
+                index,
+                shapes,
+                fill,
+                ..
+            } => {
+                let (coords, new_pixels) = layers.reshaped(*index, shapes.clone(), *fill);
+                for (&coord, &pixel) in zip(&coords, &new_pixels) {
+                    *image.at_mut(coord) = pixel;
+                }
+                layers.reshape(*index, shapes.clone(), *fill);
+            }
+        }
+    }
+}
+
+/// Draws the outlines of the accepted shapes on top of the annealed image,
+/// color-coded by the order they were accepted in (early shapes are blue, late shapes are red)
+fn draw_overlay(image: &[Vec<Rgb<u8>>], shapes: &[(Shape, Fill, BlendMode)]) -> Image {
+    let mut overlay = image.to_vec();
+    let last = shapes.len().saturating_sub(1).max(1) as f64;
+    for (i, (shape, _, _)) in shapes.iter().enumerate() {
+        let t = i as f64 / last;
+        let color = Rgb([(255.0 * t) as u8, 0, (255.0 * (1.0 - t)) as u8]);
+        for coord in shape.outline(image.len(), image[0].len()) {
+            *overlay.at_mut(coord) = color;
+        }
+    }
+    overlay
+}
+
+/// Fills every `tile_size` by `tile_size` tile of a `w` by `h` image with the
+/// color returned by `color` for that tile's (column, row) index
+fn tile_map(
+    w: usize,
+    h: usize,
+    tile_size: usize,
+    color: impl Fn(usize, usize) -> Rgb<u8>,
+) -> Image {
+    (0..w)
+        .map(|x| {
+            (0..h)
+                .map(|y| color(x / tile_size, y / tile_size))
+                .collect()
+        })
+        .collect()
+}
+
+/// Image where each tile's brightness is the average difference per channel
+/// between the original image and the generated image within that tile.
+/// With a `colorspace`, each tile's red, green, and blue are instead the average differences
+/// in that colorspace's first, second, and third channels
+fn error_map(
+    target: &Target,
+    generated_image: &[Vec<Rgb<u8>>],
+    tile_size: usize,
+    colorspace: Option<Colorspace>,
+) -> Image {
+    let w = target.width();
+    let h = target.height();
+    let tiles_w = w.div_ceil(tile_size);
+    let tiles_h = h.div_ceil(tile_size);
+    let mut sums = vec![vec![([0.0; 3], 0u64); tiles_h]; tiles_w];
+    for x in 0..w {
+        for y in 0..h {
+            let tile = &mut sums[x / tile_size][y / tile_size];
+            let coord = Coord::new(x, y);
+            let (original, generated) = (target.pixel(coord), generated_image.at(coord));
+            let differences = match colorspace {
+                Some(colorspace) => colorspace.differences(original, generated),
+                None => [pixel_difference(original, generated) as f64 / 3.0; 3],
+            };
+            for (sum, difference) in tile.0.iter_mut().zip(differences) {
+                *sum += difference;
+            }
+            tile.1 += 1;
+        }
+    }
+    tile_map(w, h, tile_size, |tx, ty| {
+        let (s, n) = sums[tx][ty];
+        Rgb(s.map(|s| (s / n as f64).min(255.0) as u8))
+    })
+}
 
-    match image {
-        EitherThreadedImage::MultiThreaded(guard) => {
-            Arc::try_unwrap(guard).unwrap().into_inner().unwrap()
+/// Image where each tile's brightness is the number of accepted shapes touching that tile,
+/// scaled so that the most touched tile is white
+fn shape_count_map(
+    w: usize,
+    h: usize,
+    shapes: &[(Shape, Fill, BlendMode)],
+    tile_size: usize,
+) -> Image {
+    let tiles_w = w.div_ceil(tile_size);
+    let tiles_h = h.div_ceil(tile_size);
+    let mut counts = vec![vec![0u64; tiles_h]; tiles_w];
+    for (shape, _, _) in shapes {
+        let mut touched = vec![vec![false; tiles_h]; tiles_w];
+        for Coord { x, y } in shape.coords(w, h) {
+            touched[x / tile_size][y / tile_size] = true;
         }
-        EitherThreadedImage::SingleThreaded(raw) => raw,
+        for (count, touched) in zip(counts.iter_mut().flatten(), touched.iter().flatten()) {
+            *count += *touched as u64;
+        }
+    }
+    let max_count = counts.iter().flatten().copied().max().unwrap_or(0).max(1);
+    tile_map(w, h, tile_size, |tx, ty| {
+        let value = (counts[tx][ty] * 255 / max_count) as u8;
+        Rgb([value, value, value])
+    })
+}
+
+/// Saves the image to a temporary file next to `path` and then renames it over `path`,
+/// so anything reading `path` never sees a half-written image.
+/// PNGs get `icc` embedded as their color profile, if it's given, and PNGs and JPEGs get
+/// `metadata` embedded, if it's given
+fn save_atomically(image: &RgbImage, path: &str, icc: Option<&[u8]>, metadata: Option<&Metadata>) {
+    let temp_path = format!("{path}.tmp");
+    let format = ImageFormat::from_path(path).unwrap();
+    let encode = |output_format| {
+        let mut bytes = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut bytes), output_format)
+            .unwrap();
+        bytes
+    };
+    match (format, icc, metadata) {
+        (ImageFormat::Png, None, None) | (ImageFormat::Jpeg, _, None) => {
+            image.save_with_format(&temp_path, format).unwrap()
+        }
+        (ImageFormat::Png, icc, metadata) => {
+            let mut png = encode(ImageOutputFormat::Png);
+            if let Some(icc) = icc {
+                png = embed_icc_profile(&png, icc);
+            }
+            if let Some(metadata) = metadata {
+                png = metadata.embed_png(&png);
+            }
+            write(&temp_path, png).unwrap();
+        }
+        (ImageFormat::Jpeg, _, Some(metadata)) => {
+            let jpeg = encode(ImageOutputFormat::from(format));
+            write(&temp_path, metadata.embed_jpeg(&jpeg)).unwrap();
+        }
+        _ => image.save_with_format(&temp_path, format).unwrap(),
+    }
+    rename(temp_path, path).unwrap();
+}
+
+/// Like `save_atomically`, but saves an image that only uses colors from the first `colors` colors
+/// of `palette` as an indexed PNG with that palette, packing pixels into as few bits as fit
+fn save_indexed_png(
+    image: &RgbImage,
+    palette: &Palette,
+    colors: usize,
+    path: &str,
+    icc: Option<&[u8]>,
+    metadata: Option<&Metadata>,
+) {
+    let (bit_depth, bits) = match colors {
+        0..=2 => (BitDepth::One, 1),
+        3..=4 => (BitDepth::Two, 2),
+        5..=16 => (BitDepth::Four, 4),
+        _ => (BitDepth::Eight, 8),
+    };
+    let (w, h) = image.dimensions();
+    let row_bytes = (w as usize * bits).div_ceil(8);
+    let mut data = vec![0u8; row_bytes * h as usize];
+    for (x, y, pixel) in image.enumerate_pixels() {
+        let index = palette.index_of(pixel) as u8;
+        let bit = x as usize * bits;
+        // pixels are packed from the most significant bits down
+        data[y as usize * row_bytes + bit / 8] |= index << (8 - bits - bit % 8);
+    }
+    let mut png = Vec::new();
+    let mut encoder = Encoder::new(&mut png, w, h);
+    encoder.set_color(ColorType::Indexed);
+    encoder.set_depth(bit_depth);
+    encoder.set_palette(
+        (0..colors)
+            .flat_map(|i| palette.lookup(i).unwrap().0)
+            .collect::<Vec<u8>>(),
+    );
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(&data).unwrap();
+    writer.finish().unwrap();
+    if let Some(icc) = icc {
+        png = embed_icc_profile(&png, icc);
+    }
+    if let Some(metadata) = metadata {
+        png = metadata.embed_png(&png);
+    }
+    let temp_path = format!("{path}.tmp");
+    write(&temp_path, png).unwrap();
+    rename(temp_path, path).unwrap();
+}
+
+/// Converts a column-major pixel grid into an image that can be saved
+fn to_image(pixels: &[Vec<Rgb<u8>>]) -> RgbImage {
+    RgbImage::from_fn(pixels.len() as u32, pixels[0].len() as u32, |x, y| {
+        pixels.at(Coord::new(x as usize, y as usize))
+    })
+}
+
+/// Brightness of every pixel (column-major) of the grayscale image at `path`, stretched to `w` by `h`
+/// if it's a different size. With `alpha`, images with an alpha channel give that instead
+fn load_grayscale(path: &str, w: u32, h: u32, alpha: bool) -> Vec<f64> {
+    let image = open(path).unwrap();
+    let mut image = if alpha && image.color().has_alpha() {
+        let alpha = image.into_luma_alpha8();
+        GrayImage::from_fn(alpha.width(), alpha.height(), |x, y| {
+            Luma([alpha.get_pixel(x, y)[1]])
+        })
+    } else {
+        image.into_luma8()
+    };
+    // depth maps and mattes often come out smaller than the photos they're for
+    if image.dimensions() != (w, h) {
+        image = resize(&image, w, h, FilterType::Triangle);
+    }
+    (0..w)
+        .flat_map(|x| (0..h).map(move |y| (x, y)))
+        .map(|(x, y)| image.get_pixel(x, y)[0] as f64)
+        .collect()
+}
+
+/// Parses a color depth schedule like `3->8` into the bits per channel to start and end with
+fn parse_color_depth_schedule(s: &str) -> Result<(u8, u8), String> {
+    let (start, end) = s
+        .split_once("->")
+        .ok_or("color depth schedule must look like `3->8`")?;
+    let parse = |bits: &str| match bits.trim().parse::<u8>() {
+        Ok(bits @ 1..=8) => Ok(bits),
+        _ => Err(String::from("color depths must be between 1 and 8 bits")),
+    };
+    Ok((parse(start)?, parse(end)?))
+}
+
+/// Parses a stroke width range like `2-8`, or a single width like `4`, into the thinnest and
+/// thickest widths
+pub fn parse_stroke_width(s: &str) -> Result<(f64, f64), String> {
+    let parse = |width: &str| {
+        width
+            .trim()
+            .parse::<f64>()
+            .map_err(|_| String::from("stroke widths must look like `2-8` or `4`"))
+    };
+    match s.split_once('-') {
+        Some((thinnest, thickest)) => Ok((parse(thinnest)?, parse(thickest)?)),
+        None => parse(s).map(|width| (width, width)),
+    }
+}
+
+/// Parses an opacity range like `0.2-0.8`, or a single opacity like `0.5`, into the least and most
+/// opaque
+pub fn parse_opacity(s: &str) -> Result<(f64, f64), String> {
+    let parse = |opacity: &str| {
+        opacity
+            .trim()
+            .parse::<f64>()
+            .map_err(|_| String::from("opacities must look like `0.2-0.8` or `0.5`"))
+    };
+    match s.split_once('-') {
+        Some((lowest, highest)) => Ok((parse(lowest)?, parse(highest)?)),
+        None => parse(s).map(|opacity| (opacity, opacity)),
+    }
+}
+
+/// Parses a budget split like `70/30` into the fraction that goes to the first part
+fn parse_budget_split(s: &str) -> Result<f64, String> {
+    let (foreground, background) = s
+        .split_once('/')
+        .ok_or("budget split must look like `70/30`")?;
+    let parse = |share: &str| match share.trim().parse::<f64>() {
+        Ok(share) if share >= 0.0 => Ok(share),
+        _ => Err(String::from("budget shares must be numbers of at least 0")),
+    };
+    let (foreground, background) = (parse(foreground)?, parse(background)?);
+    if foreground + background > 0.0 {
+        Ok(foreground / (foreground + background))
+    } else {
+        Err(String::from("budget shares can't both be 0"))
+    }
+}
+
+/// Parses a percentage like `50%` or `50` into a fraction in (0, 1]
+fn parse_percentage(s: &str) -> Result<f64, String> {
+    let percentage = s
+        .strip_suffix('%')
+        .unwrap_or(s)
+        .parse::<f64>()
+        .map_err(|e| e.to_string())?;
+    if 0.0 < percentage && percentage <= 100.0 {
+        Ok(percentage / 100.0)
+    } else {
+        Err(String::from(
+            "percentage must be greater than 0 and at most 100",
+        ))
     }
 }
 
 #[derive(Parser)]
 struct Args {
     /// Input image path
-    #[arg(short, long)]
-    input: String,
+    #[arg(short, long, required_unless_present_any = ["worker", "bench", "self_test"])]
+    input: Option<String>,
 
     /// Output image path
-    #[arg(short, long)]
-    output: String,
+    #[arg(short, long, required_unless_present_any = ["worker", "bench", "self_test"])]
+    output: Option<String>,
 
     /// Temperature change value
     #[arg(short, long, default_value_t = 0.999)]
     alpha: f64,
 
-    /// Flag for drawing triangles instead of rectangles
-    #[arg(short, long)]
+    /// Kind of shapes to draw. If several comma-separated kinds are given, each proposal picks one
+    /// of them at random
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "rectangle")]
+    shape: Vec<ShapeKind>,
+
+    /// Shorthand for `--shape triangle`
+    #[arg(short, long, conflicts_with = "shape")]
     triangle: bool,
 
+    /// Most vertices polygons get proposed with
+    #[arg(long, default_value_t = 5)]
+    vertices: usize,
+
+    /// How thick strokes get proposed, in pixels, as a range like `2-8` (or just one width)
+    #[arg(long, value_parser = parse_stroke_width, default_value = "2-8")]
+    stroke_width: (f64, f64),
+
+    /// Directory of PNGs for `--shape stamp` to paint, each covering pixels as much as its alpha
+    /// says (or as dark as it is, if it doesn't have any)
+    #[arg(
+        long,
+        conflicts_with_all = ["tileable", "pdf", "eps", "svg", "export_code", "viewer", "coordinator", "worker"]
+    )]
+    stamps: Option<String>,
+
+    /// TrueType font whose glyphs `--shape stamp` paints instead of a directory of PNGs, for
+    /// typographic mosaics
+    #[arg(
+        long,
+        conflicts_with_all = ["stamps", "tileable", "pdf", "eps", "svg", "export_code", "viewer", "coordinator", "worker"]
+    )]
+    font: Option<String>,
+
+    /// Characters whose glyphs get used from `--font`
+    #[arg(
+        long,
+        requires = "font",
+        default_value = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789"
+    )]
+    glyphs: String,
+
+    /// Flag for growing the triangles as one connected mesh, with every new triangle sharing an
+    /// edge with one that was already accepted, which looks like stained glass
+    #[arg(long, conflicts_with_all = ["tileable", "removal_moves"])]
+    mesh: bool,
+
+    /// Stipples the image with black dots this many pixels across on white instead of annealing
+    /// shapes, packing them as densely as the image is dark
+    #[arg(
+        long,
+        value_name = "DOT_SIZE",
+        conflicts_with_all = ["triangle", "mesh", "symmetry", "tileable", "polish", "luma_first", "journal", "coordinator", "fixed_shapes"]
+    )]
+    stipple: Option<usize>,
+
+    /// Hatches the image like an engraving instead of annealing shapes, with tapering strokes at
+    /// this many angles (2 for cross-hatching) on white, spaced and darkened to match its tone
+    #[arg(
+        long,
+        value_name = "ANGLES",
+        conflicts_with_all = ["stipple", "triangle", "mesh", "symmetry", "tileable", "polish", "luma_first", "journal", "coordinator", "fixed_shapes"]
+    )]
+    hatch: Option<usize>,
+
+    /// Snaps shape vertices to a grid with cells of this many pixels
+    #[arg(long, default_value_t = 1)]
+    grid: usize,
+
+    /// Flag for letting shapes wrap around the edges of the image, which makes the result
+    /// seamlessly tileable
+    #[arg(long)]
+    tileable: bool,
+
+    /// How far past the edges of the image rectangles can get proposed, as a fraction of its size,
+    /// with the part hanging off getting clipped. Helps fill in the edges and corners
+    #[arg(long, default_value_t = 0.0, conflicts_with = "tileable")]
+    overhang: f64,
+
+    /// Fraction of proposals that get one side (or vertex) put near an edge or corner of the
+    /// image, which shapes rarely reach otherwise
+    #[arg(long, default_value_t = 0.0, conflicts_with = "tileable")]
+    edge_bias: f64,
+
+    /// Flag for keeping shapes about as big as the details the annealed image is still missing,
+    /// going by how far apart its errors stop looking alike (checked every 1000 iterations)
+    #[arg(long)]
+    adaptive_sizes: bool,
+
+    /// Throws away random shapes that would only land on 16 by 16 tiles that are already less than
+    /// this far off the original image on average in each channel (checked every 1000 iterations)
+    #[arg(long)]
+    converged_threshold: Option<f64>,
+
+    /// Mirrors every shape according to the given symmetry
+    #[arg(long, value_enum, conflicts_with = "tileable")]
+    symmetry: Option<Symmetry>,
+
+    /// How shapes get blended with what's underneath them. If several comma-separated modes are
+    /// given, each shape picks one of them at random
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "normal")]
+    blend_mode: Vec<Blend>,
+
+    /// Flag for anti-aliasing the edges of shapes, painting them over the pixels they only partly
+    /// cover by how much of each pixel they cover
+    #[arg(long, conflicts_with = "sample")]
+    anti_alias: bool,
+
+    /// How opaque shapes are, from 0 to 1, as a range like `0.2-0.8` that each shape picks its own
+    /// opacity from (or just one opacity)
+    #[arg(long, value_parser = parse_opacity, default_value = "1")]
+    opacity: (f64, f64),
+
+    /// Fraction of new shapes that get painted with a linear gradient between two colors instead of
+    /// a single color, going from one random pixel of the shape to another
+    #[arg(long, default_value_t = 0.0, conflicts_with_all = ["symmetry", "luma_first"])]
+    gradients: f64,
+
+    /// Flag for picking shape colors close to the color that best fits the original image
+    /// instead of completely at random, getting closer as the temperature drops
+    #[arg(long)]
+    color_jitter: bool,
+
+    /// Bits per channel that shape colors are limited to, going from the first number to the second
+    /// as the temperature drops, e.g. `3->8`. Coarse colors early on get accepted more easily
+    #[arg(long, value_parser = parse_color_depth_schedule)]
+    color_depth_schedule: Option<(u8, u8)>,
+
+    /// How much a move has to lower the cost by to be accepted once the temperature is below
+    /// `min_improvement_below`, so numerical noise doesn't keep churning the canvas
+    #[arg(long)]
+    min_improvement: Option<f64>,
+
+    /// Temperature below which moves have to lower the cost by at least `min_improvement`
+    #[arg(long, requires = "min_improvement", default_value_t = 1.0)]
+    min_improvement_below: f64,
+
+    /// Fraction of proposals that try taking an already accepted shape back out
+    /// instead of adding a new one
+    #[arg(long, default_value_t = 0.0)]
+    removal_moves: f64,
+
+    /// Fraction of proposals that try a new color on an already accepted shape instead of adding
+    /// a new one, going through them from the oldest (and usually worst) shapes on
+    #[arg(long, default_value_t = 0.0)]
+    recolor_moves: f64,
+
+    /// Fraction of proposals that try changing just one color channel of an already accepted
+    /// shape (red, green or blue) to that channel of the color that fits it best
+    #[arg(long, default_value_t = 0.0)]
+    channel_moves: f64,
+
+    /// Flag for nudging the corners and colors of the accepted shapes one step at a time after
+    /// annealing, for as long as that keeps lowering the cost (going over them at most 10 times)
+    #[arg(long, conflicts_with_all = ["mesh", "tileable"])]
+    polish: bool,
+
+    /// Flag for annealing the shapes against a grayscale copy of the original image first, and
+    /// then only their colors against the original, so no shapes get spent on color noise
+    #[arg(long)]
+    luma_first: bool,
+
     /// Flag for enabling multithreading
     #[arg(short, long)]
     multithreading: bool,
 
-    /// Randomly sample pixels for cost calculation.
-    /// Much faster than non-sampled, at the cost of loss of accuracy
+    /// Estimate the cost of moves from this many randomly drawn pixels,
+    /// drawing more for moves that are too close to call
     #[arg(short, long)]
     sample: Option<u32>,
+
+    /// Flag for periodically overwriting the output image with the image annealed so far,
+    /// so there's always something usable at the output path even if the program gets killed
+    #[arg(long)]
+    progressive_output: bool,
+
+    /// Seconds between saves when `progressive_output` is set
+    #[arg(long, default_value_t = 30.0)]
+    progressive_interval: f64,
+
+    /// Saves every progressive output snapshot as a frame of its own next to the output, numbered
+    /// with this many digits, instead of overwriting the output
+    #[arg(long, requires = "progressive_output")]
+    frame_digits: Option<usize>,
+
+    /// The number the first frame gets when `frame_digits` is set
+    #[arg(long, requires = "frame_digits", default_value_t = 0)]
+    frame_start: u64,
+
+    /// How many snapshots get left out after each one that's saved as a frame
+    #[arg(long, requires = "frame_digits", default_value_t = 0)]
+    frame_skip: u64,
+
+    /// Seconds between updates of the status line
+    #[arg(long, default_value_t = 0.25)]
+    status_interval: f64,
+
+    /// Stops annealing after this many iterations, even if the temperature hasn't gotten low enough
+    #[arg(long)]
+    max_iterations: Option<u64>,
+
+    /// Stops annealing if fewer than this many iterations get done per second (averaged over
+    /// 10 seconds), keeping whatever's been annealed so far
+    #[arg(long)]
+    min_iterations_per_second: Option<f64>,
+
+    /// Stops annealing once the dHash (a perceptual hash) of the canvas is at most this many bits
+    /// (out of 64) off the original image's, for drafts that only need to be recognizable
+    #[arg(long)]
+    dhash_distance: Option<u32>,
+
+    /// Limits how much of the time the annealing loop is allowed to run for, e.g. `50%`.
+    /// The loop runs in short bursts and sleeps in between them to keep CPU usage and heat down
+    #[arg(long, value_parser = parse_percentage)]
+    throttle: Option<f64>,
+
+    /// Optional path to a file of settings (alpha, sample, removal-moves, recolor-moves,
+    /// channel-moves) that get changed to whatever's in it whenever it's edited, so long runs can be tuned as they go
+    #[arg(long)]
+    live_params: Option<String>,
+
+    /// Optional path to a cache file that the original image gets decoded into and read back from
+    /// through a memory map, instead of being kept in memory. Slower, but fits on smaller devices
+    #[arg(long)]
+    low_memory: Option<String>,
+
+    /// Megabytes of memory that the pixels covered by each accepted shape can take up, which saves
+    /// rasterizing shapes again to repaint around them
+    #[arg(long, default_value_t = 256)]
+    footprint_memory: usize,
+
+    /// Optional path to a file that the pixels covered by accepted shapes go into once they don't
+    /// fit in `footprint_memory` anymore, instead of not being kept at all
+    #[arg(long)]
+    footprint_spill: Option<String>,
+
+    /// Limits the saved image to a palette of this many colors (at most 256)
+    #[arg(long, value_parser = clap::value_parser!(u16).range(2..=256))]
+    output_colors: Option<u16>,
+
+    /// Flag for applying Floyd-Steinberg dithering when limiting the saved image's colors
+    #[arg(long, requires = "output_colors")]
+    dither: bool,
+
+    /// Flag for adding a caption with the number of shapes, the final cost, and how long the run
+    /// took in a strip below the saved image
+    #[arg(long)]
+    annotate: bool,
+
+    /// Optional path to a file that gets memory-mapped and kept in sync with the annealed image,
+    /// so other processes can map it too and watch the image as it's being annealed.
+    /// Pixels are stored row-major as packed RGB bytes
+    #[arg(long)]
+    shared_canvas: Option<String>,
+
+    /// Flag for annealing in the input's own color space instead of converting wide-gamut inputs
+    /// to sRGB. PNG output gets the input's color profile so the colors still show up right
+    #[arg(long)]
+    keep_gamut: bool,
+
+    /// Flag for stretching the contrast of the input before annealing, and squeezing the result
+    /// back afterwards, which helps with dark or washed-out inputs
+    #[arg(long, conflicts_with = "progressive_output")]
+    pre_normalize: bool,
+
+    /// Optional path to a journal that every accepted move gets logged to as it happens
+    #[arg(long)]
+    journal: Option<String>,
+
+    /// Flag for replaying the moves in `journal` (if it exists) and carrying on from there,
+    /// instead of starting the journal over
+    #[arg(long, requires = "journal")]
+    resume: bool,
+
+    /// Number of moves to undo at the end of the journal when resuming, for getting rid of a late
+    /// move that ruined part of the image
+    #[arg(long, requires = "resume", default_value_t = 0)]
+    rewind: usize,
+
+    /// Optional path to save the accepted shapes to. Saved as JSON if the path ends in `.json`,
+    /// in a compact binary format if it ends in `.bin`, and compressed with zstd if it ends in `.zst`
+    #[arg(long)]
+    shapes: Option<String>,
+
+    /// Flag for leaving shapes that are completely hidden under later ones out of the shape list,
+    /// the vector outputs, the exported code, and the maps
+    #[arg(long)]
+    prune_shapes: bool,
+
+    /// Optional path to save the accepted shapes to as a PDF
+    #[arg(long)]
+    pdf: Option<String>,
+
+    /// Optional path to save the accepted shapes to as EPS
+    #[arg(long)]
+    eps: Option<String>,
+
+    /// Optional path to save the accepted shapes to as SVG, along with the arguments, final cost,
+    /// and shape counts of the run
+    #[arg(long)]
+    svg: Option<String>,
+
+    /// Exports JavaScript that draws the accepted shapes one after the other, for showing the
+    /// image being built up on a web page. Saved to `code_output`
+    #[arg(long, value_enum, requires = "code_output")]
+    export_code: Option<SketchKind>,
+
+    /// Path to save the JavaScript from `export_code` to
+    #[arg(long, requires = "export_code")]
+    code_output: Option<String>,
+
+    /// Optional path to save a web page to that draws the accepted shapes up to wherever a slider
+    /// is, for scrubbing through the order they were accepted in
+    #[arg(long)]
+    viewer: Option<String>,
+
+    /// Optional path to save an image of the accepted shapes' outlines drawn over the result.
+    /// Outlines are color-coded by acceptance order, going from blue (early) to red (late)
+    #[arg(long)]
+    debug_overlay: Option<String>,
+
+    /// Optional path to save an image where each tile's brightness is its remaining error
+    #[arg(long)]
+    error_map: Option<String>,
+
+    /// Colorspace to split the error map's channels by, showing the error in each of its channels
+    /// as red, green, and blue instead of the overall error as brightness
+    #[arg(long, value_enum, requires = "error_map")]
+    debug_colorspace: Option<Colorspace>,
+
+    /// Optional path to save an image where each tile's brightness is the number of accepted
+    /// shapes touching it
+    #[arg(long)]
+    shape_count_map: Option<String>,
+
+    /// Width and height in pixels of the tiles used by `error_map` and `shape_count_map`
+    #[arg(long, default_value_t = 16)]
+    tile_size: usize,
+
+    /// Optional path to a JSON file of regions (polygons with priorities) where shapes should be
+    /// concentrated, or left out with a priority of 0
+    #[arg(long)]
+    hints: Option<String>,
+
+    /// Optional path to a grayscale image (like a depth map) whose brightness scales how much each
+    /// pixel counts towards the cost, so brighter areas get approximated more closely
+    #[arg(long)]
+    weights: Option<String>,
+
+    /// How much lining shapes up with the structure of the original image counts for, in the same
+    /// units as the cost. Elongated shapes running along edges look more like brush strokes
+    #[arg(long)]
+    orientation_weight: Option<f64>,
+
+    /// Optional path to a matte of the subject (its alpha channel, or its brightness if it doesn't
+    /// have one). Only the subject gets annealed, and the original background is kept around it
+    #[arg(long)]
+    subject_matte: Option<String>,
+
+    /// How the difference in each channel counts towards the cost. Huber and truncated costs
+    /// count big differences for less, so noise and tiny details don't pull the shapes around
+    #[arg(long, value_enum, default_value_t = Loss::Absolute)]
+    cost: Loss,
+
+    /// How the accepted shapes get split between the foreground and the background of
+    /// `budget_mask`, like `70/30`
+    #[arg(long, value_parser = parse_budget_split, requires = "budget_mask")]
+    budget_split: Option<f64>,
+
+    /// Optional path to a mask or saliency map (its alpha channel, or its brightness if it doesn't
+    /// have one) whose bright half is the foreground for `budget_split`
+    #[arg(long, requires = "budget_split")]
+    budget_mask: Option<String>,
+
+    /// Channel difference (out of 255) where the huber cost goes from squared to linear, and where
+    /// the truncated cost stops growing
+    #[arg(long, default_value_t = 16.0)]
+    huber_delta: f64,
+
+    /// Fraction of proposals that are shapes roughly covering superpixels of the original image
+    /// (patches of similar color), in their average color, instead of random ones
+    #[arg(long)]
+    superpixel_proposals: Option<f64>,
+
+    /// Fraction of proposals that are short strokes along the strong edges of the original image,
+    /// so outlines show up early, instead of random shapes
+    #[arg(long)]
+    edge_proposals: Option<f64>,
+
+    /// Fraction of proposals that are strokes running along a smoothed flow field of the original
+    /// image, which paints it in swirls, instead of random shapes
+    #[arg(long, conflicts_with = "mesh")]
+    flow_proposals: Option<f64>,
+
+    /// How far the flow field gets smoothed every way, in pixels. Higher values make it swirl more
+    /// broadly
+    #[arg(long, default_value_t = 12, requires = "flow_proposals")]
+    flow_smoothness: usize,
+
+    /// Optional path to an SVG file whose paths get painted onto the canvas before annealing and
+    /// then left alone, so annealing only fills in the rest
+    #[arg(long)]
+    fixed_shapes: Option<String>,
+
+    /// Optional path to a JSON lines file (like `runs.jsonl`) that every run appends its ID,
+    /// start time, version, arguments, and final cost to
+    #[arg(long)]
+    run_log: Option<String>,
+
+    /// Flag for printing the peak memory usage at the end, along with how much got allocated
+    /// while loading, annealing, and saving the output when built with the `mem-stats` feature
+    #[arg(long)]
+    mem_stats: bool,
+
+    /// Optional address (like `0.0.0.0:7878`) to listen on for workers that help find shapes
+    #[arg(long)]
+    coordinator: Option<String>,
+
+    /// Runs as a worker for the coordinator at the given address instead of annealing an image.
+    /// Everything about the image and shapes comes from the coordinator
+    #[arg(long, conflicts_with_all = ["input", "output", "coordinator"])]
+    worker: Option<String>,
+
+    /// Seed for the random numbers, so a run can be repeated exactly
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Separate seed for the random numbers deciding which moves get accepted, leaving `seed` to
+    /// everything else, so the same shapes can get proposed under different temperatures
+    #[arg(long)]
+    acceptance_seed: Option<u64>,
+
+    /// Anneals every image in the given folder with the rest of the arguments and a fixed seed,
+    /// then prints the cost and time of each, instead of annealing a single image
+    #[arg(long, conflicts_with_all = ["input", "output", "worker", "coordinator"])]
+    bench: Option<String>,
+
+    /// Runs quick consistency checks (of the rasterizers, the cost math, and seeding) on synthetic
+    /// images and reports which ones passed, instead of annealing anything
+    #[arg(long, conflicts_with_all = ["input", "output", "worker", "coordinator", "bench"])]
+    self_test: bool,
 }
 
 fn main() {
     let args = Args::parse();
-    if !(0.0 < args.alpha && args.alpha < 1.0) {
-        panic!("alpha must be greater than 0 and less than 1");
+    if let Some(address) = args.worker {
+        distributed::work(&address);
+        return;
+    }
+    if args.self_test {
+        self_test::self_test();
+        return;
+    }
+    if let Some(ref corpus) = args.bench {
+        bench::bench(corpus, args.seed.is_some(), args.run_log.as_deref());
+        return;
+    }
+    // unseeded runs get a seed of their own, so that they can be repeated from the output too
+    let seed = args.seed.unwrap_or_else(rand::random);
+    rng::seed(seed);
+    if let Some(acceptance_seed) = args.acceptance_seed {
+        rng::seed_acceptance(acceptance_seed);
+    }
+    let run = Run::start();
+    let output_path = args.output.unwrap();
+    if args.progressive_interval <= 0.0 {
+        panic!("progressive interval must be greater than 0");
+    }
+    if args.status_interval <= 0.0 {
+        panic!("status interval must be greater than 0");
+    }
+    if args.huber_delta <= 0.0 {
+        panic!("huber delta must be greater than 0");
+    }
+    let shape_kinds = if args.triangle {
+        vec![ShapeKind::Triangle]
+    } else {
+        args.shape.clone()
+    };
+    if shape_kinds.contains(&ShapeKind::Stamp) != (args.stamps.is_some() || args.font.is_some()) {
+        panic!(
+            "stamps need both `--shape stamp` and a `--stamps` directory or `--font` to come from"
+        );
+    }
+    if args.mesh && shape_kinds != [ShapeKind::Triangle] {
+        panic!("only triangles can be grown into a mesh");
+    }
+    // a triangle with its corner clipped off isn't a triangle anymore
+    if args.overhang > 0.0 && shape_kinds.iter().any(|&kind| kind != ShapeKind::Rectangle) {
+        panic!("only rectangles can overhang the edges");
+    }
+    // the settings that don't need anything loaded get checked before loading anything
+    let params = AnnealParams::new()
+        .alpha(args.alpha)
+        .shape_kinds(&shape_kinds)
+        .vertices(args.vertices)
+        .stroke_width(args.stroke_width)
+        .grid(args.grid)
+        .tileable(args.tileable)
+        .overhang(args.overhang)
+        .edge_bias(args.edge_bias)
+        .adaptive_sizes(args.adaptive_sizes)
+        .converged_threshold(args.converged_threshold)
+        .symmetry(args.symmetry)
+        .blend_modes(&args.blend_mode)
+        .opacity(args.opacity)
+        .anti_alias(args.anti_alias)
+        .gradients(args.gradients)
+        .color_jitter(args.color_jitter)
+        .removal_moves(args.removal_moves)
+        .recolor_moves(args.recolor_moves)
+        .channel_moves(args.channel_moves)
+        .sample(args.sample)
+        .multithreading(args.multithreading)
+        .throttle(args.throttle)
+        .frames(args.frame_digits.map(|digits| Frames {
+            start: args.frame_start,
+            digits,
+            skip: args.frame_skip,
+        }))
+        .status_interval(Duration::from_secs_f64(args.status_interval))
+        .max_iterations(args.max_iterations)
+        .min_iterations_per_second(args.min_iterations_per_second)
+        .dhash_distance(args.dhash_distance)
+        .mesh(args.mesh)
+        .color_depth_schedule(args.color_depth_schedule)
+        .polish(args.polish)
+        .min_improvement(
+            args.min_improvement
+                .map(|epsilon| (epsilon, args.min_improvement_below)),
+        );
+    if args.tile_size == 0 {
+        panic!("tile size must be greater than 0");
+    }
+    // better to find out now than after annealing
+    if cfg!(not(feature = "zstd"))
+        && args
+            .shapes
+            .as_ref()
+            .is_some_and(|path| path.ends_with(".zst"))
+    {
+        panic!("compressed shape lists need the zstd feature");
+    }
+    let input_path = args.input.unwrap();
+    let mut original_image = open(&input_path)
+        .unwrap_or_else(|error| panic!("{input_path}: {error}"))
+        .into_rgb8();
+    let input_icc = icc_profile(&input_path);
+    match input_icc.as_deref().map(RgbProfile::parse) {
+        Some(Some(profile)) if !args.keep_gamut && !profile.is_srgb() => {
+            profile.to_srgb(&mut original_image);
+            println!("converted the input from its color profile to sRGB");
+        }
+        Some(None) => {
+            println!("couldn't make sense of the input's color profile, treating it as sRGB")
+        }
+        _ => (),
+    }
+    // the output is in the input's color space when it's kept, so it needs the same profile
+    let output_icc = input_icc.as_deref().filter(|_| args.keep_gamut);
+    if output_icc.is_some() && ImageFormat::from_path(&output_path).ok() != Some(ImageFormat::Png) {
+        println!("only PNG output gets the input's color profile, so colors will look off");
+    }
+    // the subject gets put back into the background as it was, before any contrast stretching
+    let background = args.subject_matte.is_some().then(|| original_image.clone());
+    let normalization = args
+        .pre_normalize
+        .then(|| Normalization::new(&original_image));
+    if let Some(ref normalization) = normalization {
+        normalization.apply(&mut original_image);
+    }
+    if args.grid == 0 {
+        panic!("grid must be greater than 0");
+    }
+    let cells_w = (original_image.width() as usize).div_ceil(args.grid);
+    let cells_h = (original_image.height() as usize).div_ceil(args.grid);
+    let needs_cells = |kind: &ShapeKind| {
+        matches!(
+            kind,
+            ShapeKind::Triangle
+                | ShapeKind::Polygon
+                | ShapeKind::RotatedRectangle
+                | ShapeKind::Blob
+                | ShapeKind::Stroke
+        )
+    };
+    let coarse = cells_w < 2 || cells_h < 2;
+    if shape_kinds.iter().any(needs_cells) && coarse {
+        panic!("grid is too coarse to fit a triangle or polygon in the image");
+    }
+    // what the live settings can switch the shapes to, by the same rules as above
+    let live_kinds = ShapeKind::value_variants()
+        .iter()
+        .copied()
+        .filter(|kind| !(args.mesh && *kind != ShapeKind::Triangle))
+        .filter(|kind| !(args.overhang > 0.0 && *kind != ShapeKind::Rectangle))
+        .filter(|kind| *kind != ShapeKind::Stamp || args.stamps.is_some() || args.font.is_some())
+        .filter(|kind| !(needs_cells(kind) && coarse))
+        .collect::<Vec<_>>();
+    if let Some(ref dir) = args.stamps {
+        stamps::load(dir);
+    }
+    if let Some(ref path) = args.font {
+        font::load(path, &args.glyphs);
     }
-    let mut original_image = open(args.input).unwrap().into_rgb8();
     let mut original_pixels = Vec::new();
     for x in 0..original_image.width() {
         let mut column = Vec::new();
@@ -405,17 +2994,230 @@ fn main() {
         }
         original_pixels.push(column);
     }
-    let generated_image = anneal(
-        &original_pixels,
-        args.alpha,
-        args.triangle,
-        args.sample,
-        args.multithreading,
-    );
-    for x in 0..generated_image.len() {
-        for y in 0..generated_image[0].len() {
-            original_image.put_pixel(x as u32, y as u32, generated_image[x][y]);
+    let (w, h) = (original_image.width(), original_image.height());
+    let weights = args
+        .weights
+        .as_ref()
+        .map(|path| load_grayscale(path, w, h, false));
+    let matte = args.subject_matte.as_ref().map(|path| {
+        load_grayscale(path, w, h, true)
+            .into_iter()
+            .map(|value| value / 255.0)
+            .collect::<Vec<_>>()
+    });
+    // only the subject counts towards the cost, since the background gets put back afterwards
+    let weights = match (weights, &matte) {
+        (Some(weights), Some(matte)) => Some(zip(weights, matte).map(|(w, m)| w * m).collect()),
+        (None, Some(matte)) => Some(matte.clone()),
+        (weights, None) => weights,
+    };
+    let budget = args
+        .budget_mask
+        .as_ref()
+        .zip(args.budget_split)
+        .map(|(path, split)| {
+            let mask = load_grayscale(path, w, h, true)
+                .into_iter()
+                .map(|value| value / 255.0)
+                .collect::<Vec<_>>();
+            BudgetSplit::new(&mask, h as usize, split)
+        });
+    let weighted = |target: Target| {
+        let target = target.with_loss(args.cost, args.huber_delta);
+        match weights {
+            Some(ref weights) => target.with_weights(weights.clone()),
+            None => target,
+        }
+    };
+    let mut target = weighted(Target::new(&original_pixels));
+    // the shapes get annealed against the grayscale copy, and the original is only for coloring
+    // them in afterwards
+    let luma_target = args
+        .luma_first
+        .then(|| weighted(Target::new(&luma_first::luma(&original_pixels))));
+    if let Some(ref path) = args.low_memory {
+        // the decoded input takes up as much memory as the target did, so it has to go too
+        drop(original_pixels);
+        drop(original_image);
+        target = target.mapped(path);
+    }
+    let orientation_map = args
+        .orientation_weight
+        .map(|_| OrientationMap::new(&target));
+    // shapes taken from the image all come out as the first kind, since they're worked out up front
+    let superpixels = args
+        .superpixel_proposals
+        .map(|_| Superpixels::new(&target, shape_kinds[0], args.grid, args.vertices));
+    let edge_strokes = args
+        .edge_proposals
+        .map(|_| EdgeStrokes::new(&target, shape_kinds[0], args.grid));
+    let flow_strokes = args
+        .flow_proposals
+        .map(|_| FlowStrokes::new(&target, args.flow_smoothness, args.grid));
+    let fixed_shapes = args
+        .fixed_shapes
+        .as_ref()
+        .map(|path| FixedShapes::load(path, target.width(), target.height()));
+    mem_stats::enter(Subsystem::Annealing);
+    let params = params
+        .shared_canvas(
+            args.shared_canvas
+                .map(|path| SharedCanvas::new(&path, target.width(), target.height())),
+        )
+        .progressive_output(args.progressive_output.then(|| {
+            (
+                &output_path[..],
+                Duration::from_secs_f64(args.progressive_interval),
+                output_icc,
+            )
+        }))
+        .journal(args.journal.as_ref().map(|path| {
+            let (w, h) = (target.width(), target.height());
+            if args.resume && Path::new(path).exists() {
+                Journal::resume(path, w, h, args.rewind)
+            } else {
+                (Journal::create(path, w, h), Vec::new())
+            }
+        }))
+        .coordinator(args.coordinator.map(|address| {
+            let settings = WorkerSettings {
+                shape_kinds: shape_kinds.clone(),
+                grid: args.grid,
+                tileable: args.tileable,
+                overhang: args.overhang,
+                edge_bias: args.edge_bias,
+                vertices: args.vertices,
+                stroke_width: args.stroke_width,
+                blend_modes: args.blend_mode.clone(),
+                opacity: args.opacity,
+            };
+            Coordinator::listen(&address, &target, settings)
+        }))
+        .hints(
+            args.hints
+                .map(|path| Hints::load(&path, target.width(), target.height())),
+        )
+        .orientation(orientation_map.as_ref().zip(args.orientation_weight))
+        .live_params(
+            args.live_params
+                .as_deref()
+                .map(|path| LiveParams::watch(path, args.mesh, live_kinds)),
+        )
+        .superpixels(superpixels.as_ref().zip(args.superpixel_proposals))
+        .edge_strokes(edge_strokes.as_ref().zip(args.edge_proposals))
+        .flow_strokes(flow_strokes.as_ref().zip(args.flow_proposals))
+        .fixed_shapes(fixed_shapes.as_ref())
+        .color_target(luma_target.as_ref().map(|_| &target))
+        .budget(budget)
+        .footprints(Footprints::new(
+            args.footprint_memory << 20,
+            args.footprint_spill.as_deref(),
+        ));
+    let (generated_image, mut accepted_shapes) = match (args.stipple, args.hatch) {
+        (Some(dot_size), _) => stipple::stipple(&target, dot_size, args.alpha),
+        (_, Some(angles)) => hatching::hatch(&target, angles, args.alpha),
+        _ => anneal(luma_target.as_ref().unwrap_or(&target), params),
+    };
+    mem_stats::enter(Subsystem::Output);
+    let mut output = to_image(&generated_image);
+    if let Some(ref normalization) = normalization {
+        output
+            .pixels_mut()
+            .for_each(|pixel| *pixel = normalization.invert(*pixel));
+        for (_, fill, _) in &mut accepted_shapes {
+            *fill = fill.map_colors(|_, color| normalization.invert(color));
+        }
+    }
+    if let (Some(matte), Some(background)) = (&matte, &background) {
+        // blending the annealed subject back into the original background
+        for (x, y, pixel) in output.enumerate_pixels_mut() {
+            let alpha = matte[x as usize * h as usize + y as usize];
+            let original = background.get_pixel(x, y);
+            *pixel = Rgb([0, 1, 2].map(|c| {
+                (pixel[c] as f64 * alpha + original[c] as f64 * (1.0 - alpha)).round() as u8
+            }));
+        }
+    }
+    let final_cost = get_cost(&target, &generated_image);
+    let metadata = Metadata::new(seed, final_cost);
+    if args.annotate {
+        let caption = annotate::caption(accepted_shapes.len(), final_cost, run.seconds());
+        output = annotate::annotate(&output, &caption);
+    }
+    match args.output_colors {
+        Some(colors) => {
+            let palette = Palette::new(&output, colors as usize);
+            if args.dither {
+                dither(&mut output, &palette);
+            } else {
+                output
+                    .pixels_mut()
+                    .for_each(|pixel| palette.map_color(pixel));
+            }
+            // PNGs can store the palette itself, which keeps them a lot smaller
+            if ImageFormat::from_path(&output_path).unwrap() == ImageFormat::Png {
+                save_indexed_png(
+                    &output,
+                    &palette,
+                    colors as usize,
+                    &output_path,
+                    output_icc,
+                    Some(&metadata),
+                );
+            } else {
+                save_atomically(&output, &output_path, output_icc, Some(&metadata));
+            }
         }
+        None => save_atomically(&output, &output_path, output_icc, Some(&metadata)),
+    }
+    let (w, h) = (generated_image.len(), generated_image[0].len());
+    if args.prune_shapes {
+        let count = accepted_shapes.len();
+        accepted_shapes = prune_hidden(w, h, &accepted_shapes);
+        println!("pruned {} hidden shapes", count - accepted_shapes.len());
+    }
+    if let Some(path) = args.shapes {
+        save_shape_list(&path, w, h, &accepted_shapes);
+    }
+    if let Some(path) = args.pdf {
+        save_pdf(&path, w, h, &accepted_shapes);
+    }
+    if let Some(path) = args.eps {
+        save_eps(&path, w, h, &accepted_shapes);
+    }
+    if let Some(path) = args.svg {
+        save_svg(&path, w, h, &accepted_shapes, final_cost);
+    }
+    if let (Some(kind), Some(path)) = (args.export_code, args.code_output) {
+        save_sketch(&path, kind, w, h, &accepted_shapes);
+    }
+    if let Some(path) = args.viewer {
+        save_viewer(&path, w, h, &accepted_shapes);
+    }
+    if let Some(path) = args.debug_overlay {
+        to_image(&draw_overlay(&generated_image, &accepted_shapes))
+            .save(path)
+            .unwrap();
+    }
+    if let Some(path) = args.error_map {
+        to_image(&error_map(
+            &target,
+            &generated_image,
+            args.tile_size,
+            args.debug_colorspace,
+        ))
+        .save(path)
+        .unwrap();
+    }
+    if let Some(path) = args.shape_count_map {
+        to_image(&shape_count_map(w, h, &accepted_shapes, args.tile_size))
+            .save(path)
+            .unwrap();
+    }
+    if let Some(path) = args.run_log {
+        run.finish(&path, final_cost, accepted_shapes.len());
+    }
+    if args.mem_stats {
+        mem_stats::report();
     }
-    original_image.save(args.output).unwrap();
 }
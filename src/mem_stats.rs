@@ -0,0 +1,129 @@
+//! Where the memory goes over a run, for `--mem-stats`. The peak resident set size comes from the
+//! OS. Counting allocations needs every single one to go through a counting allocator, which slows
+//! everything down a little, so that only gets built in with the `mem-stats` feature
+
+use std::{
+    fs,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Parts of a run that allocations get counted towards
+#[derive(Clone, Copy)]
+pub enum Subsystem {
+    /// Decoding the input and getting it ready to anneal against
+    Loading,
+    /// Annealing, including the polishing pass and any helper threads
+    Annealing,
+    /// Saving the result and everything exported alongside it
+    Output,
+}
+
+/// The subsystem running right now. There's only ever one, since they run one after another
+static CURRENT: AtomicUsize = AtomicUsize::new(Subsystem::Loading as usize);
+
+/// Counts allocations towards `subsystem` from now on
+pub fn enter(subsystem: Subsystem) {
+    CURRENT.store(subsystem as usize, Ordering::Relaxed);
+}
+
+#[cfg(feature = "mem-stats")]
+mod counting {
+    use super::CURRENT;
+    use std::{
+        alloc::{GlobalAlloc, Layout, System},
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    pub static ALLOCATIONS: [AtomicUsize; 3] = [
+        AtomicUsize::new(0),
+        AtomicUsize::new(0),
+        AtomicUsize::new(0),
+    ];
+    pub static BYTES: [AtomicUsize; 3] = [
+        AtomicUsize::new(0),
+        AtomicUsize::new(0),
+        AtomicUsize::new(0),
+    ];
+    static LIVE: AtomicUsize = AtomicUsize::new(0);
+    pub static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+    /// The system allocator, counting everything allocated through it
+    struct CountingAllocator;
+
+    fn count(grown: usize) {
+        let subsystem = CURRENT.load(Ordering::Relaxed);
+        ALLOCATIONS[subsystem].fetch_add(1, Ordering::Relaxed);
+        BYTES[subsystem].fetch_add(grown, Ordering::Relaxed);
+        let live = LIVE.fetch_add(grown, Ordering::Relaxed) + grown;
+        PEAK.fetch_max(live, Ordering::Relaxed);
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let pointer = System.alloc(layout);
+            if !pointer.is_null() {
+                count(layout.size());
+            }
+            pointer
+        }
+
+        unsafe fn dealloc(&self, pointer: *mut u8, layout: Layout) {
+            System.dealloc(pointer, layout);
+            LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        }
+
+        unsafe fn realloc(&self, pointer: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            let new_pointer = System.realloc(pointer, layout, new_size);
+            if !new_pointer.is_null() {
+                // growing only counts the new bytes, the rest were already counted
+                if new_size >= layout.size() {
+                    count(new_size - layout.size());
+                } else {
+                    LIVE.fetch_sub(layout.size() - new_size, Ordering::Relaxed);
+                }
+            }
+            new_pointer
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+}
+
+/// Peak resident set size in bytes, if the OS keeps track of it where we can find it
+fn peak_rss() -> Option<usize> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kilobytes: usize = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kilobytes * 1024)
+}
+
+fn mebibytes(bytes: usize) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+/// Prints the peak memory usage, and how much each subsystem allocated if that got counted
+pub fn report() {
+    match peak_rss() {
+        Some(bytes) => println!("peak resident memory: {:.1} MiB", mebibytes(bytes)),
+        None => println!("peak resident memory: unknown on this platform"),
+    }
+    #[cfg(feature = "mem-stats")]
+    {
+        use counting::{ALLOCATIONS, BYTES, PEAK};
+        const SUBSYSTEM_NAMES: [&str; 3] = ["loading", "annealing", "output"];
+        println!(
+            "peak heap memory: {:.1} MiB",
+            mebibytes(PEAK.load(Ordering::Relaxed))
+        );
+        for (i, name) in SUBSYSTEM_NAMES.iter().enumerate() {
+            println!(
+                "{name}: {} allocations, {:.1} MiB allocated",
+                ALLOCATIONS[i].load(Ordering::Relaxed),
+                mebibytes(BYTES[i].load(Ordering::Relaxed))
+            );
+        }
+    }
+    #[cfg(not(feature = "mem-stats"))]
+    println!("allocations aren't counted without the mem-stats feature");
+}
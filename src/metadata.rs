@@ -0,0 +1,130 @@
+//! What a run was, embedded in the output image so it can be reproduced from the image alone: the
+//! version of the program, the arguments it was run with, the seed, and the final cost. PNGs get
+//! them as text chunks (`tEXt`, or `iTXt` for text that isn't Latin-1), and JPEGs as EXIF, with the
+//! seed and cost in the image description along with the arguments
+
+use std::env::args;
+
+/// Biggest an EXIF segment can be, since its length has to fit in 2 bytes
+const MAX_EXIF_LENGTH: usize = u16::MAX as usize - 2;
+
+pub struct Metadata {
+    software: String,
+    arguments: String,
+    seed: u64,
+    cost: f64,
+}
+
+/// A PNG chunk of type `kind` holding `data`
+fn png_chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+    chunk.extend(kind);
+    chunk.extend(data);
+    chunk.extend(crc32fast::hash(&chunk[4..]).to_be_bytes());
+    chunk
+}
+
+/// A text chunk with `value` under `keyword`, which has to be Latin-1 for `tEXt`
+fn text_chunk(keyword: &str, value: &str) -> Vec<u8> {
+    let latin1 = value
+        .chars()
+        .map(|c| u8::try_from(c as u32).ok())
+        .collect::<Option<Vec<u8>>>();
+    let mut data = keyword.as_bytes().to_vec();
+    data.push(0);
+    match latin1 {
+        Some(bytes) => {
+            data.extend(bytes);
+            png_chunk(b"tEXt", &data)
+        }
+        None => {
+            // uncompressed, with no language tag or translated keyword
+            data.extend([0, 0, 0, 0]);
+            data.extend(value.as_bytes());
+            png_chunk(b"iTXt", &data)
+        }
+    }
+}
+
+/// An EXIF directory entry for the ASCII string `value` (with its terminating zero) under `tag`,
+/// whose value lives at `offset`
+fn ascii_entry(tag: u16, value: &[u8], offset: u32) -> Vec<u8> {
+    let mut entry = tag.to_le_bytes().to_vec();
+    // type 2 is ASCII
+    entry.extend(2u16.to_le_bytes());
+    entry.extend((value.len() as u32).to_le_bytes());
+    entry.extend(offset.to_le_bytes());
+    entry
+}
+
+impl Metadata {
+    /// Metadata for this run, which was seeded with `seed` and ended up at `cost`
+    pub fn new(seed: u64, cost: f64) -> Metadata {
+        Metadata {
+            software: format!("anneal_image {}", env!("GIT_DESCRIBE")),
+            arguments: args().skip(1).collect::<Vec<_>>().join(" "),
+            seed,
+            cost,
+        }
+    }
+
+    /// The encoded PNG with the metadata in text chunks, just before its end chunk
+    pub fn embed_png(&self, png: &[u8]) -> Vec<u8> {
+        // the end chunk is always the last 12 bytes
+        let (image, end) = png.split_at(png.len() - 12);
+        let mut output = image.to_vec();
+        output.extend(text_chunk("Software", &self.software));
+        output.extend(text_chunk("anneal_image arguments", &self.arguments));
+        output.extend(text_chunk("anneal_image seed", &self.seed.to_string()));
+        output.extend(text_chunk("anneal_image cost", &self.cost.to_string()));
+        output.extend(end);
+        output
+    }
+
+    /// The encoded JPEG with the metadata in an EXIF segment, right after its start marker
+    pub fn embed_jpeg(&self, jpeg: &[u8]) -> Vec<u8> {
+        let ascii = |text: &str| {
+            let mut bytes = text
+                .chars()
+                .map(|c| if c.is_ascii() { c as u8 } else { b'?' })
+                .collect::<Vec<u8>>();
+            bytes.push(0);
+            bytes
+        };
+        let software = ascii(&self.software);
+        let mut description = ascii(&format!(
+            "seed {} | cost {} | arguments: {}",
+            self.seed, self.cost, self.arguments
+        ));
+        // the TIFF header, a directory of 2 entries, and the software all come first
+        let values_start = 8 + 2 + 2 * 12 + 4;
+        let fixed = 6 + values_start + software.len();
+        if fixed + description.len() > MAX_EXIF_LENGTH {
+            description.truncate(MAX_EXIF_LENGTH - fixed - 1);
+            description.push(0);
+        }
+        // little-endian TIFF header, with the directory right after it
+        let mut tiff = b"II*\0".to_vec();
+        tiff.extend(8u32.to_le_bytes());
+        tiff.extend(2u16.to_le_bytes());
+        // entries have to be sorted by tag: image description, then software
+        tiff.extend(ascii_entry(
+            0x010e,
+            &description,
+            (values_start + software.len()) as u32,
+        ));
+        tiff.extend(ascii_entry(0x0131, &software, values_start as u32));
+        // no more directories
+        tiff.extend(0u32.to_le_bytes());
+        tiff.extend(&software);
+        tiff.extend(&description);
+        let mut segment = vec![0xff, 0xe1];
+        segment.extend(((2 + 6 + tiff.len()) as u16).to_be_bytes());
+        segment.extend(b"Exif\0\0");
+        segment.extend(tiff);
+        let mut output = jpeg[..2].to_vec();
+        output.extend(segment);
+        output.extend(&jpeg[2..]);
+        output
+    }
+}
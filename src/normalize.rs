@@ -0,0 +1,69 @@
+//! Stretching the contrast of dark or washed-out images before annealing. Their colors are
+//! bunched up in a small part of the range, so shapes that get them slightly wrong barely change
+//! the cost, and the annealing settles for a muddy result. Spreading them out over the whole range
+//! first (and squeezing the result back afterwards) makes those mistakes count
+
+use image::{Rgb, RgbImage};
+
+/// Fraction of the pixels at each end of every channel's histogram that get clipped, so a few
+/// stray pixels don't stop the rest from being stretched
+const CLIP_FRACTION: f64 = 0.005;
+
+/// A per-channel linear stretch of `low..=high` onto the full range
+pub struct Normalization {
+    low: [u8; 3],
+    high: [u8; 3],
+}
+
+impl Normalization {
+    /// The stretch that spreads the image's colors out over the full range
+    pub fn new(image: &RgbImage) -> Normalization {
+        let mut histograms = [[0usize; 256]; 3];
+        for pixel in image.pixels() {
+            for (histogram, &value) in histograms.iter_mut().zip(&pixel.0) {
+                histogram[value as usize] += 1;
+            }
+        }
+        let clipped = (image.pixels().len() as f64 * CLIP_FRACTION) as usize;
+        // the first value (counting down from the top if `from_top`) with more than the clipped
+        // pixels at or before it
+        let percentile = |histogram: &[usize; 256], from_top: bool| {
+            let mut count = 0;
+            (0..256)
+                .map(|i| if from_top { 255 - i } else { i })
+                .find(|&value| {
+                    count += histogram[value];
+                    count > clipped
+                })
+                .unwrap_or(0) as u8
+        };
+        let low = histograms.map(|histogram| percentile(&histogram, false));
+        let high = histograms.map(|histogram| percentile(&histogram, true));
+        Normalization { low, high }
+    }
+
+    /// Stretches the image's colors
+    pub fn apply(&self, image: &mut RgbImage) {
+        for pixel in image.pixels_mut() {
+            for channel in 0..3 {
+                let (low, high) = (self.low[channel] as f64, self.high[channel] as f64);
+                if high > low {
+                    let value = (pixel[channel] as f64 - low) * 255.0 / (high - low);
+                    pixel[channel] = value.round().clamp(0.0, 255.0) as u8;
+                }
+            }
+        }
+    }
+
+    /// Undoes the stretch on a single color
+    pub fn invert(&self, color: Rgb<u8>) -> Rgb<u8> {
+        Rgb([0, 1, 2].map(|channel| {
+            let (low, high) = (self.low[channel] as f64, self.high[channel] as f64);
+            if high > low {
+                (low + color[channel] as f64 * (high - low) / 255.0).round() as u8
+            } else {
+                color[channel]
+            }
+        }))
+    }
+}
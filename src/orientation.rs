@@ -0,0 +1,138 @@
+//! Which way the structure of the original image runs at each pixel, found from its structure
+//! tensor, so shapes can be nudged into lining up with it the way brush strokes would.
+//! Orientations are stored as doubled-angle vectors, so that a line at 0° and one at 180° (which are
+//! the same line) average out to the same direction instead of canceling out
+
+use crate::{coord::Coord, target::Target};
+
+/// Radius in pixels of the neighborhood the structure tensor gets averaged over, unless it's
+/// smoothed further
+const TENSOR_RADIUS: usize = 4;
+
+pub struct OrientationMap {
+    h: usize,
+    /// `(cos 2θ, sin 2θ)` of the direction along the image's structure at each pixel (column-major),
+    /// scaled by how strongly the structure runs one way
+    field: Vec<(f64, f64)>,
+}
+
+/// Averages a column-major `w` by `h` grid over boxes of `radius` around each pixel
+fn box_blur(values: &[f64], w: usize, h: usize, radius: usize) -> Vec<f64> {
+    let blur_1d = |values: &[f64], len: usize, index: &dyn Fn(usize, usize) -> usize, lines| {
+        let mut blurred = vec![0.0; values.len()];
+        for line in 0..lines {
+            for i in 0..len {
+                let lo = i.saturating_sub(radius);
+                let hi = (i + radius).min(len - 1);
+                blurred[index(line, i)] =
+                    (lo..=hi).map(|j| values[index(line, j)]).sum::<f64>() / (hi - lo + 1) as f64;
+            }
+        }
+        blurred
+    };
+    let down_columns = blur_1d(values, h, &|x, y| x * h + y, w);
+    blur_1d(&down_columns, w, &|y, x| x * h + y, h)
+}
+
+/// Sobel gradients `(gx, gy)` of the original image's luminance at each pixel (column-major),
+/// clamping at the edges
+pub fn gradients(target: &Target) -> Vec<(f64, f64)> {
+    let (w, h) = (target.width(), target.height());
+    let luminance = |x: usize, y: usize| {
+        let [r, g, b] = target
+            .pixel(Coord::new(x.min(w - 1), y.min(h - 1)))
+            .0
+            .map(|value| value as f64);
+        0.299 * r + 0.587 * g + 0.114 * b
+    };
+    (0..w)
+        .flat_map(|x| (0..h).map(move |y| (x, y)))
+        .map(|(x, y)| {
+            let (l, r) = (x.saturating_sub(1), x + 1);
+            let (u, d) = (y.saturating_sub(1), y + 1);
+            let gx = luminance(r, u) + 2.0 * luminance(r, y) + luminance(r, d)
+                - luminance(l, u)
+                - 2.0 * luminance(l, y)
+                - luminance(l, d);
+            let gy = luminance(l, d) + 2.0 * luminance(x, d) + luminance(r, d)
+                - luminance(l, u)
+                - 2.0 * luminance(x, u)
+                - luminance(r, u);
+            (gx, gy)
+        })
+        .collect()
+}
+
+impl OrientationMap {
+    pub fn new(target: &Target) -> OrientationMap {
+        OrientationMap::smoothed(target, TENSOR_RADIUS)
+    }
+
+    /// The orientations with the structure tensor averaged over `radius` pixels every way, which
+    /// makes them turn more gradually
+    pub fn smoothed(target: &Target, radius: usize) -> OrientationMap {
+        let (w, h) = (target.width(), target.height());
+        let gradients = gradients(target);
+        let jxx = gradients.iter().map(|(gx, _)| gx * gx).collect::<Vec<_>>();
+        let jyy = gradients.iter().map(|(_, gy)| gy * gy).collect::<Vec<_>>();
+        let jxy = gradients.iter().map(|(gx, gy)| gx * gy).collect::<Vec<_>>();
+        let (jxx, jyy, jxy) = (
+            box_blur(&jxx, w, h, radius),
+            box_blur(&jyy, w, h, radius),
+            box_blur(&jxy, w, h, radius),
+        );
+        let field = (0..w * h)
+            .map(|i| {
+                // the tensor's doubled-angle vector points along the gradient, and flipping it
+                // turns it to run along the structure instead
+                let (c, s) = (jyy[i] - jxx[i], -2.0 * jxy[i]);
+                let trace = jxx[i] + jyy[i];
+                if trace <= f64::EPSILON {
+                    (0.0, 0.0)
+                } else {
+                    // normalizing by the trace scales it by the coherence
+                    (c / trace, s / trace)
+                }
+            })
+            .collect();
+        OrientationMap { h, field }
+    }
+
+    /// Which way the structure runs at `coord`, as a unit vector (pointing either way along it),
+    /// unless it doesn't run any particular way there
+    pub fn direction(&self, coord: Coord) -> Option<(f64, f64)> {
+        let (c, s) = self.field[coord.index(self.h)];
+        let angle = s.atan2(c) / 2.0;
+        (c.hypot(s) > f64::EPSILON).then(|| (angle.cos(), angle.sin()))
+    }
+
+    /// How well a shape covering the given coordinates lines up with the image's structure under
+    /// it, from -1 (across it) to 1 (along it). Shapes that don't run any particular way get 0
+    pub fn alignment(&self, coords: &[Coord]) -> f64 {
+        if coords.len() < 2 {
+            return 0.0;
+        }
+        let n = coords.len() as f64;
+        let mean_x = coords.iter().map(|c| c.x as f64).sum::<f64>() / n;
+        let mean_y = coords.iter().map(|c| c.y as f64).sum::<f64>() / n;
+        // the shape's own orientation, from its second moments
+        let (mut mxx, mut myy, mut mxy) = (0.0, 0.0, 0.0);
+        let (mut field_c, mut field_s) = (0.0, 0.0);
+        for &coord in coords {
+            let (dx, dy) = (coord.x as f64 - mean_x, coord.y as f64 - mean_y);
+            mxx += dx * dx;
+            myy += dy * dy;
+            mxy += dx * dy;
+            let (c, s) = self.field[coord.index(self.h)];
+            field_c += c;
+            field_s += s;
+        }
+        let trace = mxx + myy;
+        if trace <= f64::EPSILON {
+            return 0.0;
+        }
+        // scaled by how elongated the shape is, so squares don't get a say
+        let (shape_c, shape_s) = ((mxx - myy) / trace, 2.0 * mxy / trace);
+        (shape_c * field_c + shape_s * field_s) / n
+    }
+}
@@ -0,0 +1,492 @@
+//! A last pass after annealing that nudges the corners and colors of every accepted shape (or where
+//! stamps go, how big they are, and how they're turned) a step at a time, keeping whatever lowers
+//! the cost, until nothing does anymore (or it's gone over them `MAX_PASSES` times). Annealing is
+//! good at finding roughly the right shapes but slow at lining them up exactly, which this does
+//! cheaply. A nudge that helps gets taken again the same way for as long as it keeps helping, and
+//! new colors only get painted over the pixels of the shape that nothing else covers. When every
+//! layer is painted plainly, nudged shapes only repaint the pixels they cover or used to, from what
+//! the layers under them add up to, rather than every layer around them
+
+use crate::{
+    canvas::{Canvas, Transaction},
+    coord::{Coord, Pixels},
+    geometry::{self, Polygon},
+    journal::Journal,
+    layers::Layers,
+    stamps::Placement,
+    target::Target,
+    update_cost, update_cost_to_pixels, BlendMode, Fill, Image, Shape, Symmetry,
+};
+
+/// Most times every layer gets gone over, since the last few passes only ever find a little more
+const MAX_PASSES: usize = 10;
+
+/// Every shape one step away from `shape`, with its corners snapped to `grid`, or `None` for the
+/// steps that would leave the image or break the shape. Each step has the same place in the list
+/// for every shape of the same kind (and number of vertices), so it can be taken again from the
+/// nudged shape
+fn nudged_shapes(shape: Shape, grid: usize, w: usize, h: usize) -> Vec<Option<Shape>> {
+    let nudge = |value: usize, up: bool| {
+        if up {
+            Some(value + grid)
+        } else {
+            value.checked_sub(grid)
+        }
+    };
+    let mut shapes = Vec::new();
+    for up in [false, true] {
+        match shape {
+            Shape::Rectangle {
+                top_left,
+                bottom_right,
+            }
+            | Shape::Ellipse {
+                top_left,
+                bottom_right,
+            } => {
+                let corners = [top_left.0, top_left.1, bottom_right.0, bottom_right.1];
+                // circles stay circles by nudging two corners at once, which resizes them from a
+                // corner or moves them along
+                let circle = matches!(shape, Shape::Ellipse { .. })
+                    && bottom_right.0 - top_left.0 == bottom_right.1 - top_left.1;
+                let moves: &[&[usize]] = if circle {
+                    &[&[0, 1], &[2, 3], &[0, 2], &[1, 3]]
+                } else {
+                    &[&[0], &[1], &[2], &[3]]
+                };
+                for &indices in moves {
+                    let mut nudged = corners;
+                    let mut moved = true;
+                    for &i in indices {
+                        match nudge(corners[i], up) {
+                            Some(value) => nudged[i] = value,
+                            None => moved = false,
+                        }
+                    }
+                    if !moved {
+                        shapes.push(None);
+                        continue;
+                    }
+                    let [x1, y1, x2, y2] = nudged;
+                    let (top_left, bottom_right) = ((x1, y1), (x2, y2));
+                    let nudged = match shape {
+                        Shape::Rectangle { .. } => Shape::Rectangle {
+                            top_left,
+                            bottom_right,
+                        },
+                        _ => Shape::Ellipse {
+                            top_left,
+                            bottom_right,
+                        },
+                    };
+                    shapes.push(geometry::validate(nudged, w, h, false));
+                }
+            }
+            Shape::Triangle(vertices) => {
+                for vertex in 0..3 {
+                    for axis in 0..2 {
+                        let mut nudged = vertices;
+                        let value = if axis == 0 {
+                            &mut nudged[vertex].0
+                        } else {
+                            &mut nudged[vertex].1
+                        };
+                        let Some(new_value) = nudge(*value, up) else {
+                            shapes.push(None);
+                            continue;
+                        };
+                        *value = new_value;
+                        shapes.push(geometry::validate(Shape::Triangle(nudged), w, h, false));
+                    }
+                }
+            }
+            // blobs' control points get nudged the same as polygons' vertices
+            Shape::Polygon(polygon) | Shape::Blob(polygon) => {
+                let vertices = polygon.vertices();
+                for vertex in 0..vertices.len() {
+                    for axis in 0..2 {
+                        let mut nudged = vertices.to_vec();
+                        let value = if axis == 0 {
+                            &mut nudged[vertex].0
+                        } else {
+                            &mut nudged[vertex].1
+                        };
+                        let Some(new_value) = nudge(*value, up) else {
+                            shapes.push(None);
+                            continue;
+                        };
+                        *value = new_value;
+                        let nudged = match shape {
+                            Shape::Blob(_) => Shape::Blob(Polygon::new(&nudged)),
+                            _ => Shape::Polygon(Polygon::new(&nudged)),
+                        };
+                        // a vertex nudged in past its neighbors would get dropped from the hull,
+                        // which isn't a nudge anymore
+                        shapes.push(geometry::validate(nudged, w, h, false).filter(|shape| {
+                            matches!(shape, Shape::Polygon(nudged) | Shape::Blob(nudged) if nudged.vertices().len() == vertices.len())
+                        }));
+                    }
+                }
+            }
+            // stamps get moved and resized a grid step at a time, and turned a degree at a time
+            Shape::Stamp(placement) => {
+                let (x, y) = placement.center;
+                let angle = (placement.angle + if up { 1 } else { 359 }) % 360;
+                let nudged = [
+                    nudge(x, up).map(|x| Placement {
+                        center: (x, y),
+                        ..placement
+                    }),
+                    nudge(y, up).map(|y| Placement {
+                        center: (x, y),
+                        ..placement
+                    }),
+                    nudge(placement.size, up).map(|size| Placement { size, ..placement }),
+                    Some(Placement { angle, ..placement }),
+                ];
+                shapes.extend(
+                    nudged
+                        .into_iter()
+                        .map(|placement| geometry::validate(Shape::Stamp(placement?), w, h, false)),
+                );
+            }
+        }
+    }
+    shapes
+}
+
+/// Every fill one step away from `fill` in a single channel of one of its colors, or `None` for
+/// the steps that would go past 0 or 255. Like with `nudged_shapes`, each step has the same place
+/// in the list for every fill of the same kind
+fn nudged_fills(fill: Fill) -> Vec<Option<Fill>> {
+    let mut fills = Vec::new();
+    for (i, color) in fill.colors().iter().enumerate() {
+        for channel in 0..3 {
+            for nudged in [color[channel].checked_sub(1), color[channel].checked_add(1)] {
+                fills.push(nudged.map(|nudged| {
+                    fill.map_colors(|j, mut color| {
+                        if j == i {
+                            color[channel] = nudged;
+                        }
+                        color
+                    })
+                }));
+            }
+        }
+    }
+    fills
+}
+
+/// What polishing works on, with the cost of the canvas as it is
+struct Polisher<'a> {
+    target: &'a Target,
+    canvas: &'a mut Canvas,
+    layers: &'a mut Layers,
+    cost: f64,
+    grid: usize,
+    symmetry: Option<Symmetry>,
+    journal: Option<&'a mut Journal>,
+    iteration: u64,
+    /// While going over the layers in order, if every one of them is painted plainly: the index
+    /// of the layer on top of every pixel as of the start of the pass (which still tells which
+    /// pixels the layers after the one being nudged cover), and the image with just the layers
+    /// before it painted over it
+    plain: Option<(Vec<Option<usize>>, Image)>,
+}
+
+impl Polisher<'_> {
+    /// Keeps what `transaction` painted if it brings the cost down to `new_cost`, and puts it back
+    /// otherwise. Returns whether it was kept
+    fn keep(&mut self, transaction: Transaction, new_cost: f64) -> bool {
+        if new_cost < self.cost {
+            self.cost = new_cost;
+            self.canvas.commit(transaction);
+            true
+        } else {
+            self.canvas.rollback(transaction);
+            false
+        }
+    }
+
+    /// Makes the layer at `index` into `shape` (and its mirrored copies) if that lowers the cost.
+    /// Returns whether it did
+    fn reshape(&mut self, index: usize, shape: Shape, fill: Fill) -> bool {
+        let (w, h) = (self.target.width(), self.target.height());
+        let shapes = match self.symmetry {
+            Some(symmetry) => symmetry.mirror(shape, w, h),
+            None => vec![shape],
+        };
+        let (coords, new_pixels) = match self.plain {
+            Some((ref top, ref below)) => {
+                // the pixels nothing after the layer covers go to its fill where it's been nudged
+                // to, and to whatever's under it where it's been nudged away from
+                let mut new_coords = self.layers.covered(&shapes).0;
+                new_coords.sort_unstable();
+                let mut coords = self.layers.layer_coords(index);
+                coords.extend_from_slice(&new_coords);
+                coords.sort_unstable();
+                coords.dedup();
+                coords.retain(|coord| top[coord.index(h)].is_none_or(|top| top <= index));
+                let new_pixels = coords
+                    .iter()
+                    .map(|&coord| match new_coords.binary_search(&coord) {
+                        Ok(_) => fill.color_at(coord),
+                        Err(_) => below.at(coord),
+                    })
+                    .collect();
+                (coords, new_pixels)
+            }
+            None => self.layers.reshaped(index, shapes.clone(), fill),
+        };
+        let transaction = self.canvas.replace(coords, &new_pixels);
+        let new_cost = update_cost_to_pixels(
+            self.cost,
+            self.target,
+            transaction.coords(),
+            transaction.old_pixels(),
+            transaction.new_pixels().unwrap_or(&new_pixels),
+        );
+        if !self.keep(transaction, new_cost) {
+            return false;
+        }
+        if let Some(ref mut journal) = self.journal {
+            journal.replace(self.iteration, index, &shapes, fill);
+        }
+        self.layers.reshape(index, shapes, fill);
+        true
+    }
+
+    /// Paints the layer at `index` with `fill` if that lowers the cost, returning whether it did.
+    /// `showing` are the layer's pixels that nothing covers, if it's painted plainly enough that
+    /// they're all that changes
+    fn recolor(&mut self, index: usize, fill: Fill, showing: Option<&[Coord]>) -> bool {
+        let kept = match showing {
+            Some(coords) => {
+                // the canvas's own errors are against the original image, which isn't always the
+                // target (like with `--luma-first`), so they're added up here instead
+                let transaction = self.canvas.paint(coords.to_vec(), fill, BlendMode::NORMAL);
+                let new_cost = match (transaction.new_pixels(), fill) {
+                    (Some(new_pixels), _) => update_cost_to_pixels(
+                        self.cost,
+                        self.target,
+                        transaction.coords(),
+                        transaction.old_pixels(),
+                        new_pixels,
+                    ),
+                    (None, Fill::Solid(color)) => update_cost(
+                        self.cost,
+                        self.target,
+                        transaction.coords(),
+                        transaction.old_pixels(),
+                        color,
+                        BlendMode::NORMAL,
+                        None,
+                    ),
+                    (None, _) => {
+                        let new_pixels = transaction
+                            .coords()
+                            .iter()
+                            .map(|&coord| fill.color_at(coord))
+                            .collect::<Vec<_>>();
+                        update_cost_to_pixels(
+                            self.cost,
+                            self.target,
+                            transaction.coords(),
+                            transaction.old_pixels(),
+                            &new_pixels,
+                        )
+                    }
+                };
+                self.keep(transaction, new_cost)
+            }
+            None => {
+                let (coords, new_pixels) = self.layers.recolored(index, fill);
+                let transaction = self.canvas.replace(coords, &new_pixels);
+                let new_cost = update_cost_to_pixels(
+                    self.cost,
+                    self.target,
+                    transaction.coords(),
+                    transaction.old_pixels(),
+                    transaction.new_pixels().unwrap_or(&new_pixels),
+                );
+                self.keep(transaction, new_cost)
+            }
+        };
+        if !kept {
+            return false;
+        }
+        if let Some(ref mut journal) = self.journal {
+            journal.recolor(self.iteration, index, fill);
+        }
+        self.layers.recolor(index, fill);
+        true
+    }
+
+    /// Nudges the layer at `index` every way it can be, each as many steps as keep lowering the
+    /// cost. Returns whether any of them did
+    fn polish_layer(&mut self, index: usize) -> bool {
+        let (w, h) = (self.target.width(), self.target.height());
+        let Some((shapes, _)) = self.layers.get(index) else {
+            return false;
+        };
+        let mut improved = false;
+        for step in 0..nudged_shapes(shapes[0], self.grid, w, h).len() {
+            loop {
+                let (shapes, fill) = self.layers.get(index).unwrap();
+                let nudged = nudged_shapes(shapes[0], self.grid, w, h).get(step).copied();
+                match nudged.flatten() {
+                    Some(shape) if self.reshape(index, shape, fill) => improved = true,
+                    _ => break,
+                }
+            }
+        }
+        // the shape has settled, so what of it shows stays the same while its fill gets nudged
+        let showing = match self.plain {
+            Some((ref top, _)) => {
+                let mut coords = self.layers.layer_coords(index);
+                coords.retain(|coord| top[coord.index(h)].is_none_or(|top| top <= index));
+                Some(coords)
+            }
+            None => self.layers.showing(index),
+        };
+        let (_, fill) = self.layers.get(index).unwrap();
+        for step in 0..nudged_fills(fill).len() {
+            loop {
+                let (_, fill) = self.layers.get(index).unwrap();
+                let nudged = nudged_fills(fill).get(step).copied();
+                match nudged.flatten() {
+                    Some(fill) if self.recolor(index, fill, showing.as_deref()) => improved = true,
+                    _ => break,
+                }
+            }
+        }
+        improved
+    }
+}
+
+/// Nudges every layer until none of the nudges lower the cost anymore (or it's gone over them
+/// `MAX_PASSES` times), returning the new cost. Mirrored copies follow along with the shape they're
+/// mirroring. Every kept nudge gets logged to the journal (if there is one) as of `iteration`
+#[allow(clippy::too_many_arguments)]
+pub fn polish(
+    target: &Target,
+    canvas: &mut Canvas,
+    layers: &mut Layers,
+    cost: f64,
+    grid: usize,
+    symmetry: Option<Symmetry>,
+    journal: Option<&mut Journal>,
+    iteration: u64,
+) -> f64 {
+    let mut polisher = Polisher {
+        target,
+        canvas,
+        layers,
+        cost,
+        grid,
+        symmetry,
+        journal,
+        iteration,
+        plain: None,
+    };
+    for pass in 1..=MAX_PASSES {
+        let mut improved = false;
+        polisher.plain = polisher
+            .layers
+            .top_layers()
+            .map(|top| (top, polisher.layers.initial().clone()));
+        for index in 0..polisher.layers.len() {
+            improved |= polisher.polish_layer(index);
+            // the layers after this one get nudged over it as it ended up
+            if let (Some((_, ref mut below)), Some((_, fill))) =
+                (&mut polisher.plain, polisher.layers.get(index))
+            {
+                for coord in polisher.layers.layer_coords(index) {
+                    *below.at_mut(coord) = fill.color_at(coord);
+                }
+            }
+        }
+        println!("polishing pass {pass} | cost: {:.5}", polisher.cost);
+        if !improved {
+            break;
+        }
+    }
+    polisher.cost
+}
+
+#[cfg(test)]
+mod tests {
+    use super::polish;
+    use crate::{
+        canvas::Canvas, footprints::Footprints, get_cost, layers::Layers, target::Target, Blend,
+        BlendMode, Fill, Shape,
+    };
+    use image::Rgb;
+
+    const W: usize = 24;
+    const H: usize = 24;
+
+    #[test]
+    fn polishing_finishes_without_raising_the_cost() {
+        let original = (0..W)
+            .map(|x| {
+                (0..H)
+                    .map(|y| Rgb([(x * 10) as u8, (y * 10) as u8, 128]))
+                    .collect()
+            })
+            .collect::<Vec<Vec<_>>>();
+        let target = Target::new(&original);
+        // overlapping shapes a little off from the image, with one of them in a gradient
+        let shapes = [
+            (
+                Shape::Rectangle {
+                    top_left: (2, 3),
+                    bottom_right: (14, 12),
+                },
+                Fill::Solid(Rgb([60, 40, 120])),
+            ),
+            (
+                Shape::Triangle([(4, 20), (20, 6), (18, 22)]),
+                Fill::Solid(Rgb([170, 150, 140])),
+            ),
+            (
+                Shape::Ellipse {
+                    top_left: (8, 8),
+                    bottom_right: (20, 16),
+                },
+                Fill::Linear {
+                    start: (8, 12),
+                    end: (20, 12),
+                    colors: [Rgb([90, 110, 130]), Rgb([190, 110, 130])],
+                },
+            ),
+            (
+                Shape::Triangle([(0, 0), (10, 2), (3, 9)]),
+                Fill::Solid(Rgb([20, 20, 100])),
+            ),
+        ];
+        // with a blended layer on top, nudges get repainted through every layer around them
+        for last_blend_mode in [BlendMode::NORMAL, BlendMode::opaque(Blend::Multiply)] {
+            let initial = vec![vec![Rgb([0, 0, 0]); H]; W];
+            let mut layers = Layers::new(initial.clone(), Footprints::new(1 << 20, None), false);
+            let mut canvas = Canvas::new(initial, false, &target, false, None);
+            for (i, (shape, fill)) in shapes.into_iter().enumerate() {
+                let blend_mode = if i + 1 == shapes.len() {
+                    last_blend_mode
+                } else {
+                    BlendMode::NORMAL
+                };
+                let (coords, _) = layers.covered(&[shape]);
+                let transaction = canvas.paint(coords.clone(), fill, blend_mode);
+                canvas.commit(transaction);
+                layers.push(vec![shape], fill, blend_mode, &coords);
+            }
+            let cost = canvas.read(|image| get_cost(&target, image));
+            let polished = polish(&target, &mut canvas, &mut layers, cost, 1, None, None, 0);
+            assert!(polished <= cost, "polishing raised the cost");
+            // the cost it kept track of has to be the cost of what actually got painted
+            let full_cost = canvas.read(|image| get_cost(&target, image));
+            assert!((polished - full_cost).abs() < 1e-6);
+        }
+    }
+}
@@ -0,0 +1,67 @@
+//! The pixels covered by recently rejected shapes, so proposing the same shape again (usually in a
+//! different color) doesn't have to rasterize it all over again. Late in a run, and especially on a
+//! coarse grid, the same small shapes come up over and over
+
+use crate::{coord::Coord, Shape};
+use std::collections::{HashMap, VecDeque};
+
+/// How many coordinates the cache holds at most, across all of its shapes
+const CACHE_PIXELS: usize = 1 << 16;
+
+pub struct RasterCache {
+    w: usize,
+    h: usize,
+    coords: HashMap<Shape, Vec<Coord>>,
+    /// Cached shapes from the oldest to the newest, which can include ones taken back out
+    order: VecDeque<Shape>,
+    pixels: usize,
+    enabled: bool,
+}
+
+impl RasterCache {
+    /// An empty cache for shapes in a `w` by `h` image with their vertices snapped to `grid`.
+    /// Without a grid the same shape basically never comes up twice, so nothing gets cached
+    pub fn new(w: usize, h: usize, grid: usize) -> RasterCache {
+        RasterCache {
+            w,
+            h,
+            coords: HashMap::new(),
+            order: VecDeque::new(),
+            pixels: 0,
+            enabled: grid > 1,
+        }
+    }
+
+    /// Coordinates of every pixel covered by the shape, as given by `Shape::coords`.
+    /// A cached shape gets taken out of the cache, and has to be put back if it's rejected again
+    pub fn coords(&mut self, shape: Shape) -> Vec<Coord> {
+        match self.coords.remove(&shape) {
+            Some(coords) => {
+                self.pixels -= coords.len();
+                coords
+            }
+            None => shape.coords(self.w, self.h),
+        }
+    }
+
+    /// Caches the coordinates of a rejected shape, throwing out the oldest shapes if it's full
+    pub fn insert(&mut self, shape: Shape, coords: Vec<Coord>) {
+        if !self.enabled || coords.len() > CACHE_PIXELS || self.coords.contains_key(&shape) {
+            return;
+        }
+        self.pixels += coords.len();
+        self.coords.insert(shape, coords);
+        self.order.push_back(shape);
+        while self.pixels > CACHE_PIXELS {
+            let oldest = self.order.pop_front().unwrap();
+            if let Some(coords) = self.coords.remove(&oldest) {
+                self.pixels -= coords.len();
+            }
+        }
+        // shapes taken back out leave stale entries behind, which shouldn't pile up forever
+        if self.order.len() > 2 * self.coords.len() + 64 {
+            let coords = &self.coords;
+            self.order.retain(|shape| coords.contains_key(shape));
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! How big shapes should be, going by how big the details are that the canvas is still missing,
+//! for `--adaptive-sizes`. The residual (how far off the original image the canvas is, in luma) is
+//! compared with shifted copies of itself, further and further apart, until it's mostly stopped
+//! looking like itself. Big blotches of error stay alike over long distances and call for big
+//! shapes, while fine leftover detail stops being alike almost straight away and calls for small
+//! ones. Early on the residual is the whole image, and as it gets filled in the shapes shrink down
+//! to whatever's left, which saves hand-tuning sizes for every image
+
+use crate::coord::Coord;
+
+/// How many pixels of the residual get looked at, at most (bigger images get looked at every so
+/// many pixels instead of at every one)
+const MAX_SAMPLES: usize = 250_000;
+
+/// How alike the shifted residual has to be (as a fraction of how alike it is unshifted) for the
+/// details to count as spanning the shift
+const CORRELATION_THRESHOLD: f64 = 0.2;
+
+/// How many times the correlation length the biggest shapes get to be. Shapes a lot smaller than
+/// the limit still get proposed, and the residual is mostly fine noise after a while, which would
+/// shrink them down too far if the limit were any tighter
+const SIZE_FACTOR: f64 = 8.0;
+
+/// The luma of each channel's difference
+fn luma_difference(difference: [f64; 3]) -> f64 {
+    0.299 * difference[0] + 0.587 * difference[1] + 0.114 * difference[2]
+}
+
+/// How alike the residual is to itself shifted by `lag` samples, horizontally and vertically
+fn autocorrelation(residual: &[Vec<f64>], lag: usize) -> f64 {
+    let (w, h) = (residual.len(), residual[0].len());
+    let mut sum = 0.0;
+    let mut count = 0;
+    for x in 0..w {
+        for y in 0..h {
+            if x + lag < w {
+                sum += residual[x][y] * residual[x + lag][y];
+                count += 1;
+            }
+            if y + lag < h {
+                sum += residual[x][y] * residual[x][y + lag];
+                count += 1;
+            }
+        }
+    }
+    sum / count.max(1) as f64
+}
+
+/// Biggest size (in pixels across) shapes should be for a `w` by `h` image whose channels are off
+/// the original by `difference(coord)`
+pub fn max_shape_size(w: usize, h: usize, difference: impl Fn(Coord) -> [f64; 3]) -> usize {
+    let step = ((w * h) as f64 / MAX_SAMPLES as f64).sqrt().ceil().max(1.0) as usize;
+    let mut residual = (0..w)
+        .step_by(step)
+        .map(|x| {
+            (0..h)
+                .step_by(step)
+                .map(|y| luma_difference(difference(Coord::new(x, y))))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let count = (residual.len() * residual[0].len()) as f64;
+    let mean = residual.iter().flatten().sum::<f64>() / count;
+    residual
+        .iter_mut()
+        .flatten()
+        .for_each(|value| *value -= mean);
+    let variance = autocorrelation(&residual, 0);
+    let longest = residual.len().max(residual[0].len());
+    if variance <= 0.0 {
+        return w.max(h);
+    }
+    // doubling the lag until it's uncorrelated, then guessing where in between it got there
+    let (mut lag, mut correlation) = (0, 1.0);
+    let length = loop {
+        let next_lag = (lag * 2).max(1);
+        if next_lag >= longest {
+            break longest as f64;
+        }
+        let next_correlation = autocorrelation(&residual, next_lag) / variance;
+        if next_correlation < CORRELATION_THRESHOLD {
+            let between = (correlation - CORRELATION_THRESHOLD) / (correlation - next_correlation);
+            break lag as f64 + between * (next_lag - lag) as f64;
+        }
+        (lag, correlation) = (next_lag, next_correlation);
+    };
+    ((length * step as f64 * SIZE_FACTOR).round() as usize).clamp(1, w.max(h))
+}
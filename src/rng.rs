@@ -0,0 +1,76 @@
+//! Where all the random numbers come from, so that a run can be repeated exactly with `--seed`.
+//! Every thread gets its own generator. Given a seed, the first thread to draw a number gets the
+//! seed itself and every thread after it gets the next one along, so runs with the same seed and
+//! settings come out the same (at least as long as nothing depends on timing, like throttling)
+//!
+//! Deciding whether to accept moves can be split off into a stream of its own with
+//! `--acceptance-seed`. Proposals then don't depend on how many moves were accepted the
+//! old-fashioned way (by drawing a number), so a run at another temperature or alpha gets proposed
+//! the same shapes, and only what happens to them changes
+
+use rand::{distributions::Standard, prelude::Distribution, rngs::StdRng, Rng, SeedableRng};
+use std::{
+    cell::RefCell,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        OnceLock,
+    },
+};
+
+static SEED: OnceLock<u64> = OnceLock::new();
+
+/// How many threads have made a generator so far
+static THREADS: AtomicU64 = AtomicU64::new(0);
+
+static ACCEPTANCE_SEED: OnceLock<u64> = OnceLock::new();
+
+static ACCEPTANCE_THREADS: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    static RNG: RefCell<StdRng> = RefCell::new(match SEED.get() {
+        Some(seed) => StdRng::seed_from_u64(seed.wrapping_add(THREADS.fetch_add(1, Ordering::Relaxed))),
+        None => StdRng::from_entropy(),
+    });
+
+    /// Only there with an acceptance seed, and seeded from it the same way
+    static ACCEPTANCE_RNG: RefCell<Option<StdRng>> = RefCell::new(ACCEPTANCE_SEED.get().map(|seed| {
+        StdRng::seed_from_u64(seed.wrapping_add(ACCEPTANCE_THREADS.fetch_add(1, Ordering::Relaxed)))
+    }));
+}
+
+/// Makes every random number from now on come from `seed`. Has to be called before anything
+/// random happens, and only once
+pub fn seed(seed: u64) {
+    if SEED.set(seed).is_err() {
+        panic!("the random number generator can only be seeded once");
+    }
+}
+
+/// Makes every random number deciding whether to accept a move come from `seed`, instead of from
+/// the same generator as everything else. Has to be called before anything random happens, and
+/// only once
+pub fn seed_acceptance(seed: u64) {
+    if ACCEPTANCE_SEED.set(seed).is_err() {
+        panic!("acceptance can only be seeded once");
+    }
+}
+
+/// A random value, like `rand::random`
+pub fn random<T>() -> T
+where
+    Standard: Distribution<T>,
+{
+    RNG.with(|rng| rng.borrow_mut().gen())
+}
+
+/// A random value for deciding whether to accept a move, from its own generator if acceptance has
+/// been seeded separately
+pub fn acceptance_random<T>() -> T
+where
+    Standard: Distribution<T>,
+{
+    ACCEPTANCE_RNG.with(|rng| match rng.borrow_mut().as_mut() {
+        Some(rng) => rng.gen(),
+        None => random(),
+    })
+}
@@ -0,0 +1,106 @@
+//! A local history of runs for `--run-log`, so experiments can be compared later without any
+//! tracking service. Every run appends one JSON object on its own line, like
+//!
+//! ```json
+//! {"id": "0b7cbf2e-...", "started": "2024-05-01T12:00:00Z", "version": "v0.1.0-12-g1a2b3c4",
+//!  "arguments": ["--input", "in.png", ...], "cost": 12.3, "shapes": 456, "seconds": 78.9}
+//! ```
+//!
+//! `version` is `git describe` of the source the binary was built from, and `arguments` are the
+//! command line arguments the run was started with
+
+use rand::random;
+use serde_json::json;
+use std::{
+    env::args,
+    fs::OpenOptions,
+    io::Write,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+pub struct Run {
+    id: String,
+    started: SystemTime,
+    timer: Instant,
+    arguments: Vec<String>,
+}
+
+/// A random (version 4) UUID
+fn uuid() -> String {
+    let mut bytes: [u8; 16] = random();
+    bytes[6] = bytes[6] & 0x0f | 0x40;
+    bytes[8] = bytes[8] & 0x3f | 0x80;
+    let hex = bytes.map(|byte| format!("{byte:02x}")).concat();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// The time in UTC, formatted like `2024-05-01T12:00:00Z`
+fn timestamp(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let (days, seconds) = (seconds / 86400, seconds % 86400);
+    // turning days since 1970 into a date, going by 400-year eras from 0000-03-01
+    let days = days as i64 + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+impl Run {
+    /// Starts timing a run with a fresh ID
+    pub fn start() -> Run {
+        Run {
+            id: uuid(),
+            started: SystemTime::now(),
+            timer: Instant::now(),
+            arguments: args().skip(1).collect(),
+        }
+    }
+
+    /// How long the run has taken so far, in seconds
+    pub fn seconds(&self) -> f64 {
+        self.timer.elapsed().as_secs_f64()
+    }
+
+    /// Appends the run, along with how it turned out, to the log at `path`
+    pub fn finish(self, path: &str, cost: f64, shapes: usize) {
+        let line = json!({
+            "id": self.id,
+            "started": timestamp(self.started),
+            "version": env!("GIT_DESCRIBE"),
+            "arguments": self.arguments,
+            "cost": cost,
+            "shapes": shapes,
+            "seconds": self.seconds(),
+        });
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        writeln!(file, "{line}").unwrap();
+        println!("logged run {} to {path}", self.id);
+    }
+}
@@ -0,0 +1,245 @@
+//! Estimating how much a move changes the cost from a sample of the pixels it paints, for
+//! `--sample`. Pixels get drawn in proportion to how far their color moves (which is the most their
+//! difference from the original image can change by), so pixels that hardly change hardly ever get
+//! looked at, and every one that does get drawn is weighted back up by how unlikely it was. That
+//! keeps the estimate unbiased, and the spread of the draws gives a confidence interval around it.
+//! A move only gets decided once the interval is clear of whatever it has to beat, with more and
+//! more pixels drawn until it is (or until adding them all up would be quicker)
+//!
+//! The pixels get drawn from the acceptance stream of random numbers, since they're part of
+//! deciding whether to accept the move
+
+use crate::{
+    coord::Coord, pixel_difference, rng::acceptance_random, target::Target, BlendMode, Fill,
+};
+use image::Rgb;
+use std::iter::zip;
+
+/// How many standard errors the confidence interval reaches out either way, for about 95%
+const CONFIDENCE: f64 = 1.96;
+
+/// Fewest pixels drawn at a time, since the spread of a single draw says nothing
+const MIN_DRAWS: usize = 8;
+
+pub struct CostEstimate<'a> {
+    target: &'a Target,
+    coords: &'a [Coord],
+    old_pixels: &'a [Rgb<u8>],
+    new_pixels: Vec<Rgb<u8>>,
+    /// Running total of how far the colors move, up to and including each pixel
+    moved: Vec<f64>,
+    draws: usize,
+    sum: f64,
+    sum_squares: f64,
+}
+
+impl<'a> CostEstimate<'a> {
+    /// An estimate of how much painting `fill` over `old_pixels` at `coords` changes the sum of
+    /// the differences from `target`, with nothing drawn yet
+    pub fn new(
+        target: &'a Target,
+        coords: &'a [Coord],
+        old_pixels: &'a [Rgb<u8>],
+        fill: Fill,
+        blend_mode: BlendMode,
+    ) -> CostEstimate<'a> {
+        let new_pixels = zip(coords, old_pixels)
+            .map(|(&coord, &pixel)| blend_mode.blend(pixel, fill.color_at(coord)))
+            .collect();
+        CostEstimate::to_pixels(target, coords, old_pixels, new_pixels)
+    }
+
+    /// Like `new`, but for when every pixel at `coords` changes to its own new color, given by the
+    /// matching pixel in `new_pixels`
+    pub fn to_pixels(
+        target: &'a Target,
+        coords: &'a [Coord],
+        old_pixels: &'a [Rgb<u8>],
+        new_pixels: Vec<Rgb<u8>>,
+    ) -> CostEstimate<'a> {
+        let mut total = 0.0;
+        let moved = zip(old_pixels, &new_pixels)
+            .map(|(&old, &new)| {
+                total += pixel_difference(old, new) as f64;
+                total
+            })
+            .collect();
+        CostEstimate {
+            target,
+            coords,
+            old_pixels,
+            new_pixels,
+            moved,
+            draws: 0,
+            sum: 0.0,
+            sum_squares: 0.0,
+        }
+    }
+
+    fn change(&self, i: usize) -> f64 {
+        let coord = self.coords[i];
+        self.target.difference(coord, self.new_pixels[i])
+            - self.target.difference(coord, self.old_pixels[i])
+    }
+
+    /// The change worked out from every pixel
+    fn exact(&self) -> f64 {
+        (0..self.coords.len()).map(|i| self.change(i)).sum()
+    }
+
+    fn draw(&mut self, n: usize) {
+        let total = *self.moved.last().unwrap();
+        for _ in 0..n {
+            // pixels whose color doesn't move take up none of the range, so they never get drawn
+            let point = acceptance_random::<f64>() * total;
+            let i = self
+                .moved
+                .partition_point(|&moved| moved <= point)
+                .min(self.moved.len() - 1);
+            let before = if i == 0 { 0.0 } else { self.moved[i - 1] };
+            let weighted = self.change(i) * total / (self.moved[i] - before);
+            self.sum += weighted;
+            self.sum_squares += weighted * weighted;
+            self.draws += 1;
+        }
+    }
+
+    /// The estimate so far, and how far the confidence interval reaches either way of it
+    fn interval(&self) -> (f64, f64) {
+        let n = self.draws as f64;
+        let mean = self.sum / n;
+        let variance = ((self.sum_squares - n * mean * mean) / (n - 1.0)).max(0.0);
+        (mean, CONFIDENCE * (variance / n).sqrt())
+    }
+
+    /// Whether the change comes in under `threshold`, along with the best estimate of it, starting
+    /// from `samples` pixels and doubling them for as long as the interval takes in the threshold
+    pub fn decide(mut self, threshold: f64, samples: usize) -> (bool, f64) {
+        if self.coords.is_empty() || *self.moved.last().unwrap() == 0.0 {
+            return (0.0 < threshold, 0.0);
+        }
+        let mut samples = samples.max(MIN_DRAWS);
+        loop {
+            if self.draws + samples >= self.coords.len() {
+                let change = self.exact();
+                return (change < threshold, change);
+            }
+            self.draw(samples);
+            let (estimate, reach) = self.interval();
+            if estimate + reach < threshold {
+                return (true, estimate);
+            }
+            if estimate - reach >= threshold {
+                return (false, estimate);
+            }
+            samples = self.draws;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CostEstimate;
+    use crate::{coord::Coord, target::Target, BlendMode, Fill};
+    use image::Rgb;
+
+    const W: usize = 64;
+    const H: usize = 64;
+
+    fn gradient() -> Target {
+        let image = (0..W)
+            .map(|x| {
+                (0..H)
+                    .map(|y| Rgb([(x * 4) as u8, (y * 4) as u8, 128]))
+                    .collect()
+            })
+            .collect::<Vec<Vec<_>>>();
+        Target::new(&image)
+    }
+
+    fn everywhere() -> Vec<Coord> {
+        (0..W)
+            .flat_map(|x| (0..H).map(move |y| Coord::new(x, y)))
+            .collect()
+    }
+
+    #[test]
+    fn unchanged_colors_cost_nothing() {
+        let (target, coords) = (gradient(), everywhere());
+        let old_pixels = vec![Rgb([10, 20, 30]); coords.len()];
+        let estimate = CostEstimate::new(
+            &target,
+            &coords,
+            &old_pixels,
+            Fill::Solid(Rgb([10, 20, 30])),
+            BlendMode::NORMAL,
+        );
+        assert_eq!(estimate.decide(1.0, 16), (true, 0.0));
+    }
+
+    #[test]
+    fn small_shapes_get_added_up_exactly() {
+        let target = gradient();
+        let coords = everywhere()[..10].to_vec();
+        let old_pixels = vec![Rgb([0, 0, 0]); coords.len()];
+        let exact = CostEstimate::new(
+            &target,
+            &coords,
+            &old_pixels,
+            Fill::Solid(Rgb([255, 255, 255])),
+            BlendMode::NORMAL,
+        )
+        .exact();
+        let (_, change) = CostEstimate::new(
+            &target,
+            &coords,
+            &old_pixels,
+            Fill::Solid(Rgb([255, 255, 255])),
+            BlendMode::NORMAL,
+        )
+        .decide(0.0, 16);
+        assert_eq!(change, exact);
+    }
+
+    #[test]
+    fn clear_cut_moves_get_decided_right() {
+        let (target, coords) = (gradient(), everywhere());
+        let old_pixels = vec![Rgb([0, 0, 0]); coords.len()];
+        // painting the middle gray over black gets a lot closer to the gradient everywhere
+        let better = || {
+            CostEstimate::new(
+                &target,
+                &coords,
+                &old_pixels,
+                Fill::Solid(Rgb([128, 128, 128])),
+                BlendMode::NORMAL,
+            )
+        };
+        assert!(better().decide(0.0, 16).0);
+        assert!(!better().decide(better().exact() * 2.0, 16).0);
+    }
+
+    #[test]
+    fn estimates_land_near_the_exact_change() {
+        let (target, coords) = (gradient(), everywhere());
+        let old_pixels = coords
+            .iter()
+            .map(|coord| Rgb([(coord.y * 4) as u8, (coord.x * 4) as u8, 0]))
+            .collect::<Vec<_>>();
+        let estimate = || {
+            CostEstimate::new(
+                &target,
+                &coords,
+                &old_pixels,
+                Fill::Solid(Rgb([100, 150, 200])),
+                BlendMode::NORMAL,
+            )
+        };
+        let exact = estimate().exact();
+        // half the pixels, drawn fairly, put it well within the interval of the exact change
+        let mut estimate = estimate();
+        estimate.draw(coords.len() / 2);
+        let (mean, reach) = estimate.interval();
+        assert!((mean - exact).abs() <= 2.0 * reach.max(1.0));
+    }
+}
@@ -0,0 +1,514 @@
+//! `--self-test`, which runs quick consistency checks on synthetic images and reports which ones
+//! passed. The checks cover the things most likely to go wrong on an unusual platform or compiler:
+//! shapes rasterizing outside the image, the incremental costs drifting from the full ones (or the
+//! errors the canvas adds up while painting being off, or locked pixels counting as painted over
+//! when they weren't), anti-aliased shapes covering more or less than their outlines do, stamps
+//! covering more or less than they should once they're scaled and turned, glyphs filling in their
+//! holes or leaving gaps where their contours overlap, and seeded runs not coming out the same.
+//! Stamps come from a made-up one, since there aren't any PNGs to load them from
+//!
+//! Every check panics when it fails, and gets run on its own thread so a failure doesn't stop the
+//! rest from being run
+
+use crate::rng::random;
+use crate::{
+    anti_alias::{coverage, SCANLINES},
+    canvas::Canvas,
+    coord::{Coord, Pixels},
+    font::rasterize,
+    get_cost, get_neighbor, pixel_difference,
+    raster_cache::RasterCache,
+    shape_gradient,
+    stamps::{self, Stamp},
+    target::Loss,
+    target::Target,
+    update_cost, update_cost_from_errors, update_cost_to_pixels,
+    vector::polygons,
+    Blend, BlendMode, Fill, Image, Shape, ShapeKind,
+};
+use clap::ValueEnum;
+use image::{Rgb, RgbImage};
+use std::{
+    collections::HashSet,
+    env::{current_exe, temp_dir},
+    fs::{create_dir_all, remove_dir_all},
+    process::{exit, Command},
+    thread,
+};
+
+/// How many random shapes each check tries
+const TRIALS: usize = 500;
+
+/// How far the incremental cost is allowed to drift from the full cost over all the trials
+const COST_TOLERANCE: f64 = 1e-6;
+
+/// Size of the synthetic images
+const W: usize = 61;
+const H: usize = 43;
+
+/// A synthetic image with gradients and a checkerboard, so no two regions look the same
+fn synthetic_image() -> Image {
+    (0..W)
+        .map(|x| {
+            (0..H)
+                .map(|y| {
+                    let checker = if (x / 8 + y / 8) % 2 == 0 { 200 } else { 30 };
+                    Rgb([(x * 255 / W) as u8, (y * 255 / H) as u8, checker])
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Width and height of the made-up stamp
+const STAMP_W: usize = 24;
+const STAMP_H: usize = 16;
+
+/// Alpha of a made-up stamp of a soft-edged ellipse, column-major, fading out over the outer quarter
+/// of it so that scaling it doesn't lose or gain much of it to the pixels along its edges
+fn made_up_alpha() -> Vec<u8> {
+    (0..STAMP_W)
+        .flat_map(|x| {
+            (0..STAMP_H).map(move |y| {
+                let dx = (x as f64 + 0.5) / STAMP_W as f64 * 2.0 - 1.0;
+                let dy = (y as f64 + 0.5) / STAMP_H as f64 * 2.0 - 1.0;
+                let fade = ((1.0 - dx.hypot(dy)) * 4.0).clamp(0.0, 1.0);
+                (fade * 255.0).round() as u8
+            })
+        })
+        .collect()
+}
+
+/// Random shapes of every kind, with and without wrapping around (or hanging off the edges), on a
+/// few grids, stay inside the image and don't cover any pixel twice
+fn rasterizer_bounds() {
+    let target = Target::new(&synthetic_image());
+    for kind in ShapeKind::value_variants() {
+        for tileable in [false, true] {
+            let overhang = if *kind == ShapeKind::Rectangle && !tileable {
+                0.5
+            } else {
+                0.0
+            };
+            let edge_bias = if tileable { 0.0 } else { 0.5 };
+            for (grid, max_size) in [(1, None), (3, Some(10)), (8, None), (1, Some(1))] {
+                let mut cache = RasterCache::new(W, H, grid);
+                for _ in 0..TRIALS {
+                    let (_, coords, _) = get_neighbor(
+                        &target,
+                        *kind,
+                        6,
+                        (1.0, 12.0),
+                        grid,
+                        tileable,
+                        overhang,
+                        edge_bias,
+                        max_size,
+                        None,
+                        None,
+                        &mut cache,
+                    );
+                    assert!(
+                        coords.iter().all(|coord| coord.x < W && coord.y < H),
+                        "a shape went outside the image"
+                    );
+                    let unique = coords.iter().collect::<HashSet<_>>();
+                    assert_eq!(unique.len(), coords.len(), "a shape covered a pixel twice");
+                }
+            }
+        }
+    }
+}
+
+/// Painting random shapes one after the other and keeping track of the cost incrementally ends up
+/// at the same cost as working it out from scratch, for every blend mode (opaque and translucent)
+/// and loss
+fn incremental_cost() {
+    let original = synthetic_image();
+    for loss in Loss::value_variants() {
+        let target = Target::new(&original).with_loss(*loss, 16.0);
+        let blend_modes = Blend::value_variants()
+            .iter()
+            .flat_map(|&blend| [255, 128].map(|opacity| BlendMode { blend, opacity }));
+        for blend_mode in blend_modes {
+            let mut image = vec![vec![Rgb([0, 0, 0]); H]; W];
+            let mut cost = get_cost(&target, &image);
+            let mut cache = RasterCache::new(W, H, 1);
+            for _ in 0..TRIALS {
+                let (_, coords, color) = get_neighbor(
+                    &target,
+                    ShapeKind::Triangle,
+                    3,
+                    (2.0, 8.0),
+                    1,
+                    true,
+                    0.0,
+                    0.0,
+                    None,
+                    None,
+                    None,
+                    &mut cache,
+                );
+                let old_pixels = coords
+                    .iter()
+                    .map(|&coord| image.at(coord))
+                    .collect::<Vec<_>>();
+                cost = update_cost(cost, &target, &coords, &old_pixels, color, blend_mode, None);
+                for (&coord, &pixel) in coords.iter().zip(&old_pixels) {
+                    *image.at_mut(coord) = blend_mode.blend(pixel, color);
+                }
+            }
+            let full_cost = get_cost(&target, &image);
+            assert!(
+                (cost - full_cost).abs() <= COST_TOLERANCE * full_cost.max(1.0),
+                "incremental cost {cost} drifted from the full cost {full_cost}"
+            );
+        }
+    }
+}
+
+/// The errors of rectangles looked up from the summed-area tables match adding them up, including
+/// after parts of the canvas get painted over (when it's worth bringing the tables up to date).
+/// Only canvases that aren't interleaved have the tables, so this one is multithreaded
+fn rectangle_errors() {
+    let original = synthetic_image();
+    let target = Target::new(&original);
+    let mut canvas = Canvas::new(vec![vec![Rgb([0, 0, 0]); H]; W], true, &target, true, None);
+    let mut lookups = 0;
+    for _ in 0..TRIALS {
+        let (x1, y1) = (random::<usize>() % W, random::<usize>() % H);
+        let (x2, y2) = (
+            x1 + 1 + random::<usize>() % (W - x1),
+            y1 + 1 + random::<usize>() % (H - y1),
+        );
+        let expected = canvas.read(|image| {
+            (x1..x2)
+                .flat_map(|x| (y1..y2).map(move |y| Coord::new(x, y)))
+                .map(|coord| pixel_difference(original.at(coord), image.at(coord)))
+                .sum::<u64>()
+        });
+        if let Some(looked_up) = canvas.rectangle_error(&target, (x1, y1), (x2, y2)) {
+            assert_eq!(looked_up, expected, "a looked up rectangle error is off");
+            lookups += 1;
+        }
+        let coords = (x1..x2)
+            .flat_map(|x| (y1..y2).map(move |y| Coord::new(x, y)))
+            .collect::<Vec<_>>();
+        let transaction = canvas.paint(coords, Fill::Solid(Rgb(random())), BlendMode::NORMAL);
+        if random::<bool>() {
+            canvas.commit(transaction);
+        } else {
+            canvas.rollback(transaction);
+        }
+    }
+    assert!(lookups > 0, "no rectangle errors got looked up");
+}
+
+/// The errors an interleaved canvas adds up while painting random shapes (in one color or a
+/// gradient) match adding them up from the image before and after, including after earlier shapes
+/// got rolled back
+fn painted_errors() {
+    let original = synthetic_image();
+    let target = Target::new(&original);
+    let mut canvas = Canvas::new(
+        vec![vec![Rgb([0, 0, 0]); H]; W],
+        false,
+        &target,
+        false,
+        None,
+    );
+    let mut cache = RasterCache::new(W, H, 1);
+    let error = |image: &[Vec<Rgb<u8>>], coords: &[Coord]| {
+        coords
+            .iter()
+            .map(|&coord| pixel_difference(original.at(coord), image.at(coord)))
+            .sum::<u64>()
+    };
+    for _ in 0..TRIALS {
+        let (_, coords, color) = get_neighbor(
+            &target,
+            ShapeKind::Triangle,
+            3,
+            (2.0, 8.0),
+            1,
+            true,
+            0.0,
+            0.0,
+            None,
+            None,
+            None,
+            &mut cache,
+        );
+        let fill = if random::<bool>() {
+            Fill::Solid(color)
+        } else {
+            shape_gradient(&target, &coords, None)
+        };
+        let old_error = canvas.read(|image| error(image, &coords));
+        let transaction = canvas.paint(coords, fill, BlendMode::NORMAL);
+        let new_error = canvas.read(|image| error(image, transaction.coords()));
+        assert_eq!(
+            transaction.errors(),
+            Some((old_error, new_error)),
+            "the errors added up while painting are off"
+        );
+        if random::<bool>() {
+            canvas.commit(transaction);
+        } else {
+            canvas.rollback(transaction);
+        }
+    }
+}
+
+/// Painting random shapes over a canvas with a locked rectangle (like `--fixed-shapes` leaves) and
+/// keeping track of the cost from what each transaction actually painted ends up at the same cost
+/// as working it out from scratch, on every kind of canvas, and the locked pixels never change
+fn locked_costs() {
+    let original = synthetic_image();
+    for (multithreading, loss) in [
+        (false, Loss::Absolute),
+        (false, Loss::Huber),
+        (true, Loss::Absolute),
+    ] {
+        let target = Target::new(&original).with_loss(loss, 16.0);
+        let image = vec![vec![Rgb([0, 0, 0]); H]; W];
+        let mut cost = get_cost(&target, &image);
+        let mut canvas = Canvas::new(image, multithreading, &target, false, None);
+        let locked = (W / 4..W / 2)
+            .flat_map(|x| (H / 4..H / 2).map(move |y| Coord::new(x, y)))
+            .collect::<Vec<_>>();
+        canvas.lock(&locked);
+        let mut cache = RasterCache::new(W, H, 1);
+        for _ in 0..TRIALS {
+            let (_, coords, color) = get_neighbor(
+                &target,
+                ShapeKind::Triangle,
+                3,
+                (2.0, 8.0),
+                1,
+                true,
+                0.0,
+                0.0,
+                None,
+                None,
+                None,
+                &mut cache,
+            );
+            let transaction = canvas.paint(coords, Fill::Solid(color), BlendMode::NORMAL);
+            let new_cost = match transaction.errors() {
+                Some(errors) => {
+                    update_cost_from_errors(cost, &target, transaction.coords(), errors)
+                }
+                None => update_cost_to_pixels(
+                    cost,
+                    &target,
+                    transaction.coords(),
+                    transaction.old_pixels(),
+                    transaction
+                        .new_pixels()
+                        .expect("a locked canvas kept no new pixels"),
+                ),
+            };
+            if random::<bool>() {
+                cost = new_cost;
+                canvas.commit(transaction);
+            } else {
+                canvas.rollback(transaction);
+            }
+        }
+        let full_cost = canvas.read(|image| {
+            assert!(
+                locked
+                    .iter()
+                    .all(|&coord| image.at(coord) == Rgb([0, 0, 0])),
+                "a locked pixel got painted over"
+            );
+            get_cost(&target, image)
+        });
+        assert!(
+            (cost - full_cost).abs() <= COST_TOLERANCE * full_cost.max(1.0),
+            "incremental cost {cost} drifted from the full cost {full_cost} around locked pixels"
+        );
+    }
+}
+
+/// The coverage of anti-aliased random shapes adds up to the areas of their outlines, give or take
+/// what the scanlines miss. Blobs are left out, since their outlines can cross themselves, and so
+/// are stamps, which aren't outlines at all
+fn anti_aliased_coverage() {
+    let target = Target::new(&synthetic_image());
+    let mut cache = RasterCache::new(W, H, 1);
+    for kind in ShapeKind::value_variants() {
+        if matches!(kind, ShapeKind::Blob | ShapeKind::Stamp) {
+            continue;
+        }
+        for _ in 0..TRIALS {
+            let (shape, _, _) = get_neighbor(
+                &target,
+                *kind,
+                6,
+                (1.0, 12.0),
+                1,
+                false,
+                0.0,
+                0.0,
+                None,
+                None,
+                None,
+                &mut cache,
+            );
+            let (_, covered) = coverage(&[shape], W, H);
+            let covered = covered.iter().map(|&c| c as f64 / 255.0).sum::<f64>();
+            let (mut area, mut width) = (0.0, 0.0);
+            for outline in polygons(shape, W, H) {
+                // the shoelace formula
+                let twice = (0..outline.len())
+                    .map(|i| {
+                        let ((ax, ay), (bx, by)) = (outline[i], outline[(i + 1) % outline.len()]);
+                        ax * by - bx * ay
+                    })
+                    .sum::<f64>();
+                area += twice.abs() / 2.0;
+                let xs = || outline.iter().map(|p| p.0);
+                width += xs().fold(f64::MIN, f64::max) - xs().fold(f64::MAX, f64::min);
+            }
+            // each scanline stands in for the width of the outline across its share of the row,
+            // which can be off by as much as the width changes over it, and rounding coverage to
+            // 255ths is off by up to half of one per pixel
+            let tolerance = width / SCANLINES as f64 + covered.ceil() / 255.0;
+            assert!(
+                (covered - area).abs() <= tolerance,
+                "an anti-aliased shape covered {covered} pixels instead of {area}"
+            );
+        }
+    }
+}
+
+/// Random stamps that fit in the image cover as much of it as the made-up stamp does scaled up or
+/// down to their size, however they're turned or flipped
+fn stamp_coverage() {
+    let target = Target::new(&synthetic_image());
+    let mut cache = RasterCache::new(W, H, 1);
+    let full = made_up_alpha()
+        .iter()
+        .map(|&a| a as f64 / 255.0)
+        .sum::<f64>();
+    for _ in 0..TRIALS {
+        let (shape, _, _) = get_neighbor(
+            &target,
+            ShapeKind::Stamp,
+            6,
+            (1.0, 12.0),
+            1,
+            false,
+            0.0,
+            0.0,
+            None,
+            None,
+            None,
+            &mut cache,
+        );
+        let Shape::Stamp(placement) = shape else {
+            panic!("a stamp came out as some other shape");
+        };
+        let inside = |&(x, y): &(f64, f64)| x >= 0.0 && y >= 0.0 && x <= W as f64 && y <= H as f64;
+        if !placement.corners().iter().all(inside) {
+            continue;
+        }
+        let (_, covered) = placement.coverage(W, H);
+        let covered = covered.iter().map(|&c| c as f64 / 255.0).sum::<f64>();
+        let scale = placement.size as f64 / STAMP_W as f64;
+        let expected = full * scale * scale;
+        // the soft edge gets sampled a little off where stamps are shrunk down to a few pixels
+        assert!(
+            (covered - expected).abs() <= expected / 20.0 + 1.0,
+            "a stamp covered {covered} pixels instead of {expected}"
+        );
+    }
+}
+
+/// A made-up glyph of a square with a square hole wound the other way, and another square wound
+/// the same way hanging off its side, rasterizes to as much ink as the hole leaves and the squares
+/// make up between them, with the overlap counted once rather than cut out
+fn glyph_rasterizing() {
+    let square = |left: f64, bottom: f64, right: f64, top: f64| {
+        vec![(left, bottom), (right, bottom), (right, top), (left, top)]
+    };
+    let mut hole = square(3.0, 3.0, 7.0, 7.0);
+    hole.reverse();
+    let contours = [
+        square(0.0, 0.0, 10.0, 10.0),
+        hole,
+        square(8.0, 0.0, 14.0, 4.0),
+    ];
+    // scaled so that the edges land partway into pixels
+    let scale = 2.5;
+    let (_, _, alpha) = rasterize(&contours, scale).unwrap();
+    let covered = alpha.iter().map(|&a| a as f64 / 255.0).sum::<f64>();
+    let expected = (100.0 - 16.0 + 16.0) * scale * scale;
+    // rounding coverage to 255ths is off by up to half of one per pixel
+    assert!(
+        (covered - expected).abs() <= alpha.len() as f64 / 510.0,
+        "a glyph covered {covered} pixels instead of {expected}"
+    );
+}
+
+/// Annealing the same image twice with the same seed gives the same result
+fn seeded_runs() {
+    let dir = temp_dir().join(format!("anneal_image_self_test_{}", std::process::id()));
+    create_dir_all(&dir).unwrap();
+    let input = dir.join("input.png");
+    let original = synthetic_image();
+    RgbImage::from_fn(W as u32, H as u32, |x, y| {
+        original.at(Coord::new(x as usize, y as usize))
+    })
+    .save(&input)
+    .unwrap();
+    let outputs = ["first.png", "second.png"].map(|name| {
+        let output = dir.join(name);
+        let status = Command::new(current_exe().unwrap())
+            .arg("--input")
+            .arg(&input)
+            .arg("--output")
+            .arg(&output)
+            .args(["--alpha", "0.99", "--seed", "1"])
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "annealing the synthetic image failed");
+        // the text chunks say which file they were saved as, so only the pixels get compared
+        image::open(&output).unwrap().into_rgb8().into_raw()
+    });
+    remove_dir_all(&dir).unwrap();
+    assert!(
+        outputs[0] == outputs[1],
+        "the same seed gave different images"
+    );
+}
+
+/// Runs every check and prints how each one went, exiting with an error if any of them failed
+pub fn self_test() {
+    stamps::install(vec![Stamp::new(STAMP_W, STAMP_H, made_up_alpha())], true);
+    let checks: [(&str, fn()); 9] = [
+        ("rasterizer bounds", rasterizer_bounds),
+        ("incremental cost", incremental_cost),
+        ("painted errors", painted_errors),
+        ("rectangle errors", rectangle_errors),
+        ("locked costs", locked_costs),
+        ("anti-aliased coverage", anti_aliased_coverage),
+        ("stamp coverage", stamp_coverage),
+        ("glyph rasterizing", glyph_rasterizing),
+        ("seeded runs", seeded_runs),
+    ];
+    let mut failed = 0;
+    for (name, check) in checks {
+        let passed = thread::spawn(check).join().is_ok();
+        println!("{name:<24} {}", if passed { "pass" } else { "FAIL" });
+        failed += !passed as usize;
+    }
+    if failed > 0 {
+        println!("{failed} of {} checks failed", checks.len());
+        exit(1);
+    }
+    println!("all {} checks passed", checks.len());
+}
@@ -0,0 +1,247 @@
+//! Saving the accepted shapes so they can be redrawn or turned into something else later.
+//! Shape lists can be saved as JSON, or in a compact binary format for runs with lots of shapes:
+//!
+//! - the magic bytes `AISL` followed by a format version byte (currently 5)
+//! - the image width and height
+//! - the number of colors in the palette, followed by each color as 3 bytes (red, green, blue)
+//! - the number of shapes, followed by each shape as
+//!   - a byte holding the kind of shape in its low 3 bits (0 for rectangles, 1 for triangles,
+//!     2 for ellipses, 3 for polygons, 4 for blobs, 5 for stamps), the blend mode in the 2 bits above that (0 for
+//!     normal, 1 for multiply, 2 for screen, 3 for overlay), and whether it's painted with a
+//!     gradient in the bit above those
+//!   - a byte holding the shape's opacity, from 0 for see-through to 255 for opaque
+//!   - rectangles: the top left and bottom right corners, triangles: the 3 vertices, ellipses: the
+//!     top left and bottom right corners of the box they're inscribed in, polygons: the number of
+//!     vertices followed by each vertex, blobs: the same with their control points, stamps: the
+//!     pixel in their middle followed by their index in the order of the stamps' file names, how
+//!     many pixels their longer side is, how many degrees they're turned clockwise, and 1 if
+//!     they're flipped over (0 if not), each corner or vertex as its x then y coordinate
+//!   - the index of the shape's color in the palette
+//!   - for gradients: the index of the color at the other end in the palette, followed by the
+//!     pixels the gradient goes from and to, each as its x then y coordinate
+//!
+//! Every number other than the single bytes above is an unsigned LEB128 varint.
+//! Binary shape lists can also be compressed with zstd
+
+use crate::{coord::Pixels, BlendMode, Fill, Shape};
+use clap::ValueEnum;
+use image::Rgb;
+use std::{collections::HashMap, fs::write, iter::zip};
+
+/// Saves the shapes of a `w` by `h` image to `path`, in the order they were painted.
+/// The format is picked from the extension: `.json` for JSON, `.bin` for the binary format,
+/// and `.zst` (as in `shapes.bin.zst`) for the zstd-compressed binary format
+pub fn save_shape_list(path: &str, w: usize, h: usize, shapes: &[(Shape, Fill, BlendMode)]) {
+    let bytes = if path.ends_with(".json") {
+        to_json(w, h, shapes).into_bytes()
+    } else if path.ends_with(".zst") {
+        compress(&to_binary(w, h, shapes))
+    } else if path.ends_with(".bin") {
+        to_binary(w, h, shapes)
+    } else {
+        panic!("shape list path must end in .json, .bin, or .zst");
+    };
+    write(path, bytes).unwrap();
+}
+
+/// The shapes of a `w` by `h` image minus the ones that are completely hidden under shapes
+/// painted after them in plain opaque paint, which don't change how the image turns out
+pub fn prune_hidden(
+    w: usize,
+    h: usize,
+    shapes: &[(Shape, Fill, BlendMode)],
+) -> Vec<(Shape, Fill, BlendMode)> {
+    let mut covered = vec![vec![false; h]; w];
+    let mut visible = Vec::with_capacity(shapes.len());
+    for &(shape, fill, blend_mode) in shapes.iter().rev() {
+        let coords = shape.coords(w, h);
+        if coords.iter().all(|&coord| covered.at(coord)) {
+            continue;
+        }
+        // blended and translucent shapes let what's under them show through, and so do the parts
+        // of stamps that aren't solid
+        if blend_mode == BlendMode::NORMAL {
+            let coverage = match shape {
+                Shape::Stamp(placement) => placement.coverage(w, h).1,
+                _ => vec![255; coords.len()],
+            };
+            for (coord, coverage) in zip(coords, coverage) {
+                if coverage == 255 {
+                    *covered.at_mut(coord) = true;
+                }
+            }
+        }
+        visible.push((shape, fill, blend_mode));
+    }
+    visible.reverse();
+    visible
+}
+
+/// Compresses a binary shape list with zstd
+#[cfg(feature = "zstd")]
+fn compress(bytes: &[u8]) -> Vec<u8> {
+    zstd::encode_all(bytes, 19).unwrap()
+}
+
+#[cfg(not(feature = "zstd"))]
+fn compress(_: &[u8]) -> Vec<u8> {
+    panic!("compressed shape lists need the zstd feature");
+}
+
+/// Name of the blend mode as it's written on the command line
+fn blend_mode_name(blend_mode: BlendMode) -> String {
+    blend_mode
+        .blend
+        .to_possible_value()
+        .unwrap()
+        .get_name()
+        .to_string()
+}
+
+fn to_json(w: usize, h: usize, shapes: &[(Shape, Fill, BlendMode)]) -> String {
+    let shapes = shapes
+        .iter()
+        .map(|&(shape, fill, blend_mode)| {
+            let geometry = match shape {
+                Shape::Rectangle {
+                    top_left,
+                    bottom_right,
+                } => format!(
+                    r#""type":"rectangle","top_left":[{},{}],"bottom_right":[{},{}]"#,
+                    top_left.0, top_left.1, bottom_right.0, bottom_right.1
+                ),
+                Shape::Triangle([v1, v2, v3]) => format!(
+                    r#""type":"triangle","vertices":[[{},{}],[{},{}],[{},{}]]"#,
+                    v1.0, v1.1, v2.0, v2.1, v3.0, v3.1
+                ),
+                Shape::Ellipse {
+                    top_left,
+                    bottom_right,
+                } => format!(
+                    r#""type":"ellipse","top_left":[{},{}],"bottom_right":[{},{}]"#,
+                    top_left.0, top_left.1, bottom_right.0, bottom_right.1
+                ),
+                Shape::Polygon(polygon) => format!(
+                    r#""type":"polygon","vertices":[{}]"#,
+                    polygon
+                        .vertices()
+                        .iter()
+                        .map(|(x, y)| format!("[{x},{y}]"))
+                        .collect::<Vec<_>>()
+                        .join(",")
+                ),
+                Shape::Blob(points) => format!(
+                    r#""type":"blob","control_points":[{}]"#,
+                    points
+                        .vertices()
+                        .iter()
+                        .map(|(x, y)| format!("[{x},{y}]"))
+                        .collect::<Vec<_>>()
+                        .join(",")
+                ),
+                Shape::Stamp(placement) => format!(
+                    r#""type":"stamp","stamp":{},"center":[{},{}],"size":{},"angle":{},"flipped":{}"#,
+                    placement.index,
+                    placement.center.0,
+                    placement.center.1,
+                    placement.size,
+                    placement.angle,
+                    placement.flipped
+                ),
+            };
+            let Rgb([r, g, b]) = fill.colors()[0];
+            // the color is where gradients start from, so anything that doesn't know about them
+            // still gets a color for the shape
+            let gradient = match fill {
+                Fill::Solid(_) => String::new(),
+                Fill::Linear { start, end, colors } => {
+                    let Rgb([r, g, b]) = colors[1];
+                    format!(
+                        r#","gradient":{{"start":[{},{}],"end":[{},{}],"end_color":[{r},{g},{b}]}}"#,
+                        start.0, start.1, end.0, end.1
+                    )
+                }
+            };
+            format!(
+                r#"{{{geometry},"color":[{r},{g},{b}]{gradient},"blend_mode":"{}","opacity":{:.3}}}"#,
+                blend_mode_name(blend_mode),
+                blend_mode.opacity as f64 / 255.0
+            )
+        })
+        .collect::<Vec<String>>();
+    format!(
+        "{{\"width\":{w},\"height\":{h},\"shapes\":[\n{}\n]}}\n",
+        shapes.join(",\n")
+    )
+}
+
+/// Appends `n` as an unsigned LEB128 varint
+fn push_varint(bytes: &mut Vec<u8>, mut n: usize) {
+    while n >= 0x80 {
+        bytes.push((n & 0x7f) as u8 | 0x80);
+        n >>= 7;
+    }
+    bytes.push(n as u8);
+}
+
+fn to_binary(w: usize, h: usize, shapes: &[(Shape, Fill, BlendMode)]) -> Vec<u8> {
+    let mut palette = Vec::new();
+    let mut palette_indices = HashMap::new();
+    for &(_, fill, _) in shapes {
+        for color in fill.colors() {
+            palette_indices.entry(color.0).or_insert_with(|| {
+                palette.push(color.0);
+                palette.len() - 1
+            });
+        }
+    }
+
+    let mut bytes = b"AISL\x05".to_vec();
+    push_varint(&mut bytes, w);
+    push_varint(&mut bytes, h);
+    push_varint(&mut bytes, palette.len());
+    bytes.extend(palette.iter().flatten());
+    push_varint(&mut bytes, shapes.len());
+    for &(shape, fill, blend_mode) in shapes {
+        let (kind, points) = match shape {
+            Shape::Rectangle {
+                top_left,
+                bottom_right,
+            } => (0, vec![top_left, bottom_right]),
+            Shape::Triangle(vertices) => (1, vertices.to_vec()),
+            Shape::Ellipse {
+                top_left,
+                bottom_right,
+            } => (2, vec![top_left, bottom_right]),
+            Shape::Polygon(polygon) => (3, polygon.vertices().to_vec()),
+            Shape::Blob(points) => (4, points.vertices().to_vec()),
+            Shape::Stamp(placement) => (5, vec![placement.center]),
+        };
+        let gradient = matches!(fill, Fill::Linear { .. });
+        bytes.push(kind | (blend_mode.blend as u8) << 3 | (gradient as u8) << 5);
+        bytes.push(blend_mode.opacity);
+        if matches!(shape, Shape::Polygon(_) | Shape::Blob(_)) {
+            push_varint(&mut bytes, points.len());
+        }
+        for (x, y) in points {
+            push_varint(&mut bytes, x);
+            push_varint(&mut bytes, y);
+        }
+        if let Shape::Stamp(placement) = shape {
+            push_varint(&mut bytes, placement.index);
+            push_varint(&mut bytes, placement.size);
+            push_varint(&mut bytes, placement.angle as usize);
+            push_varint(&mut bytes, placement.flipped as usize);
+        }
+        for color in fill.colors() {
+            push_varint(&mut bytes, palette_indices[&color.0]);
+        }
+        if let Fill::Linear { start, end, .. } = fill {
+            for (x, y) in [start, end] {
+                push_varint(&mut bytes, x);
+                push_varint(&mut bytes, y);
+            }
+        }
+    }
+    bytes
+}
@@ -0,0 +1,222 @@
+//! Exporting the accepted shapes as JavaScript that redraws them in order, a few per frame,
+//! so the annealed image can be shown being built up on a web page, or as a web page of its own
+//! with a slider for scrubbing through them
+
+use crate::{
+    vector::{gradient, polygons},
+    BlendMode, Fill, Shape,
+};
+use clap::ValueEnum;
+use image::Rgb;
+use std::fs::write;
+
+/// Roughly how many frames the sketch takes to draw every shape (10 seconds at 60 fps)
+const SKETCH_FRAMES: usize = 600;
+
+/// Kind of JavaScript to export the shapes as
+#[derive(Clone, Copy, ValueEnum)]
+pub enum SketchKind {
+    /// A p5.js sketch
+    P5,
+    /// Plain JavaScript drawing onto a `<canvas>` with the id `anneal`
+    Canvas,
+}
+
+/// The shapes as a JavaScript array, each shape being `[r, g, b, blend mode, opacity, polygons]`,
+/// with `[x1, y1, x2, y2, r, g, b]` on the end for shapes painted with a gradient from the first
+/// color to the second. Blend modes are stored as their index in `Blend`, and opacities go from 0
+/// to 255
+fn shapes_array(w: usize, h: usize, shapes: &[(Shape, Fill, BlendMode)]) -> String {
+    let shapes = shapes
+        .iter()
+        .map(|&(shape, fill, blend_mode)| {
+            let Rgb([r, g, b]) = fill.colors()[0];
+            let polygons = polygons(shape, w, h)
+                .iter()
+                .map(|polygon| {
+                    let points = polygon
+                        .iter()
+                        .map(|(x, y)| format!("[{x},{y}]"))
+                        .collect::<Vec<String>>();
+                    format!("[{}]", points.join(","))
+                })
+                .collect::<Vec<String>>();
+            let gradient = match gradient(fill) {
+                Some(((x1, y1), (x2, y2), [_, Rgb([r, g, b])])) => {
+                    format!(",[{x1},{y1},{x2},{y2},{r},{g},{b}]")
+                }
+                None => String::new(),
+            };
+            format!(
+                "  [{r},{g},{b},{},{},[{}]{gradient}],",
+                blend_mode.blend as u8,
+                blend_mode.opacity,
+                polygons.join(",")
+            )
+        })
+        .collect::<Vec<String>>();
+    format!("const shapes = [\n{}\n];\n", shapes.join("\n"))
+}
+
+/// Saves JavaScript that draws the shapes of a `w` by `h` image in order to `path`
+pub fn save_sketch(
+    path: &str,
+    kind: SketchKind,
+    w: usize,
+    h: usize,
+    shapes: &[(Shape, Fill, BlendMode)],
+) {
+    let shapes_per_frame = shapes.len().div_ceil(SKETCH_FRAMES).max(1);
+    let mut js = format!(
+        "// Generated by anneal_image. Draws the annealed shapes in the order they were accepted\n\
+         {}const shapesPerFrame = {shapes_per_frame};\nlet drawn = 0;\n\n",
+        shapes_array(w, h, shapes)
+    );
+    js += &match kind {
+        SketchKind::P5 => format!(
+            r#"function setup() {{
+  createCanvas({w}, {h});
+  noStroke();
+  background(0);
+}}
+
+function draw() {{
+  for (let i = 0; i < shapesPerFrame && drawn < shapes.length; i++, drawn++) {{
+    const [r, g, b, mode, opacity, polygons, gradient] = shapes[drawn];
+    blendMode([BLEND, MULTIPLY, SCREEN, OVERLAY][mode]);
+    fill(r, g, b, opacity);
+    if (gradient) {{
+      const [x1, y1, x2, y2, r2, g2, b2] = gradient;
+      const linear = drawingContext.createLinearGradient(x1, y1, x2, y2);
+      linear.addColorStop(0, color(r, g, b, opacity).toString());
+      linear.addColorStop(1, color(r2, g2, b2, opacity).toString());
+      drawingContext.fillStyle = linear;
+    }}
+    for (const polygon of polygons) {{
+      beginShape();
+      for (const [x, y] of polygon) {{
+        vertex(x, y);
+      }}
+      endShape(CLOSE);
+    }}
+  }}
+  if (drawn === shapes.length) {{
+    noLoop();
+  }}
+}}
+"#
+        ),
+        SketchKind::Canvas => format!(
+            r#"const canvas = document.getElementById("anneal");
+canvas.width = {w};
+canvas.height = {h};
+const ctx = canvas.getContext("2d");
+ctx.fillStyle = "black";
+ctx.fillRect(0, 0, {w}, {h});
+
+function frame() {{
+  for (let i = 0; i < shapesPerFrame && drawn < shapes.length; i++, drawn++) {{
+    const [r, g, b, mode, opacity, polygons, gradient] = shapes[drawn];
+    ctx.globalCompositeOperation = ["source-over", "multiply", "screen", "overlay"][mode];
+    ctx.fillStyle = `rgba(${{r}}, ${{g}}, ${{b}}, ${{opacity / 255}})`;
+    if (gradient) {{
+      const [x1, y1, x2, y2, r2, g2, b2] = gradient;
+      const linear = ctx.createLinearGradient(x1, y1, x2, y2);
+      linear.addColorStop(0, ctx.fillStyle);
+      linear.addColorStop(1, `rgba(${{r2}}, ${{g2}}, ${{b2}}, ${{opacity / 255}})`);
+      ctx.fillStyle = linear;
+    }}
+    for (const polygon of polygons) {{
+      ctx.beginPath();
+      polygon.forEach(([x, y], j) => (j === 0 ? ctx.moveTo(x, y) : ctx.lineTo(x, y)));
+      ctx.closePath();
+      ctx.fill();
+    }}
+  }}
+  if (drawn < shapes.length) {{
+    requestAnimationFrame(frame);
+  }}
+}}
+
+requestAnimationFrame(frame);
+"#
+        ),
+    };
+    write(path, js).unwrap();
+}
+
+/// Saves a web page to `path` that draws the shapes of a `w` by `h` image up to wherever a slider
+/// is, so the order they were accepted in can be scrubbed through. Everything is in the one file,
+/// so it can be opened straight from the disk or sent to someone
+pub fn save_viewer(path: &str, w: usize, h: usize, shapes: &[(Shape, Fill, BlendMode)]) {
+    let n = shapes.len();
+    let html = format!(
+        r#"<!DOCTYPE html>
+<!-- Generated by anneal_image. Drag the slider to see the shapes up to that point -->
+<html>
+<head>
+<meta charset="utf-8">
+<title>anneal_image</title>
+<style>
+  body {{ background: #222; color: #ddd; font-family: sans-serif; }}
+  canvas {{ display: block; max-width: 100%; image-rendering: pixelated; }}
+  input {{ width: 100%; }}
+</style>
+</head>
+<body>
+<canvas id="anneal"></canvas>
+<input id="scrubber" type="range" min="0" max="{n}" value="{n}">
+<div id="count"></div>
+<script>
+{}const canvas = document.getElementById("anneal");
+canvas.width = {w};
+canvas.height = {h};
+const ctx = canvas.getContext("2d");
+const scrubber = document.getElementById("scrubber");
+const count = document.getElementById("count");
+let drawn = 0;
+
+function drawShape([r, g, b, mode, opacity, polygons, gradient]) {{
+  ctx.globalCompositeOperation = ["source-over", "multiply", "screen", "overlay"][mode];
+  ctx.fillStyle = `rgba(${{r}}, ${{g}}, ${{b}}, ${{opacity / 255}})`;
+  if (gradient) {{
+    const [x1, y1, x2, y2, r2, g2, b2] = gradient;
+    const linear = ctx.createLinearGradient(x1, y1, x2, y2);
+    linear.addColorStop(0, ctx.fillStyle);
+    linear.addColorStop(1, `rgba(${{r2}}, ${{g2}}, ${{b2}}, ${{opacity / 255}})`);
+    ctx.fillStyle = linear;
+  }}
+  for (const polygon of polygons) {{
+    ctx.beginPath();
+    polygon.forEach(([x, y], j) => (j === 0 ? ctx.moveTo(x, y) : ctx.lineTo(x, y)));
+    ctx.closePath();
+    ctx.fill();
+  }}
+}}
+
+// going forward only draws the new shapes, and going back starts over from the black canvas
+function scrub() {{
+  const target = Number(scrubber.value);
+  if (target < drawn) {{
+    ctx.globalCompositeOperation = "source-over";
+    ctx.fillStyle = "black";
+    ctx.fillRect(0, 0, {w}, {h});
+    drawn = 0;
+  }}
+  for (; drawn < target; drawn++) {{
+    drawShape(shapes[drawn]);
+  }}
+  count.textContent = `${{drawn}} of {n} shapes`;
+}}
+
+scrubber.addEventListener("input", scrub);
+ctx.fillRect(0, 0, {w}, {h});
+scrub();
+</script>
+</body>
+</html>
+"#,
+        shapes_array(w, h, shapes)
+    );
+    write(path, html).unwrap();
+}
@@ -0,0 +1,311 @@
+//! Images from a directory of PNGs painted as shapes, for `--shape stamp`. A stamp only gives the
+//! shape, like a brush tip in a paint program: it gets scaled, turned, flipped over, and painted in
+//! the shape's color (or gradient) as much as its alpha says, so the same few stamps make painterly
+//! strokes or cut-out collage pieces depending on what's in them. Stamps without an alpha channel
+//! go by how dark they are instead, so black on white scans of brush marks work too
+
+use crate::{coord::Coord, rng::random, Shape};
+use image::open;
+use std::{fs::read_dir, iter::zip, sync::OnceLock};
+
+/// The stamps, in the order of their file names (or of their characters, for glyphs), which is
+/// what shapes refer to them by
+static STAMPS: OnceLock<Stamps> = OnceLock::new();
+
+struct Stamps {
+    stamps: Vec<Stamp>,
+    /// Whether new placements get flipped over half the time
+    flippable: bool,
+}
+
+/// A stamp's alpha at full size and at every halving of it down to a single pixel, so shrunk
+/// stamps get sampled from a copy about their size instead of skipping over most of their pixels
+pub struct Stamp {
+    levels: Vec<Level>,
+}
+
+/// Alpha at one size of a stamp, column-major like the images
+struct Level {
+    w: usize,
+    h: usize,
+    alpha: Vec<u8>,
+}
+
+impl Level {
+    /// Alpha at `(u, v)` in the level's pixels, interpolated between the middles of its pixels and
+    /// fading out past its edges
+    fn sample(&self, u: f64, v: f64) -> f64 {
+        let (x, y) = (u - 0.5, v - 0.5);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        if x0 >= 0.0 && y0 >= 0.0 && x0 + 1.0 < self.w as f64 && y0 + 1.0 < self.h as f64 {
+            // well inside, so all four pixels are there
+            let i = x0 as usize * self.h + y0 as usize;
+            let [a, b] = [self.alpha[i], self.alpha[i + 1]].map(f64::from);
+            let [c, d] = [self.alpha[i + self.h], self.alpha[i + self.h + 1]].map(f64::from);
+            return (a * (1.0 - fy) + b * fy) * (1.0 - fx) + (c * (1.0 - fy) + d * fy) * fx;
+        }
+        let at = |x: f64, y: f64| {
+            if x < 0.0 || y < 0.0 || x >= self.w as f64 || y >= self.h as f64 {
+                0.0
+            } else {
+                self.alpha[x as usize * self.h + y as usize] as f64
+            }
+        };
+        let top = at(x0, y0) * (1.0 - fx) + at(x0 + 1.0, y0) * fx;
+        let bottom = at(x0, y0 + 1.0) * (1.0 - fx) + at(x0 + 1.0, y0 + 1.0) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+
+    /// The level at half the size, each pixel averaging the (up to) four under it
+    fn halved(&self) -> Level {
+        let (w, h) = (self.w.div_ceil(2), self.h.div_ceil(2));
+        let mut alpha = Vec::with_capacity(w * h);
+        for x in 0..w {
+            for y in 0..h {
+                let (mut sum, mut count) = (0, 0);
+                for sx in 2 * x..(2 * x + 2).min(self.w) {
+                    for sy in 2 * y..(2 * y + 2).min(self.h) {
+                        sum += self.alpha[sx * self.h + sy] as usize;
+                        count += 1;
+                    }
+                }
+                alpha.push(((sum + count / 2) / count) as u8);
+            }
+        }
+        Level { w, h, alpha }
+    }
+}
+
+impl Stamp {
+    /// A `w` by `h` stamp with the given alpha, column-major
+    pub fn new(w: usize, h: usize, alpha: Vec<u8>) -> Stamp {
+        let mut levels = vec![Level { w, h, alpha }];
+        while let Some(last) = levels.last().filter(|last| last.w.max(last.h) > 1) {
+            levels.push(last.halved());
+        }
+        Stamp { levels }
+    }
+
+    /// Width and height at full size
+    fn size(&self) -> (usize, usize) {
+        (self.levels[0].w, self.levels[0].h)
+    }
+}
+
+/// Loads every PNG in `dir` as a stamp. There has to be at least one
+pub fn load(dir: &str) {
+    let mut paths = read_dir(dir)
+        .unwrap_or_else(|error| panic!("{dir}: {error}"))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("png"))
+        })
+        .collect::<Vec<_>>();
+    paths.sort();
+    if paths.is_empty() {
+        panic!("there are no PNGs in {dir} to use as stamps");
+    }
+    let stamps = paths
+        .iter()
+        .map(|path| {
+            let image = open(path).unwrap_or_else(|error| panic!("{}: {error}", path.display()));
+            let has_alpha = image.color().has_alpha();
+            let image = image.into_rgba8();
+            let (w, h) = (image.width(), image.height());
+            let mut alpha = Vec::with_capacity((w * h) as usize);
+            for x in 0..w {
+                for y in 0..h {
+                    let [r, g, b, a] = image.get_pixel(x, y).0;
+                    alpha.push(if has_alpha {
+                        a
+                    } else {
+                        255 - ((r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000) as u8
+                    });
+                }
+            }
+            Stamp::new(w as usize, h as usize, alpha)
+        })
+        .collect();
+    install(stamps, true);
+}
+
+/// Makes `stamps` the ones shapes get stamped with, with new ones flipped over half the time if
+/// they're `flippable`. Only one set of stamps can be installed
+pub fn install(stamps: Vec<Stamp>, flippable: bool) {
+    if STAMPS.set(Stamps { stamps, flippable }).is_err() {
+        panic!("stamps can only be loaded once");
+    }
+}
+
+/// How many stamps there are to pick from
+pub fn count() -> usize {
+    STAMPS.get().map_or(0, |stamps| stamps.stamps.len())
+}
+
+fn stamp(index: usize) -> &'static Stamp {
+    STAMPS
+        .get()
+        .and_then(|stamps| stamps.stamps.get(index))
+        .unwrap_or_else(|| panic!("there's no stamp {index}"))
+}
+
+/// Where one of the stamps goes: scaled so its longer side is `size` pixels, flipped left to right
+/// if `flipped`, and then turned `angle` degrees clockwise around the middle of the `center` pixel
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Placement {
+    pub index: usize,
+    pub center: (usize, usize),
+    pub size: usize,
+    pub angle: u16,
+    pub flipped: bool,
+}
+
+impl Placement {
+    /// A random one of the stamps, as big across its longer side as the box from `top_left` up to
+    /// (but not including) `bottom_right` is across its own, turned any way around the middle of
+    /// the box and flipped over half the time (if the stamps can be)
+    pub fn random(top_left: (usize, usize), bottom_right: (usize, usize)) -> Placement {
+        Placement {
+            index: random::<usize>() % count(),
+            center: (
+                (top_left.0 + bottom_right.0) / 2,
+                (top_left.1 + bottom_right.1) / 2,
+            ),
+            size: (bottom_right.0 - top_left.0).max(bottom_right.1 - top_left.1),
+            angle: random::<u16>() % 360,
+            flipped: STAMPS.get().is_some_and(|stamps| stamps.flippable) && random(),
+        }
+    }
+
+    /// How many of the image's pixels across each of the stamp's pixels takes up
+    fn scale(&self) -> f64 {
+        let (w, h) = stamp(self.index).size();
+        self.size as f64 / w.max(h) as f64
+    }
+
+    /// The middle of the stamp in the image, and the sine and cosine of its angle
+    fn frame(&self) -> ((f64, f64), (f64, f64)) {
+        let center = (self.center.0 as f64 + 0.5, self.center.1 as f64 + 0.5);
+        (center, (self.angle as f64).to_radians().sin_cos())
+    }
+
+    /// Corners of the stamp as it's placed in the image, going clockwise on screen
+    pub fn corners(&self) -> Vec<(f64, f64)> {
+        let (w, h) = stamp(self.index).size();
+        let scale = self.scale();
+        let (half_w, half_h) = (w as f64 * scale / 2.0, h as f64 * scale / 2.0);
+        let ((cx, cy), (sin, cos)) = self.frame();
+        [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+            .into_iter()
+            .map(|(a, b): (f64, f64)| {
+                let (x, y) = (a * half_w, b * half_h);
+                (cx + x * cos - y * sin, cy + x * sin + y * cos)
+            })
+            .collect()
+    }
+
+    /// Pixels of a `w` by `h` image the stamp covers, column-major like the rasterizers, and how
+    /// much of each one it covers, from 1 to 255. Whatever hangs off the image gets clipped off
+    pub fn coverage(&self, w: usize, h: usize) -> (Vec<Coord>, Vec<u8>) {
+        let stamp = stamp(self.index);
+        let (stamp_w, stamp_h) = stamp.size();
+        let scale = self.scale();
+        // the smallest level whose pixels are still no bigger than the image's, which is as sharp
+        // as it needs to be without skipping over any of the stamp
+        let level = ((1.0 / scale).log2().floor().max(0.0) as usize).min(stamp.levels.len() - 1);
+        let shrink = (1 << level) as f64;
+        let level = &stamp.levels[level];
+        let corners = self.corners();
+        let xs = || corners.iter().map(|p| p.0);
+        let left = xs().fold(f64::INFINITY, f64::min).floor().max(0.0) as usize;
+        let right = (xs().fold(0.0, f64::max).ceil() as usize).min(w);
+        let (half_w, half_h) = (stamp_w as f64 * scale / 2.0, stamp_h as f64 * scale / 2.0);
+        let ((cx, cy), (sin, cos)) = self.frame();
+        // turning back the other way, into the level's own pixels, goes this far across and down
+        // it for every pixel down the column
+        let to_level = 1.0 / (scale * shrink);
+        let mirror = if self.flipped { -1.0 } else { 1.0 };
+        let (step_u, step_v) = (sin * to_level * mirror, cos * to_level);
+        let mut coords = Vec::new();
+        let mut coverage = Vec::new();
+        for x in left..right {
+            let dx = x as f64 + 0.5 - cx;
+            // only the part of the column inside the stamp's box, going by where each of the
+            // stamp's own coordinates (which change steadily down the column) are in range
+            let (mut low, mut high) = (f64::NEG_INFINITY, f64::INFINITY);
+            for (start, step, half) in [(dx * cos, sin, half_w), (-dx * sin, cos, half_h)] {
+                if step == 0.0 {
+                    if start.abs() > half {
+                        high = low;
+                    }
+                    continue;
+                }
+                let (a, b) = ((-half - start) / step, (half - start) / step);
+                low = low.max(a.min(b));
+                high = high.min(a.max(b));
+            }
+            let top = (cy + low - 0.5).ceil().max(0.0) as usize;
+            let bottom = ((cy + high - 0.5).floor() + 1.0).clamp(0.0, h as f64) as usize;
+            let dy = top as f64 + 0.5 - cy;
+            let mut u = ((dx * cos + dy * sin) * mirror / scale + stamp_w as f64 / 2.0) / shrink;
+            let mut v = ((dy * cos - dx * sin) / scale + stamp_h as f64 / 2.0) / shrink;
+            for y in top..bottom {
+                let alpha = level.sample(u, v).round() as u8;
+                if alpha > 0 {
+                    coords.push(Coord::new(x, y));
+                    coverage.push(alpha);
+                }
+                u += step_u;
+                v += step_v;
+            }
+        }
+        (coords, coverage)
+    }
+
+    /// The placement mirrored left to right and/or top to bottom within a `w` by `h` image
+    pub fn mirrored(&self, w: usize, h: usize, flip_x: bool, flip_y: bool) -> Placement {
+        // mirroring turns the stamp the other way and flips it over, and mirroring top to bottom
+        // is the same as left to right and then half a turn
+        let mut mirrored = *self;
+        if flip_x {
+            mirrored.center.0 = w - 1 - mirrored.center.0;
+            mirrored.angle = (360 - mirrored.angle) % 360;
+            mirrored.flipped = !mirrored.flipped;
+        }
+        if flip_y {
+            mirrored.center.1 = h - 1 - mirrored.center.1;
+            mirrored.angle = (540 - mirrored.angle) % 360;
+            mirrored.flipped = !mirrored.flipped;
+        }
+        mirrored
+    }
+}
+
+/// Pixels covered by any of `shapes` (which all have to be stamps) in a `w` by `h` image,
+/// column-major, and how much of each one they cover. Where the stamps overlap, their coverage adds
+/// up
+pub fn coverage(shapes: &[Shape], w: usize, h: usize) -> (Vec<Coord>, Vec<u8>) {
+    let placements = shapes.iter().map(|shape| match shape {
+        Shape::Stamp(placement) => placement,
+        _ => panic!("only stamps have their coverage worked out from a stamp"),
+    });
+    let mut covered = placements
+        .flat_map(|placement| {
+            let (coords, coverage) = placement.coverage(w, h);
+            zip(coords, coverage)
+        })
+        .collect::<Vec<_>>();
+    if shapes.len() > 1 {
+        covered.sort_unstable_by_key(|&(coord, _)| coord);
+        covered.dedup_by(|(coord, coverage), (kept_coord, kept_coverage)| {
+            let same = coord == kept_coord;
+            if same {
+                *kept_coverage = kept_coverage.saturating_add(*coverage);
+            }
+            same
+        });
+    }
+    covered.into_iter().unzip()
+}
@@ -0,0 +1,211 @@
+//! Stippling for `--stipple`, which draws the image as black dots on white paper like pen and ink
+//! instead of annealing shapes. The dots are all the same size, so the tone only comes from how
+//! close together they are. They get scattered about as densely as the image is dark to start with,
+//! and then annealed by moving, adding, and removing them until the blurred ink matches the image
+
+use crate::rng::{acceptance_random, random};
+use crate::{
+    ink::{self, Ink},
+    target::Target,
+    BlendMode, Fill, Image, Shape,
+};
+use image::Rgb;
+
+/// How far the ink gets blurred every way, in dot sizes. It has to take in a few dots at once to
+/// tell how densely they're packed
+const BLUR_REACH: usize = 2;
+
+/// Temperature the dots start annealing at, in dots' worth of ink
+const INITIAL_TEMP: f64 = 1.0;
+
+const FINAL_TEMP: f64 = 0.001;
+
+/// How many moves get tried at every temperature, one for every this many pixels of the image
+const PIXELS_PER_MOVE: usize = 256;
+
+/// Fractions of the moves that add a dot and that remove one. The rest move one a bit
+const ADD_MOVES: f64 = 0.1;
+const REMOVE_MOVES: f64 = 0.1;
+
+const INK: Rgb<u8> = Rgb([0, 0, 0]);
+
+struct Stipple {
+    w: usize,
+    h: usize,
+    size: usize,
+    ink: Ink,
+    /// Top left corners of the boxes the dots are inscribed in
+    dots: Vec<(usize, usize)>,
+    /// How many dots cover each pixel (column-major)
+    coverage: Vec<u16>,
+}
+
+impl Stipple {
+    fn dot(&self, top_left: (usize, usize)) -> Shape {
+        Shape::Ellipse {
+            top_left,
+            bottom_right: (top_left.0 + self.size, top_left.1 + self.size),
+        }
+    }
+
+    /// Top left corner of a dot centered as close to (`x`, `y`) as fits in the image
+    fn centered(&self, x: isize, y: isize) -> (usize, usize) {
+        let half = (self.size / 2) as isize;
+        (
+            (x - half).clamp(0, (self.w - self.size) as isize) as usize,
+            (y - half).clamp(0, (self.h - self.size) as isize) as usize,
+        )
+    }
+
+    /// Tries taking away the dot at `removed` and putting one at `added`, keeping the change if
+    /// `accept` says so about the change in cost
+    fn try_change(
+        &mut self,
+        removed: Option<usize>,
+        added: Option<(usize, usize)>,
+        accept: impl FnOnce(f64) -> bool,
+    ) {
+        let removed_coords = removed.map(|index| self.dot(self.dots[index]).coords(self.w, self.h));
+        let added_coords = added.map(|top_left| self.dot(top_left).coords(self.w, self.h));
+        let mut changes = removed_coords
+            .iter()
+            .flatten()
+            .map(|&coord| (coord, -1))
+            .chain(added_coords.iter().flatten().map(|&coord| (coord, 1)))
+            .collect::<Vec<_>>();
+        changes.sort_unstable();
+        // pixels under both dots stay inked, and only pixels going from inked to not (or back)
+        // change the ink
+        let mut flips = Vec::new();
+        for chunk in changes.chunk_by(|a, b| a.0 == b.0) {
+            let coord = chunk[0].0;
+            let before = self.coverage[coord.index(self.h)] as i32;
+            let after = before + chunk.iter().map(|&(_, change)| change).sum::<i32>();
+            if (before > 0) != (after > 0) {
+                flips.push((coord, if after > 0 { 1.0 } else { -1.0 }));
+            }
+        }
+        if !self.ink.try_change(&flips, accept) {
+            return;
+        }
+        for &(coord, change) in &changes {
+            let coverage = &mut self.coverage[coord.index(self.h)];
+            *coverage = (*coverage as i32 + change) as u16;
+        }
+        match (removed, added) {
+            (Some(index), Some(top_left)) => self.dots[index] = top_left,
+            (Some(index), None) => {
+                self.dots.swap_remove(index);
+            }
+            (None, Some(top_left)) => self.dots.push(top_left),
+            (None, None) => (),
+        }
+    }
+}
+
+/// Stipples `target` with dots `size` pixels across, cooling down by `alpha` after every round of
+/// moves. Returns the stippled image along with its shapes: the paper, then every dot
+pub fn stipple(target: &Target, size: usize, alpha: f64) -> (Image, Vec<(Shape, Fill, BlendMode)>) {
+    let (w, h) = (target.width(), target.height());
+    if size == 0 {
+        panic!("dots have to be at least 1 pixel across");
+    }
+    if size > w || size > h {
+        panic!("dots can't be bigger than the image");
+    }
+    let darkness = ink::darkness(target);
+    let mut stipple = Stipple {
+        w,
+        h,
+        size,
+        ink: Ink::new(target, BLUR_REACH * size),
+        dots: Vec::new(),
+        coverage: vec![0; w * h],
+    };
+    let dot_ink = stipple.dot((0, 0)).coords(w, h).len() as f64;
+    // about enough dots to cover the image's darkness, each one landing on a pixel more likely the
+    // darker it is
+    let count = (darkness.iter().sum::<f64>() / dot_ink).round() as usize;
+    while stipple.dots.len() < count {
+        let i = random::<usize>() % (w * h);
+        if random::<f64>() < darkness[i] {
+            let top_left = stipple.centered((i / h) as isize, (i % h) as isize);
+            stipple.try_change(None, Some(top_left), |_| true);
+        }
+    }
+    println!(
+        "scattered {} dots | cost: {:.5}",
+        stipple.dots.len(),
+        stipple.ink.mean_cost()
+    );
+    let moves = (w * h).div_ceil(PIXELS_PER_MOVE);
+    let steps = (FINAL_TEMP / INITIAL_TEMP).ln() / alpha.ln();
+    let mut temp = INITIAL_TEMP;
+    let mut step = 0;
+    while temp >= FINAL_TEMP {
+        let progress = step as f64 / steps;
+        // dots get moved shorter and shorter distances as it cools down
+        let reach = (((BLUR_REACH * size) as f64 * (1.0 - progress)).round() as isize).max(1);
+        for _ in 0..moves {
+            let accept = |diff: f64| {
+                diff < 0.0 || acceptance_random::<f64>() < (-diff / (temp * dot_ink)).exp()
+            };
+            let kind = random::<f64>();
+            if kind < ADD_MOVES || stipple.dots.is_empty() {
+                let top_left = (
+                    random::<usize>() % (w - size + 1),
+                    random::<usize>() % (h - size + 1),
+                );
+                stipple.try_change(None, Some(top_left), accept);
+            } else if kind < ADD_MOVES + REMOVE_MOVES {
+                let index = random::<usize>() % stipple.dots.len();
+                stipple.try_change(Some(index), None, accept);
+            } else {
+                let index = random::<usize>() % stipple.dots.len();
+                let (x, y) = stipple.dots[index];
+                let offset = || random::<isize>().rem_euclid(2 * reach + 1) - reach;
+                let half = (size / 2) as isize;
+                let top_left =
+                    stipple.centered(x as isize + half + offset(), y as isize + half + offset());
+                stipple.try_change(Some(index), Some(top_left), accept);
+            }
+        }
+        temp *= alpha;
+        step += 1;
+        if step % (steps as usize / 10).max(1) == 0 {
+            println!(
+                "stippling {:.0}% | dots: {} | cost: {:.5}",
+                (progress * 100.0).min(100.0),
+                stipple.dots.len(),
+                stipple.ink.mean_cost()
+            );
+        }
+    }
+    println!(
+        "stippled with {} dots | cost: {:.5}",
+        stipple.dots.len(),
+        stipple.ink.mean_cost()
+    );
+    let image = (0..w)
+        .map(|x| {
+            (0..h)
+                .map(|y| {
+                    if stipple.coverage[x * h + y] > 0 {
+                        INK
+                    } else {
+                        ink::PAPER
+                    }
+                })
+                .collect()
+        })
+        .collect();
+    let shapes = std::iter::once(ink::paper(w, h))
+        .chain(
+            stipple
+                .dots
+                .iter()
+                .map(|&top_left| (stipple.dot(top_left), Fill::Solid(INK), BlendMode::NORMAL)),
+        )
+        .collect();
+    (image, shapes)
+}
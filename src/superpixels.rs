@@ -0,0 +1,212 @@
+//! Shapes taken from SLIC superpixels of the original image, for proposing alongside the random
+//! ones. Superpixels are patches of similar color that follow the edges in the image, so a shape
+//! roughly covering one, in its average color, usually lowers the cost right away. That gets
+//! photographs to something recognizable a lot sooner than waiting for random shapes to line up
+
+use crate::rng::random;
+use crate::{
+    colorspace::Colorspace,
+    coord::Coord,
+    geometry::{self, Polygon, MAX_BLOB_POINTS},
+    target::Target,
+    Shape, ShapeKind,
+};
+use image::Rgb;
+
+/// Roughly how many superpixels the image gets split into
+const SUPERPIXEL_COUNT: usize = 256;
+
+/// How many times pixels get reassigned to their closest superpixel
+const ITERATIONS: usize = 10;
+
+/// How much distance counts against color when assigning pixels, in Lab units per superpixel
+/// width. Higher values make the superpixels squarer
+const COMPACTNESS: f64 = 25.0;
+
+/// Fraction of each superpixel's pixels at each end of its extent that its rectangle leaves out,
+/// since the odd stray pixel would blow the rectangle up
+const TRIM_FRACTION: f64 = 0.1;
+
+pub struct Superpixels {
+    shapes: Vec<(Shape, Rgb<u8>)>,
+}
+
+/// Labels every pixel (column-major) with the superpixel it belongs to
+fn slic(target: &Target) -> Vec<usize> {
+    let (w, h) = (target.width(), target.height());
+    let spacing = ((w * h) as f64 / SUPERPIXEL_COUNT as f64).sqrt().max(1.0);
+    let lab = (0..w)
+        .flat_map(|x| (0..h).map(move |y| Coord::new(x, y)))
+        .map(|coord| Colorspace::Lab.channels(target.pixel(coord)))
+        .collect::<Vec<_>>();
+    // centers start out evenly spread on a grid, as (x, y, color)
+    let step = spacing as usize;
+    let mut centers = (step / 2..w)
+        .step_by(step.max(1))
+        .flat_map(|x| (step / 2..h).step_by(step.max(1)).map(move |y| (x, y)))
+        .map(|(x, y)| (x as f64, y as f64, lab[x * h + y]))
+        .collect::<Vec<_>>();
+    let mut labels = vec![0; w * h];
+    let spatial_weight = (COMPACTNESS / spacing).powi(2);
+    for _ in 0..ITERATIONS {
+        let mut distances = vec![f64::INFINITY; w * h];
+        // each center only looks at the pixels within two spacings of it
+        for (label, &(cx, cy, color)) in centers.iter().enumerate() {
+            let reach = 2.0 * spacing;
+            let x_range = (cx - reach).max(0.0) as usize..((cx + reach) as usize).min(w);
+            for x in x_range {
+                let y_range = (cy - reach).max(0.0) as usize..((cy + reach) as usize).min(h);
+                for y in y_range {
+                    let i = x * h + y;
+                    let color_distance =
+                        (0..3).map(|c| (lab[i][c] - color[c]).powi(2)).sum::<f64>();
+                    let spatial_distance = (x as f64 - cx).powi(2) + (y as f64 - cy).powi(2);
+                    let distance = color_distance + spatial_weight * spatial_distance;
+                    if distance < distances[i] {
+                        distances[i] = distance;
+                        labels[i] = label;
+                    }
+                }
+            }
+        }
+        // moving every center to the middle of its pixels
+        let mut sums = vec![(0.0, 0.0, [0.0; 3], 0.0); centers.len()];
+        for x in 0..w {
+            for y in 0..h {
+                let i = x * h + y;
+                let sum = &mut sums[labels[i]];
+                sum.0 += x as f64;
+                sum.1 += y as f64;
+                for (sum, value) in sum.2.iter_mut().zip(lab[i]) {
+                    *sum += value;
+                }
+                sum.3 += 1.0;
+            }
+        }
+        for (center, (x, y, color, count)) in centers.iter_mut().zip(sums) {
+            if count > 0.0 {
+                *center = (x / count, y / count, color.map(|c| c / count));
+            }
+        }
+    }
+    labels
+}
+
+/// Twice the area of the triangle between three points
+fn area(a: (i64, i64), b: (i64, i64), c: (i64, i64)) -> i64 {
+    ((b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)).abs()
+}
+
+/// The biggest triangle with its vertices on the hull
+fn biggest_triangle(hull: &[(i64, i64)]) -> Option<[(i64, i64); 3]> {
+    let n = hull.len();
+    let mut biggest = None;
+    let mut biggest_area = 0;
+    for i in 0..n {
+        for j in i + 1..n {
+            for k in j + 1..n {
+                let area = area(hull[i], hull[j], hull[k]);
+                if area > biggest_area {
+                    biggest_area = area;
+                    biggest = Some([hull[i], hull[j], hull[k]]);
+                }
+            }
+        }
+    }
+    biggest
+}
+
+/// The hull cut down to at most `vertices` vertices, by dropping whichever vertex takes the least
+/// area with it each time
+fn simplified(mut hull: Vec<(i64, i64)>, vertices: usize) -> Vec<(i64, i64)> {
+    while hull.len() > vertices {
+        let n = hull.len();
+        let least = (0..n)
+            .min_by_key(|&i| area(hull[(i + n - 1) % n], hull[i], hull[(i + 1) % n]))
+            .unwrap();
+        hull.remove(least);
+    }
+    hull
+}
+
+impl Superpixels {
+    /// Splits the original image into superpixels, each turned into a shape of the given kind
+    /// with its corners snapped to `grid` (and polygons having at most `vertices` of them)
+    pub fn new(target: &Target, kind: ShapeKind, grid: usize, vertices: usize) -> Superpixels {
+        let (w, h) = (target.width(), target.height());
+        let labels = slic(target);
+        let mut members = vec![Vec::new(); labels.iter().max().map_or(0, |&max| max + 1)];
+        for x in 0..w {
+            for y in 0..h {
+                members[labels[x * h + y]].push((x, y));
+            }
+        }
+        let snap = |value: i64, limit: usize| {
+            ((value as f64 / grid as f64).round() as usize * grid).min(limit)
+        };
+        let shapes = members
+            .into_iter()
+            .filter(|pixels| !pixels.is_empty())
+            .filter_map(|pixels| {
+                let shape = match kind {
+                    ShapeKind::Triangle => {
+                        let hull = geometry::convex_hull(
+                            pixels.iter().map(|&(x, y)| (x as i64, y as i64)).collect(),
+                        );
+                        let vertices = biggest_triangle(&hull)?;
+                        Shape::Triangle(vertices.map(|(x, y)| (snap(x, w - 1), snap(y, h - 1))))
+                    }
+                    // blobs go around the hull cut down to as many control points as they can have,
+                    // which keeps them inside it. Superpixels are hardly ever long and thin, so
+                    // strokes just get the hull as a polygon
+                    ShapeKind::Polygon | ShapeKind::Blob | ShapeKind::Stroke => {
+                        let hull = geometry::convex_hull(
+                            pixels.iter().map(|&(x, y)| (x as i64, y as i64)).collect(),
+                        );
+                        let most = match kind {
+                            ShapeKind::Blob => MAX_BLOB_POINTS,
+                            _ => vertices,
+                        };
+                        let snapped = simplified(hull, most)
+                            .into_iter()
+                            .map(|(x, y)| (snap(x, w - 1), snap(y, h - 1)))
+                            .collect::<Vec<_>>();
+                        match kind {
+                            ShapeKind::Blob => Shape::Blob(Polygon::new(&snapped)),
+                            _ => Shape::Polygon(Polygon::new(&snapped)),
+                        }
+                    }
+                    // everything else fills the superpixel's bounding box
+                    _ => {
+                        let mut xs = pixels.iter().map(|&(x, _)| x).collect::<Vec<_>>();
+                        let mut ys = pixels.iter().map(|&(_, y)| y).collect::<Vec<_>>();
+                        xs.sort_unstable();
+                        ys.sort_unstable();
+                        let trim = (pixels.len() as f64 * TRIM_FRACTION) as usize;
+                        let (low, high) = (trim, pixels.len() - 1 - trim);
+                        geometry::boxed(
+                            kind,
+                            (snap(xs[low] as i64, w), snap(ys[low] as i64, h)),
+                            (snap(xs[high] as i64 + 1, w), snap(ys[high] as i64 + 1, h)),
+                        )
+                    }
+                };
+                let shape = geometry::validate(shape, w, h, false)?;
+                let mut sums = [0.0; 3];
+                for &(x, y) in &pixels {
+                    for (sum, value) in sums.iter_mut().zip(target.pixel(Coord::new(x, y)).0) {
+                        *sum += value as f64;
+                    }
+                }
+                let color = Rgb(sums.map(|sum| (sum / pixels.len() as f64).round() as u8));
+                Some((shape, color))
+            })
+            .collect();
+        Superpixels { shapes }
+    }
+
+    /// One of the superpixels' shapes and colors, at random
+    pub fn proposal(&self) -> Option<(Shape, Rgb<u8>)> {
+        (!self.shapes.is_empty()).then(|| self.shapes[random::<usize>() % self.shapes.len()])
+    }
+}
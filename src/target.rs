@@ -0,0 +1,181 @@
+//! The original image that's being approximated, stored as a separate plane per channel
+//! so the cost kernels can run over one channel at a time
+
+use crate::{coord::Coord, pixel_difference};
+use clap::ValueEnum;
+use image::Rgb;
+use memmap2::Mmap;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    iter::zip,
+};
+
+/// Where the channel planes are kept
+enum Planes {
+    Resident([Vec<u8>; 3]),
+    /// In a file mapped into memory, one plane after another, so the OS can page them in and out
+    /// as they're needed
+    Mapped(Mmap),
+}
+
+/// How the difference in each channel of a pixel counts towards the cost
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum Loss {
+    /// The difference itself
+    Absolute,
+    /// Like the difference, but less than it for differences below the delta, going down to
+    /// nothing smoothly (squared), so small errors like noise count for less than big ones
+    Huber,
+    /// The difference, up to the delta at most, so big errors like tiny bright details can't
+    /// dominate the cost
+    Truncated,
+}
+
+impl Loss {
+    /// What a difference of `difference` in a single channel counts for
+    fn apply(self, difference: f64, delta: f64) -> f64 {
+        match self {
+            Loss::Absolute => difference,
+            Loss::Huber if difference <= delta => difference * difference / (2.0 * delta),
+            Loss::Huber => difference - delta / 2.0,
+            Loss::Truncated => difference.min(delta),
+        }
+    }
+}
+
+pub struct Target {
+    w: usize,
+    h: usize,
+    /// Red, green, and blue planes, each column-major like the annealed image
+    planes: Planes,
+    /// How much each pixel counts towards the cost (column-major), if they don't all count the same
+    weights: Option<Vec<f64>>,
+    /// How channel differences count, along with the delta for the losses that have one
+    loss: (Loss, f64),
+}
+
+impl Target {
+    /// Splits a column-major pixel grid into channel planes
+    pub fn new(pixels: &[Vec<Rgb<u8>>]) -> Target {
+        Target {
+            w: pixels.len(),
+            h: pixels[0].len(),
+            planes: Planes::Resident(to_planes(pixels.iter().flatten().copied())),
+            weights: None,
+            loss: (Loss::Absolute, 0.0),
+        }
+    }
+
+    /// Makes each pixel count towards the cost by its weight (column-major). The weights get scaled
+    /// to average 1, so costs stay comparable with the temperatures
+    pub fn with_weights(self, weights: Vec<f64>) -> Target {
+        let mean = weights.iter().sum::<f64>() / weights.len() as f64;
+        if mean <= 0.0 {
+            panic!("weights can't all be 0");
+        }
+        Target {
+            weights: Some(weights.iter().map(|weight| weight / mean).collect()),
+            ..self
+        }
+    }
+
+    /// Makes the differences in each channel count according to `loss`, with `delta` as the point
+    /// where its behavior changes
+    pub fn with_loss(self, loss: Loss, delta: f64) -> Target {
+        Target {
+            loss: (loss, delta),
+            ..self
+        }
+    }
+
+    /// Moves the channel planes out of memory into the file at `path` (overwriting it), which gets
+    /// mapped in their place. Every lookup might have to wait on the disk then, but the OS only has
+    /// to keep the parts of the image that are being looked at in memory
+    pub fn mapped(self, path: &str) -> Target {
+        let Planes::Resident(ref planes) = self.planes else {
+            return self;
+        };
+        let mut writer = BufWriter::new(File::create(path).unwrap());
+        for plane in planes {
+            writer.write_all(plane).unwrap();
+        }
+        writer.into_inner().unwrap().sync_all().unwrap();
+        // the file is ours for the rest of the run, nothing else should be changing it underneath us
+        let mmap = unsafe { Mmap::map(&File::open(path).unwrap()).unwrap() };
+        Target {
+            planes: Planes::Mapped(mmap),
+            ..self
+        }
+    }
+
+    /// Whether every pixel counts the same and differences count as they are, which is all the
+    /// fast kernels and summed-area tables know how to add up
+    pub fn is_plain(&self) -> bool {
+        self.weights.is_none() && self.loss.0 == Loss::Absolute
+    }
+
+    /// Whether the planes are in a file mapped into memory, rather than kept in memory
+    pub fn is_mapped(&self) -> bool {
+        matches!(self.planes, Planes::Mapped(_))
+    }
+
+    pub fn width(&self) -> usize {
+        self.w
+    }
+
+    pub fn height(&self) -> usize {
+        self.h
+    }
+
+    pub fn planes(&self) -> [&[u8]; 3] {
+        match self.planes {
+            Planes::Resident(ref planes) => planes.each_ref().map(Vec::as_slice),
+            Planes::Mapped(ref mmap) => {
+                let n = self.w * self.h;
+                [0, 1, 2].map(|channel| &mmap[channel * n..(channel + 1) * n])
+            }
+        }
+    }
+
+    pub fn pixel(&self, coord: Coord) -> Rgb<u8> {
+        let i = coord.index(self.h);
+        Rgb(self.planes().map(|plane| plane[i]))
+    }
+
+    /// Difference between the pixel at `coord` and `pixel`, going by the loss and scaled by the
+    /// pixel's weight
+    pub fn difference(&self, coord: Coord, pixel: Rgb<u8>) -> f64 {
+        let difference = match self.loss {
+            (Loss::Absolute, _) => pixel_difference(self.pixel(coord), pixel) as f64,
+            (loss, delta) => zip(self.pixel(coord).0, pixel.0)
+                .map(|(a, b)| loss.apply((a as f64 - b as f64).abs(), delta))
+                .sum(),
+        };
+        match self.weights {
+            Some(ref weights) => difference * weights[coord.index(self.h)],
+            None => difference,
+        }
+    }
+
+    /// Channels of the pixels at the given coordinates, one plane per channel
+    pub fn gather(&self, coords: &[Coord]) -> [Vec<u8>; 3] {
+        self.planes().map(|plane| {
+            coords
+                .iter()
+                .map(|coord| plane[coord.index(self.h)])
+                .collect()
+        })
+    }
+}
+
+/// Splits pixels into one plane per channel
+pub fn to_planes(pixels: impl IntoIterator<Item = Rgb<u8>>) -> [Vec<u8>; 3] {
+    let mut planes = [Vec::new(), Vec::new(), Vec::new()];
+    for pixel in pixels {
+        for (plane, value) in planes.iter_mut().zip(pixel.0) {
+            plane.push(value);
+        }
+    }
+    planes
+}
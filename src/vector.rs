@@ -0,0 +1,441 @@
+//! Exporting the accepted shapes as vector graphics, so the result can be printed at any size
+//! without upscaling the annealed image
+
+use crate::{geometry, Blend, BlendMode, Fill, Shape};
+use image::Rgb;
+use std::{env::args, fs::write};
+
+/// Corners of the shape as a polygon in a `w` by `h` image.
+/// Besides the shape itself, this includes copies shifted back over the image for the parts of it
+/// that wrap around the edges in tileable mode
+pub fn polygons(shape: Shape, w: usize, h: usize) -> Vec<Vec<(f64, f64)>> {
+    // pixels are unit squares, so rectangles (and the boxes of ellipses) cover whole pixels and
+    // triangles and polygons join pixel centers, and so do the curves around blobs' control
+    // points
+    let points = match shape {
+        Shape::Rectangle {
+            top_left,
+            bottom_right,
+        } => {
+            let (x1, y1) = (top_left.0 as f64, top_left.1 as f64);
+            let (x2, y2) = (bottom_right.0 as f64, bottom_right.1 as f64);
+            vec![(x1, y1), (x2, y1), (x2, y2), (x1, y2)]
+        }
+        Shape::Triangle(vertices) => vertices
+            .iter()
+            .map(|&(x, y)| (x as f64 + 0.5, y as f64 + 0.5))
+            .collect(),
+        Shape::Ellipse {
+            top_left,
+            bottom_right,
+        } => geometry::ellipse_polygon(top_left, bottom_right),
+        Shape::Polygon(polygon) => polygon
+            .vertices()
+            .iter()
+            .map(|&(x, y)| (x as f64 + 0.5, y as f64 + 0.5))
+            .collect(),
+        Shape::Blob(points) => geometry::blob_curve(points.vertices())
+            .into_iter()
+            .map(|(x, y)| (x + 0.5, y + 0.5))
+            .collect(),
+        // stamps are bitmaps, so the most an outline can say about them is their box
+        Shape::Stamp(placement) => placement.corners(),
+    };
+    let (w, h) = (w as f64, h as f64);
+    let wraps_x = points.iter().any(|p| p.0 > w);
+    let wraps_y = points.iter().any(|p| p.1 > h);
+    let mut polygons = Vec::new();
+    for dx in [0.0, w].into_iter().take(1 + wraps_x as usize) {
+        for dy in [0.0, h].into_iter().take(1 + wraps_y as usize) {
+            polygons.push(points.iter().map(|&(x, y)| (x - dx, y - dy)).collect());
+        }
+    }
+    polygons
+}
+
+/// A point in the image, going right and down from its top left corner
+type Point = (f64, f64);
+
+/// Where a gradient fill starts and ends in the image, at the middles of its pixels the same as the
+/// polygons' corners, and its colors. Solid fills, and gradients starting and ending in the same
+/// place, are just painted with their first color
+pub fn gradient(fill: Fill) -> Option<(Point, Point, [Rgb<u8>; 2])> {
+    match fill {
+        Fill::Linear { start, end, colors } if start != end => Some((
+            (start.0 as f64 + 0.5, start.1 as f64 + 0.5),
+            (end.0 as f64 + 0.5, end.1 as f64 + 0.5),
+            colors,
+        )),
+        _ => None,
+    }
+}
+
+/// A gradient going from `start` to `end` on a page `h` high, as a PDF or PostScript shading
+/// dictionary. Both languages go up the page, and both carry on the end colors past the ends
+fn axial_shading(h: usize, (x1, y1): Point, (x2, y2): Point, colors: [Rgb<u8>; 2]) -> String {
+    let (y1, y2) = (h as f64 - y1, h as f64 - y2);
+    format!(
+        "<< /ShadingType 2 /ColorSpace /DeviceRGB /Coords [{x1} {y1} {x2} {y2}] \
+         /Function << /FunctionType 2 /Domain [0 1] /C0 [{}] /C1 [{}] /N 1 >> /Extend [true true] >>",
+        color_fractions(colors[0]),
+        color_fractions(colors[1])
+    )
+}
+
+/// Color channels as fractions, the way both PDF and PostScript like them
+fn color_fractions(Rgb([r, g, b]): Rgb<u8>) -> String {
+    format!(
+        "{:.3} {:.3} {:.3}",
+        r as f64 / 255.0,
+        g as f64 / 255.0,
+        b as f64 / 255.0
+    )
+}
+
+/// Name of the blend in PDF
+fn pdf_blend(blend: Blend) -> &'static str {
+    match blend {
+        Blend::Normal => "Normal",
+        Blend::Multiply => "Multiply",
+        Blend::Screen => "Screen",
+        Blend::Overlay => "Overlay",
+    }
+}
+
+/// How opaque a blend mode is, as a fraction
+fn opacity_fraction(blend_mode: BlendMode) -> String {
+    format!("{:.3}", blend_mode.opacity as f64 / 255.0)
+}
+
+/// How many groups back a shape gets looked for a group of its color in, when merging them
+const MERGE_LOOKBACK: usize = 64;
+
+/// Polygons that get drawn as one element, all in the same fill and blend mode
+struct Group {
+    fill: Fill,
+    blend_mode: BlendMode,
+    polygons: Vec<Vec<(f64, f64)>>,
+    /// Bounding box of each polygon, as its smallest and biggest x and y
+    bounds: Vec<[f64; 4]>,
+}
+
+/// Smallest and biggest x and y of the polygon's corners
+fn bounds(polygon: &[(f64, f64)]) -> [f64; 4] {
+    polygon.iter().fold(
+        [
+            f64::INFINITY,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            f64::NEG_INFINITY,
+        ],
+        |[x1, y1, x2, y2], &(x, y)| [x1.min(x), y1.min(y), x2.max(x), y2.max(y)],
+    )
+}
+
+/// Whether two bounding boxes overlap by more than their edges
+fn overlaps(a: [f64; 4], b: [f64; 4]) -> bool {
+    a[0] < b[2] && b[0] < a[2] && a[1] < b[3] && b[1] < a[3]
+}
+
+/// The same polygon going clockwise on screen
+fn clockwise(mut polygon: Vec<(f64, f64)>) -> Vec<(f64, f64)> {
+    let area = (0..polygon.len())
+        .map(|i| {
+            let ((x1, y1), (x2, y2)) = (polygon[i], polygon[(i + 1) % polygon.len()]);
+            x1 * y2 - x2 * y1
+        })
+        .sum::<f64>();
+    if area < 0.0 {
+        polygon.reverse();
+    }
+    polygon
+}
+
+/// The shapes' polygons, with plain painted ones of the same fill grouped together. A shape can
+/// only join a group painted before it if none of the shapes in between overlap it, since it
+/// gets painted along with that group instead of where it was
+fn merged_groups(shapes: &[(Shape, Fill, BlendMode)], w: usize, h: usize) -> Vec<Group> {
+    let mut groups: Vec<Group> = Vec::new();
+    for &(shape, fill, blend_mode) in shapes {
+        let polygons = polygons(shape, w, h)
+            .into_iter()
+            .map(clockwise)
+            .collect::<Vec<_>>();
+        let shape_bounds = polygons
+            .iter()
+            .map(|polygon| bounds(polygon))
+            .collect::<Vec<_>>();
+        let mut joined = None;
+        if blend_mode == BlendMode::NORMAL {
+            for (index, group) in groups.iter().enumerate().rev().take(MERGE_LOOKBACK) {
+                if group.fill == fill && group.blend_mode == BlendMode::NORMAL {
+                    joined = Some(index);
+                    break;
+                }
+                let in_the_way = group
+                    .bounds
+                    .iter()
+                    .any(|&a| shape_bounds.iter().any(|&b| overlaps(a, b)));
+                if in_the_way {
+                    break;
+                }
+            }
+        }
+        match joined {
+            Some(index) => {
+                groups[index].polygons.extend(polygons);
+                groups[index].bounds.extend(shape_bounds);
+            }
+            None => groups.push(Group {
+                fill,
+                blend_mode,
+                polygons,
+                bounds: shape_bounds,
+            }),
+        }
+    }
+    groups
+}
+
+/// Saves the shapes of a `w` by `h` image as a single page PDF at `path`.
+/// Each pixel of the image is a point on the page, and pages start from the bottom left
+pub fn save_pdf(path: &str, w: usize, h: usize, shapes: &[(Shape, Fill, BlendMode)]) {
+    // every blend mode the shapes use gets a graphics state of its own, named by its place here
+    let mut blend_modes = vec![BlendMode::NORMAL];
+    for &(_, _, blend_mode) in shapes {
+        if !blend_modes.contains(&blend_mode) {
+            blend_modes.push(blend_mode);
+        }
+    }
+    let mut content = format!("0 0 0 rg 0 0 {w} {h} re f\n");
+    let mut current_blend_mode = BlendMode::NORMAL;
+    // gradients get painted by clipping to the shape and filling the clip with a shading
+    let mut shadings = Vec::new();
+    for &(shape, fill, blend_mode) in shapes {
+        if blend_mode != current_blend_mode {
+            let index = blend_modes.iter().position(|&mode| mode == blend_mode);
+            content += &format!("/GS{} gs\n", index.unwrap());
+            current_blend_mode = blend_mode;
+        }
+        let gradient = gradient(fill);
+        match gradient {
+            Some(_) => content += "q\n",
+            None => content += &format!("{} rg\n", color_fractions(fill.colors()[0])),
+        }
+        for polygon in polygons(shape, w, h) {
+            for (i, (x, y)) in polygon.iter().enumerate() {
+                let op = if i == 0 { "m" } else { "l" };
+                content += &format!("{x} {} {op}\n", h as f64 - y);
+            }
+            content += if gradient.is_some() { "h\n" } else { "h f\n" };
+        }
+        if let Some((start, end, colors)) = gradient {
+            content += &format!("W n /Sh{} sh Q\n", shadings.len());
+            shadings.push(axial_shading(h, start, end, colors));
+        }
+    }
+
+    let graphics_states = (0..blend_modes.len())
+        .map(|i| format!("/GS{i} {} 0 R", 5 + i))
+        .collect::<Vec<String>>()
+        .join(" ");
+    let shading_names = (0..shadings.len())
+        .map(|i| format!("/Sh{i} {} 0 R", 5 + blend_modes.len() + i))
+        .collect::<Vec<String>>()
+        .join(" ");
+    let mut objects = vec![
+        String::from("<< /Type /Catalog /Pages 2 0 R >>"),
+        String::from("<< /Type /Pages /Kids [3 0 R] /Count 1 >>"),
+        format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {w} {h}] /Contents 4 0 R \
+             /Resources << /ExtGState << {graphics_states} >> /Shading << {shading_names} >> >> >>"
+        ),
+        format!(
+            "<< /Length {} >>\nstream\n{content}endstream",
+            content.len()
+        ),
+    ];
+    objects.extend(blend_modes.iter().map(|&mode| {
+        format!(
+            "<< /Type /ExtGState /BM /{} /ca {} >>",
+            pdf_blend(mode.blend),
+            opacity_fraction(mode)
+        )
+    }));
+    objects.extend(shadings);
+
+    let mut pdf = String::from("%PDF-1.4\n");
+    let mut offsets = Vec::new();
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf += &format!("{} 0 obj\n{object}\nendobj\n", i + 1);
+    }
+    let xref_offset = pdf.len();
+    pdf += &format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+    for offset in offsets {
+        pdf += &format!("{offset:010} 00000 n \n");
+    }
+    pdf += &format!(
+        "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{xref_offset}\n%%EOF\n",
+        objects.len() + 1
+    );
+    write(path, pdf).unwrap();
+}
+
+/// Saves the shapes of a `w` by `h` image as EPS at `path`.
+/// PostScript has no blend modes or transparency, so every shape is painted normally and opaquely.
+/// Gradients need PostScript 3 for their shadings
+pub fn save_eps(path: &str, w: usize, h: usize, shapes: &[(Shape, Fill, BlendMode)]) {
+    let mut eps = format!(
+        "%!PS-Adobe-3.0 EPSF-3.0\n%%BoundingBox: 0 0 {w} {h}\n%%EndComments\n\
+         0 0 {w} {h} rectclip\n0 0 0 setrgbcolor 0 0 {w} {h} rectfill\n"
+    );
+    for &(shape, fill, _) in shapes {
+        let gradient = gradient(fill);
+        match gradient {
+            Some(_) => eps += "gsave newpath\n",
+            None => eps += &format!("{} setrgbcolor\n", color_fractions(fill.colors()[0])),
+        }
+        for polygon in polygons(shape, w, h) {
+            if gradient.is_none() {
+                eps += "newpath\n";
+            }
+            for (i, (x, y)) in polygon.iter().enumerate() {
+                let op = if i == 0 { "moveto" } else { "lineto" };
+                eps += &format!("{x} {} {op}\n", h as f64 - y);
+            }
+            eps += if gradient.is_some() {
+                "closepath\n"
+            } else {
+                "closepath fill\n"
+            };
+        }
+        if let Some((start, end, colors)) = gradient {
+            eps += &format!(
+                "clip\n{} shfill grestore\n",
+                axial_shading(h, start, end, colors)
+            );
+        }
+    }
+    eps += "showpage\n%%EOF\n";
+    write(path, eps).unwrap();
+}
+
+/// Escapes text for putting in XML
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Name of the blend in CSS
+fn css_blend(blend: Blend) -> &'static str {
+    match blend {
+        Blend::Normal => "normal",
+        Blend::Multiply => "multiply",
+        Blend::Screen => "screen",
+        Blend::Overlay => "overlay",
+    }
+}
+
+/// Saves the shapes of a `w` by `h` image as SVG at `path`. The file documents how it was made:
+/// the command line arguments, the version, the final `cost`, and how many shapes of each kind
+/// there are go in its description and (for programs to read) its metadata
+pub fn save_svg(path: &str, w: usize, h: usize, shapes: &[(Shape, Fill, BlendMode)], cost: f64) {
+    let version = env!("GIT_DESCRIBE");
+    let arguments = args().skip(1).collect::<Vec<_>>();
+    let rectangles = shapes
+        .iter()
+        .filter(|(shape, _, _)| matches!(shape, Shape::Rectangle { .. }))
+        .count();
+    let ellipses = shapes
+        .iter()
+        .filter(|(shape, _, _)| matches!(shape, Shape::Ellipse { .. }))
+        .count();
+    let polygons = shapes
+        .iter()
+        .filter(|(shape, _, _)| matches!(shape, Shape::Polygon(_)))
+        .count();
+    let blobs = shapes
+        .iter()
+        .filter(|(shape, _, _)| matches!(shape, Shape::Blob(_)))
+        .count();
+    let triangles = shapes.len() - rectangles - ellipses - polygons - blobs;
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" xmlns:anneal=\"urn:anneal_image\" \
+         width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n"
+    );
+    svg += "<title>anneal_image</title>\n";
+    svg += &format!(
+        "<desc>{} shapes ({rectangles} rectangles, {triangles} triangles, {ellipses} ellipses, {polygons} polygons, {blobs} blobs) \
+         annealed by anneal_image {version} down to a cost of {cost:.5}, with the arguments: {}</desc>\n",
+        shapes.len(),
+        escape_xml(&arguments.join(" "))
+    );
+    svg += &format!(
+        "<metadata>\n<anneal:run version=\"{}\" cost=\"{cost}\" shapes=\"{}\" \
+         rectangles=\"{rectangles}\" triangles=\"{triangles}\" ellipses=\"{ellipses}\" polygons=\"{polygons}\" blobs=\"{blobs}\">\n",
+        escape_xml(version),
+        shapes.len()
+    );
+    for argument in &arguments {
+        svg += &format!(
+            "<anneal:argument>{}</anneal:argument>\n",
+            escape_xml(argument)
+        );
+    }
+    svg += "</anneal:run>\n</metadata>\n";
+    svg += &format!("<rect width=\"{w}\" height=\"{h}\" fill=\"#000000\"/>\n");
+    for (i, group) in merged_groups(shapes, w, h).into_iter().enumerate() {
+        let opacity = match group.blend_mode.opacity {
+            255 => String::new(),
+            _ => format!(" fill-opacity=\"{}\"", opacity_fraction(group.blend_mode)),
+        };
+        let style = match group.blend_mode.blend {
+            Blend::Normal => String::new(),
+            blend => format!(" style=\"mix-blend-mode:{}\"", css_blend(blend)),
+        };
+        let paint = match gradient(group.fill) {
+            Some(((x1, y1), (x2, y2), [Rgb([r1, g1, b1]), Rgb([r2, g2, b2])])) => {
+                svg += &format!(
+                    "<linearGradient id=\"g{i}\" gradientUnits=\"userSpaceOnUse\" \
+                     x1=\"{x1}\" y1=\"{y1}\" x2=\"{x2}\" y2=\"{y2}\">\
+                     <stop stop-color=\"#{r1:02x}{g1:02x}{b1:02x}\"/>\
+                     <stop offset=\"1\" stop-color=\"#{r2:02x}{g2:02x}{b2:02x}\"/></linearGradient>\n"
+                );
+                format!("url(#g{i})")
+            }
+            None => {
+                let Rgb([r, g, b]) = group.fill.colors()[0];
+                format!("#{r:02x}{g:02x}{b:02x}")
+            }
+        };
+        let fill = format!("fill=\"{paint}\"{opacity}{style}");
+        if let [ref polygon] = group.polygons[..] {
+            let points = polygon
+                .iter()
+                .map(|(x, y)| format!("{x},{y}"))
+                .collect::<Vec<_>>()
+                .join(" ");
+            svg += &format!("<polygon points=\"{points}\" {fill}/>\n");
+        } else {
+            // every outline goes the same way round, so the nonzero rule fills in all of them
+            let data = group
+                .polygons
+                .iter()
+                .map(|polygon| {
+                    let points = polygon
+                        .iter()
+                        .map(|(x, y)| format!("{x},{y}"))
+                        .collect::<Vec<_>>()
+                        .join(" L");
+                    format!("M{points}Z")
+                })
+                .collect::<String>();
+            svg += &format!("<path d=\"{data}\" {fill}/>\n");
+        }
+    }
+    svg += "</svg>\n";
+    write(path, svg).unwrap();
+}